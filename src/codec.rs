@@ -51,6 +51,8 @@ pub fn lookup(name: &str) -> Result<Box<dyn Codec>> {
         "string" => Ok(Box::new(string::String {})),
         "statsd" => Ok(Box::new(statsd::StatsD {})),
         "yaml" => Ok(Box::new(yaml::YAML {})),
-        _ => Err(format!("Codec '{}' not found.", name).into()),
+        _ => {
+            crate::plugin::codec(name).ok_or_else(|| format!("Codec '{}' not found.", name).into())
+        }
     }
 }
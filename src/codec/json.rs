@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::prelude::*;
+use tremor_script::binary;
 
 #[derive(Clone)]
 pub struct JSON {}
@@ -27,7 +28,7 @@ impl Codec for JSON {
     }
     fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
         let mut v = Vec::new();
-        data.write(&mut v)?;
+        binary::portable(data).write(&mut v)?;
         Ok(v)
     }
 }
@@ -52,4 +53,12 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn binaries_are_arrays() -> Result<()> {
+        let codec = JSON {};
+        let data = Value::from(vec![binary::from_bytes(&[1, 2])]);
+        assert_eq!(codec.encode(&data)?, b"[[1,2]]".to_vec());
+        Ok(())
+    }
 }
@@ -14,20 +14,150 @@
 
 use super::prelude::*;
 use rmp_serde as rmps;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use simd_json::StaticNode;
+use std::fmt;
+use tremor_script::binary;
 
 #[derive(Clone)]
 pub struct MsgPack {}
 
+/// A decoded msgpack value, unlike the `Deserialize` implementation of
+/// `Value` this turns `bin` payloads into binaries
+struct Decoded(Value<'static>);
+
+impl<'de> Deserialize<'de> for Decoded {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(DecodedVisitor).map(Decoded)
+    }
+}
+
+struct DecodedVisitor;
+
+impl<'de> Visitor<'de> for DecodedVisitor {
+    type Value = Value<'static>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a msgpack value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> std::result::Result<Self::Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> std::result::Result<Self::Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> std::result::Result<Self::Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> std::result::Result<Self::Value, E> {
+        Ok(Value::from(binary::sanitize_f64(v)))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> std::result::Result<Self::Value, E> {
+        Ok(Value::from(v.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> std::result::Result<Self::Value, E> {
+        Ok(Value::from(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> std::result::Result<Self::Value, E> {
+        Ok(binary::from_bytes(v))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> std::result::Result<Self::Value, E> {
+        Ok(Value::null())
+    }
+
+    fn visit_none<E: de::Error>(self) -> std::result::Result<Self::Value, E> {
+        Ok(Value::null())
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Decoded::deserialize(deserializer).map(|d| d.0)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(Decoded(value)) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut object = Object::new();
+        while let Some((key, Decoded(value))) = map.next_entry::<String, Decoded>()? {
+            object.insert(key.into(), value);
+        }
+        Ok(Value::from(object))
+    }
+}
+
+/// A value to encode, unlike the `Serialize` implementation of `Value`
+/// this writes binaries as `bin` payloads
+struct Encoded<'value, 'event>(&'value Value<'event>);
+
+impl<'value, 'event> Serialize for Encoded<'value, 'event> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if let Some(bytes) = binary::as_bytes(self.0) {
+            return serializer.serialize_bytes(&bytes);
+        }
+        match self.0 {
+            Value::Static(StaticNode::Null) => serializer.serialize_unit(),
+            Value::Static(StaticNode::Bool(b)) => serializer.serialize_bool(*b),
+            Value::Static(StaticNode::I64(i)) => serializer.serialize_i64(*i),
+            Value::Static(StaticNode::U64(u)) => serializer.serialize_u64(*u),
+            Value::Static(StaticNode::F64(f)) => serializer.serialize_f64(*f),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Array(a) => {
+                let mut seq = serializer.serialize_seq(Some(a.len()))?;
+                for v in a {
+                    seq.serialize_element(&Encoded(v))?;
+                }
+                seq.end()
+            }
+            Value::Object(o) => {
+                let mut map = serializer.serialize_map(Some(o.len()))?;
+                for (k, v) in o.iter() {
+                    map.serialize_entry(k, &Encoded(v))?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
 impl Codec for MsgPack {
     fn decode(&mut self, data: Vec<u8>, _ingest_ns: u64) -> Result<Option<LineValue>> {
         LineValue::try_new(vec![data], |data| {
-            rmps::from_slice::<Value>(&data[0]).map(ValueAndMeta::from)
+            rmps::from_slice::<Decoded>(&data[0]).map(|decoded| ValueAndMeta::from(decoded.0))
         })
         .map(Some)
         .map_err(|e| e.0.into())
     }
     fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
-        Ok(rmps::to_vec(&data)?)
+        Ok(rmps::to_vec(&Encoded(data))?)
     }
 }
 
@@ -51,4 +181,24 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn bin_round_trip() -> Result<()> {
+        let mut codec = MsgPack {};
+        // {"data": bin8 [1, 2, 255]}
+        let raw = vec![
+            0x81, 0xa4, b'd', b'a', b't', b'a', 0xc4, 0x03, 0x01, 0x02, 0xff,
+        ];
+        let decoded = codec.decode(raw.clone(), 0)?.expect("no value");
+        let data = decoded.suffix().value().get("data").expect("no data");
+        assert_eq!(binary::as_bytes(data), Some(vec![1, 2, 255]));
+
+        // binaries encode back into the same `bin` payload
+        assert_eq!(codec.encode(decoded.suffix().value())?, raw);
+
+        // arrays of small integers stay arrays
+        let ints = Value::from(vec![1, 2, 3]);
+        assert_eq!(codec.encode(&ints)?, vec![0x93, 0x01, 0x02, 0x03]);
+        Ok(())
+    }
 }
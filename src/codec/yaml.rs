@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use super::prelude::*;
+use tremor_script::binary;
 
 #[derive(Clone)]
 pub struct YAML {}
@@ -28,7 +29,7 @@ impl Codec for YAML {
         .map_err(|e| e.0.into())
     }
     fn encode(&self, data: &simd_json::BorrowedValue) -> Result<Vec<u8>> {
-        Ok(serde_yaml::to_vec(data)?)
+        Ok(serde_yaml::to_vec(&binary::portable(data))?)
     }
 }
 
@@ -24,6 +24,7 @@ pub(crate) type PipelineVec = Vec<dynaconfig::Pipeline>;
 pub(crate) type BindingVec = Vec<Binding>;
 pub(crate) type BindingMap = HashMap<TremorURL, Vec<TremorURL>>;
 pub(crate) type MappingMap = HashMap<TremorURL, HashMap<String, String>>;
+pub(crate) type ParallelismMap = HashMap<TremorURL, Parallelism>;
 
 /// A full tremopr config
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -77,10 +78,94 @@ pub struct OffRamp {
     pub(crate) postprocessors: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) metrics_interval_s: Option<u64>,
+    /// Disk backed queue in front of the offramp
+    #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
+    pub(crate) queue: Option<Queue>,
+    /// Retries and circuit breaking around the offramp
+    #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
+    pub(crate) circuit_breaker: Option<CircuitBreaker>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) config: dynaconfig::ConfigMap,
 }
 
+fn dflt_max_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn dflt_segment_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+/// A write ahead queue on local disk, events are stored in it before they
+/// are handed to the offramp and replayed after a restart if they weren't
+/// delivered yet
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Queue {
+    /// Directory the queue is stored in, defaults to the `queue` directory
+    /// in the storage directory
+    #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Maximum size of the queue on disk, once it is reached the offramp
+    /// applies backpressure
+    #[serde(default = "dflt_max_bytes")]
+    pub max_bytes: u64,
+    /// Size after which a new segment file is started
+    #[serde(default = "dflt_segment_bytes")]
+    pub segment_bytes: u64,
+}
+
+fn dflt_retries() -> u32 {
+    3
+}
+
+fn dflt_backoff_ms() -> u64 {
+    100
+}
+
+fn dflt_max_backoff_ms() -> u64 {
+    10_000
+}
+
+fn dflt_threshold() -> u32 {
+    5
+}
+
+fn dflt_reset_ms() -> u64 {
+    5_000
+}
+
+fn dflt_confirm_timeout_ms() -> u64 {
+    10_000
+}
+
+/// Retries failed events with a jittered backoff and stops sending to the
+/// offramp for a while once too many events in a row failed
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CircuitBreaker {
+    /// Number of retries for a failed event
+    #[serde(default = "dflt_retries")]
+    pub retries: u32,
+    /// Backoff before the first retry in ms, doubled for every further one
+    #[serde(default = "dflt_backoff_ms")]
+    pub backoff_ms: u64,
+    /// Upper bound for the backoff in ms
+    #[serde(default = "dflt_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Number of events in a row that failed all retries before the
+    /// circuit opens
+    #[serde(default = "dflt_threshold")]
+    pub threshold: u32,
+    /// Time in ms the circuit stays open before the next event is tried
+    #[serde(default = "dflt_reset_ms")]
+    pub reset_ms: u64,
+    /// Time in ms an offramp that confirms its deliveries has to confirm
+    /// an event before it counts as failed
+    #[serde(default = "dflt_confirm_timeout_ms")]
+    pub confirm_timeout_ms: u64,
+}
+
 /// Configuration for a Binding
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -90,6 +175,37 @@ pub struct Binding {
     #[serde(default = "dflt")]
     pub(crate) description: String,
     pub(crate) links: BindingMap, // is this right? this should be url to url?
+    /// Parallelism of the pipelines in the binding, keyed by pipeline id
+    #[serde(default = "dflt", skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) parallelism: ParallelismMap,
+}
+
+fn dflt_instances() -> usize {
+    1
+}
+
+/// How many instances of a pipeline run side by side and how events
+/// are distributed over them
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Parallelism {
+    /// Number of pipeline instances, each runs on its own thread
+    #[serde(default = "dflt_instances")]
+    pub instances: usize,
+    /// Tremor script expression events are partitioned by, events with
+    /// the same key always end up in the same instance. Events are
+    /// distributed round robin if no key is given.
+    #[serde(default = "dflt", skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl Default for Parallelism {
+    fn default() -> Self {
+        Self {
+            instances: dflt_instances(),
+            key: None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -110,4 +226,21 @@ mod test {
         assert_eq!(&c.offramp[0].id, "blackhole");
         assert_eq!(&c.pipeline[0].id, "main");
     }
+
+    #[test]
+    fn load_parallelism() {
+        let c = slurp("tests/configs/parallelism.yaml");
+        let id = TremorURL::parse("/pipeline/main").expect("bad url");
+        let p = &c.binding[0].parallelism[&id];
+        assert_eq!(p.instances, 4);
+        assert_eq!(p.key, Some("event.application".to_string()));
+        let id = TremorURL::parse("/pipeline/passthrough").expect("bad url");
+        assert_eq!(
+            c.binding[0].parallelism[&id],
+            Parallelism {
+                instances: 2,
+                key: None
+            }
+        );
+    }
 }
@@ -187,6 +187,15 @@ error_chain! {
                 display("Cannot unpublish system artefact {}.", key)
         }
 
+        ReplaceFailedSystemArtefact(key: String) {
+            description("The artefact is a system artefact and cannot be replaced")
+                display("Cannot replace system artefact {}.", key)
+        }
+        ReplaceFailedIncompatible(key: String, reason: String) {
+            description("The replacement is not compatible with the running artefact")
+                display("Cannot replace artefact {}: {}.", key, reason)
+        }
+
         BindFailedAlreadyExists(key: String) {
             description("The binding already exists")
                 display("The binding with the id {} already exists.", key)
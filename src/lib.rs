@@ -53,11 +53,15 @@ pub mod metrics;
 pub(crate) mod offramp;
 pub(crate) mod onramp;
 pub(crate) mod pipeline;
+/// Dynamically loaded plugins
+pub mod plugin;
 pub(crate) mod postprocessor;
 pub(crate) mod preprocessor;
 pub(crate) mod ramp;
 /// Tremor registry
 pub mod registry;
+/// Applying changed config files to a running system
+pub mod reload;
 /// The tremor repository
 pub mod repository;
 /// Tremor runtime system
@@ -58,6 +58,17 @@ impl<A: Artefact> ActivatorLifecycleFsm<A> {
         Ok(fresh)
     }
 
+    /// Wraps a servant that was already spawned outside of the lifecycle
+    pub fn spawned(world: World, artefact: A, id: ServantId, resolution: A::SpawnResult) -> Self {
+        Self {
+            artefact,
+            world,
+            state: ActivationState::Deactivated,
+            resolution: Some(resolution),
+            id,
+        }
+    }
+
     async fn on_spawn(&self) -> Result<A::SpawnResult> {
         self.artefact.spawn(&self.world, self.id.clone()).await
     }
@@ -15,24 +15,23 @@
 use crate::pipeline;
 use crate::url::TremorURL;
 use halfbrown::HashMap;
+use prometheus::RampCounters;
 use simd_json::json;
 use std::borrow::Cow;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use tremor_script::prelude::*;
 
+/// Prometheus exposition of the runtime metrics
+pub mod prometheus;
+
 /// Metrics instance name
 pub static mut INSTANCE: &str = "tremor";
 
-#[derive(Debug)]
-pub(crate) struct Ramp {
-    r#in: u64,
-    out: u64,
-    error: u64,
-}
-
 #[derive(Debug)]
 pub(crate) struct RampReporter {
     artefact_url: TremorURL,
-    metrics: Ramp,
+    metrics: Arc<RampCounters>,
     metrics_pipeline: Option<(TremorURL, pipeline::Addr)>,
     flush_interval: Option<u64>, // as nano-seconds
     last_flush_ns: u64,
@@ -41,12 +40,8 @@ pub(crate) struct RampReporter {
 impl RampReporter {
     pub fn new(artefact_url: TremorURL, flush_interval_s: Option<u64>) -> Self {
         Self {
+            metrics: prometheus::register_ramp(&artefact_url),
             artefact_url,
-            metrics: Ramp {
-                r#in: 0,
-                out: 0,
-                error: 0,
-            },
             metrics_pipeline: None,
             flush_interval: flush_interval_s.map(|n| n * 1_000_000_000),
             last_flush_ns: 0,
@@ -60,17 +55,24 @@ impl RampReporter {
     // TODO inline useful on these?
     #[inline]
     pub fn increment_in(&mut self) {
-        self.metrics.r#in += 1;
+        self.metrics.r#in.fetch_add(1, Ordering::Relaxed);
     }
 
     #[inline]
     pub fn increment_out(&mut self) {
-        self.metrics.out += 1;
+        self.metrics.out.fetch_add(1, Ordering::Relaxed);
     }
 
     #[inline]
     pub fn increment_error(&mut self) {
-        self.metrics.error += 1;
+        self.metrics.error.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.metrics
+            .queue_depth
+            .store(depth as u64, Ordering::Relaxed);
     }
 
     #[inline]
@@ -83,9 +85,13 @@ impl RampReporter {
     }
 
     fn flush(&mut self, timestamp: u64) {
-        self.send_metric(timestamp, "in", self.metrics.r#in);
-        self.send_metric(timestamp, "out", self.metrics.out);
-        self.send_metric(timestamp, "error", self.metrics.error);
+        self.send_metric(timestamp, "in", self.metrics.r#in.load(Ordering::Relaxed));
+        self.send_metric(timestamp, "out", self.metrics.out.load(Ordering::Relaxed));
+        self.send_metric(
+            timestamp,
+            "error",
+            self.metrics.error.load(Ordering::Relaxed),
+        );
         self.last_flush_ns = timestamp;
     }
 
@@ -117,7 +123,7 @@ impl RampReporter {
                     kind: None,
                 };
 
-                if let Err(e) = metrics_addr.addr.send(pipeline::Msg::Event {
+                if let Err(e) = metrics_addr.send(pipeline::Msg::Event {
                     input: input.into(),
                     event: metrics_event,
                 }) {
@@ -0,0 +1,424 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prometheus text exposition of the runtime metrics.
+//!
+//! Ramps and pipeline instances register their counters here when they
+//! are started, the registry only keeps weak references so everything
+//! that was stopped disappears from the output on the next scrape.
+//! Operator metrics are taken from the `metrics` output of pipelines and
+//! are only available for pipelines with a `metrics_interval_s`, they are
+//! removed when the pipeline or ramp they belong to is unbound.
+
+use crate::url::TremorURL;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tremor_pipeline::Event;
+use tremor_script::prelude::*;
+
+/// Upper bounds of the pipeline latency buckets in nanoseconds
+const LATENCY_BUCKETS_NS: [u64; 8] = [
+    10_000,
+    50_000,
+    100_000,
+    500_000,
+    1_000_000,
+    10_000_000,
+    100_000_000,
+    1_000_000_000,
+];
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+/// Counters of an on- or offramp
+#[derive(Debug, Default)]
+pub(crate) struct RampCounters {
+    pub(crate) r#in: AtomicU64,
+    pub(crate) out: AtomicU64,
+    pub(crate) error: AtomicU64,
+    pub(crate) queue_depth: AtomicU64,
+}
+
+/// Latency histogram with the buckets from `LATENCY_BUCKETS_NS`
+#[derive(Debug, Default)]
+pub(crate) struct Histogram {
+    buckets: [AtomicU64; 8],
+    count: AtomicU64,
+    sum_ns: AtomicU64,
+}
+
+impl Histogram {
+    pub(crate) fn observe(&self, ns: u64) {
+        if let Some(idx) = LATENCY_BUCKETS_NS.iter().position(|le| ns <= *le) {
+            self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ns.fetch_add(ns, Ordering::Relaxed);
+    }
+}
+
+/// Statistics of a pipeline instance
+#[derive(Debug, Default)]
+pub(crate) struct PipelineStats {
+    pub(crate) queue_depth: AtomicU64,
+    pub(crate) latency: Histogram,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    ramps: Vec<(String, Weak<RampCounters>)>,
+    pipelines: Vec<(String, Weak<PipelineStats>)>,
+    // metric name -> rendered labels -> (labels of the owner, value)
+    series: BTreeMap<String, BTreeMap<String, (String, f64)>>,
+}
+
+/// Registers the counters for a ramp
+pub(crate) fn register_ramp(id: &TremorURL) -> Arc<RampCounters> {
+    let counters = Arc::new(RampCounters::default());
+    if let Ok(mut registry) = REGISTRY.lock() {
+        let mut labels = BTreeMap::new();
+        url_labels(id, &mut labels);
+        registry
+            .ramps
+            .push((render_labels(&labels), Arc::downgrade(&counters)));
+    }
+    counters
+}
+
+/// Registers the statistics for a pipeline instance, `lane` is the index
+/// of the instance for parallel pipelines.
+pub(crate) fn register_pipeline(id: &TremorURL, lane: Option<usize>) -> Arc<PipelineStats> {
+    let stats = Arc::new(PipelineStats::default());
+    if let Ok(mut registry) = REGISTRY.lock() {
+        let mut labels = BTreeMap::new();
+        url_labels(id, &mut labels);
+        if let Some(lane) = lane {
+            labels.insert("lane".to_string(), lane.to_string());
+        }
+        registry
+            .pipelines
+            .push((render_labels(&labels), Arc::downgrade(&stats)));
+    }
+    stats
+}
+
+/// Removes the metrics recorded for a pipeline or ramp
+pub(crate) fn unregister(id: &TremorURL) {
+    let mut labels = BTreeMap::new();
+    url_labels(id, &mut labels);
+    let owner = render_labels(&labels);
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.remove(&owner);
+    }
+}
+
+/// Records the metric events sent to the `metrics` output of a pipeline
+pub(crate) fn record_metrics(eventset: &[(Cow<'static, str>, Event)]) {
+    let mut metrics = eventset
+        .iter()
+        .filter(|(output, _)| output == "metrics")
+        .peekable();
+    if metrics.peek().is_none() {
+        return;
+    }
+    if let Ok(mut registry) = REGISTRY.lock() {
+        for (_, event) in metrics {
+            registry.record(event.data.suffix().value());
+        }
+    }
+}
+
+impl Registry {
+    fn record(&mut self, metric: &Value) {
+        let measurement =
+            if let Some(measurement) = metric.get("measurement").and_then(Value::as_str) {
+                measurement
+            } else {
+                return;
+            };
+        let mut labels = BTreeMap::new();
+        // labels of the pipeline or ramp the metric belongs to
+        let mut owner = BTreeMap::new();
+        if let Some(tags) = metric.get("tags").and_then(Value::as_object) {
+            for (k, v) in tags {
+                let v = v.as_str().map_or_else(|| v.encode(), ToString::to_string);
+                if k == "pipeline" || k == "ramp" {
+                    if let Ok(id) = TremorURL::parse(&v) {
+                        url_labels(&id, &mut owner);
+                    }
+                }
+                labels.insert(sanitize(k), v);
+            }
+        }
+        let owner_labels = render_labels(&owner);
+        // tags take precedence over the labels of the owner
+        owner.extend(labels);
+        let labels = render_labels(&owner);
+        if let Some(fields) = metric.get("fields").and_then(Value::as_object) {
+            for (field, value) in fields {
+                if let Some(value) = value.cast_f64() {
+                    self.series
+                        .entry(metric_name(measurement, field))
+                        .or_insert_with(BTreeMap::new)
+                        .insert(labels.clone(), (owner_labels.clone(), value));
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, owner: &str) {
+        for series in self.series.values_mut() {
+            series.retain(|_, (o, _)| o != owner);
+        }
+        self.series.retain(|_, series| !series.is_empty());
+    }
+}
+
+/// Renders all metrics in the prometheus text format
+pub fn render() -> String {
+    let mut out = String::new();
+    let mut registry = if let Ok(registry) = REGISTRY.lock() {
+        registry
+    } else {
+        return out;
+    };
+    registry.ramps.retain(|(_, r)| r.strong_count() > 0);
+    registry.pipelines.retain(|(_, p)| p.strong_count() > 0);
+    if let Err(e) = write_metrics(&mut out, &registry) {
+        error!("Failed to render metrics: {}", e);
+    }
+    out
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn write_metrics(out: &mut String, registry: &Registry) -> fmt::Result {
+    let ramps: Vec<_> = registry
+        .ramps
+        .iter()
+        .filter_map(|(labels, r)| Some((labels, r.upgrade()?)))
+        .collect();
+    writeln!(out, "# TYPE tremor_ramp_events_total counter")?;
+    for (labels, r) in &ramps {
+        for (port, count) in &[("in", &r.r#in), ("out", &r.out), ("error", &r.error)] {
+            writeln!(
+                out,
+                "tremor_ramp_events_total{{{},port=\"{}\"}} {}",
+                labels,
+                port,
+                count.load(Ordering::Relaxed)
+            )?;
+        }
+    }
+    writeln!(out, "# TYPE tremor_ramp_queue_depth gauge")?;
+    for (labels, r) in &ramps {
+        if labels.contains("type=\"offramp\"") {
+            writeln!(
+                out,
+                "tremor_ramp_queue_depth{{{}}} {}",
+                labels,
+                r.queue_depth.load(Ordering::Relaxed)
+            )?;
+        }
+    }
+
+    let pipelines: Vec<_> = registry
+        .pipelines
+        .iter()
+        .filter_map(|(labels, p)| Some((labels, p.upgrade()?)))
+        .collect();
+    writeln!(out, "# TYPE tremor_pipeline_queue_depth gauge")?;
+    for (labels, p) in &pipelines {
+        writeln!(
+            out,
+            "tremor_pipeline_queue_depth{{{}}} {}",
+            labels,
+            p.queue_depth.load(Ordering::Relaxed)
+        )?;
+    }
+    writeln!(out, "# TYPE tremor_pipeline_latency_seconds histogram")?;
+    for (labels, p) in &pipelines {
+        let mut cumulative = 0;
+        for (le, bucket) in LATENCY_BUCKETS_NS.iter().zip(p.latency.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            writeln!(
+                out,
+                "tremor_pipeline_latency_seconds_bucket{{{},le=\"{}\"}} {}",
+                labels,
+                *le as f64 / 1_000_000_000.0,
+                cumulative
+            )?;
+        }
+        let count = p.latency.count.load(Ordering::Relaxed);
+        writeln!(
+            out,
+            "tremor_pipeline_latency_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            labels, count
+        )?;
+        writeln!(
+            out,
+            "tremor_pipeline_latency_seconds_sum{{{}}} {}",
+            labels,
+            p.latency.sum_ns.load(Ordering::Relaxed) as f64 / 1_000_000_000.0
+        )?;
+        writeln!(
+            out,
+            "tremor_pipeline_latency_seconds_count{{{}}} {}",
+            labels, count
+        )?;
+    }
+
+    for (name, series) in &registry.series {
+        let kind = if name.ends_with("_total") {
+            "counter"
+        } else {
+            "untyped"
+        };
+        writeln!(out, "# TYPE {} {}", name, kind)?;
+        for (labels, (_, value)) in series {
+            writeln!(out, "{}{{{}}} {}", name, labels, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// `count` fields are cumulative counters, everything else is reported as is
+fn metric_name(measurement: &str, field: &str) -> String {
+    if field == "count" {
+        format!("tremor_{}_total", sanitize(measurement))
+    } else {
+        format!("tremor_{}_{}", sanitize(measurement), sanitize(field))
+    }
+}
+
+fn url_labels(id: &TremorURL, labels: &mut BTreeMap<String, String>) {
+    if let Some(resource_type) = id.resource_type() {
+        labels.insert("type".to_string(), resource_type.to_string());
+    }
+    if let Some(artefact) = id.artefact() {
+        labels.insert("artefact".to_string(), artefact.to_string());
+    }
+    if let Some(servant) = id.instance() {
+        labels.insert("servant".to_string(), servant.to_string());
+    }
+}
+
+fn render_labels(labels: &BTreeMap<String, String>) -> String {
+    let mut res = String::new();
+    for (k, v) in labels {
+        if !res.is_empty() {
+            res.push(',');
+        }
+        res.push_str(k);
+        res.push_str("=\"");
+        for c in v.chars() {
+            match c {
+                '\\' => res.push_str("\\\\"),
+                '"' => res.push_str("\\\""),
+                '\n' => res.push_str("\\n"),
+                c => res.push(c),
+            }
+        }
+        res.push('"');
+    }
+    res
+}
+
+/// Turns a string into a valid prometheus metric or label name
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    #[test]
+    fn labels() {
+        let mut labels = BTreeMap::new();
+        labels.insert("b".to_string(), "sn\"ot".to_string());
+        labels.insert("a".to_string(), "bad\\ger".to_string());
+        assert_eq!(render_labels(&labels), r#"a="bad\\ger",b="sn\"ot""#);
+        assert_eq!(sanitize("ramp-events.1"), "ramp_events_1");
+        assert_eq!(metric_name("events", "count"), "tremor_events_total");
+        assert_eq!(metric_name("dedup", "dropped"), "tremor_dedup_dropped");
+    }
+
+    #[test]
+    fn record() {
+        let mut registry = Registry::default();
+        registry.record(&Value::from(json!({
+            "measurement": "events",
+            "tags": {
+                "pipeline": "tremor://localhost/pipeline/main/01",
+                "node": "in",
+                "direction": "output",
+                "port": "out"
+            },
+            "fields": {"count": 42},
+            "timestamp": 0
+        })));
+        // not a metric
+        registry.record(&Value::from("snot"));
+        assert_eq!(registry.series.len(), 1);
+        let series = &registry.series["tremor_events_total"];
+        assert_eq!(
+            series
+                .get(r#"artefact="main",direction="output",node="in",pipeline="tremor://localhost/pipeline/main/01",port="out",servant="01",type="pipeline""#)
+                .map(|(_, value)| *value),
+            Some(42.0)
+        );
+        // unbinding another instance keeps them
+        registry.remove(r#"artefact="main",servant="02",type="pipeline""#);
+        assert_eq!(registry.series.len(), 1);
+        registry.remove(r#"artefact="main",servant="01",type="pipeline""#);
+        assert!(registry.series.is_empty());
+    }
+
+    #[test]
+    fn histogram() {
+        let h = Histogram::default();
+        h.observe(5_000);
+        h.observe(20_000);
+        h.observe(2_000_000_000);
+        assert_eq!(h.buckets[0].load(Ordering::Relaxed), 1);
+        assert_eq!(h.buckets[1].load(Ordering::Relaxed), 1);
+        assert_eq!(h.count.load(Ordering::Relaxed), 3);
+        assert_eq!(h.sum_ns.load(Ordering::Relaxed), 2_000_025_000);
+    }
+
+    #[test]
+    fn render_ramps() {
+        let id = TremorURL::parse("/offramp/blackhole/01/in").expect("bad url");
+        let counters = register_ramp(&id);
+        counters.r#in.fetch_add(3, Ordering::Relaxed);
+        counters.queue_depth.store(7, Ordering::Relaxed);
+        let out = render();
+        assert!(out.contains(
+            r#"tremor_ramp_events_total{artefact="blackhole",servant="01",type="offramp",port="in"} 3"#
+        ));
+        assert!(out.contains(
+            r#"tremor_ramp_queue_depth{artefact="blackhole",servant="01",type="offramp"} 7"#
+        ));
+        drop(counters);
+        assert!(!render().contains("blackhole"));
+    }
+}
@@ -19,15 +19,20 @@ use crate::pipeline;
 use crate::registry::ServantId;
 use crate::system::METRICS_PIPELINE;
 use crate::url::TremorURL;
+use crate::utils::nanotime;
 use crate::{Event, OpConfig};
 use async_std::sync::channel;
 use async_std::task::{self, JoinHandle};
-use crossbeam_channel::{bounded, Sender as CbSender};
+use crossbeam_channel::{
+    bounded, unbounded, Receiver, RecvTimeoutError, Select, Sender as CbSender, TryRecvError,
+};
 use std::borrow::Cow;
 use std::fmt;
 use std::thread;
+use std::time::Duration;
 
 mod blackhole;
+pub(crate) mod breaker;
 mod debug;
 mod elastic;
 mod exit;
@@ -44,6 +49,7 @@ mod stderr;
 mod stdout;
 mod tcp;
 mod udp;
+pub(crate) mod wal;
 mod ws;
 
 pub enum Msg {
@@ -59,22 +65,142 @@ pub enum Msg {
         id: TremorURL,
         tx: CbSender<bool>,
     },
+    /// The outcome of delivering an event, sent by offramps that deliver
+    /// asynchronously
+    Delivered {
+        id: u64,
+        result: Result<()>,
+    },
+    /// An event the circuit breaker tries again once its backoff passed
+    Retry {
+        input: String,
+        event: Event,
+        retry: u32,
+    },
+    /// The circuit breaker has deliveries that weren't confirmed in time
+    Expire,
 }
 
 pub(crate) type Sender = async_std::sync::Sender<ManagerMsg>;
 pub type Addr = CbSender<Msg>;
 
+/// The channels the event loop of an offramp receives messages on, its
+/// address and the one delivery confirmations are sent to
+pub(crate) struct Inbox {
+    rx: Receiver<Msg>,
+    confirmations: Receiver<Msg>,
+}
+
+impl Inbox {
+    pub(crate) fn new(rx: Receiver<Msg>, confirmations: Receiver<Msg>) -> Self {
+        Self { rx, confirmations }
+    }
+
+    /// Number of messages waiting
+    pub(crate) fn len(&self) -> usize {
+        self.rx.len() + self.confirmations.len()
+    }
+
+    pub(crate) fn try_recv(&self) -> std::result::Result<Msg, TryRecvError> {
+        self.confirmations
+            .try_recv()
+            .or_else(|_| self.rx.try_recv())
+    }
+
+    /// Waits for a message for at most `timeout`, the inbox is disconnected
+    /// once the address of the offramp is dropped
+    pub(crate) fn recv_timeout(
+        &self,
+        timeout: Option<Duration>,
+    ) -> std::result::Result<Msg, RecvTimeoutError> {
+        let mut select = Select::new();
+        let confirmations = select.recv(&self.confirmations);
+        select.recv(&self.rx);
+        let op = if let Some(timeout) = timeout {
+            select
+                .select_timeout(timeout)
+                .map_err(|_| RecvTimeoutError::Timeout)?
+        } else {
+            select.select()
+        };
+        let msg = if op.index() == confirmations {
+            op.recv(&self.confirmations)
+        } else {
+            op.recv(&self.rx)
+        };
+        msg.map_err(|_| RecvTimeoutError::Disconnected)
+    }
+}
+
 // We allow this here since we can't pass in &dyn Code as that would taint the
 // overlying object with lifetimes.
 // We also can't pass in Box<dyn Codec> as that would try to move it out of
 // borrowed contest
 #[allow(clippy::borrowed_box)]
+/// An offramp
 pub trait Offramp: Send {
+    /// Starts the offramp
     fn start(&mut self, codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()>;
+    /// Handles an event sent to the offramp, `input` is the pipeline that
+    /// sent it
     fn on_event(&mut self, codec: &Box<dyn Codec>, input: String, event: Event) -> Result<()>;
+    /// The codec used when none is configured
     fn default_codec(&self) -> &str;
+    /// Connects a pipeline, used for contraflow
     fn add_pipeline(&mut self, id: TremorURL, addr: pipeline::Addr);
+    /// Disconnects a pipeline, returns `true` if no pipelines are left
     fn remove_pipeline(&mut self, id: TremorURL) -> bool;
+    /// Offramps that deliver events asynchronously keep the address for as
+    /// long as they live and confirm every event `on_event` accepted with a
+    /// `Msg::Delivered` once it was delivered, they return `true`. For all
+    /// others `on_event` returning `Ok` confirms the delivery.
+    fn confirm_delivery(&mut self, _addr: Addr) -> bool {
+        false
+    }
+}
+
+/// Reports the outcome of an event the circuit breaker handled to the
+/// connected pipelines and records it
+fn settle(
+    offramp_id: &TremorURL,
+    metrics_reporter: &mut RampReporter,
+    queue: &mut Option<wal::Wal>,
+    pipelines: &[(TremorURL, pipeline::Addr)],
+    id: u64,
+    outcome: breaker::Outcome,
+) {
+    if let Some(insight) = outcome.insight() {
+        for (pid, p) in pipelines {
+            if p.send(pipeline::Msg::Insight(insight.clone())).is_err() {
+                error!("Failed to send contraflow to pipeline {}", pid)
+            };
+        }
+    }
+    delivered(offramp_id, metrics_reporter, queue, id, outcome.result)
+}
+
+/// Records the outcome of delivering an event, only confirmed events are
+/// committed to the queue
+fn delivered(
+    offramp_id: &TremorURL,
+    metrics_reporter: &mut RampReporter,
+    queue: &mut Option<wal::Wal>,
+    id: u64,
+    result: Result<()>,
+) {
+    let ok = result.is_ok();
+    match result {
+        Ok(()) => metrics_reporter.increment_out(),
+        Err(e) => {
+            metrics_reporter.increment_error();
+            error!("[Offramp::{}] On Event error: {}", offramp_id, e);
+        }
+    }
+    if let Some(queue) = queue {
+        if let Err(e) = queue.ack(id, ok) {
+            error!("[Offramp::{}] Failed to commit queue: {}", offramp_id, e);
+        }
+    }
 }
 
 pub trait Impl {
@@ -102,7 +228,8 @@ pub fn lookup(name: &str, config: &Option<OpConfig>) -> Result<Box<dyn Offramp>>
         "tcp" => tcp::Tcp::from_config(config),
         "udp" => udp::Udp::from_config(config),
         "ws" => ws::Ws::from_config(config),
-        _ => Err(format!("Offramp {} not known", name).into()),
+        _ => crate::plugin::offramp(name, config)
+            .unwrap_or_else(|| Err(format!("Offramp {} not known", name).into())),
     }
 }
 
@@ -112,6 +239,8 @@ pub(crate) struct Create {
     pub codec: Box<dyn Codec>,
     pub postprocessors: Vec<String>,
     pub metrics_reporter: RampReporter,
+    pub queue: Option<wal::Wal>,
+    pub breaker: Option<breaker::Breaker>,
 }
 
 impl fmt::Debug for Create {
@@ -156,6 +285,8 @@ impl Manager {
                             postprocessors,
                             mut metrics_reporter,
                             id,
+                            mut queue,
+                            mut breaker,
                         },
                     )) => {
                         match offramp.start(&codec, &postprocessors) {
@@ -170,24 +301,141 @@ impl Manager {
                         let offramp_id = id.clone();
                         thread::spawn(move || {
                             info!("[Offramp::{}] started", offramp_id);
-                            for m in rx {
+                            // Confirmations and retries have a channel of their
+                            // own so the offramp doesn't keep itself alive
+                            let (confirmations_tx, confirmations) = unbounded();
+                            let confirms = offramp.confirm_delivery(confirmations_tx.clone());
+                            let inbox = Inbox::new(rx, confirmations);
+                            // Pipelines that get insights from the circuit breaker
+                            let mut pipelines: Vec<(TremorURL, pipeline::Addr)> = Vec::new();
+                            loop {
+                                // With a queue events are delivered from disk,
+                                // they stay there while the circuit is open
+                                let m = if let Some(queue) = &mut queue {
+                                    if let Some(wait) =
+                                        breaker.as_ref().and_then(|cb| cb.holding(nanotime()))
+                                    {
+                                        queue.hold(wait);
+                                    }
+                                    queue.next(&inbox)
+                                } else {
+                                    inbox.recv_timeout(None).ok()
+                                };
+                                let m = if let Some(m) = m { m } else { break };
+                                let queued = queue.as_ref().map_or(0, wal::Wal::len);
+                                metrics_reporter.set_queue_depth(inbox.len() + queued);
                                 match m {
                                     Msg::Event { event, input } => {
                                         metrics_reporter.periodic_flush(event.ingest_ns);
 
                                         metrics_reporter.increment_in();
+                                        let event_id = event.id;
                                         // TODO FIXME implement postprocessors
-                                        match offramp.on_event(&codec, input.into(), event) {
-                                            Ok(_) => metrics_reporter.increment_out(),
-                                            Err(e) => {
-                                                metrics_reporter.increment_error();
-                                                error!(
-                                                    "[Offramp::{}] On Event error: {}",
-                                                    offramp_id, e
+                                        if let Some(cb) = &mut breaker {
+                                            let outcome = cb.send(
+                                                &confirmations_tx,
+                                                input.to_string(),
+                                                event,
+                                                0,
+                                                confirms,
+                                                |input, event| {
+                                                    offramp.on_event(&codec, input, event)
+                                                },
+                                            );
+                                            if let Some(outcome) = outcome {
+                                                settle(
+                                                    &offramp_id,
+                                                    &mut metrics_reporter,
+                                                    &mut queue,
+                                                    &pipelines,
+                                                    event_id,
+                                                    outcome,
+                                                );
+                                            }
+                                        } else {
+                                            let r = offramp.on_event(&codec, input.into(), event);
+                                            // Accepted events are confirmed later
+                                            // by offramps that confirm delivery
+                                            if r.is_err() || !confirms {
+                                                delivered(
+                                                    &offramp_id,
+                                                    &mut metrics_reporter,
+                                                    &mut queue,
+                                                    event_id,
+                                                    r,
                                                 );
                                             }
                                         }
                                     }
+                                    Msg::Retry {
+                                        input,
+                                        event,
+                                        retry,
+                                    } => {
+                                        let event_id = event.id;
+                                        let outcome = breaker.as_mut().and_then(|cb| {
+                                            cb.send(
+                                                &confirmations_tx,
+                                                input,
+                                                event,
+                                                retry,
+                                                confirms,
+                                                |input, event| {
+                                                    offramp.on_event(&codec, input, event)
+                                                },
+                                            )
+                                        });
+                                        if let Some(outcome) = outcome {
+                                            settle(
+                                                &offramp_id,
+                                                &mut metrics_reporter,
+                                                &mut queue,
+                                                &pipelines,
+                                                event_id,
+                                                outcome,
+                                            );
+                                        }
+                                    }
+                                    Msg::Expire => {
+                                        if let Some(cb) = &mut breaker {
+                                            for (id, outcome) in
+                                                cb.expire(&confirmations_tx, nanotime())
+                                            {
+                                                settle(
+                                                    &offramp_id,
+                                                    &mut metrics_reporter,
+                                                    &mut queue,
+                                                    &pipelines,
+                                                    id,
+                                                    outcome,
+                                                );
+                                            }
+                                        }
+                                    }
+                                    Msg::Delivered { id, result } => {
+                                        if let Some(cb) = &mut breaker {
+                                            if let Some(outcome) =
+                                                cb.delivered(&confirmations_tx, id, result)
+                                            {
+                                                settle(
+                                                    &offramp_id,
+                                                    &mut metrics_reporter,
+                                                    &mut queue,
+                                                    &pipelines,
+                                                    id,
+                                                    outcome,
+                                                );
+                                            }
+                                        } else {
+                                            delivered(
+                                                &offramp_id,
+                                                &mut metrics_reporter,
+                                                &mut queue,
+                                                id,
+                                                result,
+                                            )
+                                        }
+                                    }
                                     Msg::Connect { id, addr } => {
                                         if id == *METRICS_PIPELINE {
                                             info!(
@@ -200,6 +448,7 @@ impl Manager {
                                                 "[Offramp::{}] Connecting pipeline {}",
                                                 offramp_id, id
                                             );
+                                            pipelines.push((id.clone(), addr.clone()));
                                             offramp.add_pipeline(id, addr);
                                         }
                                     }
@@ -208,6 +457,7 @@ impl Manager {
                                             "[Offramp::{}] Disconnecting pipeline {}",
                                             offramp_id, id
                                         );
+                                        pipelines.retain(|(pid, _)| pid != &id);
                                         let r = offramp.remove_pipeline(id.clone());
                                         info!(
                                             "[Offramp::{}] Pipeline {} disconnected",
@@ -0,0 +1,644 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Retries and circuit breaking around `Offramp::on_event`.
+//!
+//! A failed event is retried with a jittered exponential backoff. Once
+//! `threshold` events in a row failed all their retries the circuit opens
+//! and a `trigger` insight is sent to the connected pipelines. After
+//! `reset_ms` a single event is tried (half open), if it succeeds the
+//! circuit closes again and a `restore` insight is sent.
+//!
+//! Retries never block the event loop of the offramp, they are sent back
+//! to it as a `Msg::Retry` once their backoff passed. For offramps that
+//! confirm their deliveries the outcome reported with `Msg::Delivered` is
+//! what counts, the breaker keeps track of those events until then and
+//! counts them as failed if they aren't confirmed within
+//! `confirm_timeout_ms`. Events are only copied while they can still be
+//! retried.
+
+use crate::config;
+use crate::errors::{Error, Result};
+use crate::offramp::{Addr, Msg};
+use crate::utils::nanotime;
+use crate::Event;
+use async_std::task;
+use hashbrown::HashMap;
+use rand::Rng;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tremor_script::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum State {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// A change of the circuit that is reported to the pipelines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Transition {
+    Trigger,
+    Restore,
+}
+
+/// The final outcome of an event handed to the breaker
+#[derive(Debug)]
+pub(crate) struct Outcome {
+    pub result: Result<()>,
+    pub transition: Option<Transition>,
+    /// The `backpressure-output` the event was routed through
+    pub output: Option<String>,
+}
+
+impl Outcome {
+    /// Contraflow insight for the outcome, if there is anything to report
+    pub fn insight(&self) -> Option<Event> {
+        insight(
+            self.result.as_ref().err(),
+            self.transition,
+            self.output.as_deref(),
+        )
+    }
+}
+
+/// An event that was accepted by the offramp but not delivered yet
+#[derive(Debug)]
+struct Pending {
+    /// Input and event, only kept if the event can still be retried
+    replay: Option<(String, Event)>,
+    output: Option<String>,
+    retry: u32,
+    deadline: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Breaker {
+    config: config::CircuitBreaker,
+    state: State,
+    failures: u32,
+    opened_at: u64,
+    // With a disk queue in front of the offramp we wait for the circuit to
+    // half open instead of failing events fast so nothing gets lost.
+    hold: bool,
+    pending: HashMap<u64, Pending>,
+    /// `(deadline, id)` of the pending events in the order they were sent
+    deadlines: VecDeque<(u64, u64)>,
+    /// If a `Msg::Expire` is on its way to the offramp
+    expiring: bool,
+}
+
+impl Breaker {
+    pub fn new(config: config::CircuitBreaker, hold: bool) -> Self {
+        Self {
+            config,
+            state: State::Closed,
+            failures: 0,
+            opened_at: 0,
+            hold,
+            pending: HashMap::new(),
+            deadlines: VecDeque::new(),
+            expiring: false,
+        }
+    }
+
+    fn reset_ns(&self) -> u64 {
+        self.config.reset_ms * 1_000_000
+    }
+
+    /// Retries an event gets, a half open circuit only gets a single try
+    fn retries(&self) -> u32 {
+        if self.state == State::HalfOpen {
+            0
+        } else {
+            self.config.retries
+        }
+    }
+
+    /// Time left until an open circuit half opens, or a half open one
+    /// gets to try again if its probe doesn't report back
+    fn remaining(&self, now: u64) -> Option<Duration> {
+        if self.state != State::Closed {
+            let until = self.opened_at + self.reset_ns();
+            if now < until {
+                return Some(Duration::from_nanos(until - now));
+            }
+        }
+        None
+    }
+
+    /// Time to hold back events for, a queue in front of the offramp keeps
+    /// them until then
+    pub fn holding(&self, now: u64) -> Option<Duration> {
+        if self.hold {
+            self.remaining(now)
+        } else {
+            None
+        }
+    }
+
+    /// Backoff before the given retry, doubled per retry and capped at
+    /// `max_backoff_ms`, the actual wait is picked randomly between half
+    /// and the full backoff.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let base = self
+            .config
+            .backoff_ms
+            .saturating_mul(1 << retry.min(31))
+            .min(self.config.max_backoff_ms);
+        if base == 0 {
+            return Duration::from_millis(0);
+        }
+        let half = base / 2;
+        Duration::from_millis(half + rand::thread_rng().gen_range(0, base - half + 1))
+    }
+
+    fn success(&mut self) -> Option<Transition> {
+        self.failures = 0;
+        if self.state == State::Closed {
+            None
+        } else {
+            self.state = State::Closed;
+            Some(Transition::Restore)
+        }
+    }
+
+    fn failure(&mut self, now: u64) -> Option<Transition> {
+        match self.state {
+            State::Closed => {
+                self.failures += 1;
+                if self.failures >= self.config.threshold {
+                    self.state = State::Open;
+                    self.opened_at = now;
+                    Some(Transition::Trigger)
+                } else {
+                    None
+                }
+            }
+            State::HalfOpen | State::Open => {
+                self.state = State::Open;
+                self.opened_at = now;
+                None
+            }
+        }
+    }
+
+    /// Hands the event to `send`, `retry` is the number of tries it had
+    /// before. Returns the outcome of the event unless it waits for a
+    /// retry or, if the offramp `confirms` deliveries, for its
+    /// confirmation. Retries are sent to `addr`.
+    pub fn send<F>(
+        &mut self,
+        addr: &Addr,
+        input: String,
+        event: Event,
+        retry: u32,
+        confirms: bool,
+        send: F,
+    ) -> Option<Outcome>
+    where
+        F: FnOnce(String, Event) -> Result<()>,
+    {
+        let now = nanotime();
+        if let Some(wait) = self.remaining(now) {
+            if self.hold {
+                schedule(addr, wait, input, event, retry);
+                return None;
+            }
+            return Some(Outcome {
+                result: Err(Error::from("Circuit open, event dropped")),
+                transition: None,
+                output: output(&event),
+            });
+        }
+        if self.state == State::Open {
+            // Until the probe reports back other events are held back or
+            // dropped as if the circuit was still open
+            self.state = State::HalfOpen;
+            self.opened_at = now;
+        }
+        // The offramp consumes the event, we only keep a copy if we might
+        // have to send it again
+        let replay = if retry < self.retries() {
+            Some((input.clone(), event.clone()))
+        } else {
+            None
+        };
+        let id = event.id;
+        let output = output(&event);
+        match send(input, event) {
+            Ok(()) if confirms => {
+                let deadline = now + self.config.confirm_timeout_ms * 1_000_000;
+                self.pending.insert(
+                    id,
+                    Pending {
+                        replay,
+                        output,
+                        retry,
+                        deadline,
+                    },
+                );
+                self.deadlines.push_back((deadline, id));
+                self.schedule_expiry(addr, now);
+                None
+            }
+            Ok(()) => Some(Outcome {
+                result: Ok(()),
+                transition: self.success(),
+                output,
+            }),
+            Err(e) => self.failed(addr, replay, output, retry, e),
+        }
+    }
+
+    /// Handles the confirmed outcome of an event the offramp delivered
+    /// asynchronously, same as for `send`. Confirmations that arrive after
+    /// the event expired are ignored, it was counted as failed already.
+    pub fn delivered(&mut self, addr: &Addr, id: u64, result: Result<()>) -> Option<Outcome> {
+        let Pending {
+            replay,
+            output,
+            retry,
+            ..
+        } = self.pending.remove(&id)?;
+        match result {
+            Ok(()) => Some(Outcome {
+                result,
+                transition: self.success(),
+                output,
+            }),
+            Err(e) => self.failed(addr, replay, output, retry, e),
+        }
+    }
+
+    /// Counts the events that weren't confirmed within `confirm_timeout_ms`
+    /// as failed, called once a `Msg::Expire` arrives. Returns the outcomes
+    /// by event id.
+    pub fn expire(&mut self, addr: &Addr, now: u64) -> Vec<(u64, Outcome)> {
+        self.expiring = false;
+        let mut outcomes = Vec::new();
+        while let Some((deadline, id)) = self.deadlines.front().copied() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop_front();
+            // Events that were confirmed or sent again meanwhile are skipped
+            if self
+                .pending
+                .get(&id)
+                .map_or(false, |p| p.deadline == deadline)
+            {
+                if let Some(p) = self.pending.remove(&id) {
+                    let e = Error::from(format!(
+                        "Delivery not confirmed within {}ms",
+                        self.config.confirm_timeout_ms
+                    ));
+                    if let Some(outcome) = self.failed(addr, p.replay, p.output, p.retry, e) {
+                        outcomes.push((id, outcome));
+                    }
+                }
+            }
+        }
+        self.schedule_expiry(addr, now);
+        outcomes
+    }
+
+    /// Sends a `Msg::Expire` to the offramp once the oldest pending event
+    /// is due, there is only ever one on its way.
+    fn schedule_expiry(&mut self, addr: &Addr, now: u64) {
+        if self.expiring {
+            return;
+        }
+        if let Some((deadline, _)) = self.deadlines.front() {
+            self.expiring = true;
+            let wait = Duration::from_nanos(deadline.saturating_sub(now));
+            let addr = addr.clone();
+            task::spawn(async move {
+                task::sleep(wait).await;
+                if addr.send(Msg::Expire).is_err() {
+                    debug!("Offramp stopped before its pending deliveries expired");
+                }
+            });
+        }
+    }
+
+    fn failed(
+        &mut self,
+        addr: &Addr,
+        replay: Option<(String, Event)>,
+        output: Option<String>,
+        retry: u32,
+        error: Error,
+    ) -> Option<Outcome> {
+        let retries = self.retries();
+        match replay {
+            Some((input, event)) if retry < retries => {
+                warn!(
+                    "Send failed (try {} of {}): {}",
+                    retry + 1,
+                    retries + 1,
+                    error
+                );
+                schedule(addr, self.backoff(retry), input, event, retry + 1);
+                None
+            }
+            _ => Some(Outcome {
+                result: Err(error),
+                transition: self.failure(nanotime()),
+                output,
+            }),
+        }
+    }
+}
+
+/// Sends the event back to the offramp once `wait` passed
+fn schedule(addr: &Addr, wait: Duration, input: String, event: Event, retry: u32) {
+    let addr = addr.clone();
+    task::spawn(async move {
+        task::sleep(wait).await;
+        if addr
+            .send(Msg::Retry {
+                input,
+                event,
+                retry,
+            })
+            .is_err()
+        {
+            // The offramp is gone, a queue in front of it still has the
+            // event for the next one
+            warn!("Offramp stopped before an event could be retried");
+        }
+    });
+}
+
+/// The `backpressure-output` an event was routed through
+fn output(event: &Event) -> Option<String> {
+    event
+        .data
+        .suffix()
+        .meta()
+        .get("backpressure-output")
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+/// Contraflow insight for the outcome of an event, `output` is the
+/// `backpressure-output` the event was routed through.
+pub(crate) fn insight(
+    error: Option<&Error>,
+    transition: Option<Transition>,
+    output: Option<&str>,
+) -> Option<Event> {
+    if error.is_none() && transition.is_none() {
+        return None;
+    }
+    let mut m = Object::new();
+    if let Some(e) = error {
+        m.insert("error".into(), e.to_string().into());
+    }
+    match transition {
+        Some(Transition::Trigger) => {
+            m.insert("cb".into(), "trigger".into());
+        }
+        Some(Transition::Restore) => {
+            m.insert("cb".into(), "restore".into());
+        }
+        None => (),
+    }
+    if let Some(output) = output {
+        m.insert("backpressure-output".into(), output.to_string().into());
+    }
+    Some(Event {
+        is_batch: false,
+        id: 0,
+        data: (Value::null(), m).into(),
+        ingest_ns: nanotime(),
+        origin_uri: None,
+        kind: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crossbeam_channel::{unbounded, Receiver};
+
+    fn config(retries: u32, threshold: u32, reset_ms: u64) -> config::CircuitBreaker {
+        config::CircuitBreaker {
+            retries,
+            backoff_ms: 0,
+            max_backoff_ms: 0,
+            threshold,
+            reset_ms,
+            confirm_timeout_ms: 60_000,
+        }
+    }
+
+    fn event(id: u64) -> Event {
+        Event {
+            id,
+            ..Event::default()
+        }
+    }
+
+    fn retried(rx: &Receiver<Msg>) -> (String, Event, u32) {
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(Msg::Retry {
+                input,
+                event,
+                retry,
+            }) => (input, event, retry),
+            _ => panic!("event was not retried"),
+        }
+    }
+
+    #[test]
+    fn retries() {
+        let (tx, rx) = unbounded();
+        let mut b = Breaker::new(config(2, 1, 1000), false);
+        let fail = |_: String, _: Event| -> Result<()> { Err("snot".into()) };
+        assert!(b.send(&tx, "in".into(), event(1), 0, false, fail).is_none());
+        let (input, e, retry) = retried(&rx);
+        assert_eq!(retry, 1);
+        assert!(b.send(&tx, input, e, retry, false, fail).is_none());
+        let (input, e, retry) = retried(&rx);
+        assert_eq!(retry, 2);
+        assert_eq!(e.id, 1);
+        let o = b
+            .send(&tx, input, e, retry, false, |_, _| Ok(()))
+            .expect("no outcome");
+        assert!(o.result.is_ok());
+        assert_eq!(o.transition, None);
+        assert_eq!(b.state, State::Closed);
+    }
+
+    #[test]
+    fn open_and_restore() {
+        let (tx, _rx) = unbounded();
+        let mut b = Breaker::new(config(0, 2, 0), false);
+        let fail = |_: String, _: Event| -> Result<()> { Err("snot".into()) };
+        let o = b.send(&tx, "in".into(), event(1), 0, false, fail);
+        let o = o.expect("no outcome");
+        assert!(o.result.is_err());
+        assert_eq!(o.transition, None);
+        assert_eq!(b.state, State::Closed);
+        let o = b.send(&tx, "in".into(), event(2), 0, false, fail);
+        let o = o.expect("no outcome");
+        assert!(o.result.is_err());
+        assert_eq!(o.transition, Some(Transition::Trigger));
+        assert_eq!(b.state, State::Open);
+        // with a reset of 0 the next event is tried half open
+        let o = b.send(&tx, "in".into(), event(3), 0, false, fail);
+        let o = o.expect("no outcome");
+        assert!(o.result.is_err());
+        assert_eq!(o.transition, None);
+        assert_eq!(b.state, State::Open);
+        let o = b.send(&tx, "in".into(), event(4), 0, false, |_, _| Ok(()));
+        let o = o.expect("no outcome");
+        assert!(o.result.is_ok());
+        assert_eq!(o.transition, Some(Transition::Restore));
+        assert_eq!(b.state, State::Closed);
+    }
+
+    #[test]
+    fn confirmed() {
+        let (tx, rx) = unbounded();
+        let mut b = Breaker::new(config(1, 1, 60_000), false);
+        // accepting an event is not enough, its delivery has to be confirmed
+        assert!(b
+            .send(&tx, "in".into(), event(1), 0, true, |_, _| Ok(()))
+            .is_none());
+        assert!(b.delivered(&tx, 1, Err("snot".into())).is_none());
+        let (input, e, retry) = retried(&rx);
+        assert!(b.send(&tx, input, e, retry, true, |_, _| Ok(())).is_none());
+        let o = b.delivered(&tx, 1, Err("snot".into()));
+        let o = o.expect("no outcome");
+        assert!(o.result.is_err());
+        assert_eq!(o.transition, Some(Transition::Trigger));
+        assert_eq!(b.state, State::Open);
+        // deliveries the breaker doesn't know are ignored
+        assert!(b.delivered(&tx, 2, Ok(())).is_none());
+    }
+
+    #[test]
+    fn unconfirmed() {
+        let (tx, rx) = unbounded();
+        let mut b = Breaker::new(
+            config::CircuitBreaker {
+                confirm_timeout_ms: 1,
+                ..config(1, 1, 60_000)
+            },
+            false,
+        );
+        assert!(b
+            .send(&tx, "in".into(), event(1), 0, true, |_, _| Ok(()))
+            .is_none());
+        // nothing expires before its deadline
+        assert!(b.expire(&tx, 0).is_empty());
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Ok(Msg::Expire) => (),
+            _ => panic!("no expiry was scheduled"),
+        }
+        // an unconfirmed event is retried like a failed one
+        assert!(b.expire(&tx, nanotime()).is_empty());
+        let (input, e, retry) = retried(&rx);
+        assert_eq!(retry, 1);
+        assert!(b.send(&tx, input, e, retry, true, |_, _| Ok(())).is_none());
+        assert!(b.pending.get(&1).map_or(false, |p| p.replay.is_none()));
+        let outcomes = b.expire(&tx, nanotime() + 2_000_000);
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].0, 1);
+        assert!(outcomes[0].1.result.is_err());
+        assert_eq!(outcomes[0].1.transition, Some(Transition::Trigger));
+        assert!(b.pending.is_empty());
+        // a late confirmation isn't counted again
+        assert!(b.delivered(&tx, 1, Ok(())).is_none());
+    }
+
+    #[test]
+    fn fail_fast() {
+        let (tx, _rx) = unbounded();
+        let mut b = Breaker::new(config(3, 1, 60_000), false);
+        let o = b.send(&tx, "in".into(), event(1), 3, false, |_, _| {
+            Err("snot".into())
+        });
+        assert_eq!(o.expect("no outcome").transition, Some(Transition::Trigger));
+        let mut called = false;
+        let o = b.send(&tx, "in".into(), event(2), 0, false, |_, _| {
+            called = true;
+            Ok(())
+        });
+        let o = o.expect("no outcome");
+        assert!(o.result.is_err());
+        assert_eq!(o.transition, None);
+        assert!(!called);
+        assert_eq!(b.state, State::Open);
+        assert_eq!(b.holding(nanotime()), None);
+    }
+
+    #[test]
+    fn hold() {
+        let (tx, rx) = unbounded();
+        let mut b = Breaker::new(config(0, 1, 10), true);
+        let o = b.send(&tx, "in".into(), event(1), 0, false, |_, _| {
+            Err("snot".into())
+        });
+        assert_eq!(o.expect("no outcome").transition, Some(Transition::Trigger));
+        assert!(b.holding(nanotime()).is_some());
+        // the event comes back once the circuit half opens
+        assert!(b
+            .send(&tx, "in".into(), event(2), 0, false, |_, _| Ok(()))
+            .is_none());
+        let (input, e, retry) = retried(&rx);
+        assert_eq!(e.id, 2);
+        let o = b.send(&tx, input, e, retry, false, |_, _| Ok(()));
+        assert_eq!(o.expect("no outcome").transition, Some(Transition::Restore));
+    }
+
+    #[test]
+    fn backoff() {
+        let b = Breaker::new(
+            config::CircuitBreaker {
+                retries: 5,
+                backoff_ms: 100,
+                max_backoff_ms: 300,
+                threshold: 1,
+                reset_ms: 0,
+                confirm_timeout_ms: 0,
+            },
+            false,
+        );
+        let d = b.backoff(0);
+        assert!(d >= Duration::from_millis(50) && d <= Duration::from_millis(100));
+        let d = b.backoff(1);
+        assert!(d >= Duration::from_millis(100) && d <= Duration::from_millis(200));
+        let d = b.backoff(10);
+        assert!(d >= Duration::from_millis(150) && d <= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn insights() {
+        assert!(insight(None, None, None).is_none());
+        let e = Error::from("snot");
+        let i = insight(Some(&e), Some(Transition::Trigger), Some("out")).expect("no insight");
+        let meta = i.data.suffix().meta();
+        assert_eq!(meta.get("cb").and_then(Value::as_str), Some("trigger"));
+        assert_eq!(meta.get("error").and_then(Value::as_str), Some("snot"));
+        assert_eq!(
+            meta.get("backpressure-output").and_then(Value::as_str),
+            Some("out")
+        );
+    }
+}
@@ -22,13 +22,21 @@
 //!
 //! ## Input Variables
 //!   * `index` - index to write to (required)
-//!   * `doc-type` - document type for the event (required)
-//!   * `pipeline` - pipeline to use
+//!   * `doc_type` - document type for the event, left out for typeless
+//!     requests against ES 7 and later
+//!   * `pipeline` - pipeline to use (`index` and `create` only)
+//!   * `elastic` - a record with further settings for the bulk action:
+//!     * `action` - one of `index` (default), `create`, `update`, `upsert`
+//!       or `delete`
+//!     * `_id` - document id, required for `update`, `upsert` and `delete`
+//!     * `routing`, `version`, `version_type`, `if_seq_no` and
+//!       `if_primary_term` - passed on as is
 //!
 //! ## Outputs
 //!
-//! The 1st additional output is used to send divert messages that can not be
-//! enqueued due to overload
+//! Documents that elastic search rejected are sent to the `error` output
+//! (`err` for queries) of the pipeline they came from together with the
+//! reason they were rejected.
 
 use crate::offramp::prelude::make_postprocessors;
 use crate::offramp::prelude::*;
@@ -37,7 +45,7 @@ use crossbeam_channel::bounded;
 use elastic::prelude::*;
 use halfbrown::HashMap;
 use simd_json::borrowed::Object;
-use simd_json::json;
+use std::io::Read;
 use std::str;
 use std::time::Instant;
 use threadpool::ThreadPool;
@@ -69,6 +77,7 @@ pub struct Elastic {
     // hostname: String,
     pipelines: HashMap<TremorURL, pipeline::Addr>,
     postprocessors: Postprocessors,
+    confirmations: Option<offramp::Addr>,
 }
 
 impl offramp::Impl for Elastic {
@@ -98,6 +107,7 @@ impl offramp::Impl for Elastic {
                 client_idx: 0,
                 pipelines: HashMap::new(),
                 postprocessors: vec![],
+                confirmations: None,
                 // config,
                 pool,
                 clients,
@@ -110,20 +120,213 @@ impl offramp::Impl for Elastic {
     }
 }
 
+/// A bulk action
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Index,
+    Create,
+    Update,
+    Upsert,
+    Delete,
+}
+
+impl Action {
+    fn parse(action: &str) -> Result<Self> {
+        match action {
+            "index" => Ok(Self::Index),
+            "create" => Ok(Self::Create),
+            "update" => Ok(Self::Update),
+            "upsert" => Ok(Self::Upsert),
+            "delete" => Ok(Self::Delete),
+            other => Err(format!("Unknown elastic action '{}'", other).into()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Index => "index",
+            Self::Create => "create",
+            Self::Update | Self::Upsert => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// Writes the bulk action and, unless it is a delete, the document for a
+/// single value to the payload.
+fn write_item(payload: &mut Vec<u8>, value: &Value, meta: &Value) -> Result<()> {
+    let elastic = meta.get("elastic");
+    let action = match elastic.and_then(|e| e.get("action")) {
+        Some(action) => Action::parse(
+            action
+                .as_str()
+                .ok_or_else(|| Error::from("'elastic.action' needs to be a string"))?,
+        )?,
+        None => Action::Index,
+    };
+    let index = meta
+        .get("index")
+        .and_then(Value::as_str)
+        .ok_or_else(|| Error::from("'index' not set for elastic offramp!"))?;
+
+    let mut header = Object::new();
+    header.insert("_index".into(), index.into());
+    if let Some(doc_type) = meta.get("doc_type").and_then(Value::as_str) {
+        header.insert("_type".into(), doc_type.into());
+    }
+    if let Some(e) = elastic {
+        for key in &[
+            "_id",
+            "routing",
+            "version",
+            "version_type",
+            "if_seq_no",
+            "if_primary_term",
+        ] {
+            if let Some(v) = e.get(*key) {
+                header.insert((*key).into(), v.clone());
+            }
+        }
+    }
+    match action {
+        Action::Index | Action::Create => {
+            if let Some(pipeline) = meta.get("pipeline").and_then(Value::as_str) {
+                header.insert("pipeline".into(), pipeline.into());
+            }
+        }
+        Action::Update | Action::Upsert | Action::Delete => {
+            if !header.contains_key("_id") {
+                return Err(format!("'elastic._id' is required for {:?}", action).into());
+            }
+        }
+    }
+    let mut line = Object::new();
+    line.insert(action.name().into(), Value::from(header));
+    Value::from(line).write(payload)?;
+    payload.push(b'\n');
+    match action {
+        Action::Index | Action::Create => {
+            value.write(payload)?;
+            payload.push(b'\n');
+        }
+        Action::Update | Action::Upsert => {
+            let mut doc = Object::new();
+            doc.insert("doc".into(), value.clone());
+            if action == Action::Upsert {
+                doc.insert("doc_as_upsert".into(), true.into());
+            }
+            Value::from(doc).write(payload)?;
+            payload.push(b'\n');
+        }
+        Action::Delete => (),
+    }
+    Ok(())
+}
+
+/// Picks the items elastic search rejected out of a bulk response, returns
+/// their position in the request together with the status and error.
+fn rejected<'v>(response: &'v Value<'v>) -> Vec<(usize, u64, &'v Value<'v>)> {
+    if response.get("errors").and_then(Value::as_bool) != Some(true) {
+        return Vec::new();
+    }
+    response
+        .get("items")
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .enumerate()
+                .filter_map(|(i, item)| {
+                    // every item is a record with the action as only key
+                    let (_, result) = item.as_object()?.iter().next()?;
+                    let error = result.get("error")?;
+                    let status = result.get("status").and_then(Value::as_u64).unwrap_or(0);
+                    Some((i, status, error))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Turns a rejected document into an error event
+fn error_event(event: &Event, status: u64, error: &Value, doc: Option<&Value>) -> Event {
+    let reason = match (
+        error.get("type").and_then(Value::as_str),
+        error.get("reason").and_then(Value::as_str),
+    ) {
+        (Some(t), Some(r)) => format!("{}: {}", t, r),
+        (None, Some(r)) => r.to_string(),
+        _ => error.encode(),
+    };
+    let mut value = Object::new();
+    value.insert("error".into(), reason.into());
+    value.insert("status".into(), status.into());
+    value.insert(
+        "event".into(),
+        doc.map_or_else(Value::null, Value::clone_static),
+    );
+    let value = Value::from(value);
+    Event {
+        id: event.id,
+        data: LineValue::new(vec![], |_| ValueAndMeta::from(value)),
+        ingest_ns: event.ingest_ns,
+        origin_uri: event.origin_uri.clone(),
+        kind: None,
+        is_batch: false,
+    }
+}
+
+/// The connected pipeline an event came from, `input` is the id of the
+/// pipeline that sent it
+fn origin(
+    pipelines: &HashMap<TremorURL, pipeline::Addr>,
+    input: &str,
+) -> Option<(TremorURL, pipeline::Addr)> {
+    pipelines
+        .iter()
+        .find(|(_, p)| p.id.to_string() == input)
+        .map(|(pid, p)| (pid.clone(), p.clone()))
+}
+
 impl Elastic {
-    fn flush(client: &SyncClient, payload: Vec<u8>) -> Result<u64> {
+    fn flush(client: &SyncClient, payload: Vec<u8>, event: &Event) -> Result<(u64, Vec<Event>)> {
         let start = Instant::now();
         let res = client.request(BulkRequest::new(payload)).send()?;
-        for item in res.into_response::<BulkErrorsResponse>()? {
-            // TODO update error metric here?
-            error!("Elastic Search item error: {:?}", item);
+        let mut raw = res.into_raw();
+        let status = raw.status();
+        let mut body = Vec::new();
+        raw.read_to_end(&mut body)?;
+        if status < 200 || status >= 300 {
+            return Err(format!(
+                "Elastic search bulk request failed with {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )
+            .into());
         }
+        let response = simd_json::to_borrowed_value(&mut body)?;
+        let errors = rejected(&response)
+            .into_iter()
+            .map(|(i, status, error)| {
+                let e = error_event(event, status, error, event.value_iter().nth(i));
+                error!(
+                    "Elastic Search item error: {}",
+                    e.data.suffix().value().encode()
+                );
+                e
+            })
+            .collect();
         let d = start.elapsed();
         let d = duration_to_millis(d);
-        Ok(d)
+        Ok((d, errors))
     }
 
-    fn enqueue_send_future(&mut self, payload: Vec<u8>) -> Result<()> {
+    fn enqueue_send_future(
+        &mut self,
+        payload: Vec<u8>,
+        event: Event,
+        origin: Option<(TremorURL, pipeline::Addr)>,
+    ) -> Result<()> {
         self.client_idx = (self.client_idx + 1) % self.clients.len();
         let destination = self.clients[self.client_idx].clone();
         let (tx, rx) = bounded(1);
@@ -132,15 +335,32 @@ impl Elastic {
             .iter()
             .map(|(i, p)| (i.clone(), p.clone()))
             .collect();
+        let confirmations = self.confirmations.clone();
         self.pool.execute(move || {
-            let r = Self::flush(&destination.client, payload);
+            let r = Self::flush(&destination.client, payload, &event);
             let mut m = Object::new();
-            if let Ok(t) = r {
-                m.insert("time".into(), t.into());
-            } else {
-                // TODO update error metric here?
-                error!("Elastic search error: {:?}", r);
-                m.insert("error".into(), "Failed to send to ES".into());
+            let r = match r {
+                Ok((t, errors)) => {
+                    m.insert("time".into(), t.into());
+                    // Rejected documents only go back to the pipeline
+                    // that sent them
+                    if let Some((pid, p)) = &origin {
+                        for e in errors {
+                            if p.send(pipeline::Msg::Error(e)).is_err() {
+                                error!("Failed to send error event to pipeline {}", pid)
+                            };
+                        }
+                    } else if !errors.is_empty() {
+                        error!("No pipeline to send {} rejected documents to", errors.len())
+                    }
+                    Ok(t)
+                }
+                Err(e) => {
+                    // TODO update error metric here?
+                    error!("Elastic search error: {}", e);
+                    m.insert("error".into(), "Failed to send to ES".into());
+                    Err(e)
+                }
             };
             let insight = Event {
                 is_batch: false,
@@ -152,14 +372,19 @@ impl Elastic {
             };
 
             for (pid, p) in pipelines {
-                if p.addr
-                    .send(pipeline::Msg::Insight(insight.clone()))
-                    .is_err()
-                {
+                if p.send(pipeline::Msg::Insight(insight.clone())).is_err() {
                     error!("Failed to send contraflow to pipeline {}", pid)
                 };
             }
-            // TODO: Handle contraflow for notification
+            if let Some(confirmations) = confirmations {
+                let result = r.as_ref().map(|_| ()).map_err(|e| e.to_string().into());
+                if let Err(e) = confirmations.send(offramp::Msg::Delivered {
+                    id: event.id,
+                    result,
+                }) {
+                    error!("Failed to confirm delivery: {}", e)
+                }
+            }
             if let Err(e) = tx.send(r) {
                 error!("Failed to send reply: {}", e)
             }
@@ -167,7 +392,12 @@ impl Elastic {
         self.queue.enqueue(rx)?;
         Ok(())
     }
-    fn maybe_enque(&mut self, payload: Vec<u8>) -> Result<()> {
+    fn maybe_enque(
+        &mut self,
+        payload: Vec<u8>,
+        event: Event,
+        origin: Option<(TremorURL, pipeline::Addr)>,
+    ) -> Result<()> {
         match self.queue.dequeue() {
             Err(SinkDequeueError::NotReady) if !self.queue.has_capacity() => {
                 let mut m = Object::new();
@@ -188,10 +418,7 @@ impl Elastic {
                     .map(|(i, p)| (i.clone(), p.clone()))
                     .collect();
                 for (pid, p) in pipelines {
-                    if p.addr
-                        .send(pipeline::Msg::Insight(insight.clone()))
-                        .is_err()
-                    {
+                    if p.send(pipeline::Msg::Insight(insight.clone())).is_err() {
                         error!("Failed to send contraflow to pipeline {}", pid)
                     };
                 }
@@ -200,7 +427,7 @@ impl Elastic {
                 Err("Dropped data due to es overload".into())
             }
             _ => {
-                if self.enqueue_send_future(payload).is_err() {
+                if self.enqueue_send_future(payload, event, origin).is_err() {
                     // TODO: handle reply to the pipeline
                     error!("Failed to enqueue send request to elastic");
                     Err("Failed to enqueue send request to elastic".into())
@@ -214,43 +441,16 @@ impl Elastic {
 
 impl Offramp for Elastic {
     // We enforce json here!
-    fn on_event(&mut self, _codec: &Box<dyn Codec>, _input: String, event: Event) -> Result<()> {
+    fn on_event(&mut self, _codec: &Box<dyn Codec>, input: String, event: Event) -> Result<()> {
         // We estimate a single message is 512 byte on everage, might be off but it's
         // a guess
         let mut payload = Vec::with_capacity(4096);
 
         for (value, meta) in event.value_meta_iter() {
-            let index = meta
-                .get("index")
-                .and_then(Value::as_str)
-                .ok_or_else(|| Error::from("'index' not set for elastic offramp!"))?;
-            let doc_type = meta
-                .get("doc_type")
-                .and_then(Value::as_str)
-                .ok_or_else(|| Error::from("'doc-type' not set for elastic offramp!"))?;
-            match meta.get("pipeline").and_then(Value::as_str) {
-                None => json!({
-                "index":
-                {
-                    "_index": index,
-                    "_type": doc_type
-                }})
-                .write(&mut payload)?,
-
-                Some(pipeline) => json!({
-                "index":
-                {
-                    "_index": index,
-                    "_type": doc_type,
-                    "pipeline": pipeline
-                }})
-                .write(&mut payload)?,
-            };
-            payload.push(b'\n');
-            value.write(&mut payload)?;
-            payload.push(b'\n');
+            write_item(&mut payload, value, meta)?;
         }
-        self.maybe_enque(payload)
+        let origin = origin(&self.pipelines, &input);
+        self.maybe_enque(payload, event, origin)
     }
     fn default_codec(&self) -> &str {
         "json"
@@ -262,8 +462,99 @@ impl Offramp for Elastic {
         self.pipelines.remove(&id);
         self.pipelines.is_empty()
     }
+    fn confirm_delivery(&mut self, addr: offramp::Addr) -> bool {
+        self.confirmations = Some(addr);
+        true
+    }
     fn start(&mut self, _codec: &Box<dyn Codec>, postprocessors: &[String]) -> Result<()> {
         self.postprocessors = make_postprocessors(postprocessors)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(value: &Value, meta: &Value) -> Result<String> {
+        let mut payload = Vec::new();
+        write_item(&mut payload, value, meta)?;
+        Ok(String::from_utf8(payload)?)
+    }
+
+    fn parse(s: &str) -> Value<'static> {
+        let mut s = s.as_bytes().to_vec();
+        simd_json::to_borrowed_value(&mut s)
+            .expect("invalid json")
+            .clone_static()
+    }
+
+    #[test]
+    fn actions() -> Result<()> {
+        let doc = parse(r#"{"snot":"badger"}"#);
+
+        let meta = parse(r#"{"index":"idx","doc_type":"_doc","pipeline":"p"}"#);
+        assert_eq!(
+            item(&doc, &meta)?,
+            "{\"index\":{\"_index\":\"idx\",\"_type\":\"_doc\",\"pipeline\":\"p\"}}\n{\"snot\":\"badger\"}\n"
+        );
+
+        // typeless, with id and routing
+        let meta =
+            parse(r#"{"index":"idx","elastic":{"action":"create","_id":"1","routing":"r"}}"#);
+        assert_eq!(
+            item(&doc, &meta)?,
+            "{\"create\":{\"_index\":\"idx\",\"_id\":\"1\",\"routing\":\"r\"}}\n{\"snot\":\"badger\"}\n"
+        );
+
+        let meta =
+            parse(r#"{"index":"idx","elastic":{"action":"upsert","_id":"1","if_seq_no":3}}"#);
+        assert_eq!(
+            item(&doc, &meta)?,
+            "{\"update\":{\"_index\":\"idx\",\"_id\":\"1\",\"if_seq_no\":3}}\n{\"doc\":{\"snot\":\"badger\"},\"doc_as_upsert\":true}\n"
+        );
+
+        let meta = parse(r#"{"index":"idx","elastic":{"action":"delete","_id":"1","version":2}}"#);
+        assert_eq!(
+            item(&doc, &meta)?,
+            "{\"delete\":{\"_index\":\"idx\",\"_id\":\"1\",\"version\":2}}\n"
+        );
+
+        let meta = parse(r#"{"index":"idx","elastic":{"action":"update"}}"#);
+        assert!(item(&doc, &meta).is_err());
+        let meta = parse(r#"{"index":"idx","elastic":{"action":"snot"}}"#);
+        assert!(item(&doc, &meta).is_err());
+        let meta = parse(r#"{"doc_type":"_doc"}"#);
+        assert!(item(&doc, &meta).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_items() {
+        let ok = parse(r#"{"took":1,"errors":false,"items":[{"index":{"status":201}}]}"#);
+        assert!(rejected(&ok).is_empty());
+
+        let response = parse(
+            r#"{"took":1,"errors":true,"items":[
+                {"index":{"status":201}},
+                {"create":{"status":400,"error":{"type":"mapper_parsing_exception","reason":"failed to parse"}}}
+            ]}"#,
+        );
+        let r = rejected(&response);
+        assert_eq!(r.len(), 1);
+        let (i, status, error) = r[0];
+        assert_eq!(i, 1);
+        assert_eq!(status, 400);
+
+        let event = Event::default();
+        let doc = parse(r#"{"snot":"badger"}"#);
+        let e = error_event(&event, status, error, Some(&doc));
+        let value = e.data.suffix().value();
+        assert_eq!(
+            value.get("error").and_then(Value::as_str),
+            Some("mapper_parsing_exception: failed to parse")
+        );
+        assert_eq!(value.get("status").and_then(Value::as_u64), Some(400));
+        assert_eq!(value.get("event"), Some(&doc));
+    }
+}
@@ -123,7 +123,10 @@ impl Offramp for Kafka {
                 Ok(f) => {
                     task::spawn(f);
                 }
-                Err((e, _)) => error!("[Kafka Offramp] failed to enque message: {}", e),
+                Err((e, _)) => {
+                    error!("[Kafka Offramp] failed to enque message: {}", e);
+                    return Err(format!("Failed to enqueue message: {}", e).into());
+                }
             }
         }
         Ok(())
@@ -42,6 +42,7 @@ pub struct Rest {
     queue: AsyncSink<u64>,
     pipelines: HashMap<TremorURL, pipeline::Addr>,
     postprocessors: Postprocessors,
+    confirmations: Option<offramp::Addr>,
 }
 
 impl offramp::Impl for Rest {
@@ -54,6 +55,7 @@ impl offramp::Impl for Rest {
                 client_idx: 0,
                 pipelines: HashMap::new(),
                 postprocessors: vec![],
+                confirmations: None,
                 config,
                 queue,
             }))
@@ -90,13 +92,14 @@ impl Rest {
             } else {
                 error!("HTTP request failed: {}", status)
             }
+            return Err(format!("HTTP request failed: {}", status).into());
         }
 
         let d = duration_to_millis(start.elapsed());
         Ok(d)
     }
 
-    fn enqueue_send_future(&mut self, payload: Vec<u8>) -> Result<()> {
+    fn enqueue_send_future(&mut self, payload: Vec<u8>, id: u64) -> Result<()> {
         self.client_idx = (self.client_idx + 1) % self.config.endpoints.len();
         let destination = self.config.endpoints[self.client_idx].clone();
         let (tx, rx) = bounded(1);
@@ -106,6 +109,7 @@ impl Rest {
             .iter()
             .map(|(i, p)| (i.clone(), p.clone()))
             .collect();
+        let confirmations = self.confirmations.clone();
         task::spawn(async move {
             let r = Self::flush(&destination, config, payload).await;
             let mut m = Object::new();
@@ -125,14 +129,18 @@ impl Rest {
             };
 
             for (pid, p) in pipelines {
-                if p.addr
-                    .send(pipeline::Msg::Insight(insight.clone()))
-                    .is_err()
-                {
+                if p.send(pipeline::Msg::Insight(insight.clone())).is_err() {
                     error!("Failed to send contraflow to pipeline {}", pid)
                 };
             }
 
+            if let Some(confirmations) = confirmations {
+                let result = r.as_ref().map(|_| ()).map_err(|e| e.to_string().into());
+                if let Err(e) = confirmations.send(offramp::Msg::Delivered { id, result }) {
+                    error!("Failed to confirm delivery: {}", e)
+                }
+            }
+
             if let Err(e) = tx.send(r) {
                 error!("Failed to send reply: {}", e)
             }
@@ -140,7 +148,7 @@ impl Rest {
         self.queue.enqueue(rx)?;
         Ok(())
     }
-    fn maybe_enque(&mut self, payload: Vec<u8>) -> Result<()> {
+    fn maybe_enque(&mut self, payload: Vec<u8>, id: u64) -> Result<()> {
         match self.queue.dequeue() {
             Err(SinkDequeueError::NotReady) if !self.queue.has_capacity() => {
                 let mut m = Object::new();
@@ -164,10 +172,7 @@ impl Rest {
                     .map(|(i, p)| (i.clone(), p.clone()))
                     .collect();
                 for (pid, p) in pipelines {
-                    if p.addr
-                        .send(pipeline::Msg::Insight(insight.clone()))
-                        .is_err()
-                    {
+                    if p.send(pipeline::Msg::Insight(insight.clone())).is_err() {
                         error!("Failed to send contraflow to pipeline {}", pid)
                     };
                 }
@@ -175,7 +180,7 @@ impl Rest {
                 Err("Dropped data due to overload".into())
             }
             _ => {
-                if self.enqueue_send_future(payload).is_err() {
+                if self.enqueue_send_future(payload, id).is_err() {
                     // TODO: handle reply to the pipeline
                     error!("Failed to enqueue send request");
                     Err("Failed to enqueue send request".into())
@@ -195,7 +200,7 @@ impl Offramp for Rest {
             payload.append(&mut raw);
             payload.push(b'\n');
         }
-        self.maybe_enque(payload)
+        self.maybe_enque(payload, event.id)
     }
     fn default_codec(&self) -> &str {
         "json"
@@ -211,4 +216,8 @@ impl Offramp for Rest {
         self.pipelines.remove(&id);
         self.pipelines.is_empty()
     }
+    fn confirm_delivery(&mut self, addr: offramp::Addr) -> bool {
+        self.confirmations = Some(addr);
+        true
+    }
 }
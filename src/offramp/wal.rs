@@ -0,0 +1,648 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A write ahead queue on local disk that sits between the pipelines and an
+//! offramp. Events are appended to segment files and a cursor records up to
+//! where the offramp confirmed their delivery, delivery is at least once:
+//! events that were handed out but not confirmed yet are replayed after a
+//! restart. Events the offramp failed to deliver are appended to the queue
+//! again and taking events from it backs off until the next try.
+
+use super::{Inbox, Msg};
+use crate::errors::{Error, Result};
+use crate::Event;
+use crossbeam_channel::{RecvTimeoutError, TryRecvError};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The cursor is persisted after this many committed events, or when the
+/// queue runs empty
+const COMMIT_EVERY: usize = 64;
+const CURSOR: &str = "cursor";
+const HEADER: u64 = 4;
+/// Backoff after the first failure, it doubles with every failure in a row
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+struct Record<'a> {
+    input: &'a str,
+    event: &'a Event,
+}
+
+#[derive(Deserialize)]
+struct OwnedRecord {
+    input: String,
+    event: Event,
+}
+
+/// An event that was handed out and waits for its delivery to be confirmed
+struct InFlight {
+    id: u64,
+    // position of the record and of the one after it
+    start: (u64, u64),
+    end: (u64, u64),
+    done: bool,
+}
+
+pub(crate) struct Wal {
+    dir: PathBuf,
+    max_bytes: u64,
+    segment_bytes: u64,
+    // sequence numbers and sizes of the segments on disk, oldest first, the
+    // last one is the one that gets written to
+    segments: VecDeque<(u64, u64)>,
+    writer: File,
+    reader: Option<BufReader<File>>,
+    // offset of the next record in the oldest segment
+    read_offset: u64,
+    // segments that were read completely but weren't committed yet
+    finished: Vec<u64>,
+    // position up to which events were delivered
+    committed: (u64, u64),
+    // events that were handed out, in the order they were read
+    in_flight: VecDeque<InFlight>,
+    // events in the queue that weren't read yet
+    len: usize,
+    uncommitted: usize,
+    // written data that wasn't synced to disk yet
+    dirty: bool,
+    // no events are taken from the queue before this
+    paused_until: Option<Instant>,
+    backoff: Duration,
+}
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}.wal", seq))
+}
+
+/// Reads the record the reader is positioned at
+/// Syncs the entries of a directory so files created or renamed in it
+/// survive a crash
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn read_record(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut len = [0_u8; 4];
+    reader.read_exact(&mut len)?;
+    let mut data = vec![0_u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn decode(mut data: Vec<u8>) -> Result<(String, Event)> {
+    let record: OwnedRecord = simd_json::from_slice(&mut data)
+        .map_err(|e| Error::from(format!("Corrupt record in queue: {}", e)))?;
+    Ok((record.input, record.event))
+}
+
+/// Counts the complete records in a segment starting at `offset`
+fn count_records(path: &Path, mut offset: u64, size: u64) -> Result<usize> {
+    let mut file = BufReader::new(File::open(path)?);
+    file.seek(SeekFrom::Start(offset))?;
+    let mut count = 0;
+    let mut len = [0_u8; 4];
+    while offset + HEADER <= size {
+        file.read_exact(&mut len)?;
+        let next = offset + HEADER + u64::from(u32::from_le_bytes(len));
+        if next > size {
+            break;
+        }
+        file.seek(SeekFrom::Start(next))?;
+        offset = next;
+        count += 1;
+    }
+    Ok(count)
+}
+
+impl Wal {
+    /// Opens the queue in `dir`, creating it if it doesn't exist yet
+    pub(crate) fn open(dir: PathBuf, max_bytes: u64, segment_bytes: u64) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut seqs: Vec<u64> = fs::read_dir(&dir)?
+            .filter_map(|e| {
+                let path = e.ok()?.path();
+                if path.extension()? == "wal" {
+                    path.file_stem()?.to_str()?.parse().ok()
+                } else {
+                    None
+                }
+            })
+            .collect();
+        seqs.sort();
+        let cursor = fs::read(dir.join(CURSOR)).ok().and_then(|c| {
+            let mut seq = [0_u8; 8];
+            let mut offset = [0_u8; 8];
+            seq.copy_from_slice(c.get(0..8)?);
+            offset.copy_from_slice(c.get(8..16)?);
+            Some((u64::from_le_bytes(seq), u64::from_le_bytes(offset)))
+        });
+        let (cursor_seq, cursor_offset) =
+            cursor.unwrap_or_else(|| (seqs.first().copied().unwrap_or_default(), 0));
+
+        let mut segments = VecDeque::new();
+        let mut len = 0;
+        let mut read_offset = 0;
+        for seq in seqs {
+            let path = segment_path(&dir, seq);
+            if seq < cursor_seq {
+                // everything in here was delivered already
+                fs::remove_file(&path)?;
+                continue;
+            }
+            let size = fs::metadata(&path)?.len();
+            let offset = if seq == cursor_seq { cursor_offset } else { 0 };
+            if segments.is_empty() {
+                read_offset = offset;
+            }
+            len += count_records(&path, offset, size)?;
+            segments.push_back((seq, size));
+        }
+        // We always start a new segment so a record that was only partially
+        // written before a crash is never appended to
+        let head = segments.back().map_or(cursor_seq, |(seq, _)| seq + 1);
+        let writer = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(segment_path(&dir, head))?;
+        sync_dir(&dir)?;
+        segments.push_back((head, 0));
+        if len > 0 {
+            info!("Replaying {} events from {}", len, dir.display());
+        }
+        Ok(Self {
+            dir,
+            max_bytes,
+            segment_bytes,
+            segments,
+            writer,
+            reader: None,
+            read_offset,
+            finished: Vec::new(),
+            committed: (cursor_seq, cursor_offset),
+            in_flight: VecDeque::new(),
+            len,
+            uncommitted: 0,
+            dirty: false,
+            paused_until: None,
+            backoff: MIN_BACKOFF,
+        })
+    }
+
+    /// Number of events in the queue
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Size of the events in the queue that weren't read yet
+    pub(crate) fn bytes(&self) -> u64 {
+        let total: u64 = self.segments.iter().map(|(_, size)| size).sum();
+        total.saturating_sub(self.read_offset)
+    }
+
+    fn has_capacity(&self) -> bool {
+        self.bytes() < self.max_bytes
+    }
+
+    /// Appends an event to the queue
+    pub(crate) fn push(&mut self, input: &str, event: &Event) -> Result<()> {
+        let data = simd_json::to_vec(&Record { input, event })?;
+        if let Some((_, size)) = self.segments.back() {
+            if *size >= self.segment_bytes {
+                let head = self.segments.back().map_or(0, |(seq, _)| seq + 1);
+                // The old segment has to be on disk before we stop writing
+                // to it, `sync` only ever looks at the current one
+                self.sync()?;
+                self.writer = File::create(segment_path(&self.dir, head))?;
+                sync_dir(&self.dir)?;
+                self.segments.push_back((head, 0));
+            }
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let len = data.len() as u32;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(&data)?;
+        if let Some((_, size)) = self.segments.back_mut() {
+            *size += HEADER + u64::from(len);
+        }
+        self.len += 1;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Syncs the written events to disk, an event only counts as stored
+    /// once this succeeded so we never hand out one that could be lost
+    fn sync(&mut self) -> Result<()> {
+        if self.dirty {
+            self.writer.sync_data()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Takes the next event from the queue, its delivery has to be
+    /// confirmed with `ack`
+    pub(crate) fn pop(&mut self) -> Result<Option<(String, Event)>> {
+        self.sync()?;
+        loop {
+            let (seq, size) = if let Some(front) = self.segments.front() {
+                *front
+            } else {
+                return Ok(None);
+            };
+            if self.read_offset + HEADER <= size {
+                let reader = if let Some(reader) = &mut self.reader {
+                    reader
+                } else {
+                    let mut file = File::open(segment_path(&self.dir, seq))?;
+                    file.seek(SeekFrom::Start(self.read_offset))?;
+                    self.reader.get_or_insert(BufReader::new(file))
+                };
+                let mut len = [0_u8; 4];
+                if let Err(e) = reader.read_exact(&mut len) {
+                    self.reader = None;
+                    return Err(e.into());
+                }
+                let len = u64::from(u32::from_le_bytes(len));
+                if self.read_offset + HEADER + len <= size {
+                    #[allow(clippy::cast_possible_truncation)]
+                    let mut data = vec![0_u8; len as usize];
+                    if let Err(e) = reader.read_exact(&mut data) {
+                        self.reader = None;
+                        return Err(e.into());
+                    }
+                    let start = (seq, self.read_offset);
+                    self.read_offset += HEADER + len;
+                    self.len = self.len.saturating_sub(1);
+                    let end = (seq, self.read_offset);
+                    match decode(data) {
+                        Ok((input, event)) => {
+                            self.in_flight.push_back(InFlight {
+                                id: event.id,
+                                start,
+                                end,
+                                done: false,
+                            });
+                            return Ok(Some((input, event)));
+                        }
+                        Err(e) => {
+                            // A corrupt record can't ever be delivered so we
+                            // skip it instead of holding up the queue
+                            error!("Skipping record in {}: {}", self.dir.display(), e);
+                            self.in_flight.push_back(InFlight {
+                                id: 0,
+                                start,
+                                end,
+                                done: true,
+                            });
+                            self.advance()?;
+                            continue;
+                        }
+                    }
+                }
+            }
+            if self.segments.len() == 1 {
+                // We've read everything that has been written so far
+                self.reader = None;
+                return Ok(None);
+            }
+            // The segment is done, a partial record at its end can only be
+            // left over from a crash. It is removed once the events we read
+            // from it are committed.
+            self.segments.pop_front();
+            self.finished.push(seq);
+            self.reader = None;
+            self.read_offset = 0;
+        }
+    }
+
+    /// Confirms the outcome of delivering the event with the given id that
+    /// was taken from the queue. Events are committed in the order they
+    /// were taken, an event that wasn't delivered is appended to the queue
+    /// again.
+    pub(crate) fn ack(&mut self, id: u64, delivered: bool) -> Result<()> {
+        let idx = if let Some(idx) = self.in_flight.iter().position(|f| !f.done && f.id == id) {
+            idx
+        } else {
+            return Err(format!("Event {} is not waiting for delivery", id).into());
+        };
+        if delivered {
+            self.backoff = MIN_BACKOFF;
+        } else {
+            let (seq, offset) = self.in_flight[idx].start;
+            let mut file = File::open(segment_path(&self.dir, seq))?;
+            file.seek(SeekFrom::Start(offset))?;
+            let (input, event) = decode(read_record(&mut file)?)?;
+            self.push(&input, &event)?;
+            self.pause();
+        }
+        self.in_flight[idx].done = true;
+        self.advance()
+    }
+
+    /// Stops taking events from the queue for a while, the pause doubles
+    /// with every failure in a row
+    fn pause(&mut self) {
+        self.paused_until = Some(Instant::now() + self.backoff);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    /// Stops taking events from the queue for at least `wait`, events
+    /// that arrive meanwhile are still queued
+    pub(crate) fn hold(&mut self, wait: Duration) {
+        let until = Instant::now() + wait;
+        if self.paused_until.map_or(true, |paused| paused < until) {
+            self.paused_until = Some(until);
+        }
+    }
+
+    /// Moves the cursor past the events at the front that are done
+    fn advance(&mut self) -> Result<()> {
+        let mut moved = false;
+        while let Some(f) = self.in_flight.front() {
+            if !f.done {
+                break;
+            }
+            self.committed = f.end;
+            self.in_flight.pop_front();
+            self.uncommitted += 1;
+            moved = true;
+        }
+        if !moved {
+            return Ok(());
+        }
+        // Segments before the one the cursor is in were delivered completely
+        let (seq, _) = self.committed;
+        let mut i = 0;
+        while i < self.finished.len() {
+            if self.finished[i] < seq {
+                fs::remove_file(segment_path(&self.dir, self.finished.remove(i)))?;
+            } else {
+                i += 1;
+            }
+        }
+        if self.uncommitted >= COMMIT_EVERY || (self.len == 0 && self.in_flight.is_empty()) {
+            self.write_cursor()?;
+        }
+        Ok(())
+    }
+
+    fn write_cursor(&mut self) -> Result<()> {
+        let (seq, offset) = self.committed;
+        let mut cursor = Vec::with_capacity(16);
+        cursor.extend_from_slice(&seq.to_le_bytes());
+        cursor.extend_from_slice(&offset.to_le_bytes());
+        // We write to a temporary file first and move it in place so a crash
+        // while writing never leaves us with a truncated cursor
+        let path = self.dir.join(CURSOR);
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&cursor)?;
+        file.sync_data()?;
+        fs::rename(&tmp, path)?;
+        sync_dir(&self.dir)?;
+        self.uncommitted = 0;
+        Ok(())
+    }
+
+    /// Pulls the next message for the offramp. Events from the channel are
+    /// appended to the queue while there is room for them, the events that
+    /// are returned are always taken from the queue so they stay in order.
+    /// Returns `None` once the channel is closed, events that are still
+    /// queued at that point are delivered by the next instance.
+    pub(crate) fn next(&mut self, inbox: &Inbox) -> Option<Msg> {
+        loop {
+            while self.has_capacity() {
+                match inbox.try_recv() {
+                    Ok(Msg::Event { event, input }) => {
+                        if let Err(e) = self.push(&input, &event) {
+                            // We rather deliver the event out of order than lose it
+                            error!("Failed to queue event in {}: {}", self.dir.display(), e);
+                            return Some(Msg::Event { event, input });
+                        }
+                    }
+                    Ok(msg) => return Some(msg),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return None,
+                }
+            }
+            let now = Instant::now();
+            let popped = match self.paused_until {
+                Some(until) if until > now => {
+                    // While we back off we only wait for other messages
+                    match inbox.recv_timeout(Some(until - now)) {
+                        Ok(Msg::Event { event, input }) => {
+                            if let Err(e) = self.push(&input, &event) {
+                                error!("Failed to queue event in {}: {}", self.dir.display(), e);
+                                return Some(Msg::Event { event, input });
+                            }
+                        }
+                        Ok(msg) => return Some(msg),
+                        Err(RecvTimeoutError::Timeout) => self.paused_until = None,
+                        Err(RecvTimeoutError::Disconnected) => return None,
+                    }
+                    continue;
+                }
+                _ => self.pop(),
+            };
+            match popped {
+                Ok(Some((input, event))) => {
+                    return Some(Msg::Event {
+                        event,
+                        input: Cow::Owned(input),
+                    })
+                }
+                Ok(None) => match inbox.recv_timeout(None) {
+                    Ok(Msg::Event { event, input }) => {
+                        if let Err(e) = self.push(&input, &event) {
+                            error!("Failed to queue event in {}: {}", self.dir.display(), e);
+                            return Some(Msg::Event { event, input });
+                        }
+                    }
+                    Ok(msg) => return Some(msg),
+                    Err(_) => return None,
+                },
+                Err(e) => {
+                    error!("Failed to read from queue {}: {}", self.dir.display(), e);
+                    self.pause();
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Wal {
+    fn drop(&mut self) {
+        if let Err(e) = self.write_cursor() {
+            error!("Failed to persist cursor of {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crossbeam_channel::{bounded, never};
+    use simd_json::prelude::*;
+    use simd_json::value::borrowed::Value;
+    use tempfile::tempdir;
+
+    fn event(id: u64) -> Event {
+        Event {
+            id,
+            data: Value::from(id).into(),
+            ..Event::default()
+        }
+    }
+
+    fn ids(wal: &mut Wal) -> Vec<u64> {
+        let mut ids = Vec::new();
+        while let Some((input, event)) = wal.pop().expect("failed to pop") {
+            assert_eq!(input, "in");
+            assert_eq!(event.data.suffix().value().as_u64(), Some(event.id));
+            ids.push(event.id);
+            wal.ack(event.id, true).expect("failed to ack");
+        }
+        ids
+    }
+
+    #[test]
+    fn push_pop() -> Result<()> {
+        let dir = tempdir()?;
+        let mut wal = Wal::open(dir.path().to_path_buf(), 1024 * 1024, 1024)?;
+        for id in 0..3 {
+            wal.push("in", &event(id))?;
+        }
+        assert_eq!(wal.len(), 3);
+        assert_eq!(ids(&mut wal), vec![0, 1, 2]);
+        assert_eq!(wal.len(), 0);
+        assert_eq!(wal.bytes(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn replay() -> Result<()> {
+        let dir = tempdir()?;
+        let mut wal = Wal::open(dir.path().to_path_buf(), 1024 * 1024, 1024)?;
+        for id in 0..4 {
+            wal.push("in", &event(id))?;
+        }
+        assert!(wal.pop()?.is_some());
+        wal.ack(0, true)?;
+        // popped but never confirmed, so it is delivered again
+        assert!(wal.pop()?.is_some());
+        drop(wal);
+
+        let mut wal = Wal::open(dir.path().to_path_buf(), 1024 * 1024, 1024)?;
+        assert_eq!(wal.len(), 3);
+        wal.push("in", &event(4))?;
+        assert_eq!(ids(&mut wal), vec![1, 2, 3, 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn ack_in_order() -> Result<()> {
+        let dir = tempdir()?;
+        let mut wal = Wal::open(dir.path().to_path_buf(), 1024 * 1024, 1024)?;
+        for id in 0..3 {
+            wal.push("in", &event(id))?;
+        }
+        for _ in 0..3 {
+            assert!(wal.pop()?.is_some());
+        }
+        // the later events are confirmed first, the cursor can't move past
+        // the first one yet
+        wal.ack(2, true)?;
+        wal.ack(1, true)?;
+        assert!(wal.ack(1, true).is_err());
+        assert_eq!(wal.committed, (0, 0));
+        drop(wal);
+
+        let mut wal = Wal::open(dir.path().to_path_buf(), 1024 * 1024, 1024)?;
+        assert_eq!(wal.len(), 3);
+        for _ in 0..3 {
+            assert!(wal.pop()?.is_some());
+        }
+        wal.ack(1, true)?;
+        wal.ack(0, true)?;
+        wal.ack(2, true)?;
+        drop(wal);
+
+        let wal = Wal::open(dir.path().to_path_buf(), 1024 * 1024, 1024)?;
+        assert_eq!(wal.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn requeue() -> Result<()> {
+        let dir = tempdir()?;
+        let mut wal = Wal::open(dir.path().to_path_buf(), 1024 * 1024, 1024)?;
+        for id in 0..2 {
+            wal.push("in", &event(id))?;
+        }
+        assert!(wal.pop()?.is_some());
+        wal.ack(0, false)?;
+        assert!(wal.paused_until.is_some());
+        // the failed event goes to the back of the queue
+        assert_eq!(ids(&mut wal), vec![1, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn segments() -> Result<()> {
+        let dir = tempdir()?;
+        // every event gets a segment of its own
+        let mut wal = Wal::open(dir.path().to_path_buf(), 1024 * 1024, 1)?;
+        for id in 0..5 {
+            wal.push("in", &event(id))?;
+        }
+        assert_eq!(ids(&mut wal), vec![0, 1, 2, 3, 4]);
+        // only the segment that is written to is left
+        let segments = fs::read_dir(dir.path())?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().extension().map_or(false, |e| e == "wal"))
+            .count();
+        assert_eq!(segments, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn backpressure() -> Result<()> {
+        let dir = tempdir()?;
+        let mut wal = Wal::open(dir.path().to_path_buf(), 1, 1024)?;
+        let (tx, rx) = bounded(4);
+        let inbox = Inbox::new(rx, never());
+        for id in 0..3 {
+            tx.send(Msg::Event {
+                event: event(id),
+                input: "in".into(),
+            })?;
+        }
+        // the queue is full after the first event, the rest stays in the channel
+        assert!(wal.next(&inbox).is_some());
+        assert_eq!(inbox.len(), 2);
+        drop(tx);
+        assert!(wal.next(&inbox).is_some());
+        assert!(wal.next(&inbox).is_some());
+        assert!(wal.next(&inbox).is_none());
+        Ok(())
+    }
+}
@@ -27,7 +27,7 @@ mod gsub;
 mod kafka;
 mod metronome;
 mod postgres;
-mod prelude;
+pub(crate) mod prelude;
 pub mod tcp;
 mod udp;
 use async_std::sync::{self, channel};
@@ -42,21 +42,34 @@ pub(crate) trait Impl {
     fn from_config(config: &Option<Value>) -> Result<Box<dyn Onramp>>;
 }
 
+/// Control messages for an onramp
 #[derive(Clone, Debug)]
 pub enum Msg {
+    /// Connects pipelines to the onramp
     Connect(Vec<(TremorURL, pipeline::Addr)>),
-    Disconnect { id: TremorURL, tx: CbSender<bool> },
+    /// Disconnects a pipeline, `tx` receives `true` once the onramp has no
+    /// pipelines left
+    Disconnect {
+        /// Pipeline to disconnect
+        id: TremorURL,
+        /// Channel for the reply
+        tx: CbSender<bool>,
+    },
 }
 
+/// Address of a running onramp
 pub type Addr = sync::Sender<Msg>;
 
-pub(crate) trait Onramp: Send {
+/// An onramp
+pub trait Onramp: Send {
+    /// Starts the onramp
     fn start(
         &mut self,
         codec: &str,
         preprocessors: &[String],
         metrics_reporter: RampReporter,
     ) -> Result<Addr>;
+    /// The codec used when none is configured
     fn default_codec(&self) -> &str;
 }
 
@@ -76,7 +89,8 @@ pub(crate) fn lookup(name: &str, config: &Option<Value>) -> Result<Box<dyn Onram
         "tcp" => tcp::Tcp::from_config(config),
         // "rest" => rest::Rest::from_config(config),
         "ws" => ws::Ws::from_config(config),
-        _ => Err(format!("Onramp {} not known", name).into()),
+        _ => crate::plugin::onramp(name, config)
+            .unwrap_or_else(|| Err(format!("Onramp {} not known", name).into())),
     }
 }
 
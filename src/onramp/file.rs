@@ -102,7 +102,7 @@ fn onramp_loop(
     // Esnure that we do not terminate before all pipelines are empty
     'outer: loop {
         for (_url, addr) in &pipelines {
-            if !addr.addr.is_empty() {
+            if !addr.is_empty() {
                 thread::sleep(Duration::from_millis(100));
                 continue 'outer;
             }
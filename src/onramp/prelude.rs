@@ -30,6 +30,7 @@ pub(crate) use tremor_pipeline::EventOriginUri;
 use std::mem;
 pub(crate) use std::thread;
 
+/// Looks up the preprocessors by name
 pub fn make_preprocessors(preprocessors: &[String]) -> Result<Preprocessors> {
     preprocessors
         .iter()
@@ -37,6 +38,7 @@ pub fn make_preprocessors(preprocessors: &[String]) -> Result<Preprocessors> {
         .collect()
 }
 
+/// Runs data through a chain of preprocessors
 pub fn handle_pp(
     preprocessors: &mut Preprocessors,
     ingest_ns: &mut u64,
@@ -60,13 +62,15 @@ pub fn handle_pp(
     Ok(data)
 }
 
+/// Preprocesses and decodes data and sends the resulting events to all
+/// connected pipelines
 // We are borrowing a dyn box as we don't want to pass ownership.
 #[allow(
     clippy::borrowed_box,
     clippy::too_many_lines,
     clippy::too_many_arguments
 )]
-pub(crate) fn send_event(
+pub fn send_event(
     pipelines: &[(TremorURL, pipeline::Addr)],
     preprocessors: &mut Preprocessors,
     codec: &mut Box<dyn Codec>,
@@ -97,7 +101,7 @@ pub(crate) fn send_event(
 
                     for (input, addr) in &pipelines[0..len - 1] {
                         if let Some(input) = input.instance_port() {
-                            if let Err(e) = addr.addr.send(pipeline::Msg::Event {
+                            if let Err(e) = addr.send(pipeline::Msg::Event {
                                 input: input.into(),
                                 event: event.clone(),
                             }) {
@@ -108,7 +112,7 @@ pub(crate) fn send_event(
 
                     let (input, addr) = &pipelines[len - 1];
                     if let Some(input) = input.instance_port() {
-                        if let Err(e) = addr.addr.send(pipeline::Msg::Event {
+                        if let Err(e) = addr.send(pipeline::Msg::Event {
                             input: input.into(),
                             event,
                         }) {
@@ -129,14 +133,18 @@ pub(crate) fn send_event(
     };
 }
 
-pub(crate) enum PipeHandlerResult {
+/// Outcome of handling pipeline connections
+pub enum PipeHandlerResult {
+    /// The last pipeline disconnected, the onramp should stop
     Terminate,
+    /// The first pipeline connected
     Retry,
+    /// Business as usual
     Normal,
 }
 
-// Handles pipeline connections for an onramp
-pub(crate) async fn handle_pipelines(
+/// Handles pipeline connections for an onramp
+pub async fn handle_pipelines(
     rx: &Receiver<onramp::Msg>,
     pipelines: &mut Vec<(TremorURL, pipeline::Addr)>,
     metrics_reporter: &mut RampReporter,
@@ -152,7 +160,8 @@ pub(crate) async fn handle_pipelines(
     }
 }
 
-pub(crate) fn handle_pipelines_msg(
+/// Handles a single control message for an onramp
+pub fn handle_pipelines_msg(
     msg: onramp::Msg,
     pipelines: &mut Vec<(TremorURL, pipeline::Addr)>,
     metrics_reporter: &mut RampReporter,
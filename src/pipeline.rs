@@ -11,25 +11,39 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use crate::config::Parallelism;
 use crate::errors::{Error, Result};
+use crate::metrics::prometheus;
 use crate::offramp;
 use crate::registry::ServantId;
 use crate::repository::PipelineArtefact;
 use crate::url::TremorURL;
+use crate::utils::nanotime;
 use async_std::sync::channel;
 use async_std::task::{self, JoinHandle};
 use crossbeam_channel::{bounded, Sender as CbSender};
+use simd_json::borrowed::Object;
+use simd_json::prelude::*;
 use std::borrow::Cow;
 use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use tremor_pipeline::Event;
+use tremor_pipeline::{Event, ExecutableGraph, ERROR_PORT, ERR_OUTPUT, FN_REGISTRY};
+use tremor_script::prelude::*;
+use tremor_script::utils::{jump_hash, sorsorted_serialize};
+use tremor_script::{LineValue, Script};
 
 pub(crate) type Sender = async_std::sync::Sender<ManagerMsg>;
 
 /// Address for a a pipeline
 #[derive(Clone)]
 pub struct Addr {
-    pub(crate) addr: CbSender<Msg>,
+    // one sender per pipeline instance, there is always at least one
+    lanes: Arc<Vec<CbSender<Msg>>>,
+    partition: Arc<Partition>,
     pub(crate) id: ServantId,
 }
 
@@ -39,7 +53,101 @@ impl fmt::Debug for Addr {
     }
 }
 
-#[derive(Debug)]
+impl Addr {
+    /// Sends a message to the pipeline. Events and errors go to the
+    /// instance picked by the partitioning, everything else is sent to all
+    /// instances.
+    pub(crate) fn send(&self, msg: Msg) -> Result<()> {
+        let lanes = self.lanes.as_slice();
+        if let Msg::Event { input, event } = msg {
+            let lane = self.partition.lane(&event, lanes.len());
+            if let Some(lane) = lanes.get(lane) {
+                lane.send(Msg::Event { input, event })?;
+            }
+        } else if let Msg::Error(event) = msg {
+            let lane = self.partition.lane(&event, lanes.len());
+            if let Some(lane) = lanes.get(lane) {
+                lane.send(Msg::Error(event))?;
+            }
+        } else if let Some((last, rest)) = lanes.split_last() {
+            for lane in rest {
+                lane.send(msg.clone())?;
+            }
+            last.send(msg)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the artefact the pipeline runs, every instance switches over
+    /// once it processed all events queued before the replacement.
+    pub(crate) async fn replace(&self, artefact: PipelineArtefact) -> Result<()> {
+        let (tx, rx) = channel(self.lanes.len());
+        self.send(Msg::Replace(artefact, tx))?;
+        for _ in 0..self.lanes.len() {
+            rx.recv().await??;
+        }
+        Ok(())
+    }
+
+    /// Returns true if no instance has any pending messages
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lanes.iter().all(CbSender::is_empty)
+    }
+}
+
+/// How events are distributed over the instances of a pipeline
+pub(crate) enum Partition {
+    /// Each event goes to the next instance
+    RoundRobin(AtomicUsize),
+    /// Events are consistently hashed by the result of a script
+    Key(Script),
+}
+
+impl Partition {
+    fn new(parallelism: &Parallelism) -> Result<Self> {
+        if let Some(key) = &parallelism.key {
+            let script = Script::parse(
+                &tremor_script::path::load(),
+                "<partition>",
+                key.clone(),
+                &*FN_REGISTRY.lock()?,
+            )?;
+            Ok(Self::Key(script))
+        } else {
+            Ok(Self::RoundRobin(AtomicUsize::new(0)))
+        }
+    }
+
+    /// Picks the instance for an event, events whose key can't be evaluated
+    /// go to the first instance.
+    #[allow(clippy::cast_possible_truncation)]
+    fn lane(&self, event: &Event, lanes: usize) -> usize {
+        if lanes < 2 {
+            return 0;
+        }
+        match self {
+            Self::RoundRobin(next) => next.fetch_add(1, Ordering::Relaxed) % lanes,
+            Self::Key(script) => {
+                let context = EventContext::new(event.ingest_ns, event.origin_uri.clone());
+                let (unwind_event, event_meta) = event.data.parts();
+                let key = match script.run(
+                    &context,
+                    AggrType::Emit,
+                    unwind_event,       // event
+                    &mut Value::null(), // state
+                    event_meta,         // $
+                ) {
+                    Ok(Return::Emit { value, .. }) => sorsorted_serialize(&value).ok(),
+                    Ok(Return::EmitEvent { .. }) => sorsorted_serialize(unwind_event).ok(),
+                    Ok(Return::Drop) | Err(_) => None,
+                };
+                key.map_or(0, |key| jump_hash(&key, lanes as u32) as usize)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) enum Msg {
     Event {
         event: Event,
@@ -51,6 +159,10 @@ pub(crate) enum Msg {
     #[allow(dead_code)]
     Signal(Event),
     Insight(Event),
+    /// An event an offramp failed to deliver, it is sent to the `error` output
+    /// or the `err` output of queries
+    Error(Event),
+    Replace(PipelineArtefact, async_std::sync::Sender<Result<()>>),
 }
 
 #[derive(Debug)]
@@ -59,18 +171,162 @@ pub enum Dest {
     Pipeline(Addr),
 }
 impl Dest {
+    /// The input of the destination `id` an event arrives at, offramps get
+    /// the pipeline `origin` the event comes from so they can report back
+    /// to it.
+    fn input(&self, origin: &Cow<'static, str>, id: &TremorURL) -> Result<Cow<'static, str>> {
+        match self {
+            Self::Offramp(_) => Ok(origin.clone()),
+            Self::Pipeline(_) => id
+                .instance_port()
+                .map(Cow::from)
+                .ok_or_else(|| Error::from(format!("missing instance port in {}.", id))),
+        }
+    }
+
     pub fn send_event(&self, input: Cow<'static, str>, event: Event) -> Result<()> {
         match self {
             Self::Offramp(addr) => addr.send(offramp::Msg::Event { input, event })?,
-            Self::Pipeline(addr) => addr.addr.send(Msg::Event { input, event })?,
+            Self::Pipeline(addr) => addr.send(Msg::Event { input, event })?,
         }
         Ok(())
     }
 }
 
+/// Sums up the metrics of the instances of a parallel pipeline so they
+/// are reported as if they came from a single pipeline. Numeric fields
+/// are summed up over the last value each instance reported for the
+/// same measurement and tags.
+///
+/// Only one instance reports the sum per metrics interval, the metrics of
+/// the other instances are dropped after they were accounted for.
+#[derive(Debug)]
+struct MetricsAggregator {
+    instances: usize,
+    series: Mutex<halfbrown::HashMap<String, Series>>,
+}
+
+/// The last fields each instance reported for a metric and the timestamp
+/// the sum was last reported at
+#[derive(Debug)]
+struct Series {
+    latest: Vec<Option<Value<'static>>>,
+    reported: Option<u64>,
+}
+
+/// What an instance does with a metric it emitted
+#[derive(Debug, PartialEq)]
+enum Report {
+    /// Report the sum of all instances instead
+    Sum(Value<'static>),
+    /// Drop it, the sum was reported during this interval already
+    Skip,
+    /// Not a metric that can be summed up, it is passed on as is
+    Pass,
+}
+
+impl MetricsAggregator {
+    fn new(instances: usize) -> Self {
+        Self {
+            instances,
+            series: Mutex::new(halfbrown::HashMap::new()),
+        }
+    }
+
+    fn aggregate(
+        &self,
+        instance: usize,
+        interval: u64,
+        eventset: &mut Vec<(Cow<'static, str>, Event)>,
+    ) {
+        if !eventset.iter().any(|(output, _)| output == "metrics") {
+            return;
+        }
+        *eventset = eventset
+            .drain(..)
+            .filter_map(|(output, mut event)| {
+                if output == "metrics" {
+                    match self.update(instance, interval, event.data.suffix().value()) {
+                        Report::Sum(value) => {
+                            event.data = LineValue::new(vec![], |_| ValueAndMeta::from(value))
+                        }
+                        Report::Skip => return None,
+                        Report::Pass => (),
+                    }
+                }
+                Some((output, event))
+            })
+            .collect();
+    }
+
+    fn update(&self, instance: usize, interval: u64, metric: &Value) -> Report {
+        let key = metric.get("measurement").and_then(|measurement| {
+            let tags = sorsorted_serialize(metric.get("tags")?).ok()?;
+            Some(format!("{}{}", measurement.encode(), tags))
+        });
+        let (key, fields) = match (key, metric.get("fields")) {
+            (Some(key), Some(fields)) => (key, fields.clone_static()),
+            _ => return Report::Pass,
+        };
+        let timestamp = metric
+            .get("timestamp")
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+
+        let mut series = if let Ok(series) = self.series.lock() {
+            series
+        } else {
+            return Report::Pass;
+        };
+        let instances = self.instances;
+        let series = series.entry(key).or_insert_with(|| Series {
+            latest: vec![None; instances],
+            reported: None,
+        });
+        if let Some(latest) = series.latest.get_mut(instance) {
+            *latest = Some(fields);
+        } else {
+            return Report::Pass;
+        }
+        match series.reported {
+            Some(reported) if timestamp < reported.saturating_add(interval) => return Report::Skip,
+            _ => series.reported = Some(timestamp),
+        }
+
+        let mut summed = Object::new();
+        for (name, value) in series
+            .latest
+            .iter()
+            .flatten()
+            .filter_map(Value::as_object)
+            .flatten()
+        {
+            let sum = match summed.get(name) {
+                Some(sum) => {
+                    if let (Some(l), Some(r)) = (sum.as_u64(), value.as_u64()) {
+                        Value::from(l + r)
+                    } else if let (Some(l), Some(r)) = (sum.cast_f64(), value.cast_f64()) {
+                        Value::from(l + r)
+                    } else {
+                        value.clone()
+                    }
+                }
+                None => value.clone(),
+            };
+            summed.insert(name.clone(), sum);
+        }
+        let mut metric = metric.clone_static();
+        if let Some(metric) = metric.as_object_mut() {
+            metric.insert("fields".into(), Value::from(summed));
+        }
+        Report::Sum(metric)
+    }
+}
+
 pub struct Create {
     pub config: PipelineArtefact,
     pub id: ServantId,
+    pub parallelism: Parallelism,
 }
 
 pub(crate) enum ManagerMsg {
@@ -81,11 +337,68 @@ pub(crate) enum ManagerMsg {
 #[derive(Default, Debug)]
 pub(crate) struct Manager {
     qsize: usize,
+    storage_directory: Option<String>,
+}
+
+/// The file the state of a pipeline instance is persisted in, parallel
+/// pipelines keep one file per instance
+fn state_path(storage_directory: &str, id: &TremorURL, lane: Option<usize>) -> Option<PathBuf> {
+    let file = if let Some(lane) = lane {
+        format!("{}-{}-{}.json", id.artefact()?, id.instance()?, lane)
+    } else {
+        format!("{}-{}.json", id.artefact()?, id.instance()?)
+    };
+    Some(Path::new(storage_directory).join("state").join(file))
+}
+
+fn restore_state(pipeline: &mut ExecutableGraph, path: &Path) -> Result<()> {
+    let mut data = fs::read(path)?;
+    let snapshot = simd_json::to_borrowed_value(&mut data)?;
+    pipeline.restore(&snapshot)?;
+    Ok(())
+}
+
+/// Swaps the graph of a running pipeline for one built from a new artefact,
+/// the state of compatible nodes is carried over.
+fn replace_graph(pipeline: &mut ExecutableGraph, artefact: &PipelineArtefact) -> Result<()> {
+    let id = pipeline.id.clone();
+    let build = || -> Result<ExecutableGraph> {
+        let mut graph = artefact.to_executable_graph(tremor_pipeline::buildin_ops)?;
+        graph.id = id.clone();
+        Ok(graph)
+    };
+    let snapshot = pipeline.snapshot()?;
+    let mut next = build()?;
+    let failed = next.carry_over(&snapshot, &[]);
+    if !failed.is_empty() {
+        // Nodes that failed might be partially restored so we start over
+        // and leave them with their initial state
+        next = build()?;
+        next.carry_over(&snapshot, &failed);
+    }
+    *pipeline = next;
+    Ok(())
+}
+
+fn persist_state(pipeline: &ExecutableGraph, path: &Path) -> Result<()> {
+    let snapshot = pipeline.snapshot()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // We write to a temporary file first and move it in place so a crash
+    // while writing never leaves us with a truncated snapshot
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, snapshot.encode())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
 }
 
 impl Manager {
-    pub fn new(qsize: usize) -> Self {
-        Self { qsize }
+    pub fn new(qsize: usize, storage_directory: Option<String>) -> Self {
+        Self {
+            qsize,
+            storage_directory,
+        }
     }
     pub fn start(self) -> (JoinHandle<bool>, Sender) {
         let (tx, rx) = channel(64);
@@ -110,121 +423,368 @@ impl Manager {
         (h, tx)
     }
 
-    #[allow(clippy::too_many_lines)]
     fn start_pipeline(&self, req: Create) -> Result<Addr> {
+        let instances = req.parallelism.instances.max(1);
+        let partition = Partition::new(&req.parallelism)?;
+        let metrics = if instances > 1 {
+            info!(
+                "[Pipeline:{}] starting {} parallel instances.",
+                req.id, instances
+            );
+            Some(Arc::new(MetricsAggregator::new(instances)))
+        } else {
+            None
+        };
+        let lanes = (0..instances)
+            .map(|lane| {
+                let lane = if instances > 1 { Some(lane) } else { None };
+                self.start_instance(&req, lane, metrics.clone())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Addr {
+            id: req.id,
+            lanes: Arc::new(lanes),
+            partition: Arc::new(partition),
+        })
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn start_instance(
+        &self,
+        req: &Create,
+        lane: Option<usize>,
+        metrics: Option<Arc<MetricsAggregator>>,
+    ) -> Result<CbSender<Msg>> {
         #[inline]
         fn send_events(
             eventset: &mut Vec<(Cow<'static, str>, Event)>,
             dests: &halfbrown::HashMap<Cow<'static, str>, Vec<(TremorURL, Dest)>>,
+            origin: &Cow<'static, str>,
         ) -> Result<()> {
             for (output, event) in eventset.drain(..) {
                 if let Some(dest) = dests.get(&output) {
                     let len = dest.len();
                     //We know we have len, so grabbing len - 1 elementsis safe
                     for (id, offramp) in unsafe { dest.get_unchecked(..len - 1) } {
-                        offramp.send_event(
-                            id.instance_port()
-                                .ok_or_else(|| {
-                                    Error::from(format!("missing instance port in {}.", id))
-                                })?
-                                .clone()
-                                .into(),
-                            event.clone(),
-                        )?;
+                        offramp.send_event(offramp.input(origin, id)?, event.clone())?;
                     }
                     //We know we have len, so grabbing the last elementsis safe
                     let (id, offramp) = unsafe { dest.get_unchecked(len - 1) };
-                    offramp.send_event(
-                        id.instance_port()
-                            .ok_or_else(|| {
-                                Error::from(format!("missing instance port in {}.", id))
-                            })?
-                            .clone()
-                            .into(),
-                        event,
-                    )?;
+                    offramp.send_event(offramp.input(origin, id)?, event)?;
                 };
             }
             Ok(())
         }
-        let config = req.config;
         let id = req.id.clone();
         let mut dests: halfbrown::HashMap<Cow<'static, str>, Vec<(TremorURL, Dest)>> =
             halfbrown::HashMap::new();
         let mut eventset: Vec<(Cow<'static, str>, Event)> = Vec::new();
         let (tx, rx) = bounded::<Msg>(self.qsize);
-        let mut pipeline = config.to_executable_graph(tremor_pipeline::buildin_ops)?;
+        let mut pipeline = req
+            .config
+            .to_executable_graph(tremor_pipeline::buildin_ops)?;
         let mut pid = req.id.clone();
         pid.trim_to_instance();
         pipeline.id = pid.to_string();
-        thread::Builder::new()
-            .name(format!("pipeline-{}", id.clone()))
-            .spawn(move || {
-                info!("[Pipeline:{}] starting thread.", id);
-                for req in rx {
-                    match req {
-                        Msg::Event { input, event } => {
-                            match pipeline.enqueue(&input, event, &mut eventset) {
-                                Ok(()) => {
-                                    if let Err(e) = send_events(&mut eventset, &dests) {
-                                        error!("Failed to send event: {}", e)
-                                    }
-                                }
-                                Err(e) => error!("error: {:?}", e),
-                            }
-                        }
-                        Msg::Insight(insight) => {
-                            pipeline.contraflow(insight);
-                        }
-                        Msg::Signal(signal) => match pipeline.enqueue_signal(signal, &mut eventset)
-                        {
+        // State is only persisted if the pipeline asks for it and we have
+        // somewhere to put it
+        let state_path = self
+            .storage_directory
+            .as_ref()
+            .and_then(|storage_directory| state_path(storage_directory, &id, lane));
+        if let (Some(path), Some(_)) = (&state_path, pipeline.snapshot_interval()) {
+            if path.exists() {
+                info!("[Pipeline:{}] restoring state from {}", id, path.display());
+                if let Err(e) = restore_state(&mut pipeline, path) {
+                    error!("[Pipeline:{}] failed to restore state: {}", id, e);
+                }
+            }
+        }
+        let thread_name = if let Some(lane) = lane {
+            format!("pipeline-{}-{}", id, lane)
+        } else {
+            format!("pipeline-{}", id)
+        };
+        let stats = prometheus::register_pipeline(&id, lane);
+        let origin: Cow<'static, str> = id.to_string().into();
+        let lane = lane.unwrap_or_default();
+        thread::Builder::new().name(thread_name).spawn(move || {
+            info!("[Pipeline:{}] starting thread.", id);
+            let mut last_snapshot = nanotime();
+            for req in rx.iter() {
+                stats.queue_depth.store(rx.len() as u64, Ordering::Relaxed);
+                match req {
+                    Msg::Event { input, event } => {
+                        let start = nanotime();
+                        let res = pipeline.enqueue(&input, event, &mut eventset);
+                        stats.latency.observe(nanotime().saturating_sub(start));
+                        match res {
                             Ok(()) => {
-                                if let Err(e) = send_events(&mut eventset, &dests) {
+                                if let Some(metrics) = &metrics {
+                                    let interval = pipeline.metrics_interval().unwrap_or_default();
+                                    metrics.aggregate(lane, interval, &mut eventset);
+                                }
+                                prometheus::record_metrics(&eventset);
+                                if let Err(e) = send_events(&mut eventset, &dests, &origin) {
                                     error!("Failed to send event: {}", e)
                                 }
                             }
                             Err(e) => error!("error: {:?}", e),
-                        },
-
-                        Msg::ConnectOfframp(output, offramp_id, offramp) => {
-                            info!(
-                                "[Pipeline:{}] connecting {} to offramp {}",
-                                id, output, offramp_id
-                            );
-                            if let Some(offramps) = dests.get_mut(&output) {
-                                offramps.push((offramp_id, Dest::Offramp(offramp)));
-                            } else {
-                                dests.insert(output, vec![(offramp_id, Dest::Offramp(offramp))]);
-                            }
                         }
-                        Msg::ConnectPipeline(output, pipeline_id, pipeline) => {
-                            info!(
-                                "[Pipeline:{}] connecting {} to pipeline {}",
-                                id, output, pipeline_id
-                            );
-                            if let Some(offramps) = dests.get_mut(&output) {
-                                offramps.push((pipeline_id, Dest::Pipeline(pipeline)));
-                            } else {
-                                dests.insert(output, vec![(pipeline_id, Dest::Pipeline(pipeline))]);
-                            }
+                    }
+                    Msg::Insight(insight) => {
+                        pipeline.contraflow(insight);
+                    }
+                    Msg::Error(event) => {
+                        let port = if dests.contains_key(ERROR_PORT) {
+                            ERROR_PORT
+                        } else {
+                            ERR_OUTPUT
+                        };
+                        eventset.push((port.into(), event));
+                        if let Err(e) = send_events(&mut eventset, &dests, &origin) {
+                            error!("Failed to send error event: {}", e)
                         }
-                        Msg::Disconnect(output, to_delete) => {
-                            let mut remove = false;
-                            if let Some(offramp_vec) = dests.get_mut(&output) {
-                                offramp_vec.retain(|(this_id, _)| this_id != &to_delete);
-                                remove = offramp_vec.is_empty();
-                            }
-                            if remove {
-                                dests.remove(&output);
+                    }
+                    Msg::Signal(signal) => match pipeline.enqueue_signal(signal, &mut eventset) {
+                        Ok(()) => {
+                            if let Err(e) = send_events(&mut eventset, &dests, &origin) {
+                                error!("Failed to send event: {}", e)
                             }
                         }
-                    };
+                        Err(e) => error!("error: {:?}", e),
+                    },
+
+                    Msg::ConnectOfframp(output, offramp_id, offramp) => {
+                        info!(
+                            "[Pipeline:{}] connecting {} to offramp {}",
+                            id, output, offramp_id
+                        );
+                        if let Some(offramps) = dests.get_mut(&output) {
+                            offramps.push((offramp_id, Dest::Offramp(offramp)));
+                        } else {
+                            dests.insert(output, vec![(offramp_id, Dest::Offramp(offramp))]);
+                        }
+                    }
+                    Msg::ConnectPipeline(output, pipeline_id, pipeline) => {
+                        info!(
+                            "[Pipeline:{}] connecting {} to pipeline {}",
+                            id, output, pipeline_id
+                        );
+                        if let Some(offramps) = dests.get_mut(&output) {
+                            offramps.push((pipeline_id, Dest::Pipeline(pipeline)));
+                        } else {
+                            dests.insert(output, vec![(pipeline_id, Dest::Pipeline(pipeline))]);
+                        }
+                    }
+                    Msg::Disconnect(output, to_delete) => {
+                        let mut remove = false;
+                        if let Some(offramp_vec) = dests.get_mut(&output) {
+                            offramp_vec.retain(|(this_id, _)| this_id != &to_delete);
+                            remove = offramp_vec.is_empty();
+                        }
+                        if remove {
+                            dests.remove(&output);
+                        }
+                    }
+                    Msg::Replace(artefact, r) => {
+                        let res = replace_graph(&mut pipeline, &artefact);
+                        match &res {
+                            Ok(()) => info!("[Pipeline:{}] replaced pipeline.", id),
+                            Err(e) => error!("[Pipeline:{}] failed to replace: {}", id, e),
+                        }
+                        task::block_on(r.send(res));
+                    }
+                };
+                if let (Some(path), Some(ival)) = (&state_path, pipeline.snapshot_interval()) {
+                    let now = nanotime();
+                    if now - last_snapshot > ival {
+                        last_snapshot = now;
+                        if let Err(e) = persist_state(&pipeline, path) {
+                            error!("[Pipeline:{}] failed to persist state: {}", id, e);
+                        }
+                    }
                 }
-                info!("[Pipeline:{}] stopping thread.", id);
-            })?;
-        Ok(Addr {
-            id: req.id,
-            addr: tx,
-        })
+            }
+            if let (Some(path), Some(_)) = (&state_path, pipeline.snapshot_interval()) {
+                if let Err(e) = persist_state(&pipeline, path) {
+                    error!("[Pipeline:{}] failed to persist state: {}", id, e);
+                }
+            }
+            info!("[Pipeline:{}] stopping thread.", id);
+        })?;
+        Ok(tx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn test_event(data: Value<'static>) -> Event {
+        Event {
+            is_batch: false,
+            id: 0,
+            data: data.into(),
+            ingest_ns: 0,
+            origin_uri: None,
+            kind: None,
+        }
+    }
+
+    fn count(report: Report) -> Option<u64> {
+        match report {
+            Report::Sum(v) => v.get("fields")?.get("count")?.as_u64(),
+            _ => None,
+        }
+    }
+
+    fn metric(count: u64, node: &str, timestamp: u64) -> Value<'static> {
+        Value::from(json!({
+            "measurement": "events",
+            "tags": {"pipeline": "test", "node": node},
+            "fields": {"count": count},
+            "timestamp": timestamp
+        }))
+    }
+
+    fn stateful_graph() -> Result<ExecutableGraph> {
+        let config: tremor_pipeline::config::Pipeline = serde_yaml::from_str(
+            r#"
+id: test
+interface:
+  inputs: [ in ]
+  outputs: [ out ]
+nodes:
+  - id: count
+    op: generic::counter
+  - id: batch
+    op: generic::batch
+    config:
+      count: 3
+links:
+  in: [ count ]
+  count: [ batch ]
+  batch: [ out ]
+"#,
+        )?;
+        let artefact =
+            PipelineArtefact::Pipeline(Box::new(tremor_pipeline::build_pipeline(config)?));
+        artefact.to_executable_graph(tremor_pipeline::buildin_ops)
+    }
+
+    #[test]
+    fn persist_restore() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state").join("test-01.json");
+        let mut results = Vec::new();
+
+        let mut a = stateful_graph()?;
+        a.enqueue("in", test_event(Value::from("snot")), &mut results)?;
+        a.enqueue("in", test_event(Value::from("badger")), &mut results)?;
+        assert!(results.is_empty());
+        persist_state(&a, &path)?;
+
+        // both the count in the node state and the pending batch survive
+        let mut b = stateful_graph()?;
+        restore_state(&mut b, &path)?;
+        b.enqueue("in", test_event(Value::from("ferret")), &mut results)?;
+        assert_eq!(results.len(), 1);
+        let counts: Vec<_> = results[0]
+            .1
+            .value_iter()
+            .map(|v| v.get("count").and_then(Value::as_u64))
+            .collect();
+        assert_eq!(counts, vec![Some(1), Some(2), Some(3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn round_robin() -> Result<()> {
+        let p = Partition::new(&Parallelism::default())?;
+        let e = test_event(Value::null());
+        let lanes: Vec<_> = (0..4).map(|_| p.lane(&e, 3)).collect();
+        assert_eq!(lanes, vec![0, 1, 2, 0]);
+        // a single instance is always picked
+        assert_eq!(p.lane(&e, 1), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn key_partition() -> Result<()> {
+        let p = Partition::new(&Parallelism {
+            instances: 8,
+            key: Some("event.application".to_string()),
+        })?;
+        let e = test_event(Value::from(json!({"application": "app1", "other": 1})));
+        let lane = p.lane(&e, 8);
+        assert_eq!(lane, jump_hash(r#""app1""#, 8) as usize);
+        let e = test_event(Value::from(json!({"application": "app1", "other": 2})));
+        assert_eq!(p.lane(&e, 8), lane);
+        // events without a key go to the first instance
+        let e = test_event(Value::from(json!({"other": 2})));
+        assert_eq!(p.lane(&e, 8), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn inputs() -> Result<()> {
+        let origin: Cow<'static, str> = "tremor://localhost/pipeline/main/01".into();
+        let (offramp_tx, _offramp_rx) = bounded(1);
+        let offramp = Dest::Offramp(offramp_tx);
+        let (tx, _rx) = bounded(1);
+        let pipeline = Dest::Pipeline(Addr {
+            lanes: Arc::new(vec![tx]),
+            partition: Arc::new(Partition::new(&Parallelism::default())?),
+            id: TremorURL::parse("/pipeline/other/01")?,
+        });
+        // offramps learn which pipeline an event comes from
+        assert_eq!(
+            offramp.input(&origin, &TremorURL::parse("/offramp/es/01/in")?)?,
+            origin
+        );
+        assert_eq!(
+            pipeline.input(&origin, &TremorURL::parse("/pipeline/other/01/in")?)?,
+            "in"
+        );
+        assert!(pipeline
+            .input(&origin, &TremorURL::parse("/pipeline/other/01")?)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn metrics_aggregation() {
+        let m = MetricsAggregator::new(2);
+        assert_eq!(count(m.update(0, 0, &metric(3, "a", 0))), Some(3));
+        assert_eq!(count(m.update(1, 0, &metric(4, "a", 0))), Some(7));
+        assert_eq!(count(m.update(0, 0, &metric(5, "a", 0))), Some(9));
+        // different tags are a different series
+        assert_eq!(count(m.update(1, 0, &metric(1, "b", 0))), Some(1));
+        // not a metric so it is passed through as is
+        assert_eq!(m.update(0, 0, &Value::from("snot")), Report::Pass);
+    }
+
+    #[test]
+    fn metrics_are_reported_once() {
+        let m = MetricsAggregator::new(3);
+        // the first instance to emit in an interval reports the sum
+        assert_eq!(count(m.update(0, 10, &metric(1, "a", 100))), Some(1));
+        assert_eq!(m.update(1, 10, &metric(2, "a", 101)), Report::Skip);
+        assert_eq!(m.update(2, 10, &metric(3, "a", 105)), Report::Skip);
+        // the next interval is reported by whichever instance is first
+        assert_eq!(count(m.update(2, 10, &metric(4, "a", 111))), Some(7));
+        assert_eq!(m.update(0, 10, &metric(5, "a", 112)), Report::Skip);
+        assert_eq!(count(m.update(1, 10, &metric(6, "a", 121))), Some(15));
+
+        // skipped metrics are dropped from the events of an instance
+        let mut eventset = vec![
+            ("metrics".into(), test_event(metric(7, "a", 122))),
+            ("out".into(), test_event(Value::from("snot"))),
+        ];
+        m.aggregate(0, 10, &mut eventset);
+        assert_eq!(eventset.len(), 1);
+        assert_eq!(eventset[0].0, "out");
     }
 }
@@ -0,0 +1,555 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Plugins are `cdylib` crates depending on `tremor-runtime` that get loaded
+//! at startup from the directories given with `--plugin-directory`. A plugin
+//! exports a `Declaration` with `export_plugin!` and registers codecs, pre-
+//! and postprocessors, onramps, offramps, operators and functions through a
+//! `Registrar`:
+//!
+//! ```ignore
+//! use tremor_runtime::errors::Result;
+//! use tremor_runtime::plugin::Registrar;
+//!
+//! fn register(registrar: &mut dyn Registrar) -> Result<()> {
+//!     registrar.codec("snot", || Box::new(Snot {}))
+//! }
+//!
+//! tremor_runtime::export_plugin!(register);
+//! ```
+//!
+//! Rust has no stable ABI and plugins hand trait objects and heap
+//! allocated values back and forth with the runtime. So plugins have to be
+//! built with the same compiler and against the same version of tremor
+//! they're loaded into, `ABI_VERSION`, the tremor version and the rustc
+//! version are all checked before anything else in the plugin is touched.
+//! `export_plugin!` also makes the plugin allocate through the allocator of
+//! the runtime so memory can be freed on either side.
+//! Built in artefacts always take precedence over plugins of the same name.
+
+use crate::errors::{Error, Result};
+use halfbrown::HashMap;
+use libloading::{Library, Symbol};
+use std::alloc::{GlobalAlloc, Layout};
+use std::env::consts::DLL_EXTENSION;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::RwLock;
+use tremor_pipeline::FN_REGISTRY;
+
+pub use crate::codec::Codec;
+pub use crate::metrics::RampReporter;
+pub use crate::offramp::Offramp;
+pub use crate::onramp::prelude::{
+    handle_pipelines, handle_pipelines_msg, make_preprocessors, send_event, PipeHandlerResult,
+};
+pub use crate::onramp::{Addr as OnrampAddr, Msg as OnrampMsg, Onramp};
+pub use crate::pipeline::Addr as PipelineAddr;
+pub use crate::postprocessor::Postprocessor;
+pub use crate::preprocessor::{Preprocessor, Preprocessors};
+pub use tremor_pipeline::{InitializableOperator, NodeConfig, Operator};
+pub use tremor_script::registry::TremorFnWrapper;
+
+/// Version of the plugin interface, bumped on every breaking change to it
+pub const ABI_VERSION: u32 = 2;
+
+/// The tremor version as a C string
+#[doc(hidden)]
+pub const TREMOR_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+/// The version of the compiler tremor was built with as a C string
+#[doc(hidden)]
+pub const RUSTC_VERSION: &str = concat!(env!("TREMOR_RUSTC_VERSION"), "\0");
+
+/// Name of the symbol a plugin exports its `Declaration` as
+const DECLARATION: &[u8] = b"TREMOR_PLUGIN\0";
+
+/// Creates a codec
+pub type CodecCtor = fn() -> Box<dyn Codec>;
+/// Creates a preprocessor
+pub type PreprocessorCtor = fn() -> Box<dyn Preprocessor>;
+/// Creates a postprocessor
+pub type PostprocessorCtor = fn() -> Box<dyn Postprocessor>;
+/// Creates an onramp from its config
+pub type OnrampCtor = fn(&Option<serde_yaml::Value>) -> Result<Box<dyn Onramp>>;
+/// Creates an offramp from its config
+pub type OfframpCtor = fn(&Option<serde_yaml::Value>) -> Result<Box<dyn Offramp>>;
+
+/// The allocator of the runtime handed to plugins
+#[repr(C)]
+pub struct HostAllocator {
+    /// Allocates `size` bytes aligned to `align`
+    pub alloc: unsafe extern "C" fn(size: usize, align: usize) -> *mut u8,
+    /// Frees memory allocated with `alloc`
+    pub dealloc: unsafe extern "C" fn(ptr: *mut u8, size: usize, align: usize),
+    /// Resizes memory allocated with `alloc`
+    pub realloc:
+        unsafe extern "C" fn(ptr: *mut u8, size: usize, align: usize, new_size: usize) -> *mut u8,
+}
+
+unsafe extern "C" fn host_alloc(size: usize, align: usize) -> *mut u8 {
+    std::alloc::alloc(Layout::from_size_align_unchecked(size, align))
+}
+
+unsafe extern "C" fn host_dealloc(ptr: *mut u8, size: usize, align: usize) {
+    std::alloc::dealloc(ptr, Layout::from_size_align_unchecked(size, align))
+}
+
+unsafe extern "C" fn host_realloc(
+    ptr: *mut u8,
+    size: usize,
+    align: usize,
+    new_size: usize,
+) -> *mut u8 {
+    std::alloc::realloc(
+        ptr,
+        Layout::from_size_align_unchecked(size, align),
+        new_size,
+    )
+}
+
+static HOST_ALLOCATOR: HostAllocator = HostAllocator {
+    alloc: host_alloc,
+    dealloc: host_dealloc,
+    realloc: host_realloc,
+};
+
+/// The global allocator of a plugin, forwards to the allocator of the
+/// runtime the plugin got loaded into. Set up by `export_plugin!`.
+///
+/// Allocations fail until the runtime handed over its allocator, which it
+/// does before calling into the plugin for the first time.
+pub struct PluginAllocator;
+
+static PLUGIN_HOST: AtomicPtr<HostAllocator> = AtomicPtr::new(ptr::null_mut());
+
+/// Hands the allocator of the runtime to a plugin, used by `export_plugin!`
+///
+/// # Safety
+/// `host` has to be null or point to a `HostAllocator` living forever.
+#[doc(hidden)]
+pub unsafe extern "C" fn set_host_allocator(host: *const HostAllocator) {
+    PLUGIN_HOST.store(host as *mut HostAllocator, Ordering::SeqCst);
+}
+
+unsafe impl GlobalAlloc for PluginAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match PLUGIN_HOST.load(Ordering::SeqCst).as_ref() {
+            Some(host) => (host.alloc)(layout.size(), layout.align()),
+            None => ptr::null_mut(),
+        }
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(host) = PLUGIN_HOST.load(Ordering::SeqCst).as_ref() {
+            (host.dealloc)(ptr, layout.size(), layout.align())
+        }
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match PLUGIN_HOST.load(Ordering::SeqCst).as_ref() {
+            Some(host) => (host.realloc)(ptr, layout.size(), layout.align(), new_size),
+            None => ptr::null_mut(),
+        }
+    }
+}
+
+/// What a plugin exports, use `export_plugin!` to create it.
+///
+/// The layout is fixed so the versions can be read and checked before
+/// anything else, `abi_version` has to stay the first field.
+#[repr(C)]
+pub struct Declaration {
+    /// The `ABI_VERSION` the plugin was built with
+    pub abi_version: u32,
+    /// The tremor version the plugin was built against, a C string
+    pub tremor_version: *const c_char,
+    /// The version of rustc the plugin was built with, a C string
+    pub rustc_version: *const c_char,
+    /// Hands the allocator of the runtime to the plugin
+    pub set_allocator: unsafe extern "C" fn(*const HostAllocator),
+    /// Registers everything the plugin provides
+    pub register: fn(&mut dyn Registrar) -> Result<()>,
+}
+
+// The pointers only ever point to static strings
+unsafe impl Sync for Declaration {}
+
+/// Exports a plugin, takes a `fn(&mut dyn Registrar) -> Result<()>`.
+///
+/// This also sets the global allocator of the plugin.
+#[macro_export]
+macro_rules! export_plugin {
+    ($register:expr) => {
+        #[global_allocator]
+        static TREMOR_PLUGIN_ALLOCATOR: $crate::plugin::PluginAllocator =
+            $crate::plugin::PluginAllocator;
+
+        #[doc(hidden)]
+        #[no_mangle]
+        pub static TREMOR_PLUGIN: $crate::plugin::Declaration = $crate::plugin::Declaration {
+            abi_version: $crate::plugin::ABI_VERSION,
+            tremor_version: $crate::plugin::TREMOR_VERSION.as_ptr() as *const _,
+            rustc_version: $crate::plugin::RUSTC_VERSION.as_ptr() as *const _,
+            set_allocator: $crate::plugin::set_host_allocator,
+            register: $register,
+        };
+    };
+}
+
+/// Interface for plugins to register what they provide
+pub trait Registrar {
+    /// Registers a codec
+    fn codec(&mut self, name: &str, ctor: CodecCtor) -> Result<()>;
+    /// Registers a preprocessor
+    fn preprocessor(&mut self, name: &str, ctor: PreprocessorCtor) -> Result<()>;
+    /// Registers a postprocessor
+    fn postprocessor(&mut self, name: &str, ctor: PostprocessorCtor) -> Result<()>;
+    /// Registers an onramp
+    fn onramp(&mut self, name: &str, ctor: OnrampCtor) -> Result<()>;
+    /// Registers an offramp
+    fn offramp(&mut self, name: &str, ctor: OfframpCtor) -> Result<()>;
+    /// Registers an operator as `namespace::name`
+    fn operator(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        factory: Box<dyn InitializableOperator + Send + Sync>,
+    ) -> Result<()>;
+    /// Registers a tremor-script function, functions that are already
+    /// registered, including the standard library, can't be replaced
+    fn function(&mut self, function: TremorFnWrapper) -> Result<()>;
+}
+
+#[derive(Default)]
+struct Plugins {
+    codecs: HashMap<String, CodecCtor>,
+    preprocessors: HashMap<String, PreprocessorCtor>,
+    postprocessors: HashMap<String, PostprocessorCtor>,
+    onramps: HashMap<String, OnrampCtor>,
+    offramps: HashMap<String, OfframpCtor>,
+    // Libraries are never unloaded as the registered constructors point
+    // into them.
+    libraries: Vec<Library>,
+}
+
+lazy_static! {
+    static ref PLUGINS: RwLock<Plugins> = RwLock::new(Plugins::default());
+}
+
+fn insert<T>(map: &mut HashMap<String, T>, kind: &str, name: &str, value: T) -> Result<()> {
+    if map.contains_key(name) {
+        return Err(format!("{} {} is already registered", kind, name).into());
+    }
+    map.insert(name.to_string(), value);
+    Ok(())
+}
+
+struct Loader {
+    plugin: String,
+}
+
+impl Registrar for Loader {
+    fn codec(&mut self, name: &str, ctor: CodecCtor) -> Result<()> {
+        info!("[Plugin::{}] Registering codec {}", self.plugin, name);
+        insert(&mut PLUGINS.write()?.codecs, "Codec", name, ctor)
+    }
+    fn preprocessor(&mut self, name: &str, ctor: PreprocessorCtor) -> Result<()> {
+        info!(
+            "[Plugin::{}] Registering preprocessor {}",
+            self.plugin, name
+        );
+        insert(
+            &mut PLUGINS.write()?.preprocessors,
+            "Preprocessor",
+            name,
+            ctor,
+        )
+    }
+    fn postprocessor(&mut self, name: &str, ctor: PostprocessorCtor) -> Result<()> {
+        info!(
+            "[Plugin::{}] Registering postprocessor {}",
+            self.plugin, name
+        );
+        insert(
+            &mut PLUGINS.write()?.postprocessors,
+            "Postprocessor",
+            name,
+            ctor,
+        )
+    }
+    fn onramp(&mut self, name: &str, ctor: OnrampCtor) -> Result<()> {
+        info!("[Plugin::{}] Registering onramp {}", self.plugin, name);
+        insert(&mut PLUGINS.write()?.onramps, "Onramp", name, ctor)
+    }
+    fn offramp(&mut self, name: &str, ctor: OfframpCtor) -> Result<()> {
+        info!("[Plugin::{}] Registering offramp {}", self.plugin, name);
+        insert(&mut PLUGINS.write()?.offramps, "Offramp", name, ctor)
+    }
+    fn operator(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        factory: Box<dyn InitializableOperator + Send + Sync>,
+    ) -> Result<()> {
+        info!(
+            "[Plugin::{}] Registering operator {}::{}",
+            self.plugin, namespace, name
+        );
+        Ok(tremor_pipeline::register_operator(
+            namespace, name, factory,
+        )?)
+    }
+    fn function(&mut self, function: TremorFnWrapper) -> Result<()> {
+        info!(
+            "[Plugin::{}] Registering function {:?}",
+            self.plugin, function
+        );
+        let mut registry = FN_REGISTRY.lock()?;
+        if registry.find(function.module(), function.name()).is_ok() {
+            return Err(format!("Function {:?} is already registered", function).into());
+        }
+        registry.insert(function);
+        Ok(())
+    }
+}
+
+/// Reads a C string of a declaration
+unsafe fn c_str<'a>(s: *const c_char) -> std::borrow::Cow<'a, str> {
+    if s.is_null() {
+        "".into()
+    } else {
+        CStr::from_ptr(s).to_string_lossy()
+    }
+}
+
+/// Checks the versions of a declaration, only the `abi_version` is read
+/// until it is known to match.
+///
+/// # Safety
+/// `decl` has to point to a `u32` that is followed by a `Declaration` if it
+/// equals `ABI_VERSION`.
+unsafe fn check(decl: *const Declaration) -> Result<()> {
+    let abi_version = ptr::read(decl as *const u32);
+    if abi_version != ABI_VERSION {
+        return Err(format!(
+            "plugin ABI version {} doesn't match {}",
+            abi_version, ABI_VERSION
+        )
+        .into());
+    }
+    let tremor_version = c_str((*decl).tremor_version);
+    let expected = c_str(TREMOR_VERSION.as_ptr() as *const c_char);
+    if tremor_version != expected {
+        return Err(format!(
+            "plugin was built for tremor {} but this is tremor {}",
+            tremor_version, expected
+        )
+        .into());
+    }
+    let rustc_version = c_str((*decl).rustc_version);
+    let expected = c_str(RUSTC_VERSION.as_ptr() as *const c_char);
+    if rustc_version != expected {
+        return Err(format!(
+            "plugin was built with {} but tremor with {}",
+            rustc_version, expected
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Loads a single plugin
+pub fn load(path: &Path) -> Result<()> {
+    let plugin = path.display().to_string();
+    let lib = Library::new(path)?;
+    // This is inherently unsafe, we trust the library to export a
+    // `Declaration` under that name. `check` only reads the version in front
+    // of it until it knows it is a declaration it understands, after that
+    // the plugin gets our allocator before it is called for the first time.
+    let register = unsafe {
+        let decl: Symbol<*const Declaration> = lib.get(DECLARATION)?;
+        let decl: *const Declaration = *decl;
+        check(decl).map_err(|e| Error::from(format!("Failed to load {}: {}", plugin, e)))?;
+        ((*decl).set_allocator)(&HOST_ALLOCATOR);
+        (*decl).register
+    };
+    PLUGINS.write()?.libraries.push(lib);
+    info!("[Plugin::{}] Loading", plugin);
+    register(&mut Loader { plugin })
+}
+
+/// Loads all plugins in a directory, returns the number of plugins loaded.
+/// Plugins failing to load are logged and skipped.
+pub fn load_dir(dir: &Path) -> Result<usize> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map_or(false, |e| e == DLL_EXTENSION))
+        .collect();
+    paths.sort();
+    let mut loaded = 0;
+    for path in &paths {
+        match load(path) {
+            Ok(()) => loaded += 1,
+            Err(e) => error!("[Plugin::{}] Skipped: {}", path.display(), e),
+        }
+    }
+    Ok(loaded)
+}
+
+pub(crate) fn codec(name: &str) -> Option<Box<dyn Codec>> {
+    PLUGINS.read().ok()?.codecs.get(name).map(|ctor| ctor())
+}
+
+pub(crate) fn preprocessor(name: &str) -> Option<Box<dyn Preprocessor>> {
+    PLUGINS
+        .read()
+        .ok()?
+        .preprocessors
+        .get(name)
+        .map(|ctor| ctor())
+}
+
+pub(crate) fn postprocessor(name: &str) -> Option<Box<dyn Postprocessor>> {
+    PLUGINS
+        .read()
+        .ok()?
+        .postprocessors
+        .get(name)
+        .map(|ctor| ctor())
+}
+
+pub(crate) fn onramp(
+    name: &str,
+    config: &Option<serde_yaml::Value>,
+) -> Option<Result<Box<dyn Onramp>>> {
+    let ctor = *PLUGINS.read().ok()?.onramps.get(name)?;
+    Some(ctor(config))
+}
+
+pub(crate) fn offramp(
+    name: &str,
+    config: &Option<serde_yaml::Value>,
+) -> Option<Result<Box<dyn Offramp>>> {
+    let ctor = *PLUGINS.read().ok()?.offramps.get(name)?;
+    Some(ctor(config))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec;
+    use tremor_script::tremor_fn;
+
+    fn register(registrar: &mut dyn Registrar) -> Result<()> {
+        registrar.codec("plugin-test", || Box::new(codec::null::Null {}))
+    }
+
+    fn register_fn(registrar: &mut dyn Registrar) -> Result<()> {
+        registrar.function(tremor_fn!(plugin_test::answer(_context) {
+            Ok(Value::from(42))
+        }))
+    }
+
+    #[test]
+    fn registrar() -> Result<()> {
+        assert!(codec::lookup("plugin-test").is_err());
+        let mut loader = Loader {
+            plugin: "test".to_string(),
+        };
+        register(&mut loader)?;
+        assert!(codec::lookup("plugin-test").is_ok());
+        assert!(register(&mut loader).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn functions_are_not_overwritten() -> Result<()> {
+        let mut loader = Loader {
+            plugin: "test".to_string(),
+        };
+        register_fn(&mut loader)?;
+        assert!(register_fn(&mut loader).is_err());
+        assert!(loader
+            .function(tremor_fn!(string::len(_context) {
+                Ok(Value::from("forged"))
+            }))
+            .is_err());
+        Ok(())
+    }
+
+    unsafe extern "C" fn no_allocator(_host: *const HostAllocator) {}
+
+    fn declaration() -> Declaration {
+        Declaration {
+            abi_version: ABI_VERSION,
+            tremor_version: TREMOR_VERSION.as_ptr() as *const c_char,
+            rustc_version: RUSTC_VERSION.as_ptr() as *const c_char,
+            set_allocator: no_allocator,
+            register,
+        }
+    }
+
+    #[test]
+    fn versions() {
+        unsafe {
+            assert!(check(&declaration()).is_ok());
+            assert!(check(&Declaration {
+                abi_version: ABI_VERSION + 1,
+                ..declaration()
+            })
+            .is_err());
+            assert!(check(&Declaration {
+                tremor_version: b"0.0.0\0".as_ptr() as *const c_char,
+                ..declaration()
+            })
+            .is_err());
+            assert!(check(&Declaration {
+                rustc_version: b"rustc 0.0.0\0".as_ptr() as *const c_char,
+                ..declaration()
+            })
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn host_allocator() {
+        let layout = Layout::from_size_align(64, 8).expect("invalid layout");
+        unsafe {
+            assert!(PluginAllocator.alloc(layout).is_null());
+            set_host_allocator(&HOST_ALLOCATOR);
+            let p = PluginAllocator.alloc(layout);
+            assert!(!p.is_null());
+            let p = PluginAllocator.realloc(p, layout, 128);
+            assert!(!p.is_null());
+            // Memory allocated by the plugin can be freed by the runtime
+            std::alloc::dealloc(p, Layout::from_size_align(128, 8).expect("invalid layout"));
+            set_host_allocator(ptr::null());
+        }
+    }
+
+    #[test]
+    fn load_dir_skips_broken_plugins() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join(format!("broken.{}", DLL_EXTENSION)), "snot")?;
+        assert_eq!(0, load_dir(dir.path())?);
+        Ok(())
+    }
+
+    #[test]
+    fn not_a_plugin() {
+        assert!(load(Path::new("Cargo.toml")).is_err());
+    }
+}
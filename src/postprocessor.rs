@@ -21,7 +21,9 @@ use std::default::Default;
 pub type Postprocessors = Vec<Box<dyn Postprocessor>>;
 use std::io::Write;
 
+/// A postprocessor, turns encoded data into one or more pieces of data
 pub trait Postprocessor: Send {
+    /// Processes a piece of data
     fn process(&mut self, ingres_ns: u64, egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>>;
 }
 
@@ -39,7 +41,8 @@ pub fn lookup(name: &str) -> Result<Box<dyn Postprocessor>> {
         "ingest-ns" => Ok(Box::new(AttachIngresTS {})),
         "length-prefixed" => Ok(Box::new(LengthPrefix::default())),
         "gelf-chunking" => Ok(Box::new(GELF::default())),
-        _ => Err(format!("Postprocessor '{}' not found.", name).into()),
+        _ => crate::plugin::postprocessor(name)
+            .ok_or_else(|| format!("Postprocessor '{}' not found.", name).into()),
     }
 }
 
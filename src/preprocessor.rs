@@ -25,8 +25,11 @@ use std::io::{self, Read};
 
 //pub type Lines = lines::Lines;
 
+/// A chain of preprocessors
 pub type Preprocessors = Vec<Box<dyn Preprocessor>>;
+/// A preprocessor, turns raw data into one or more pieces of data
 pub trait Preprocessor: Sync + Send {
+    /// Processes a piece of data
     fn process(&mut self, ingest_ns: &mut u64, data: &[u8]) -> Result<Vec<Vec<u8>>>;
 }
 
@@ -52,7 +55,8 @@ pub fn lookup(name: &str) -> Result<Box<dyn Preprocessor>> {
         "gelf-chunking-tcp" => Ok(Box::new(GELF::tcp())),
         "ingest-ns" => Ok(Box::new(ExtractIngresTs {})),
         "length-prefixed" => Ok(Box::new(LengthPrefix::default())),
-        _ => Err(format!("Preprocessor '{}' not found.", name).into()),
+        _ => crate::plugin::preprocessor(name)
+            .ok_or_else(|| format!("Preprocessor '{}' not found.", name).into()),
     }
 }
 
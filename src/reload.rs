@@ -0,0 +1,461 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::{Binding, Config, MappingMap, OffRamp, OnRamp};
+use crate::errors::Result;
+use crate::repository::{BindingArtefact, PipelineArtefact};
+use crate::system::{check_replacement, World};
+use crate::url::{ResourceType, TremorURL};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::iter;
+use tremor_pipeline::query::Query;
+
+/// The artefacts defined by a single config or query file. Changes to the
+/// file are applied to a running system by diffing two versions of it.
+#[derive(Clone, Default)]
+pub struct Artefacts {
+    onramps: Vec<OnRamp>,
+    offramps: Vec<OffRamp>,
+    pipelines: Vec<(TremorURL, PipelineArtefact)>,
+    bindings: Vec<Binding>,
+    mappings: MappingMap,
+}
+
+impl Artefacts {
+    /// Validates a YAML config, this compiles all pipelines it contains
+    pub fn from_config(config: Config) -> Result<Self> {
+        let config = crate::incarnate(config)?;
+        let mut pipelines = Vec::new();
+        for pipeline in config.pipes {
+            let id = TremorURL::parse(&format!("/pipeline/{}", pipeline.id))?;
+            let pipeline = PipelineArtefact::Pipeline(Box::new(pipeline));
+            pipeline.to_executable_graph(tremor_pipeline::buildin_ops)?;
+            pipelines.push((id, pipeline));
+        }
+        Ok(Self {
+            onramps: config.onramps,
+            offramps: config.offramps,
+            pipelines,
+            bindings: config.bindings,
+            mappings: config.mappings,
+        })
+    }
+
+    /// Validates a trickle query that gets published as pipeline `id`
+    pub fn from_query(id: TremorURL, query: Query) -> Result<Self> {
+        let pipeline = PipelineArtefact::Query(query);
+        pipeline.to_executable_graph(tremor_pipeline::buildin_ops)?;
+        Ok(Self {
+            pipelines: vec![(id, pipeline)],
+            ..Self::default()
+        })
+    }
+}
+
+fn same<T: Serialize>(a: &T, b: &T) -> bool {
+    match (serde_yaml::to_value(a), serde_yaml::to_value(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn same_pipeline(a: &PipelineArtefact, b: &PipelineArtefact) -> bool {
+    match (a, b) {
+        (PipelineArtefact::Pipeline(a), PipelineArtefact::Pipeline(b)) => {
+            same(&a.config, &b.config)
+        }
+        (PipelineArtefact::Query(a), PipelineArtefact::Query(b)) => a.0.source == b.0.source,
+        _ => false,
+    }
+}
+
+/// Checks if a binding links any of the given artefacts
+fn references(binding: &Binding, artefacts: &[TremorURL]) -> bool {
+    binding
+        .links
+        .iter()
+        .flat_map(|(from, tos)| iter::once(from).chain(tos))
+        .any(|url| {
+            artefacts
+                .iter()
+                .any(|a| a.resource_type() == url.resource_type() && a.artefact() == url.artefact())
+        })
+}
+
+/// An artefact as it is stored in the repository
+enum Published {
+    Onramp(OnRamp),
+    Offramp(OffRamp),
+    Pipeline(PipelineArtefact),
+    Binding(BindingArtefact),
+}
+
+/// A change made to the running system, kept so it can be undone when a
+/// later step fails
+enum Change {
+    Published(TremorURL),
+    Unpublished(TremorURL, Published),
+    Replaced(TremorURL, PipelineArtefact),
+    Linked(TremorURL, HashMap<String, String>),
+    Unlinked(TremorURL, HashMap<String, String>),
+}
+
+async fn publish(world: &World, id: &TremorURL, artefact: Published) -> Result<()> {
+    info!("Publishing {}", id);
+    match artefact {
+        Published::Onramp(o) => world.repo.publish_onramp(id, false, o).await.map(|_| ()),
+        Published::Offramp(o) => world.repo.publish_offramp(id, false, o).await.map(|_| ()),
+        Published::Pipeline(p) => world.repo.publish_pipeline(id, false, p).await.map(|_| ()),
+        Published::Binding(b) => world.repo.publish_binding(id, false, b).await.map(|_| ()),
+    }
+}
+
+async fn unpublish(world: &World, id: &TremorURL) -> Result<Published> {
+    info!("Unpublishing {}", id);
+    match id.resource_type() {
+        Some(ResourceType::Onramp) => world.repo.unpublish_onramp(id).await.map(Published::Onramp),
+        Some(ResourceType::Offramp) => world
+            .repo
+            .unpublish_offramp(id)
+            .await
+            .map(Published::Offramp),
+        Some(ResourceType::Pipeline) => world
+            .repo
+            .unpublish_pipeline(id)
+            .await
+            .map(Published::Pipeline),
+        Some(ResourceType::Binding) => world
+            .repo
+            .unpublish_binding(id)
+            .await
+            .map(Published::Binding),
+        None => Err(format!("{} is not an artefact", id).into()),
+    }
+}
+
+async fn undo(world: &World, change: Change) -> Result<()> {
+    match change {
+        Change::Published(id) => unpublish(world, &id).await.map(|_| ()),
+        Change::Unpublished(id, artefact) => publish(world, &id, artefact).await,
+        Change::Replaced(id, artefact) => world.replace_pipeline(&id, artefact).await.map(|_| ()),
+        Change::Linked(instance, mapping) => {
+            world.unlink_binding(&instance, mapping).await.map(|_| ())
+        }
+        Change::Unlinked(instance, mapping) => {
+            world.link_binding(&instance, mapping).await.map(|_| ())
+        }
+    }
+}
+
+/// Applies the changes between two versions of a file to the running
+/// system. If any step fails everything done so far is undone again, so the
+/// system keeps running the old version.
+pub async fn apply(world: &World, old: &Artefacts, new: &Artefacts) -> Result<()> {
+    let mut journal = Vec::new();
+    let res = apply_changes(world, old, new, &mut journal).await;
+    if res.is_err() {
+        warn!("Rolling back {} changes", journal.len());
+        while let Some(change) = journal.pop() {
+            if let Err(e) = undo(world, change).await {
+                error!("Failed to roll back a change: {}", e);
+            }
+        }
+    }
+    res
+}
+
+/// New artefacts are published first and changed pipelines are replaced in
+/// place when possible. Bindings that use any other changed or removed
+/// artefact get unlinked, those artefacts are swapped or unpublished and
+/// the bindings are linked again afterwards.
+#[allow(clippy::too_many_lines)]
+async fn apply_changes(
+    world: &World,
+    old: &Artefacts,
+    new: &Artefacts,
+    journal: &mut Vec<Change>,
+) -> Result<()> {
+    let mut added: Vec<(TremorURL, Published)> = Vec::new();
+    let mut changed: Vec<(TremorURL, Published)> = Vec::new();
+    let mut replace: Vec<(TremorURL, PipelineArtefact)> = Vec::new();
+    let mut removed: Vec<TremorURL> = Vec::new();
+
+    for o in &new.offramps {
+        let id = TremorURL::parse(&format!("/offramp/{}", o.id))?;
+        let artefact = Published::Offramp(o.clone());
+        match world.repo.find_offramp(&id).await? {
+            Some(current) if !same(&current.artefact, o) => changed.push((id, artefact)),
+            Some(_) => (),
+            None => added.push((id, artefact)),
+        }
+    }
+    for o in &new.onramps {
+        let id = TremorURL::parse(&format!("/onramp/{}", o.id))?;
+        let artefact = Published::Onramp(o.clone());
+        match world.repo.find_onramp(&id).await? {
+            Some(current) if !same(&current.artefact, o) => changed.push((id, artefact)),
+            Some(_) => (),
+            None => added.push((id, artefact)),
+        }
+    }
+    for (id, p) in &new.pipelines {
+        match world.repo.find_pipeline(id).await? {
+            Some(current) if !same_pipeline(&current.artefact, p) => {
+                if check_replacement(id, &current.artefact, p).is_ok() {
+                    replace.push((id.clone(), p.clone()));
+                } else {
+                    changed.push((id.clone(), Published::Pipeline(p.clone())));
+                }
+            }
+            Some(_) => (),
+            None => added.push((id.clone(), Published::Pipeline(p.clone()))),
+        }
+    }
+    for b in &new.bindings {
+        let id = TremorURL::parse(&format!("/binding/{}", b.id))?;
+        let artefact = Published::Binding(BindingArtefact {
+            binding: b.clone(),
+            mapping: None,
+        });
+        match world.repo.find_binding(&id).await? {
+            Some(current) if !same(&current.artefact.binding, b) => changed.push((id, artefact)),
+            Some(_) => (),
+            None => added.push((id, artefact)),
+        }
+    }
+    // Whatever was in the old version but isn't in the new one any more,
+    // unless it got removed from the running system in the meantime
+    for o in &old.onramps {
+        let id = TremorURL::parse(&format!("/onramp/{}", o.id))?;
+        if !new.onramps.iter().any(|n| n.id == o.id) && world.repo.find_onramp(&id).await?.is_some()
+        {
+            removed.push(id);
+        }
+    }
+    for o in &old.offramps {
+        let id = TremorURL::parse(&format!("/offramp/{}", o.id))?;
+        if !new.offramps.iter().any(|n| n.id == o.id)
+            && world.repo.find_offramp(&id).await?.is_some()
+        {
+            removed.push(id);
+        }
+    }
+    for (id, _) in &old.pipelines {
+        if !new.pipelines.iter().any(|(n, _)| n == id)
+            && world.repo.find_pipeline(id).await?.is_some()
+        {
+            removed.push(id.clone());
+        }
+    }
+    for b in &old.bindings {
+        let id = TremorURL::parse(&format!("/binding/{}", b.id))?;
+        if !new.bindings.iter().any(|n| n.id == b.id)
+            && world.repo.find_binding(&id).await?.is_some()
+        {
+            removed.push(id);
+        }
+    }
+
+    for (id, artefact) in added {
+        publish(world, &id, artefact).await?;
+        journal.push(Change::Published(id));
+    }
+    for (id, p) in replace {
+        let previous = world.replace_pipeline(&id, p).await?;
+        journal.push(Change::Replaced(id, previous));
+    }
+
+    // Unlink every running binding instance that uses a stale artefact or
+    // whose mapping changed, instances this file doesn't own are linked
+    // again afterwards with the mapping they had
+    let stale: Vec<TremorURL> = changed
+        .iter()
+        .map(|(id, _)| id.clone())
+        .chain(removed.iter().cloned())
+        .collect();
+    let mut relink = MappingMap::new();
+    let running = world.reg.serialize_mappings().await;
+    for (instance, mapping) in &running {
+        let mut binding_id = instance.clone();
+        binding_id.trim_to_artefact();
+        let owned = old.mappings.contains_key(instance) || new.mappings.contains_key(instance);
+        let mapping_changed = owned && new.mappings.get(instance) != Some(mapping);
+        let uses_stale = stale.contains(&binding_id)
+            || world
+                .repo
+                .find_binding(&binding_id)
+                .await?
+                .map_or(false, |b| references(&b.artefact.binding, &stale));
+        if mapping_changed || uses_stale {
+            info!("Unlinking {}", instance);
+            world.unlink_binding(instance, mapping.clone()).await?;
+            journal.push(Change::Unlinked(instance.clone(), mapping.clone()));
+            if !owned {
+                relink.insert(instance.clone(), mapping.clone());
+            }
+        }
+    }
+
+    for (id, artefact) in changed {
+        let previous = unpublish(world, &id).await?;
+        journal.push(Change::Unpublished(id.clone(), previous));
+        publish(world, &id, artefact).await?;
+        journal.push(Change::Published(id));
+    }
+    for id in removed {
+        let previous = unpublish(world, &id).await?;
+        journal.push(Change::Unpublished(id, previous));
+    }
+
+    for (instance, mapping) in new.mappings.iter().chain(relink.iter()) {
+        if world.reg.find_binding(instance).await?.is_none() {
+            info!("Linking {}", instance);
+            world.link_binding(instance, mapping.clone()).await?;
+            journal.push(Change::Linked(instance.clone(), mapping.clone()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    macro_rules! b {
+        ($f:expr) => {
+            async_std::task::block_on($f)
+        };
+    }
+
+    fn slurp(file: &str) -> Artefacts {
+        let file = File::open(file).expect("could not open file");
+        let buffered_reader = BufReader::new(file);
+        let config = serde_yaml::from_reader(buffered_reader).expect("failed to parse config");
+        Artefacts::from_config(config).expect("invalid config")
+    }
+
+    #[test]
+    fn diff_helpers() -> Result<()> {
+        let artefacts = slurp("tests/configs/ut.passthrough.yaml");
+        let binding = &artefacts.bindings[0];
+        assert!(references(binding, &[TremorURL::parse("/onramp/blaster")?]));
+        assert!(references(binding, &[TremorURL::parse("/pipeline/main")?]));
+        assert!(!references(
+            binding,
+            &[TremorURL::parse("/pipeline/other")?]
+        ));
+        assert!(!references(
+            binding,
+            &[TremorURL::parse("/offramp/blaster")?]
+        ));
+
+        let (_, pipeline) = &artefacts.pipelines[0];
+        assert!(same_pipeline(pipeline, &pipeline.clone()));
+        let onramp = &artefacts.onramps[0];
+        assert!(same(onramp, &onramp.clone()));
+        let mut renamed = onramp.clone();
+        renamed.id = "renamed".into();
+        assert!(!same(onramp, &renamed));
+        Ok(())
+    }
+
+    /// Adds a binding that can never be linked, links have to go from
+    /// onramps to pipelines or from pipelines to offramps
+    fn broken(mut artefacts: Artefacts) -> Result<Artefacts> {
+        let binding: Binding = serde_yaml::from_str(
+            r#"
+id: broken
+links:
+  /offramp/blackhole/{instance}/out: [ /pipeline/main/{instance}/in ]
+"#,
+        )?;
+        artefacts.bindings.push(binding);
+        let mut mapping = HashMap::new();
+        mapping.insert("instance".to_string(), "01".to_string());
+        artefacts
+            .mappings
+            .insert(TremorURL::parse("/binding/broken/01")?, mapping);
+        Ok(artefacts)
+    }
+
+    #[test]
+    fn apply_publishes_and_removes() -> Result<()> {
+        let (world, _) = b!(World::start(10, None))?;
+        let empty = Artefacts::default();
+        let artefacts = slurp("tests/configs/ut.passthrough.yaml");
+        let pipeline = TremorURL::parse("/pipeline/main")?;
+        let onramp = TremorURL::parse("/onramp/blaster")?;
+        let binding = TremorURL::parse("/binding/default")?;
+
+        b!(apply(&world, &empty, &artefacts))?;
+        assert!(b!(world.repo.find_pipeline(&pipeline))?.is_some());
+        assert!(b!(world.repo.find_onramp(&onramp))?.is_some());
+        assert!(b!(world.repo.find_binding(&binding))?.is_some());
+
+        // applying the same version again changes nothing
+        b!(apply(&world, &artefacts, &artefacts))?;
+        assert!(b!(world.repo.find_pipeline(&pipeline))?.is_some());
+
+        b!(apply(&world, &artefacts, &empty))?;
+        assert!(b!(world.repo.find_pipeline(&pipeline))?.is_none());
+        assert!(b!(world.repo.find_onramp(&onramp))?.is_none());
+        assert!(b!(world.repo.find_binding(&binding))?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn apply_swaps_changed() -> Result<()> {
+        let (world, _) = b!(World::start(10, None))?;
+        let artefacts = slurp("tests/configs/ut.passthrough.yaml");
+        let mut changed = artefacts.clone();
+        changed.offramps[0].config = Some(serde_yaml::from_str("warmup_secs: 5")?);
+        let offramp = TremorURL::parse("/offramp/blackhole")?;
+
+        b!(apply(&world, &Artefacts::default(), &artefacts))?;
+        b!(apply(&world, &artefacts, &changed))?;
+        let current = b!(world.repo.find_offramp(&offramp))?.expect("offramp not published");
+        assert!(same(&current.artefact, &changed.offramps[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn apply_rolls_back() -> Result<()> {
+        let (world, _) = b!(World::start(10, None))?;
+        let artefacts = slurp("tests/configs/ut.passthrough.yaml");
+        let pipeline = TremorURL::parse("/pipeline/main")?;
+        let offramp = TremorURL::parse("/offramp/blackhole")?;
+        let binding = TremorURL::parse("/binding/broken")?;
+
+        // nothing of a new file stays published if it can't be linked
+        let bad = broken(artefacts.clone())?;
+        assert!(b!(apply(&world, &Artefacts::default(), &bad)).is_err());
+        assert!(b!(world.repo.find_pipeline(&pipeline))?.is_none());
+        assert!(b!(world.repo.find_binding(&binding))?.is_none());
+
+        // a failed change keeps the old version running
+        b!(apply(&world, &Artefacts::default(), &artefacts))?;
+        let mut changed = artefacts.clone();
+        changed.offramps[0].config = Some(serde_yaml::from_str("warmup_secs: 5")?);
+        let bad = broken(changed)?;
+        assert!(b!(apply(&world, &artefacts, &bad)).is_err());
+        let current = b!(world.repo.find_offramp(&offramp))?.expect("offramp not restored");
+        assert!(same(&current.artefact, &artefacts.offramps[0]));
+        assert!(b!(world.repo.find_pipeline(&pipeline))?.is_some());
+        assert!(b!(world.repo.find_binding(&binding))?.is_none());
+        Ok(())
+    }
+}
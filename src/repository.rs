@@ -112,6 +112,19 @@ impl<A: Artefact> Repository<A> {
         }
     }
 
+    /// Replaces a published artefact, the instances bound to it are kept
+    /// and the previous artefact is returned
+    pub fn replace(&mut self, mut id: ArtefactId, artefact: A) -> Result<A> {
+        id.trim_to_artefact();
+        match self.map.get_mut(&id) {
+            Some(w) if w.system => {
+                Err(ErrorKind::ReplaceFailedSystemArtefact(id.to_string()).into())
+            }
+            Some(w) => Ok(std::mem::replace(&mut w.artefact, artefact)),
+            None => Err(ErrorKind::ArtifactNotFound(id.to_string()).into()),
+        }
+    }
+
     /// Binds an artefact to a given servant
     pub fn bind(&mut self, mut id: ArtefactId, mut sid: ServantId) -> Result<&A> {
         id.trim_to_artefact();
@@ -146,6 +159,7 @@ pub(crate) enum Msg<A: Artefact> {
     FindArtefact(sync::Sender<Result<Option<RepoWrapper<A>>>>, ArtefactId),
     PublishArtefact(sync::Sender<Result<A>>, ArtefactId, bool, A),
     UnpublishArtefact(sync::Sender<Result<A>>, ArtefactId),
+    ReplaceArtefact(sync::Sender<Result<A>>, ArtefactId, A),
     RegisterInstance(sync::Sender<Result<A>>, ArtefactId, ServantId),
     UnregisterInstance(sync::Sender<Result<A>>, ArtefactId, ServantId),
 }
@@ -174,6 +188,10 @@ impl<A: Artefact + Send + Sync + 'static> Repository<A> {
                         r.send(A::artefact_id(&id).and_then(|id| self.unpublish(id)))
                             .await
                     }
+                    Ok(Msg::ReplaceArtefact(r, id, a)) => {
+                        r.send(A::artefact_id(&id).and_then(|id| self.replace(id, a)))
+                            .await
+                    }
                     Ok(Msg::RegisterInstance(r, a_id, s_id)) => {
                         r.send(
                             A::artefact_id(&a_id)
@@ -281,6 +299,19 @@ impl Repositories {
         rx.recv().await?
     }
 
+    /// Replace a published pipeline, returns the previous artefact
+    pub async fn replace_pipeline(
+        &self,
+        id: &TremorURL,
+        artefact: PipelineArtefact,
+    ) -> Result<PipelineArtefact> {
+        let (tx, rx) = channel(1);
+        self.pipeline
+            .send(Msg::ReplaceArtefact(tx, id.clone(), artefact))
+            .await;
+        rx.recv().await?
+    }
+
     /// Bind a pipeline
     pub async fn bind_pipeline(&self, id: &TremorURL) -> Result<PipelineArtefact> {
         let (tx, rx) = channel(1);
@@ -529,4 +560,34 @@ mod test {
             Some(Error(ErrorKind::PublishFailedAlreadyExists { .. }, _))
         );
     }
+
+    #[test]
+    fn test_pipeline_repo_replace() {
+        let config = slurp("tests/configs/ut.passthrough.yaml");
+        let mut runtime = incarnate(config).expect("failed to incarnate");
+        let pipeline = runtime.pipes.pop().expect("failed to find artefact");
+        let mut repo: Repository<PipelineArtefact> = Repository::new();
+        let id = TremorURL::parse("/pipeline/test").expect("failed to parse id");
+        let sid = TremorURL::parse("/pipeline/test/01").expect("failed to parse id");
+        assert_matches!(
+            repo.replace(id.clone(), pipeline.clone().into()).err(),
+            Some(Error(ErrorKind::ArtifactNotFound { .. }, _))
+        );
+        assert!(repo
+            .publish(id.clone(), false, pipeline.clone().into())
+            .is_ok());
+        assert!(repo.bind(id.clone(), sid).is_ok());
+        assert!(repo.replace(id.clone(), pipeline.clone().into()).is_ok());
+        // instances survive the replacement
+        assert_eq!(repo.find(id).map(|w| w.instances.len()), Some(1));
+
+        let id = TremorURL::parse("/pipeline/system").expect("failed to parse id");
+        assert!(repo
+            .publish(id.clone(), true, pipeline.clone().into())
+            .is_ok());
+        assert_matches!(
+            repo.replace(id, pipeline.into()).err(),
+            Some(Error(ErrorKind::ReplaceFailedSystemArtefact { .. }, _))
+        );
+    }
 }
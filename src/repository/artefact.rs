@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use crate::codec;
+use crate::config::Parallelism;
 use crate::errors::{Error, Result};
 use crate::metrics::RampReporter;
 use crate::offramp;
@@ -22,6 +23,7 @@ use crate::registry::ServantId;
 use crate::system::{self, World};
 use crate::url::{ResourceType, TremorURL};
 use hashbrown::HashMap;
+use std::path::Path;
 use tremor_pipeline::query;
 pub(crate) type Id = TremorURL;
 pub(crate) use crate::OffRamp as OfframpArtefact;
@@ -40,7 +42,7 @@ pub struct Binding {
 }
 
 /// A Pipeline
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Pipeline {
     /// A normal pipeline
     Pipeline(Box<tremor_pipeline::Pipeline>),
@@ -112,7 +114,9 @@ impl Artefact for Pipeline {
 
     //    type Configuration = tremor_pipeline::Pipeline;
     async fn spawn(&self, world: &World, servant_id: ServantId) -> Result<Self::SpawnResult> {
-        world.start_pipeline(self.clone(), servant_id).await
+        world
+            .start_pipeline(self.clone(), servant_id, &Parallelism::default())
+            .await
     }
 
     async fn link(
@@ -129,8 +133,6 @@ impl Artefact for Pipeline {
                     Some(ResourceType::Offramp) => {
                         if let Some(offramp) = system.reg.find_offramp(&to).await? {
                             pipeline
-                                .addr
-                                .clone()
                                 .send(pipeline::Msg::ConnectOfframp(
                                     from.clone().into(),
                                     to.clone(),
@@ -147,8 +149,6 @@ impl Artefact for Pipeline {
                         info!("[Pipeline:{}] Linking port {} to {}", id, from, to);
                         if let Some(p) = system.reg.find_pipeline(&to).await? {
                             pipeline
-                                .addr
-                                .clone()
                                 .send(pipeline::Msg::ConnectPipeline(
                                     from.clone().into(),
                                     to.clone(),
@@ -183,13 +183,11 @@ impl Artefact for Pipeline {
                 match to.resource_type() {
                     Some(ResourceType::Offramp) => {
                         pipeline
-                            .addr
                             .send(pipeline::Msg::Disconnect(from.clone().into(), to))
                             .map_err(|_e| Error::from("Failed to unlink pipeline"))?;
                     }
                     Some(ResourceType::Pipeline) => {
                         pipeline
-                            .addr
                             .send(pipeline::Msg::Disconnect(from.clone().into(), to))
                             .map_err(|_e| Error::from("Failed to unlink pipeline"))?;
                     }
@@ -242,6 +240,35 @@ impl Artefact for OfframpArtefact {
             vec![]
         };
         let metrics_reporter = RampReporter::new(servant_id.clone(), self.metrics_interval_s);
+        let queue = if let Some(queue) = &self.queue {
+            let base = match (&queue.path, world.storage_directory()) {
+                (Some(path), _) => Path::new(path).to_path_buf(),
+                (None, Some(storage_directory)) => Path::new(storage_directory).join("queue"),
+                (None, None) => {
+                    return Err(format!(
+                        "The queue of {} needs a path or a storage directory",
+                        servant_id
+                    )
+                    .into())
+                }
+            };
+            let dir = base.join(format!(
+                "{}-{}",
+                servant_id.artefact().unwrap_or_default(),
+                servant_id.instance().unwrap_or_default()
+            ));
+            Some(offramp::wal::Wal::open(
+                dir,
+                queue.max_bytes,
+                queue.segment_bytes,
+            )?)
+        } else {
+            None
+        };
+        let breaker = self
+            .circuit_breaker
+            .clone()
+            .map(|c| offramp::breaker::Breaker::new(c, queue.is_some()));
 
         let (tx, rx) = channel(1);
 
@@ -255,6 +282,8 @@ impl Artefact for OfframpArtefact {
                     offramp,
                     postprocessors,
                     metrics_reporter,
+                    queue,
+                    breaker,
                 },
             ))
             .await;
@@ -441,6 +470,19 @@ impl Artefact for OnrampArtefact {
     }
 }
 
+impl Binding {
+    /// The parallelism the binding configures for a pipeline
+    fn parallelism(&self, id: &TremorURL) -> Parallelism {
+        let mut id = id.clone();
+        id.trim_to_artefact();
+        self.binding
+            .parallelism
+            .get(&id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
 #[async_trait]
 impl Artefact for Binding {
     type SpawnResult = Self;
@@ -526,7 +568,9 @@ impl Artefact for Binding {
                 Some(ResourceType::Pipeline) => {
                     if system.reg.find_pipeline(&to).await?.is_none() {
                         info!("Pipeline not found during binding process, binding {} to create a new instance.", &to);
-                        system.bind_pipeline(&to).await?;
+                        system
+                            .bind_parallel_pipeline(&to, &self.parallelism(&to))
+                            .await?;
                     } else {
                         info!("Existing pipeline {} found", to);
                     }
@@ -538,7 +582,9 @@ impl Artefact for Binding {
                     "Pipeline (src) not found during binding process, binding {} to create a new instance.",
                     from
                 );
-                system.bind_pipeline(&from).await?;
+                system
+                    .bind_parallel_pipeline(&from, &self.parallelism(&from))
+                    .await?;
             }
             system
                 .link_pipeline(
@@ -571,7 +617,9 @@ impl Artefact for Binding {
         for (from, to) in onramps {
             if system.reg.find_pipeline(&to).await?.is_none() {
                 info!("Pipeline (dst) not found during binding process, binding {} to create a new instance.", to);
-                system.bind_pipeline(&to).await?;
+                system
+                    .bind_parallel_pipeline(&to, &self.parallelism(&to))
+                    .await?;
             }
             if system.reg.find_onramp(&from).await?.is_none() {
                 info!(
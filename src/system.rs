@@ -12,9 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::config::{BindingVec, Config, MappingMap, OffRampVec, OnRampVec, PipelineVec};
-use crate::errors::{Error, Result};
+use crate::config::{
+    BindingVec, Config, MappingMap, OffRampVec, OnRampVec, Parallelism, PipelineVec,
+};
+use crate::errors::{Error, ErrorKind, Result};
 use crate::lifecycle::{ActivationState, ActivatorLifecycleFsm};
+use crate::metrics::prometheus;
 use crate::registry::{Registries, ServantId};
 use crate::repository::{
     Artefact, BindingArtefact, OfframpArtefact, OnrampArtefact, PipelineArtefact, Repositories,
@@ -120,18 +123,56 @@ pub struct World {
     storage_directory: Option<String>,
 }
 
+/// Compiles both artefacts to validate the replacement and makes sure it
+/// provides all inputs and outputs of the current pipeline
+pub(crate) fn check_replacement(
+    id: &TremorURL,
+    current: &PipelineArtefact,
+    replacement: &PipelineArtefact,
+) -> Result<()> {
+    let old_graph = current.to_executable_graph(tremor_pipeline::buildin_ops)?;
+    let new_graph = replacement.to_executable_graph(tremor_pipeline::buildin_ops)?;
+    let incompatible = |reason: String| -> Error {
+        ErrorKind::ReplaceFailedIncompatible(id.to_string(), reason).into()
+    };
+    for input in old_graph.input_names() {
+        if !new_graph.input_names().any(|i| i == input) {
+            return Err(incompatible(format!("input {} is missing", input)));
+        }
+    }
+    for output in old_graph.output_names() {
+        if !new_graph.output_names().any(|o| o == output) {
+            return Err(incompatible(format!("output {} is missing", output)));
+        }
+    }
+    Ok(())
+}
+
 impl World {
     /// Bind a pipeline
     pub async fn bind_pipeline(&self, id: &TremorURL) -> Result<ActivationState> {
+        self.bind_parallel_pipeline(id, &Parallelism::default())
+            .await
+    }
+
+    /// Bind a pipeline running multiple instances side by side
+    pub async fn bind_parallel_pipeline(
+        &self,
+        id: &TremorURL,
+        parallelism: &Parallelism,
+    ) -> Result<ActivationState> {
         info!("Binding pipeline {}", id);
         match (&self.repo.find_pipeline(id).await?, &id.instance()) {
             (Some(artefact), Some(_instance_id)) => {
-                let servant = ActivatorLifecycleFsm::new(
+                let addr = self
+                    .start_pipeline(artefact.artefact.to_owned(), id.clone(), parallelism)
+                    .await?;
+                let servant = ActivatorLifecycleFsm::spawned(
                     self.clone(),
                     artefact.artefact.to_owned(),
                     id.clone(),
-                )
-                .await?;
+                    addr,
+                );
                 self.repo.bind_pipeline(id).await?;
                 // We link to the metrics pipeline
                 let res = self.reg.publish_pipeline(id, servant).await?;
@@ -155,6 +196,7 @@ impl World {
             (Some(_artefact), Some(_instance_id)) => {
                 let r = self.reg.unpublish_pipeline(id).await?;
                 self.repo.unbind_pipeline(id).await?;
+                prometheus::unregister(id);
                 Ok(r)
             }
             (None, _) => Err(format!("Artefact not found: {}", id).into()),
@@ -162,6 +204,67 @@ impl World {
         }
     }
 
+    /// Replaces a published pipeline. The new artefact has to provide all
+    /// inputs and outputs of the current one, running instances switch over
+    /// in place and stay connected to their onramps and offramps. The state
+    /// of nodes with the same id and operator is carried over.
+    pub async fn replace_pipeline(
+        &self,
+        id: &TremorURL,
+        artefact: PipelineArtefact,
+    ) -> Result<PipelineArtefact> {
+        info!("Replacing pipeline {}", id);
+        let current = self
+            .repo
+            .find_pipeline(id)
+            .await?
+            .ok_or_else(|| Error::from(ErrorKind::ArtifactNotFound(id.to_string())))?;
+        check_replacement(id, &current.artefact, &artefact)?;
+        // The repository only gets the new artefact once every running
+        // instance switched over, if any step fails the instances that
+        // already did are switched back
+        let mut switched = Vec::new();
+        let res = match self
+            .switch_instances(&current.instances, &artefact, &mut switched)
+            .await
+        {
+            Ok(()) => self.repo.replace_pipeline(id, artefact).await,
+            Err(e) => Err(e),
+        };
+        if res.is_err() {
+            for addr in switched {
+                if let Err(e) = addr.replace(current.artefact.clone()).await {
+                    error!("Failed to restore pipeline {}: {}", id, e);
+                }
+            }
+        }
+        res
+    }
+
+    /// Switches running pipeline instances over to a new artefact, every
+    /// instance that was touched is added to `switched`
+    async fn switch_instances(
+        &self,
+        instances: &[ServantId],
+        artefact: &PipelineArtefact,
+        switched: &mut Vec<pipeline::Addr>,
+    ) -> Result<()> {
+        for instance in instances {
+            if let Some(addr) = self.reg.find_pipeline(instance).await? {
+                // Some lanes of a failed instance might have switched
+                // already, so it gets restored as well
+                switched.push(addr.clone());
+                addr.replace(artefact.clone()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Directory runtime state is stored in, if any
+    pub(crate) fn storage_directory(&self) -> Option<&str> {
+        self.storage_directory.as_deref()
+    }
+
     /// Stop the runtime
     pub async fn stop(&self) {
         self.system.send(ManagerMsg::Stop).await;
@@ -265,6 +368,7 @@ impl World {
             (Some(_artefact), Some(_instsance_id)) => {
                 let r = self.reg.unpublish_onramp(id).await;
                 self.repo.unbind_onramp(id).await?;
+                prometheus::unregister(id);
                 r
             }
             (None, _) => Err(format!("Artefact not found: {}", id).into()),
@@ -358,6 +462,7 @@ impl World {
             (Some(_artefact), Some(_instsance_id)) => {
                 let r = self.reg.unpublish_offramp(id).await;
                 self.repo.unbind_offramp(id).await?;
+                prometheus::unregister(id);
                 r
             }
             (None, _) => Err(format!("Artefact not found: {}", id).into()),
@@ -554,7 +659,8 @@ impl World {
     ) -> Result<(Self, JoinHandle<()>)> {
         let (onramp_h, onramp) = onramp::Manager::new(qsize).start();
         let (offramp_h, offramp) = offramp::Manager::new(qsize).start();
-        let (pipeline_h, pipeline) = pipeline::Manager::new(qsize).start();
+        let (pipeline_h, pipeline) =
+            pipeline::Manager::new(qsize, storage_directory.clone()).start();
 
         let (system_h, system) = Manager {
             offramp,
@@ -644,12 +750,17 @@ type: stderr
         &self,
         config: PipelineArtefact,
         id: ServantId,
+        parallelism: &Parallelism,
     ) -> Result<pipeline::Addr> {
         let (tx, rx) = channel(1);
         self.system
             .send(ManagerMsg::CreatePipeline(
                 tx,
-                pipeline::Create { id, config },
+                pipeline::Create {
+                    id,
+                    config,
+                    parallelism: parallelism.clone(),
+                },
             ))
             .await;
         rx.recv().await?
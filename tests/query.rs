@@ -93,6 +93,7 @@ macro_rules! test_cases {
 }
 
 test_cases!(
+    dedup_operator,
     default_rule,
     dimensions,
     example_rule,
@@ -107,6 +108,7 @@ test_cases!(
     merge,
     multi_dimensions,
     mutate,
+    order_by_limit,
     passthrough,
     patch,
     rewrite_root,
@@ -128,5 +130,6 @@ test_cases!(
     pp_alias_script,
     pp_alias_operator,
     pp_config_directive,
+    pp_aggregate,
     //INSERT
 );
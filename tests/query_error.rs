@@ -119,12 +119,14 @@ macro_rules! test_cases {
 }
 
 test_cases!(
+    aggregate_missing_emit,
     const_in_select,
     let_in_select,
     local_in_having,
     local_in_select,
     local_in_where,
     local_in_group_by,
+    order_by_without_window,
     pp_unrecognized_token,
     pp_unrecognized_token2,
     pp_unrecognized_token3,
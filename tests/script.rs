@@ -88,6 +88,7 @@ test_cases!(
     binary,
     bit_ops,
     bit_shift,
+    bytes,
     cidr_multi,
     cidr,
     const_fn,
@@ -106,9 +107,12 @@ test_cases!(
     influx,
     json,
     jump,
+    keyword_idents,
     kv,
+    lambda,
     let_field,
     logical,
+    match_table,
     merge,
     multi_case,
     multiline,
@@ -129,6 +133,7 @@ test_cases!(
     string_interpolation,
     subslice,
     subslice_repeated,
+    try_catch,
     unary,
     // preprocessor
     pp_nest0,
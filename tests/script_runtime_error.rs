@@ -138,6 +138,7 @@ test_cases!(
     bad_bitshift,
     bad_index_type,
     bad_unary,
+    forged_fn_value,
     function_error_1,
     function_error_2,
     function_error_3,
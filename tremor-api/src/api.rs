@@ -21,6 +21,7 @@ use tremor_runtime::system::World;
 use tremor_runtime::url::TremorURL;
 
 pub mod binding;
+pub mod metrics;
 pub mod offramp;
 pub mod onramp;
 pub mod pipeline;
@@ -181,6 +182,14 @@ impl From<TremorError> for Error {
                 StatusCode::Forbidden,
                 r#"{"error": "System Artefacts can not be unpublished"}"#.into(),
             ),
+            ErrorKind::ReplaceFailedSystemArtefact(_) => Error::JSON(
+                StatusCode::Forbidden,
+                r#"{"error": "System Artefacts can not be replaced"}"#.into(),
+            ),
+            ErrorKind::ReplaceFailedIncompatible(_, reason) => Error::generic(
+                StatusCode::Conflict,
+                &format!("Replacement is not compatible: {}", reason),
+            ),
             _e => Error::JSON(
                 StatusCode::InternalServerError,
                 r#"{"error": "Internal server error"}"#.into(),
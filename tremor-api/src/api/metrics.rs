@@ -0,0 +1,23 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::prelude::*;
+use tremor_runtime::metrics::prometheus;
+
+pub async fn get(_req: Request) -> Result<Response> {
+    let mut r = Response::new(StatusCode::Ok);
+    r.insert_header(headers::CONTENT_TYPE, "text/plain; version=0.0.4");
+    r.set_body(prometheus::render());
+    Ok(r)
+}
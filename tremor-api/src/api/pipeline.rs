@@ -13,6 +13,8 @@
 // limitations under the License.
 
 use crate::api::prelude::*;
+use tremor_pipeline::query::Query;
+use tremor_pipeline::FN_REGISTRY;
 use tremor_runtime::repository::PipelineArtefact;
 
 #[derive(Serialize)]
@@ -51,6 +53,52 @@ pub async fn publish_artefact(req: Request) -> Result<Response> {
     reply(req, result, true, StatusCode::NoContent).await
 }
 
+pub async fn replace_artefact(mut req: Request) -> Result<Response> {
+    let id: String = req.param("aid").unwrap_or_default();
+    let url = build_url(&["pipeline", &id])?;
+    let is_trickle = req
+        .header(&headers::CONTENT_TYPE)
+        .map(headers::HeaderValues::last)
+        .map(headers::HeaderValue::as_str)
+        == Some("application/vnd.trickle");
+    let (req, artefact) = if is_trickle {
+        let raw = req.body_string().await?;
+        let aggr_reg = tremor_script::registry::aggr();
+        let module_path = tremor_script::path::load();
+        let query = Query::parse(
+            &module_path,
+            &raw,
+            &id,
+            vec![],
+            &*FN_REGISTRY
+                .lock()
+                .map_err(|e| Error::generic(StatusCode::InternalServerError, &e.to_string()))?,
+            &aggr_reg,
+        )
+        .map_err(|e| {
+            Error::generic(
+                StatusCode::BadRequest,
+                &format!("Query error: {}", e.error()),
+            )
+        })?;
+        (req, PipelineArtefact::Query(query))
+    } else {
+        let (req, decoded_data): (_, tremor_pipeline::config::Pipeline) = decode(req).await?;
+        if decoded_data.id != id {
+            return Err(Error::json(
+                StatusCode::BadRequest,
+                &r#"{"error": "Pipeline id does not match the artefact"}"#,
+            ));
+        }
+        let pipeline = tremor_pipeline::build_pipeline(decoded_data)?;
+        (req, PipelineArtefact::Pipeline(Box::new(pipeline)))
+    };
+
+    let world = &req.state().world;
+    world.replace_pipeline(&url, artefact).await?;
+    reply(req, (), true, StatusCode::NoContent).await
+}
+
 pub async fn unpublish_artefact(req: Request) -> Result<Response> {
     let id: String = req.param("aid").unwrap_or_default();
     let url = build_url(&["pipeline", &id])?;
@@ -0,0 +1,37 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![allow(deprecated)]
+#![allow(missing_docs)]
+
+use error_chain::error_chain;
+
+impl From<crossbeam_channel::SendError<lsp_server::Message>> for Error {
+    fn from(e: crossbeam_channel::SendError<lsp_server::Message>) -> Self {
+        Self::from(format!("Send Error: {:?}", e))
+    }
+}
+
+impl From<lsp_server::ProtocolError> for Error {
+    fn from(e: lsp_server::ProtocolError) -> Self {
+        Self::from(format!("Protocol Error: {:?}", e))
+    }
+}
+
+error_chain! {
+    foreign_links {
+        Io(std::io::Error);
+        JSONError(serde_json::Error);
+    }
+}
@@ -0,0 +1,496 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The language features of the server, independent of the protocol.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use tremor_script::highlighter::{Error as Callout, ErrorLevel};
+use tremor_script::lexer::{Token, TokenSpan, Tokenizer};
+use tremor_script::path::ModulePath;
+use tremor_script::pos::Location;
+use tremor_script::{registry, Query, Script};
+
+/// Token types of semantic tokens, a token's kind is an index into this
+pub const TOKEN_TYPES: [&str; 7] = [
+    "keyword", "operator", "string", "number", "comment", "variable", "function",
+];
+
+/// The language of a document
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    /// tremor-script, `.tremor` files
+    Script,
+    /// trickle, `.trickle` files
+    Query,
+}
+
+impl Language {
+    /// The language of a file by its extension
+    pub fn of(path: &str) -> Option<Self> {
+        if path.ends_with(".tremor") {
+            Some(Self::Script)
+        } else if path.ends_with(".trickle") {
+            Some(Self::Query)
+        } else {
+            None
+        }
+    }
+}
+
+/// A position in a document, zero based like in the protocol
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pos {
+    /// The line
+    pub line: usize,
+    /// The column
+    pub column: usize,
+}
+
+impl Pos {
+    /// Creates a position
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+impl From<Location> for Pos {
+    fn from(location: Location) -> Self {
+        Self {
+            line: location.line.saturating_sub(1),
+            column: location.column.saturating_sub(1),
+        }
+    }
+}
+
+/// An error or warning in a document
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    /// Where the problem starts
+    pub start: Pos,
+    /// Where the problem ends
+    pub end: Pos,
+    /// What the problem is
+    pub message: String,
+    /// If the problem is an error rather than a warning
+    pub is_error: bool,
+}
+
+impl From<&Callout> for Problem {
+    fn from(callout: &Callout) -> Self {
+        // Problems in included files are shown at the start of the document
+        let (start, end) = if callout.start().unit_id == 0 {
+            (callout.start().into(), callout.end().into())
+        } else {
+            (Pos::default(), Pos::default())
+        };
+        let message = if let Some(hint) = callout.hint() {
+            format!("{}\nNOTE: {}", callout.callout(), hint)
+        } else {
+            callout.callout().clone()
+        };
+        Self {
+            start,
+            end,
+            message,
+            is_error: matches!(callout.level(), ErrorLevel::Error),
+        }
+    }
+}
+
+/// What a completion completes to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    /// A module
+    Module,
+    /// A function
+    Function,
+    /// A constant
+    Constant,
+}
+
+/// A possible completion
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Completion {
+    /// The text to insert
+    pub label: String,
+    /// What the completion is
+    pub kind: Kind,
+    /// The signature of functions
+    pub detail: Option<String>,
+}
+
+/// A token of a document for semantic highlighting
+#[derive(Clone, Debug, PartialEq)]
+pub struct SemanticToken {
+    /// Where the token starts
+    pub start: Pos,
+    /// Length of the token in characters
+    pub length: usize,
+    /// Index of the type of the token in `TOKEN_TYPES`
+    pub kind: usize,
+}
+
+/// The documentation of a module
+struct ModuleDocs {
+    module: Option<String>,
+    /// Functions and constants by their name, with signatures of functions
+    items: Vec<(String, Kind, Option<String>, String)>,
+}
+
+/// Compiles a document and returns its errors and warnings
+pub fn check(language: Language, file: &str, text: &str, module_path: &ModulePath) -> Vec<Problem> {
+    let reg = registry::registry();
+    let warnings = match language {
+        Language::Script => {
+            Script::parse(module_path, file, text.to_string(), &reg).map(|s| s.warnings().clone())
+        }
+        Language::Query => Query::parse(module_path, file, text, vec![], &reg, &registry::aggr())
+            .map(|q| q.warnings),
+    };
+    match warnings {
+        Ok(warnings) => warnings
+            .iter()
+            .map(|w| Problem::from(&Callout::from(w)))
+            .collect(),
+        Err(e) => vec![Problem::from(&Callout::from(&e))],
+    }
+}
+
+/// The documentation of the module function or constant at a position
+pub fn hover(text: &str, pos: Pos, module_path: &ModulePath) -> Option<String> {
+    let path = path_at(text, pos)?;
+    let mut segments: Vec<&str> = path.split("::").collect();
+    let name = segments.pop()?;
+    if let Some(docs) = module_docs(module_path, &segments) {
+        if let Some((_, _, _, doc)) = docs.items.into_iter().find(|item| item.0 == name) {
+            return Some(doc);
+        }
+    }
+    // It might be a module itself
+    segments.push(name);
+    module_docs(module_path, &segments)?.module
+}
+
+/// Completes the module path in front of a position with the modules below
+/// it and the functions and constants in it
+pub fn complete(text: &str, pos: Pos, module_path: &ModulePath) -> Vec<Completion> {
+    let prefix = prefix_at(text, pos);
+    let (module, partial) = match prefix.rfind("::") {
+        Some(i) => (&prefix[..i], &prefix[i + 2..]),
+        None => ("", prefix.as_str()),
+    };
+    let segments: Vec<&str> = module.split("::").filter(|s| !s.is_empty()).collect();
+    let mut completions = Vec::new();
+    let dir = segments.join("/");
+    // std modules can be used without their `std::` prefix
+    for dir in &[PathBuf::from(&dir), Path::new("std").join(&dir)] {
+        for mount in &module_path.mounts {
+            let entries = if let Ok(entries) = fs::read_dir(Path::new(mount).join(dir)) {
+                entries
+            } else {
+                continue;
+            };
+            for path in entries.filter_map(Result::ok).map(|e| e.path()) {
+                let is_module = path.is_dir()
+                    || path
+                        .extension()
+                        .map_or(false, |e| e == "tremor" || e == "trickle");
+                if let (true, Some(name)) = (is_module, path.file_stem()) {
+                    completions.push(Completion {
+                        label: name.to_string_lossy().to_string(),
+                        kind: Kind::Module,
+                        detail: None,
+                    });
+                }
+            }
+        }
+    }
+    if let Some(docs) = module_docs(module_path, &segments) {
+        for (label, kind, detail, _) in docs.items {
+            completions.push(Completion {
+                label,
+                kind,
+                detail,
+            });
+        }
+    }
+    completions.retain(|c| c.label.starts_with(partial));
+    completions.sort();
+    completions.dedup();
+    completions
+}
+
+/// Where the function, constant, window, script or operator at a position
+/// is defined in the document
+pub fn definition(text: &str, pos: Pos) -> Option<(Pos, Pos)> {
+    let path = path_at(text, pos)?;
+    let name = path.rsplit("::").next()?;
+    definitions(text)
+        .into_iter()
+        .find(|(n, _, _)| n == name)
+        .map(|(_, start, end)| (start, end))
+}
+
+/// The tokens of a document for semantic highlighting
+pub fn semantic_tokens(text: &str) -> Vec<SemanticToken> {
+    let tokens: Vec<TokenSpan> = Tokenizer::new(text).filter_map(Result::ok).collect();
+    let mut semantic = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let (start, end) = (token.span.start, token.span.end);
+        // Tokens spanning multiple lines, like here docs, are left to the
+        // editor
+        if start.line != end.line {
+            continue;
+        }
+        let kind = match &token.value {
+            Token::SingleLineComment(_) | Token::ModComment(_) | Token::DocComment(_) => 4,
+            Token::Ident(_, _) => {
+                let next = tokens[i + 1..].iter().find(|t| !t.value.is_ignorable());
+                if next.map_or(false, |t| t.value == Token::LParen) {
+                    6
+                } else {
+                    5
+                }
+            }
+            t if t.is_keyword() => 0,
+            t if t.is_string_like() => 2,
+            t if t.is_literal() => 3,
+            t if t.is_operator() => 1,
+            _ => continue,
+        };
+        semantic.push(SemanticToken {
+            start: start.into(),
+            length: end.column.saturating_sub(start.column),
+            kind,
+        });
+    }
+    semantic
+}
+
+/// The names declared by `fn`, `const` and `define` statements with their
+/// location
+fn definitions(text: &str) -> Vec<(String, Pos, Pos)> {
+    let tokens: Vec<TokenSpan> = Tokenizer::new(text)
+        .filter_map(Result::ok)
+        .filter(|t| !t.value.is_ignorable())
+        .collect();
+    let mut definitions = Vec::new();
+    let mut in_define = false;
+    for (i, token) in tokens.iter().enumerate() {
+        let name = match &token.value {
+            Token::Define => {
+                in_define = true;
+                continue;
+            }
+            Token::Ident(name, _) => name,
+            _ => continue,
+        };
+        let before = i.checked_sub(1).map(|i| &tokens[i].value);
+        let after = tokens.get(i + 1).map(|t| &t.value);
+        let declares = match before {
+            // `fn name` without arguments refers to a function
+            Some(Token::Fun) => after == Some(&Token::LParen),
+            Some(Token::Const) => true,
+            Some(Token::Window) | Some(Token::Script) | Some(Token::Operator) => in_define,
+            _ => false,
+        };
+        if declares {
+            in_define = false;
+            definitions.push((
+                name.to_string(),
+                token.span.start.into(),
+                token.span.end.into(),
+            ));
+        }
+    }
+    definitions
+}
+
+/// Finds the file of a module, std modules can be referred to without their
+/// `std::` prefix
+fn module_file(module_path: &ModulePath, segments: &[&str]) -> Option<PathBuf> {
+    if segments.is_empty() {
+        return None;
+    }
+    let module = segments.join("/");
+    let candidates = [
+        format!("{}.tremor", module),
+        format!("std/{}.tremor", module),
+        format!("{}.trickle", module),
+    ];
+    candidates
+        .iter()
+        .find_map(|file| module_path.resolve(file))
+        .map(PathBuf::from)
+}
+
+fn module_docs(module_path: &ModulePath, segments: &[&str]) -> Option<ModuleDocs> {
+    let file = module_file(module_path, segments)?;
+    let source = fs::read_to_string(&file).ok()?;
+    let script = Script::parse(
+        module_path,
+        &file.to_string_lossy(),
+        source,
+        &registry::registry(),
+    )
+    .ok()?;
+    let docs = script.docs();
+    let module = segments.join("::");
+    let mut items = Vec::new();
+    for f in &docs.fns {
+        let signature = format!("{}::{}({})", module, f.name, f.args.join(", "));
+        items.push((
+            f.name.to_string(),
+            Kind::Function,
+            Some(signature),
+            f.to_string().trim().to_string(),
+        ));
+    }
+    for c in &docs.consts {
+        items.push((
+            c.name.to_string(),
+            Kind::Constant,
+            None,
+            c.to_string().trim().to_string(),
+        ));
+    }
+    Some(ModuleDocs {
+        module: docs
+            .module
+            .as_ref()
+            .map(|m| m.print_with_name(&module).trim().to_string()),
+        items,
+    })
+}
+
+fn path_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == ':'
+}
+
+/// The part of a module path in front of a position
+fn prefix_at(text: &str, pos: Pos) -> String {
+    let line: Vec<char> = text.lines().nth(pos.line).unwrap_or("").chars().collect();
+    let end = pos.column.min(line.len());
+    let start = end
+        - line[..end]
+            .iter()
+            .rev()
+            .take_while(|c| path_char(**c))
+            .count();
+    line[start..end].iter().collect()
+}
+
+/// The module path a position is in
+fn path_at(text: &str, pos: Pos) -> Option<String> {
+    let line: Vec<char> = text.lines().nth(pos.line)?.chars().collect();
+    let mut path = prefix_at(text, pos);
+    let start = pos.column.min(line.len());
+    path.extend(line[start..].iter().take_while(|c| path_char(**c)));
+    let path = path.trim_matches(':');
+    if path.is_empty() {
+        None
+    } else {
+        Some(path.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn module_path() -> ModulePath {
+        ModulePath {
+            mounts: vec![format!(
+                "{}/../tremor-script/lib",
+                env!("CARGO_MANIFEST_DIR")
+            )],
+        }
+    }
+
+    #[test]
+    fn paths() {
+        let text = "let x = string::format(\"{}\", 1);";
+        assert_eq!(
+            path_at(text, Pos::new(0, 10)),
+            Some("string::format".into())
+        );
+        assert_eq!(prefix_at(text, Pos::new(0, 10)), "st");
+        assert_eq!(path_at(text, Pos::new(0, 7)), None);
+        assert_eq!(path_at(text, Pos::new(3, 0)), None);
+    }
+
+    #[test]
+    fn problems() {
+        let mp = module_path();
+        assert!(check(Language::Script, "test.tremor", "let x = 1;\nx", &mp).is_empty());
+        let problems = check(Language::Script, "test.tremor", "let x = ;", &mp);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].is_error);
+        assert_eq!(problems[0].start.line, 0);
+    }
+
+    #[test]
+    fn hovers() {
+        let mp = module_path();
+        let text = "string::format(\"{}\", 1)";
+        let doc = hover(text, Pos::new(0, 10), &mp).expect("no docs for string::format");
+        assert!(doc.starts_with("### format("));
+        assert!(hover(text, Pos::new(0, 20), &mp).is_none());
+    }
+
+    #[test]
+    fn completions() {
+        let mp = module_path();
+        let modules = complete("std::str", Pos::new(0, 8), &mp);
+        assert!(modules
+            .iter()
+            .any(|c| c.label == "string" && c.kind == Kind::Module));
+        assert!(modules.iter().all(|c| c.label.starts_with("str")));
+        let fns = complete("string::for", Pos::new(0, 11), &mp);
+        assert_eq!(
+            fns.into_iter().map(|c| c.label).collect::<Vec<_>>(),
+            vec!["format".to_string()]
+        );
+    }
+
+    #[test]
+    fn definitions() {
+        let text =
+            "fn double(x) with\n  x * 2\nend;\nconst two = 2;\nlet f = fn double;\ndouble(two)";
+        assert_eq!(
+            definition(text, Pos::new(5, 1)),
+            Some((Pos::new(0, 3), Pos::new(0, 9)))
+        );
+        assert_eq!(
+            definition(text, Pos::new(5, 8)),
+            Some((Pos::new(3, 6), Pos::new(3, 9)))
+        );
+        let query = "define tumbling window fifteen_secs with interval = 15 end;";
+        assert_eq!(
+            super::definitions(query),
+            vec![("fifteen_secs".to_string(), Pos::new(0, 23), Pos::new(0, 35))]
+        );
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens = semantic_tokens("let x = f(1); # snot");
+        let kinds: Vec<usize> = tokens.iter().map(|t| t.kind).collect();
+        // let, x, =, f, 1, comment
+        assert_eq!(kinds, vec![0, 5, 1, 6, 3, 4]);
+        assert_eq!(tokens[3].start, Pos::new(0, 8));
+        assert_eq!(tokens[3].length, 1);
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A language server for tremor-script and trickle speaking LSP over
+//! stdio.
+
+// This isn't a external crate so we don't worry about docs
+// #![deny(missing_docs)]
+#![forbid(warnings)]
+#![recursion_limit = "1024"]
+#![deny(
+    clippy::all,
+    clippy::result_unwrap_used,
+    clippy::option_unwrap_used,
+    clippy::unnecessary_unwrap,
+    clippy::pedantic
+)]
+#![allow(clippy::must_use_candidate)]
+
+#[macro_use]
+extern crate serde_derive;
+
+mod errors;
+mod language;
+
+use crate::errors::Result;
+use crate::language::{Kind, Language, Pos, TOKEN_TYPES};
+use halfbrown::HashMap;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionResponse, Diagnostic, DiagnosticSeverity,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    GotoDefinitionResponse, Hover, HoverContents, Location, MarkupContent, MarkupKind, Position,
+    Range, TextDocumentIdentifier, TextDocumentPositionParams, Url,
+};
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use tremor_script::path::{load as load_module_path, ModulePath};
+
+/// Parameters of requests only about a document
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentParams {
+    text_document: TextDocumentIdentifier,
+}
+
+struct Server {
+    module_path: ModulePath,
+    /// The text of the open documents
+    documents: HashMap<Url, String>,
+}
+
+fn position(pos: Pos) -> Position {
+    Position::new(pos.line as u64, pos.column as u64)
+}
+
+fn pos(position: Position) -> Pos {
+    Pos::new(
+        usize::try_from(position.line).unwrap_or(usize::max_value()),
+        usize::try_from(position.character).unwrap_or(usize::max_value()),
+    )
+}
+
+/// The file name of a document as far as error messages and includes are
+/// concerned
+fn file_name(uri: &Url) -> String {
+    uri.to_file_path().map_or_else(
+        |_| uri.path().to_string(),
+        |p| p.to_string_lossy().to_string(),
+    )
+}
+
+impl Server {
+    fn new() -> Self {
+        Self {
+            module_path: load_module_path(),
+            documents: HashMap::new(),
+        }
+    }
+
+    fn run(&mut self, connection: &Connection) -> Result<()> {
+        for message in &connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    connection
+                        .sender
+                        .send(Message::Response(self.request(request)))?;
+                }
+                Message::Notification(notification) => {
+                    if let Some(diagnostics) = self.notification(notification)? {
+                        connection.sender.send(Message::Notification(diagnostics))?;
+                    }
+                }
+                Message::Response(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn request(&self, request: Request) -> Response {
+        let Request { id, method, params } = request;
+        let result = match method.as_str() {
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/semanticTokens/full" => self.semantic_tokens(params),
+            _ => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported request: {}", method),
+                )
+            }
+        };
+        match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    /// Keeps track of the open documents, returns the diagnostics to
+    /// publish for the changed one
+    fn notification(&mut self, notification: Notification) -> Result<Option<Notification>> {
+        let Notification { method, params } = notification;
+        let (uri, text) = match method.as_str() {
+            "textDocument/didOpen" => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(params)?;
+                (params.text_document.uri, Some(params.text_document.text))
+            }
+            "textDocument/didChange" => {
+                // We ask for the full text on every change
+                let params: DidChangeTextDocumentParams = serde_json::from_value(params)?;
+                let text = params.content_changes.into_iter().last().map(|c| c.text);
+                (params.text_document.uri, text)
+            }
+            "textDocument/didClose" => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(params)?;
+                (params.text_document.uri, None)
+            }
+            _ => return Ok(None),
+        };
+        let diagnostics = if let Some(text) = text {
+            let diagnostics = self.diagnostics(&uri, &text);
+            self.documents.insert(uri.clone(), text);
+            diagnostics
+        } else {
+            // Closed documents have their diagnostics cleared
+            self.documents.remove(&uri);
+            Vec::new()
+        };
+        Ok(Some(Notification::new(
+            "textDocument/publishDiagnostics".to_string(),
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )))
+    }
+
+    fn diagnostics(&self, uri: &Url, text: &str) -> Vec<Diagnostic> {
+        let file = file_name(uri);
+        let language = if let Some(language) = Language::of(&file) {
+            language
+        } else {
+            return Vec::new();
+        };
+        language::check(language, &file, text, &self.module_path)
+            .into_iter()
+            .map(|problem| Diagnostic {
+                range: Range::new(position(problem.start), position(problem.end)),
+                severity: Some(if problem.is_error {
+                    DiagnosticSeverity::Error
+                } else {
+                    DiagnosticSeverity::Warning
+                }),
+                source: Some("tremor".to_string()),
+                message: problem.message,
+                ..Diagnostic::default()
+            })
+            .collect()
+    }
+
+    /// The text of a document and a position in it
+    fn document(&self, params: Value) -> Result<(&str, TextDocumentPositionParams)> {
+        let params: TextDocumentPositionParams = serde_json::from_value(params)?;
+        let text = self
+            .documents
+            .get(&params.text_document.uri)
+            .ok_or_else(|| format!("Unknown document: {}", params.text_document.uri))?;
+        Ok((text, params))
+    }
+
+    fn hover(&self, params: Value) -> Result<Value> {
+        let (text, params) = self.document(params)?;
+        let hover =
+            language::hover(text, pos(params.position), &self.module_path).map(|doc| Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: doc,
+                }),
+                range: None,
+            });
+        Ok(serde_json::to_value(hover)?)
+    }
+
+    fn completion(&self, params: Value) -> Result<Value> {
+        let (text, params) = self.document(params)?;
+        let items = language::complete(text, pos(params.position), &self.module_path)
+            .into_iter()
+            .map(|c| CompletionItem {
+                label: c.label,
+                kind: Some(match c.kind {
+                    Kind::Module => CompletionItemKind::Module,
+                    Kind::Function => CompletionItemKind::Function,
+                    Kind::Constant => CompletionItemKind::Constant,
+                }),
+                detail: c.detail,
+                ..CompletionItem::default()
+            })
+            .collect();
+        Ok(serde_json::to_value(CompletionResponse::Array(items))?)
+    }
+
+    fn definition(&self, params: Value) -> Result<Value> {
+        let (text, params) = self.document(params)?;
+        let uri = params.text_document.uri;
+        let definition = language::definition(text, pos(params.position)).map(|(start, end)| {
+            GotoDefinitionResponse::Scalar(Location::new(
+                uri,
+                Range::new(position(start), position(end)),
+            ))
+        });
+        Ok(serde_json::to_value(definition)?)
+    }
+
+    fn semantic_tokens(&self, params: Value) -> Result<Value> {
+        let params: DocumentParams = serde_json::from_value(params)?;
+        let text = self
+            .documents
+            .get(&params.text_document.uri)
+            .ok_or_else(|| format!("Unknown document: {}", params.text_document.uri))?;
+        // Tokens are encoded relative to the one before them
+        let mut data = Vec::new();
+        let mut last = Pos::default();
+        for token in language::semantic_tokens(text) {
+            let line = token.start.line.saturating_sub(last.line);
+            let column = if line == 0 {
+                token.start.column.saturating_sub(last.column)
+            } else {
+                token.start.column
+            };
+            data.extend_from_slice(&[line, column, token.length, token.kind, 0]);
+            last = token.start;
+        }
+        Ok(json!({ "data": data }))
+    }
+}
+
+fn main() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = json!({
+        // We always get the full text of a document
+        "textDocumentSync": 1,
+        "hoverProvider": true,
+        "completionProvider": { "triggerCharacters": [":"] },
+        "definitionProvider": true,
+        "semanticTokensProvider": {
+            "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": [] },
+            "full": true
+        }
+    });
+    connection.initialize(capabilities)?;
+    Server::new().run(&connection)?;
+    io_threads.join()?;
+    Ok(())
+}
@@ -133,6 +133,50 @@ pub(crate) struct Interfaces {
     pub outputs: Vec<PipelineOutputPort>,
 }
 
+/// What happens to an event when an operator fails to process it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorPolicy {
+    /// The event and the error are emitted on the `error` port of the
+    /// operator
+    Error,
+    /// The event is logged and dropped
+    Drop,
+    /// The event and the error are sent to the `error` output of the
+    /// pipeline, or the `err` output if there is none
+    DeadLetter,
+    /// The operator is retried the given number of times before the
+    /// event is dead lettered. Every retry starts from the state the node
+    /// had before the event, operators that can't snapshot their internal
+    /// state have to be idempotent to be retried safely.
+    Retry(u32),
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        Self::Error
+    }
+}
+
+impl ErrorPolicy {
+    /// Reads a policy from a query config value, this is either one of
+    /// the strings `"error"`, `"drop"` and `"dead-letter"` or a record
+    /// `{"retry": <n>}`
+    pub fn from_value(value: &tremor_script::Value) -> Option<Self> {
+        use simd_json::prelude::*;
+        match value.as_str() {
+            Some("error") => Some(Self::Error),
+            Some("drop") => Some(Self::Drop),
+            Some("dead-letter") => Some(Self::DeadLetter),
+            Some(_) => None,
+            None => value
+                .get("retry")
+                .and_then(ValueTrait::as_u32)
+                .map(Self::Retry),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct Node {
@@ -142,6 +186,8 @@ pub(crate) struct Node {
     #[serde(rename = "op")]
     pub node_type: String,
     pub config: ConfigMap,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_policy: Option<ErrorPolicy>,
 }
 
 /// The configuration for a pipeline
@@ -159,6 +205,10 @@ pub struct Pipeline {
     pub(crate) links: IndexMap<OutputPort, Vec<InputPort>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) metrics_interval_s: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) state_snapshot_interval_s: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error_policy: Option<ErrorPolicy>,
 }
 
 #[cfg(test)]
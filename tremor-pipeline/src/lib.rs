@@ -30,11 +30,10 @@
 extern crate serde_derive;
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate rental;
 
+use crate::config::ErrorPolicy;
 use crate::errors::{Error, ErrorKind, Result};
-use halfbrown::HashMap;
+use halfbrown::{hashmap, HashMap};
 use lazy_static::lazy_static;
 use op::trickle::select::WindowImpl;
 use petgraph::algo::is_cyclic_directed;
@@ -48,10 +47,9 @@ use simd_json::{json, BorrowedValue};
 use std::borrow::Cow;
 use std::iter;
 use std::iter::Iterator;
-use std::sync::Arc;
 use std::sync::Mutex;
 use tremor_script::prelude::*;
-use tremor_script::query::StmtRentalWrapper;
+use tremor_script::query::OwnedStmt;
 
 /// Pipeline Configuration
 pub mod config;
@@ -72,8 +70,8 @@ pub(crate) type ExecPortIndexMap =
 /// A lookup function to used to look up operators
 pub type NodeLookupFn = fn(
     config: &NodeConfig,
-    defn: Option<StmtRentalWrapper>,
-    node: Option<StmtRentalWrapper>,
+    defn: Option<OwnedStmt>,
+    node: Option<OwnedStmt>,
     windows: Option<HashMap<String, WindowImpl>>,
 ) -> Result<OperatorNode>;
 pub(crate) type NodeMap = HashMap<Cow<'static, str>, NodeIndex>;
@@ -86,6 +84,55 @@ lazy_static! {
         let registry: Registry = tremor_script::registry();
         Mutex::new(registry)
     };
+    /// Operators registered from outside the pipeline crate, for example
+    /// by plugins, they are keyed by `namespace::name`.
+    static ref OPERATOR_REGISTRY: Mutex<HashMap<String, Box<dyn InitializableOperator + Send + Sync>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Registers an operator under `namespace::name`, built in operators
+/// always take precedence over registered ones.
+pub fn register_operator(
+    namespace: &str,
+    name: &str,
+    factory: Box<dyn InitializableOperator + Send + Sync>,
+) -> Result<()> {
+    let key = format!("{}::{}", namespace, name);
+    let mut ops = OPERATOR_REGISTRY.lock()?;
+    if ops.contains_key(&key) {
+        return Err(format!("Operator {} is already registered", key).into());
+    }
+    ops.insert(key, factory);
+    Ok(())
+}
+
+/// Looks up a registered operator and initialises it from the node
+pub(crate) fn registered_op(
+    node: &NodeConfig,
+    namespace: &str,
+    name: &str,
+) -> Result<Box<dyn Operator>> {
+    let ops = OPERATOR_REGISTRY.lock()?;
+    if let Some(factory) = ops.get(&format!("{}::{}", namespace, name)) {
+        factory.from_node(node)
+    } else {
+        Err(ErrorKind::UnknownOp(namespace.to_string(), name.to_string()).into())
+    }
+}
+
+/// The port operators emit events they failed to process on, dead lettered
+/// events are sent to the pipeline output of the same name
+pub const ERROR_PORT: &str = "error";
+
+/// The error output of trickle queries, dead lettered events are sent here
+/// if a pipeline has no `error` output
+pub const ERR_OUTPUT: &str = "err";
+
+/// An operator failed on an event if it returned an error or emitted the
+/// event on its error port
+fn failed(res: &Result<Vec<(Cow<'static, str>, Event)>>) -> bool {
+    res.as_ref()
+        .map_or(true, |res| res.iter().any(|(port, _)| port == ERROR_PORT))
 }
 
 pub(crate) fn common_cow(s: &str) -> Cow<'static, str> {
@@ -97,7 +144,7 @@ pub(crate) fn common_cow(s: &str) -> Cow<'static, str> {
             }
         };
     }
-    cows!(s, "in", "out", "err", "main")
+    cows!(s, "in", "out", "error", "err", "main")
 }
 
 /// A non yet compiled pipeline
@@ -264,8 +311,8 @@ pub struct NodeConfig {
     pub(crate) kind: NodeKind,
     pub(crate) op_type: String,
     pub(crate) config: config::ConfigMap,
-    pub(crate) defn: Option<Arc<StmtRentalWrapper>>,
-    pub(crate) node: Option<Arc<StmtRentalWrapper>>,
+    pub(crate) defn: Option<OwnedStmt>,
+    pub(crate) node: Option<OwnedStmt>,
 }
 
 // We ignore stmt on equality and hasing as they're only
@@ -338,6 +385,14 @@ impl Operator for OperatorNode {
     fn skippable(&self) -> bool {
         self.op.skippable()
     }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        self.op.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        self.op.restore(snapshot)
+    }
 }
 
 // TODO We need an actual operator registry ...
@@ -349,14 +404,14 @@ impl Operator for OperatorNode {
 #[allow(clippy::implicit_hasher, clippy::needless_pass_by_value)]
 pub fn buildin_ops(
     node: &NodeConfig,
-    _defn: Option<StmtRentalWrapper>,
-    _nobody_knows: Option<StmtRentalWrapper>,
+    _defn: Option<OwnedStmt>,
+    _nobody_knows: Option<OwnedStmt>,
     _windows: Option<HashMap<String, WindowImpl>>,
 ) -> Result<OperatorNode> {
     // Resolve from registry
 
     use op::debug::EventHistoryFactory;
-    use op::generic::{BackpressureFactory, BatchFactory, CounterFactory};
+    use op::generic::{BackpressureFactory, BatchFactory, CounterFactory, DedupFactory};
     use op::grouper::BucketGrouperFactory;
     use op::identity::PassthroughFactory;
     use op::runtime::TremorFactory;
@@ -369,8 +424,14 @@ pub fn buildin_ops(
         ["generic", "batch"] => BatchFactory::new_boxed(),
         ["generic", "backpressure"] => BackpressureFactory::new_boxed(),
         ["generic", "counter"] => CounterFactory::new_boxed(),
+        ["generic", "dedup"] => DedupFactory::new_boxed(),
         [namespace, name] => {
-            return Err(ErrorKind::UnknownOp((*namespace).to_string(), (*name).to_string()).into());
+            return Ok(OperatorNode {
+                id: node.id.clone(),
+                kind: node.kind,
+                op_type: node.op_type.clone(),
+                op: registered_op(node, namespace, name)?,
+            });
         }
         _ => return Err(ErrorKind::UnknownNamespace(node.op_type.clone()).into()),
     };
@@ -382,11 +443,21 @@ pub fn buildin_ops(
     })
 }
 impl NodeConfig {
+    /// The id of the node
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The configuration of the node
+    pub fn config(&self) -> &config::ConfigMap {
+        &self.config
+    }
+
     pub(crate) fn to_op(
         &self,
         resolver: NodeLookupFn,
-        defn: Option<StmtRentalWrapper>,
-        node: Option<StmtRentalWrapper>,
+        defn: Option<OwnedStmt>,
+        node: Option<OwnedStmt>,
         window: Option<HashMap<String, WindowImpl>>,
     ) -> Result<OperatorNode> {
         resolver(&self, defn, node, window)
@@ -567,6 +638,9 @@ pub struct ExecutableGraph {
     metrics_idx: usize,
     last_metrics: u64,
     metric_interval: Option<u64>,
+    snapshot_interval: Option<u64>,
+    // error policy per node (ordered in the same way as nodes in the executable graph)
+    error_policies: Vec<ErrorPolicy>,
 }
 
 /// The return of a graph execution
@@ -718,6 +792,114 @@ impl ExecutableGraph {
         }
         Some(did_chage)
     }
+    /// The interval in nanoseconds in which the state of the graph
+    /// should be snapshotted, `None` if state shouldn't be persisted.
+    pub fn snapshot_interval(&self) -> Option<u64> {
+        self.snapshot_interval
+    }
+
+    /// The interval in nanoseconds in which the graph emits metrics,
+    /// `None` if it doesn't emit metrics.
+    pub fn metrics_interval(&self) -> Option<u64> {
+        self.metric_interval
+    }
+
+    /// Captures the state of all nodes in the graph, keyed by node id.
+    /// This includes both the node level state and the internal state
+    /// of operators (such as windows and aggregates).
+    pub fn snapshot(&self) -> Result<Value<'static>> {
+        let mut snapshot = HashMap::new();
+        for (node, state) in self.graph.iter().zip(&self.state.ops) {
+            let op = node.snapshot()?;
+            if op.is_none() && state.is_null() {
+                continue;
+            }
+            let mut node_snapshot = HashMap::new();
+            node_snapshot.insert("type".into(), Value::from(node.op_type.clone()));
+            node_snapshot.insert("state".into(), state.clone());
+            node_snapshot.insert("op".into(), op.unwrap_or_else(Value::null));
+            snapshot.insert(node.id.clone(), Value::from(node_snapshot));
+        }
+        Ok(Value::from(snapshot))
+    }
+
+    /// Restores the state of the graph from a value created by `snapshot`.
+    /// Nodes that are not part of the snapshot keep their initial state.
+    pub fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let snapshot = snapshot
+            .as_object()
+            .ok_or_else(|| Error::from("Invalid pipeline snapshot"))?;
+        for (node, state) in self.graph.iter_mut().zip(self.state.ops.iter_mut()) {
+            if let Some(node_snapshot) = snapshot.get(node.id.as_ref()) {
+                if let Some(s) = node_snapshot.get("state") {
+                    *state = s.clone_static();
+                }
+                match node_snapshot.get("op") {
+                    Some(op) if !op.is_null() => node.restore(op)?,
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Carries over the state of a previous version of this graph from a
+    /// value created by `snapshot`. Only nodes with the same id and operator
+    /// type are considered compatible, nodes listed in `skip` are left alone.
+    /// Returns the ids of the nodes that failed to restore, those might be
+    /// left in a partially restored state.
+    pub fn carry_over(&mut self, snapshot: &Value, skip: &[String]) -> Vec<String> {
+        let mut failed = Vec::new();
+        let snapshot = if let Some(snapshot) = snapshot.as_object() {
+            snapshot
+        } else {
+            return failed;
+        };
+        for (node, state) in self.graph.iter_mut().zip(self.state.ops.iter_mut()) {
+            if skip.iter().any(|s| s == &node.id) {
+                continue;
+            }
+            let node_snapshot = match snapshot.get(node.id.as_ref()) {
+                Some(node_snapshot)
+                    if node_snapshot.get("type").and_then(Value::as_str)
+                        == Some(node.op_type.as_str()) =>
+                {
+                    node_snapshot
+                }
+                _ => continue,
+            };
+            let restored = match node_snapshot.get("op") {
+                Some(op) if !op.is_null() => node.restore(op),
+                _ => Ok(()),
+            };
+            match restored {
+                Ok(()) => {
+                    if let Some(s) = node_snapshot.get("state") {
+                        *state = s.clone_static();
+                    }
+                }
+                Err(e) => {
+                    warn!("[Node::{}] Failed to carry over state: {}", node.id, e);
+                    failed.push(node.id.to_string());
+                }
+            }
+        }
+        failed
+    }
+
+    /// Names of the inputs of the graph
+    pub fn input_names(&self) -> impl Iterator<Item = &str> {
+        self.inputs.keys().map(AsRef::as_ref)
+    }
+
+    /// Names of the outputs of the graph
+    pub fn output_names(&self) -> impl Iterator<Item = &str> {
+        self.graph
+            .iter()
+            .filter(|n| n.kind == NodeKind::Output)
+            .map(|n| n.id.as_ref())
+    }
+
     /// This is a performance critial function!
     pub fn enqueue(
         &mut self,
@@ -761,7 +943,43 @@ impl ExecutableGraph {
             if node.kind == NodeKind::Output {
                 returns.push((node.id.clone(), event));
             } else {
-                let res = node.on_event(&port, &mut self.state.ops[idx], event)?;
+                let policy = unsafe { *self.error_policies.get_unchecked(idx) };
+                // We only keep a copy of the event around if the policy needs
+                // the original after the operator failed
+                let retained = match policy {
+                    ErrorPolicy::Retry(_) | ErrorPolicy::DeadLetter => Some(event.clone()),
+                    ErrorPolicy::Error | ErrorPolicy::Drop => None,
+                };
+                let state = &mut self.state.ops[idx];
+                // A failed attempt may have changed the state of the node so
+                // every retry starts from the state it had before the event
+                let before = if let ErrorPolicy::Retry(_) = policy {
+                    Some((state.clone(), node.snapshot()?))
+                } else {
+                    None
+                };
+                let mut res = node.on_event(&port, state, event);
+                if let (ErrorPolicy::Retry(retries), Some(event), Some((before, snapshot))) =
+                    (policy, &retained, &before)
+                {
+                    let mut attempt = 0;
+                    while failed(&res) && attempt < retries {
+                        attempt += 1;
+                        *state = before.clone();
+                        if let Some(snapshot) = snapshot {
+                            node.restore(snapshot)?;
+                        }
+                        res = node.on_event(&port, state, event.clone());
+                    }
+                }
+                let res = match res {
+                    Ok(res) if policy == ErrorPolicy::Error => res,
+                    Ok(res) => self.divert_errors(idx, policy, res),
+                    Err(e) => {
+                        self.on_error(idx, retained, &e);
+                        return Ok(!self.stack.is_empty());
+                    }
+                };
                 for (out_port, _) in &res {
                     if let Some(count) = unsafe { self.metrics.get_unchecked_mut(idx) }
                         .outputs
@@ -783,6 +1001,79 @@ impl ExecutableGraph {
         }
     }
 
+    /// Applies the error policy of a node to an event it failed to process.
+    /// The event is only retained for the `retry` and `dead-letter` policies.
+    fn on_error(&mut self, idx: usize, event: Option<Event>, error: &Error) {
+        if let Some(event) = event {
+            let (value, _) = event.data.parts();
+            let mut o = Value::from(hashmap! {
+                "error".into() => Value::from(error.to_string()),
+            });
+            std::mem::swap(&mut o, value);
+            if let Some(error) = value.as_object_mut() {
+                error.insert("event".into(), o);
+            };
+            self.dead_letter(idx, event);
+        } else {
+            let node_id = &unsafe { self.graph.get_unchecked(idx) }.id;
+            error!(
+                "[Pipeline:{}] error in node {}: {}",
+                self.id, node_id, error
+            );
+        }
+    }
+
+    /// Applies the error policy of a node to the events it emitted on its
+    /// error port
+    fn divert_errors(
+        &mut self,
+        idx: usize,
+        policy: ErrorPolicy,
+        res: Vec<(Cow<'static, str>, Event)>,
+    ) -> Vec<(Cow<'static, str>, Event)> {
+        if !res.iter().any(|(port, _)| port == ERROR_PORT) {
+            return res;
+        }
+        let mut kept = Vec::with_capacity(res.len());
+        for (port, event) in res {
+            if port != ERROR_PORT {
+                kept.push((port, event));
+            } else if policy == ErrorPolicy::Drop {
+                let node_id = &unsafe { self.graph.get_unchecked(idx) }.id;
+                error!(
+                    "[Pipeline:{}] dropped failed event {} of node {}",
+                    self.id, event.id, node_id
+                );
+            } else {
+                self.dead_letter(idx, event);
+            }
+        }
+        kept
+    }
+
+    /// Sends an event a node failed to process to the error output of the
+    /// pipeline, recording the node in it
+    fn dead_letter(&mut self, idx: usize, event: Event) {
+        let node_id = unsafe { self.graph.get_unchecked(idx) }.id.clone();
+        if let Some(error) = event.data.parts().0.as_object_mut() {
+            error.insert("node".into(), Value::from(node_id.to_string()));
+        }
+        let graph = &self.graph;
+        let output = |id| {
+            graph
+                .iter()
+                .position(|n| n.kind == NodeKind::Output && n.id == id)
+        };
+        if let Some(err_idx) = output(ERROR_PORT).or_else(|| output(ERR_OUTPUT)) {
+            self.stack.push((err_idx, "in".into(), event));
+        } else {
+            error!(
+                "[Pipeline:{}] error in node {} and no {} or {} output to send it to",
+                self.id, node_id, ERROR_PORT, ERR_OUTPUT
+            );
+        }
+    }
+
     fn enqueue_metrics(
         &mut self,
         metric_name: &str,
@@ -953,6 +1244,22 @@ impl Pipeline {
         }
 
         let metric_interval = self.config.metrics_interval_s.map(|s| s * 1_000_000_000);
+        let snapshot_interval = self
+            .config
+            .state_snapshot_interval_s
+            .map(|s| s * 1_000_000_000);
+        let default_policy = self.config.error_policy.unwrap_or_default();
+        let error_policies = graph
+            .iter()
+            .map(|op| {
+                self.config
+                    .nodes
+                    .iter()
+                    .find(|n| n.id == op.id)
+                    .and_then(|n| n.error_policy)
+                    .unwrap_or(default_policy)
+            })
+            .collect();
         Ok(ExecutableGraph {
             metrics: iter::repeat(NodeMetrics::default())
                 .take(graph.len())
@@ -973,6 +1280,8 @@ impl Pipeline {
             contraflow,
             signalflow,
             metric_interval,
+            snapshot_interval,
+            error_policies,
         })
     }
 }
@@ -1060,6 +1369,71 @@ mod test {
         assert_eq!(results[0].1.id, 1);
     }
 
+    #[test]
+    fn error_policy_exec() {
+        let c = slurp("tests/configs/error_policy.yaml");
+        let p: Pipeline = build_pipeline(c).expect("failed to build pipeline");
+        let mut e = p
+            .to_executable_graph(buildin_ops)
+            .expect("failed to build executable graph");
+        let event = Event {
+            is_batch: false,
+            id: 1,
+            ingest_ns: 1,
+            origin_uri: None,
+            data: Value::from(json!({"a": "snot"})).into(),
+            kind: None,
+        };
+        let mut results = Vec::new();
+        e.enqueue("in", event, &mut results)
+            .expect("failed to enqueue");
+        // `increment` dead letters its error, `ignored` drops it and
+        // `routed` emits it on its own error port which is linked to `out`
+        assert_eq!(results.len(), 2);
+        let (dead, routed) = if results[0].0 == ERROR_PORT {
+            (&results[0], &results[1])
+        } else {
+            (&results[1], &results[0])
+        };
+        assert_eq!(dead.0, ERROR_PORT);
+        let error = dead.1.data.suffix().value();
+        assert_eq!(error.get("node"), Some(&Value::from("increment")));
+        assert!(error.get("error").and_then(Value::as_str).is_some());
+        assert_eq!(error.get("event"), Some(&Value::from(json!({"a": "snot"}))));
+        assert_eq!(routed.0, "out");
+        let error = routed.1.data.suffix().value();
+        assert_eq!(error.get("node"), None);
+        assert!(error.get("error").and_then(Value::as_str).is_some());
+        assert_eq!(error.get("event"), Some(&Value::from(json!({"a": "snot"}))));
+    }
+
+    #[test]
+    fn error_policy_retry() {
+        let c = slurp("tests/configs/error_policy_retry.yaml");
+        let p: Pipeline = build_pipeline(c).expect("failed to build pipeline");
+        let mut e = p
+            .to_executable_graph(buildin_ops)
+            .expect("failed to build executable graph");
+        let event = Event {
+            is_batch: false,
+            id: 1,
+            ingest_ns: 1,
+            origin_uri: None,
+            data: Value::from(json!({"a": "snot"})).into(),
+            kind: None,
+        };
+        let mut results = Vec::new();
+        e.enqueue("in", event, &mut results)
+            .expect("failed to enqueue");
+        // without an `error` output the event is dead lettered to `err`
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, ERR_OUTPUT);
+        // every retry started from the state before the event
+        let snapshot = e.snapshot().expect("failed to snapshot");
+        let state = snapshot.get("retried").and_then(|n| n.get("state"));
+        assert_eq!(state, Some(&Value::from(json!({"count": 1}))));
+    }
+
     #[test]
     fn complex_graph_exec() {
         let c = slurp("tests/configs/two_input_complex_graph.yaml");
@@ -1160,6 +1534,72 @@ mod test {
         );
     }
 
+    #[test]
+    fn carry_over_exec() {
+        fn event(id: u64) -> Event {
+            Event {
+                is_batch: false,
+                id,
+                ingest_ns: id,
+                origin_uri: None,
+                data: Value::null().into(),
+                kind: None,
+            }
+        }
+        let c = slurp("tests/configs/counter.yaml");
+        let p: Pipeline = build_pipeline(c).expect("failed to build pipeline");
+        let mut e = p
+            .to_executable_graph(buildin_ops)
+            .expect("failed to build executable graph");
+        let mut results = Vec::new();
+        e.enqueue("in", event(1), &mut results)
+            .expect("failed to enqueue event");
+        e.enqueue("in", event(2), &mut results)
+            .expect("failed to enqueue event");
+        let snapshot = e.snapshot().expect("failed to snapshot");
+
+        // the same graph picks up where the old one left off
+        let mut e = p
+            .to_executable_graph(buildin_ops)
+            .expect("failed to build executable graph");
+        assert!(e.carry_over(&snapshot, &[]).is_empty());
+        let mut results = Vec::new();
+        e.enqueue("in", event(3), &mut results)
+            .expect("failed to enqueue event");
+        assert_eq!(
+            results[0]
+                .1
+                .data
+                .suffix()
+                .value()
+                .get("count")
+                .and_then(Value::as_u64),
+            Some(3)
+        );
+
+        // skipped nodes start fresh
+        let mut e = p
+            .to_executable_graph(buildin_ops)
+            .expect("failed to build executable graph");
+        assert!(e.carry_over(&snapshot, &["counter".to_string()]).is_empty());
+        let mut results = Vec::new();
+        e.enqueue("in", event(3), &mut results)
+            .expect("failed to enqueue event");
+        assert_eq!(
+            results[0]
+                .1
+                .data
+                .suffix()
+                .value()
+                .get("count")
+                .and_then(Value::as_u64),
+            Some(1)
+        );
+
+        assert_eq!(e.input_names().collect::<Vec<_>>(), vec!["in"]);
+        assert!(e.output_names().any(|o| o == "out"));
+    }
+
     #[test]
     fn load_simple() {
         let c = slurp("tests/configs/pipe.simple.yaml");
@@ -1170,4 +1610,33 @@ mod test {
             (&l.0.id, l.1.iter().map(|u| u.id.clone()).collect())
         );
     }
+
+    #[test]
+    fn registered_operator() {
+        use op::identity::PassthroughFactory;
+        register_operator(
+            "test",
+            "passthrough",
+            Box::new(PassthroughFactory::default()),
+        )
+        .expect("failed to register operator");
+        assert!(register_operator(
+            "test",
+            "passthrough",
+            Box::new(PassthroughFactory::default())
+        )
+        .is_err());
+        let mut node = NodeConfig {
+            id: "registered".into(),
+            kind: NodeKind::Operator,
+            op_type: "test::passthrough".into(),
+            config: None,
+            defn: None,
+            node: None,
+        };
+        let op = buildin_ops(&node, None, None, None).expect("failed to build operator");
+        assert_eq!(op.op_type, "test::passthrough");
+        node.op_type = "test::badger".into();
+        assert!(buildin_ops(&node, None, None, None).is_err());
+    }
 }
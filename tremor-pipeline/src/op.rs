@@ -76,6 +76,19 @@ pub trait Operator: std::fmt::Debug + Send {
     fn skippable(&self) -> bool {
         false
     }
+
+    /// Captures the internal state of the operator so it can be persisted,
+    /// defaults to `None` for operators without internal state.
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        Ok(None)
+    }
+
+    /// Restores the internal state of the operator from a value created
+    /// by `snapshot`, defaults to a noop.
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        // Make the trait signature nicer
+        Ok(())
+    }
 }
 
 /// Initialisable trait that can be turned from a `NodeConfig`
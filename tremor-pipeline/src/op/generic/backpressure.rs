@@ -25,7 +25,7 @@
 //! The 1st additional output is used to route data that was decided to
 //! be discarded.
 
-use crate::errors::{ErrorKind, Result};
+use crate::errors::{Error, ErrorKind, Result};
 use crate::{ConfigImpl, Event, Operator};
 use std::borrow::Cow;
 use tremor_script::prelude::*;
@@ -162,6 +162,21 @@ impl Operator for Backpressure {
         if let Some(output) = meta.get("backpressure-output").and_then(Value::as_str) {
             for (i, o) in self.outputs.iter().enumerate() {
                 if o.output == output {
+                    // A circuit breaker in front of the sink opened or closed
+                    match meta.get("cb").and_then(Value::as_str) {
+                        Some("trigger") => {
+                            let backoff = self.steps.last().copied().unwrap_or_default();
+                            self.outputs[i].backoff = backoff;
+                            self.outputs[i].next = insight.ingest_ns + backoff;
+                            return;
+                        }
+                        Some("restore") => {
+                            self.outputs[i].backoff = 0;
+                            self.outputs[i].next = 0;
+                            return;
+                        }
+                        _ => (),
+                    }
                     if meta.get("error").and_then(Value::as_str).is_some() {
                         let backoff = self.next_backoff(o.backoff);
                         self.outputs[i].backoff = backoff;
@@ -181,6 +196,49 @@ impl Operator for Backpressure {
             }
         }
     }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        let mut outputs = Object::with_capacity(self.outputs.len());
+        for o in &self.outputs {
+            outputs.insert(
+                o.output.clone().into(),
+                Value::from(vec![Value::from(o.backoff), Value::from(o.next)]),
+            );
+        }
+        let mut snapshot = Object::with_capacity(2);
+        snapshot.insert("next".into(), Value::from(self.next));
+        snapshot.insert("outputs".into(), Value::from(outputs));
+        Ok(Some(Value::from(snapshot)))
+    }
+
+    /// Outputs are matched by name, outputs that are new since the snapshot
+    /// was taken start without a backoff.
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let outputs = snapshot
+            .get("outputs")
+            .and_then(Value::as_object)
+            .ok_or_else(invalid_snapshot)?;
+        for o in &mut self.outputs {
+            if let Some(output) = outputs.get(o.output.as_str()) {
+                match output.as_array().map(Vec::as_slice) {
+                    Some([backoff, next]) => {
+                        o.backoff = backoff.as_u64().ok_or_else(invalid_snapshot)?;
+                        o.next = next.as_u64().ok_or_else(invalid_snapshot)?;
+                    }
+                    _ => return Err(invalid_snapshot()),
+                }
+            }
+        }
+        self.next = snapshot
+            .get("next")
+            .and_then(Value::as_usize)
+            .ok_or_else(invalid_snapshot)?;
+        Ok(())
+    }
+}
+
+fn invalid_snapshot() -> Error {
+    ErrorKind::PipelineError("Invalid backpressure snapshot".into()).into()
 }
 
 #[cfg(test)]
@@ -391,6 +449,50 @@ mod test {
         assert_eq!(op.outputs[0].backoff, 0);
     }
 
+    #[test]
+    fn circuit_breaker() {
+        let mut op: Backpressure = Config {
+            timeout: 100.0,
+            steps: vec![1, 10, 100],
+            outputs: d_outputs(),
+        }
+        .into();
+
+        let mut m = Object::new();
+        m.insert("cb".into(), "trigger".into());
+        m.insert("error".into(), "snot".into());
+        m.insert("backpressure-output".into(), "out".into());
+        let mut trigger = Event {
+            origin_uri: None,
+            is_batch: false,
+            id: 1,
+            ingest_ns: 2,
+            data: (Value::null(), m).into(),
+            kind: None,
+        };
+
+        let mut m = Object::new();
+        m.insert("cb".into(), "restore".into());
+        m.insert("backpressure-output".into(), "out".into());
+        let mut restore = Event {
+            origin_uri: None,
+            is_batch: false,
+            id: 1,
+            ingest_ns: 3,
+            data: (Value::null(), m).into(),
+            kind: None,
+        };
+
+        // An open circuit moves straight to the last step
+        op.on_contraflow(&mut trigger);
+        assert_eq!(op.outputs[0].backoff, 100_000_000);
+        assert_eq!(op.outputs[0].next, 100_000_002);
+        // and closing it resets the backoff
+        op.on_contraflow(&mut restore);
+        assert_eq!(op.outputs[0].backoff, 0);
+        assert_eq!(op.outputs[0].next, 0);
+    }
+
     #[test]
     fn multi_output_block() {
         let mut op: Backpressure = Config {
@@ -510,4 +612,49 @@ mod test {
         let (out, _event) = r.pop().expect("no results");
         assert_eq!("snot", out);
     }
+
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        let config = Config {
+            timeout: 100.0,
+            steps: vec![1, 10, 100],
+            outputs: d_outputs(),
+        };
+        let mut op: Backpressure = config.clone().into();
+        let mut m = Object::new();
+        m.insert("time".into(), 200.0.into());
+        m.insert("backpressure-output".into(), "out".into());
+        let mut insight = Event {
+            origin_uri: None,
+            is_batch: false,
+            id: 1,
+            ingest_ns: 1_000_000,
+            data: (Value::null(), m).into(),
+            kind: None,
+        };
+        op.on_contraflow(&mut insight);
+        let snapshot = op.snapshot()?.ok_or_else(|| Error::from("no snapshot"))?;
+
+        let mut op: Backpressure = config.into();
+        op.restore(&snapshot)?;
+        assert_eq!(op.outputs[0].backoff, 1_000_000);
+        let mut state = Value::null();
+        let event = |ingest_ns| Event {
+            origin_uri: None,
+            is_batch: false,
+            id: 2,
+            ingest_ns,
+            data: Value::from("badger").into(),
+            kind: None,
+        };
+        let mut r = op.on_event("in", &mut state, event(2_000_000 - 1))?;
+        let (out, _event) = r.pop().ok_or_else(|| Error::from("no results"))?;
+        assert_eq!("overflow", out);
+        let mut r = op.on_event("in", &mut state, event(2_000_000))?;
+        let (out, _event) = r.pop().ok_or_else(|| Error::from("no results"))?;
+        assert_eq!("out", out);
+
+        assert!(op.restore(&Value::from("snot")).is_err());
+        Ok(())
+    }
 }
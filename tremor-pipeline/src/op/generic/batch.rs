@@ -165,6 +165,41 @@ impl Operator for Batch {
             Ok(vec![])
         }
     }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        let mut snapshot = Object::with_capacity(4);
+        snapshot.insert("data".into(), self.data.suffix().value().clone_static());
+        snapshot.insert("len".into(), Value::from(self.len));
+        snapshot.insert("first_ns".into(), Value::from(self.first_ns));
+        snapshot.insert("event_id".into(), Value::from(self.event_id));
+        Ok(Some(Value::from(snapshot)))
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let data = snapshot
+            .get("data")
+            .filter(|data| data.is_array())
+            .ok_or_else(invalid_snapshot)?
+            .clone_static();
+        self.len = snapshot
+            .get("len")
+            .and_then(Value::as_usize)
+            .ok_or_else(invalid_snapshot)?;
+        self.first_ns = snapshot
+            .get("first_ns")
+            .and_then(Value::as_u64)
+            .ok_or_else(invalid_snapshot)?;
+        self.event_id = snapshot
+            .get("event_id")
+            .and_then(Value::as_u64)
+            .ok_or_else(invalid_snapshot)?;
+        self.data = LineValue::new(vec![], |_| ValueAndMeta::from(data));
+        Ok(())
+    }
+}
+
+fn invalid_snapshot() -> Error {
+    ErrorKind::PipelineError("Invalid batch snapshot".into()).into()
 }
 
 #[cfg(test)]
@@ -401,4 +436,44 @@ mod test {
             .expect("failed to run piepeline");
         assert_eq!(r.len(), 0);
     }
+
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        let op = || Batch {
+            config: Config {
+                count: 2,
+                timeout: None,
+            },
+            event_id: 0,
+            first_ns: 0,
+            max_delay_ns: None,
+            data: empty(),
+            len: 0,
+            id: "badger".into(),
+        };
+        let event = |ingest_ns, data: &str| Event {
+            is_batch: false,
+            id: 1,
+            ingest_ns,
+            origin_uri: None,
+            data: Value::from(data.to_string()).into(),
+            kind: None,
+        };
+        let mut state = Value::null();
+
+        let mut a = op();
+        assert!(a.on_event("in", &mut state, event(1, "snot"))?.is_empty());
+        let snapshot = a.snapshot()?.ok_or_else(|| Error::from("no snapshot"))?;
+
+        let mut b = op();
+        b.restore(&snapshot)?;
+        let mut r = b.on_event("in", &mut state, event(2, "badger"))?;
+        let (_, batch) = r.pop().ok_or_else(|| Error::from("no batch"))?;
+        assert_eq!(batch.ingest_ns, 1);
+        let events: Vec<&Value> = batch.value_iter().collect();
+        assert_eq!(events, vec![&Value::from("snot"), &Value::from("badger")]);
+
+        assert!(b.restore(&Value::from("snot")).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,535 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Deduplication of events
+//!
+//! Drops events whose key was already seen, optionally only if it was seen
+//! within a given time to live.
+//!
+//! The key is a tremor script expression evaluated against each event, the
+//! result of the expression is serialized and used for the comparison.
+//!
+//! ## Modes
+//!
+//! * `lru` (default) - exact deduplication, the last `capacity` keys are
+//!   kept in a LRU cache. Keys that were evicted from the cache are treated
+//!   as unseen.
+//! * `bloom` - approximate deduplication using a bloom filter sized for
+//!   `capacity` keys and the given `false_positive_rate`. This uses a fixed
+//!   amount of memory but might drop events that were never seen before.
+//!   With a `ttl` two generations of filters are kept and rotated every
+//!   `ttl`, so keys are remembered for between one and two `ttl`s.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+//!
+//! ## Outputs
+//!
+//! Events for which the key can't be evaluated are sent to the `error`
+//! output.
+//!
+//! # Example
+//!
+//! ```yaml
+//! - id: dedup
+//!   op: generic::dedup
+//!   config:
+//!     key: event.id
+//!     capacity: 10000
+//!     ttl: 60000 # 1 minute
+//! ```
+
+use crate::op::prelude::*;
+use crate::{ERROR_PORT, FN_REGISTRY};
+use lru::LruCache;
+use simd_json::borrowed::Object;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use tremor_script::path::load as load_module_path;
+use tremor_script::prelude::*;
+use tremor_script::{AggrType, EventContext, Return, Script};
+
+/// The deduplication strategy
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Exact deduplication using a LRU cache
+    Lru,
+    /// Approximate deduplication using a bloom filter
+    Bloom,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Self::Lru
+    }
+}
+
+fn dflt_capacity() -> usize {
+    10_000
+}
+
+fn dflt_false_positive_rate() -> f64 {
+    0.01
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Tremor script expression the events are deduplicated by
+    pub key: String,
+    /// Deduplication mode (default: `lru`)
+    #[serde(default)]
+    pub mode: Mode,
+    /// Number of keys to remember (default: 10000)
+    #[serde(default = "dflt_capacity")]
+    pub capacity: usize,
+    /// Time in milliseconds a key is remembered, forever if not set
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    /// False positive rate of the bloom filter (default: 0.01)
+    #[serde(default = "dflt_false_positive_rate")]
+    pub false_positive_rate: f64,
+}
+
+impl ConfigImpl for Config {}
+
+/// A plain bloom filter using double hashing
+#[derive(Debug, Clone)]
+struct Bloom {
+    bits: Vec<u64>,
+    len: u64,
+    hashes: u64,
+}
+
+impl Bloom {
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let capacity = capacity.max(1) as f64;
+        let len = (-capacity * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let hashes = ((len as f64 / capacity) * ln2).round().max(1.0) as u64;
+        Self {
+            bits: vec![0; ((len + 63) / 64) as usize],
+            len,
+            hashes,
+        }
+    }
+
+    /// The bit positions of a key, using double hashing
+    #[allow(clippy::cast_possible_truncation)]
+    fn positions(&self, key: &str) -> impl Iterator<Item = (usize, u64)> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let h1 = hasher.finish();
+        0x9e37_79b9_7f4a_7c15_u64.hash(&mut hasher);
+        let h2 = hasher.finish();
+        let len = self.len;
+        (0..self.hashes).map(move |i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % len;
+            ((bit / 64) as usize, 1 << (bit % 64))
+        })
+    }
+
+    /// Inserts a key and returns if it was (probably) present before
+    fn insert(&mut self, key: &str) -> bool {
+        let mut present = true;
+        for (word, mask) in self.positions(key).collect::<Vec<_>>() {
+            if let Some(word) = self.bits.get_mut(word) {
+                present &= *word & mask != 0;
+                *word |= mask;
+            }
+        }
+        present
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.positions(key)
+            .all(|(word, mask)| self.bits.get(word).map_or(false, |word| *word & mask != 0))
+    }
+
+    fn clear(&mut self) {
+        for word in &mut self.bits {
+            *word = 0;
+        }
+    }
+
+    fn snapshot(&self) -> Value<'static> {
+        Value::from(
+            self.bits
+                .iter()
+                .copied()
+                .map(Value::from)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Restores the bits, the filter has to have the same size it had when
+    /// the snapshot was taken
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let words = snapshot.as_array().ok_or_else(invalid_snapshot)?;
+        if words.len() != self.bits.len() {
+            return Err(invalid_snapshot());
+        }
+        for (word, snapshot) in self.bits.iter_mut().zip(words) {
+            *word = snapshot.as_u64().ok_or_else(invalid_snapshot)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_snapshot() -> Error {
+    ErrorKind::PipelineError("Invalid dedup snapshot".into()).into()
+}
+
+enum Seen {
+    Lru(LruCache<String, u64>),
+    Bloom {
+        current: Bloom,
+        previous: Bloom,
+        rotate_ns: Option<u64>,
+    },
+}
+
+pub struct Dedup {
+    pub config: Config,
+    id: Cow<'static, str>,
+    key: Script,
+    ttl_ns: Option<u64>,
+    seen: Seen,
+    pass: u64,
+    dropped: u64,
+}
+
+impl std::fmt::Debug for Dedup {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Dedup({})", self.id)
+    }
+}
+
+op!(DedupFactory(node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        Ok(Box::new(Dedup::new(node.id.clone(), config)?))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+impl Dedup {
+    pub fn new(id: Cow<'static, str>, config: Config) -> Result<Self> {
+        if config.capacity == 0 {
+            return Err(ErrorKind::BadOpConfig("`capacity` has to be positive".into()).into());
+        }
+        if config.false_positive_rate <= 0.0 || config.false_positive_rate >= 1.0 {
+            return Err(ErrorKind::BadOpConfig(
+                "`false_positive_rate` has to be between 0 and 1".into(),
+            )
+            .into());
+        }
+        let key = Script::parse(
+            &load_module_path(),
+            "<dedup>",
+            config.key.clone(),
+            &*FN_REGISTRY.lock()?,
+        )
+        .map_err(|e| e.error())?;
+        let ttl_ns = config.ttl.map(|ttl| ttl * 1_000_000);
+        let seen = match config.mode {
+            Mode::Lru => Seen::Lru(LruCache::new(config.capacity)),
+            Mode::Bloom => Seen::Bloom {
+                current: Bloom::new(config.capacity, config.false_positive_rate),
+                previous: Bloom::new(config.capacity, config.false_positive_rate),
+                rotate_ns: None,
+            },
+        };
+        Ok(Self {
+            config,
+            id,
+            key,
+            ttl_ns,
+            seen,
+            pass: 0,
+            dropped: 0,
+        })
+    }
+
+    /// Records the key and returns if it was seen within the ttl
+    fn seen(&mut self, key: String, ingest_ns: u64) -> bool {
+        let ttl_ns = self.ttl_ns;
+        match &mut self.seen {
+            Seen::Lru(cache) => {
+                if let Some(seen_ns) = cache.get(&key) {
+                    if ttl_ns.map_or(true, |ttl| ingest_ns.saturating_sub(*seen_ns) < ttl) {
+                        return true;
+                    }
+                }
+                cache.put(key, ingest_ns);
+                false
+            }
+            Seen::Bloom {
+                current,
+                previous,
+                rotate_ns,
+            } => {
+                if let Some(ttl) = ttl_ns {
+                    match rotate_ns {
+                        Some(next) if *next <= ingest_ns => {
+                            std::mem::swap(current, previous);
+                            current.clear();
+                            *rotate_ns = Some(ingest_ns + ttl);
+                        }
+                        Some(_) => (),
+                        None => *rotate_ns = Some(ingest_ns + ttl),
+                    }
+                }
+                let in_current = current.insert(&key);
+                in_current || previous.contains(&key)
+            }
+        }
+    }
+}
+
+impl Seen {
+    /// Captures the keys that were seen, the configuration is not part of
+    /// the snapshot.
+    fn snapshot(&self) -> Value<'static> {
+        match self {
+            // least recently used first so restoring them keeps the order
+            Self::Lru(cache) => Value::from(
+                cache
+                    .iter()
+                    .rev()
+                    .map(|(key, seen_ns)| {
+                        Value::from(vec![Value::from(key.clone()), Value::from(*seen_ns)])
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Self::Bloom {
+                current,
+                previous,
+                rotate_ns,
+            } => {
+                let mut snapshot = HashMap::new();
+                snapshot.insert("current".into(), current.snapshot());
+                snapshot.insert("previous".into(), previous.snapshot());
+                snapshot.insert(
+                    "rotate_ns".into(),
+                    rotate_ns.map_or_else(Value::null, Value::from),
+                );
+                Value::from(snapshot)
+            }
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        match self {
+            Self::Lru(cache) => {
+                cache.clear();
+                for entry in snapshot.as_array().ok_or_else(invalid_snapshot)? {
+                    match entry.as_array().map(Vec::as_slice) {
+                        Some([key, seen_ns]) => cache.put(
+                            key.as_str().ok_or_else(invalid_snapshot)?.to_string(),
+                            seen_ns.as_u64().ok_or_else(invalid_snapshot)?,
+                        ),
+                        _ => return Err(invalid_snapshot()),
+                    };
+                }
+            }
+            Self::Bloom {
+                current,
+                previous,
+                rotate_ns,
+            } => {
+                current.restore(snapshot.get("current").ok_or_else(invalid_snapshot)?)?;
+                previous.restore(snapshot.get("previous").ok_or_else(invalid_snapshot)?)?;
+                let next = snapshot.get("rotate_ns").ok_or_else(invalid_snapshot)?;
+                *rotate_ns = if next.is_null() {
+                    None
+                } else {
+                    Some(next.as_u64().ok_or_else(invalid_snapshot)?)
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Operator for Dedup {
+    fn on_event(
+        &mut self,
+        _port: &str,
+        state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<Vec<(Cow<'static, str>, Event)>> {
+        let key = {
+            let context = EventContext::new(event.ingest_ns, event.origin_uri.clone());
+            let (unwind_event, event_meta) = event.data.parts();
+            match self.key.run(
+                &context,
+                AggrType::Emit,
+                unwind_event, // event
+                state,        // state
+                event_meta,   // $
+            ) {
+                Ok(Return::Emit { value, .. }) => Some(value.encode()),
+                Ok(Return::EmitEvent { .. }) => Some(unwind_event.encode()),
+                Ok(Return::Drop) | Err(_) => None,
+            }
+        };
+        let key = if let Some(key) = key {
+            key
+        } else {
+            return Ok(vec![(ERROR_PORT.into(), event)]);
+        };
+        if self.seen(key, event.ingest_ns) {
+            self.dropped += 1;
+            Ok(vec![])
+        } else {
+            self.pass += 1;
+            Ok(vec![("out".into(), event)])
+        }
+    }
+
+    fn metrics(
+        &self,
+        mut tags: HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        let mut res = Vec::with_capacity(2);
+        for (action, count) in &[("pass", self.pass), ("drop", self.dropped)] {
+            tags.insert("action".into(), (*action).into());
+            let mut m = Object::with_capacity(4);
+            m.insert("measurement".into(), "dedup".into());
+            m.insert("tags".into(), Value::from(tags.clone()));
+            let mut fields = Object::with_capacity(1);
+            fields.insert("count".into(), (*count).into());
+            m.insert("fields".into(), Value::from(fields));
+            m.insert("timestamp".into(), timestamp.into());
+            res.push(Value::from(m));
+        }
+        Ok(res)
+    }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        Ok(Some(self.seen.snapshot()))
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        self.seen.restore(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn test_event(id: u64, ingest_ns: u64, key: &str) -> Event {
+        Event {
+            origin_uri: None,
+            is_batch: false,
+            id,
+            ingest_ns,
+            data: Value::from(json!({ "key": key })).into(),
+            kind: None,
+        }
+    }
+
+    fn config(mode: Mode, ttl: Option<u64>) -> Config {
+        Config {
+            key: "event.key".to_string(),
+            mode,
+            capacity: 100,
+            ttl,
+            false_positive_rate: 0.01,
+        }
+    }
+
+    fn passes(op: &mut Dedup, id: u64, ingest_ns: u64, key: &str) -> bool {
+        let mut state = Value::null();
+        let r = op
+            .on_event("in", &mut state, test_event(id, ingest_ns, key))
+            .expect("could not run pipeline");
+        !r.is_empty()
+    }
+
+    #[test]
+    fn lru() {
+        let mut op = Dedup::new("dedup".into(), config(Mode::Lru, Some(1)))
+            .expect("failed to create operator");
+        assert!(passes(&mut op, 1, 0, "snot"));
+        assert!(passes(&mut op, 2, 0, "badger"));
+        assert!(!passes(&mut op, 3, 500_000, "snot"));
+        // the ttl of 1ms has passed
+        assert!(passes(&mut op, 4, 1_000_000, "snot"));
+        assert_eq!(op.pass, 3);
+        assert_eq!(op.dropped, 1);
+    }
+
+    #[test]
+    fn bloom() {
+        let mut op = Dedup::new("dedup".into(), config(Mode::Bloom, Some(1)))
+            .expect("failed to create operator");
+        assert!(passes(&mut op, 1, 0, "snot"));
+        assert!(passes(&mut op, 2, 0, "badger"));
+        assert!(!passes(&mut op, 3, 500_000, "snot"));
+        // the previous generation still remembers the key
+        assert!(!passes(&mut op, 4, 1_000_000, "badger"));
+        // both generations that have seen the key have been rotated out
+        assert!(passes(&mut op, 5, 2_000_000, "snot"));
+    }
+
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        for mode in &[Mode::Lru, Mode::Bloom] {
+            let mut op = Dedup::new("dedup".into(), config(*mode, Some(1)))?;
+            assert!(passes(&mut op, 1, 0, "snot"));
+            assert!(passes(&mut op, 2, 0, "badger"));
+            let snapshot = op.snapshot()?.expect("dedup has a snapshot");
+
+            let mut op = Dedup::new("dedup".into(), config(*mode, Some(1)))?;
+            op.restore(&snapshot)?;
+            assert!(!passes(&mut op, 3, 500_000, "snot"));
+            assert!(!passes(&mut op, 4, 500_000, "badger"));
+            assert!(passes(&mut op, 5, 500_000, "ferret"));
+
+            // snapshots of the other mode are rejected
+            let other = if *mode == Mode::Lru {
+                Mode::Bloom
+            } else {
+                Mode::Lru
+            };
+            let mut op = Dedup::new("dedup".into(), config(other, Some(1)))?;
+            assert!(op.restore(&snapshot).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bad_config() {
+        let mut c = config(Mode::Lru, None);
+        c.capacity = 0;
+        assert!(Dedup::new("dedup".into(), c).is_err());
+        let mut c = config(Mode::Bloom, None);
+        c.false_positive_rate = 1.5;
+        assert!(Dedup::new("dedup".into(), c).is_err());
+    }
+}
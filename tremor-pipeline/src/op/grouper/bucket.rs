@@ -61,13 +61,14 @@
 //! }
 //! ```
 
-use crate::errors::{ErrorKind, Result};
-use crate::{Event, Operator};
+use crate::errors::{Error, ErrorKind, Result};
+use crate::{Event, Operator, ERROR_PORT};
 use halfbrown::HashMap;
 use lru::LruCache;
 use simd_json::borrowed::Object;
 use simd_json::prelude::*;
 use std::borrow::Cow;
+use std::collections::VecDeque;
 use tremor_script::prelude::*;
 use window::TimeWindow;
 
@@ -82,7 +83,7 @@ op!(BucketGrouperFactory(node) {
     }
 });
 /// Single bucket specification
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Rate {
     /// the maximum number of events per time range
     pub rate: u64,
@@ -107,10 +108,105 @@ impl Rate {
             windows,
         })
     }
+
+    fn snapshot(&self) -> Value<'static> {
+        let mut snapshot = Object::with_capacity(3);
+        snapshot.insert("rate".into(), Value::from(self.rate));
+        snapshot.insert("time_range".into(), Value::from(self.time_range));
+        snapshot.insert("windows".into(), Value::from(self.windows));
+        Value::from(snapshot)
+    }
+
+    /// Duration of a single window in nanoseconds
+    fn slot_ns(&self) -> u64 {
+        (self.time_range * 1_000_000 / self.windows.max(1) as u64).max(1)
+    }
+}
+
+/// The window of a single dimension, the events that passed are counted
+/// per slot of the window so it can be rebuilt from a snapshot.
+struct Limit {
+    rate: Rate,
+    window: TimeWindow,
+    /// `(ingest_ns, count)` of the first event of each slot
+    slots: VecDeque<(u64, u64)>,
+}
+
+impl Limit {
+    fn new(rate: Rate) -> Self {
+        let window = TimeWindow::new(
+            rate.windows,
+            rate.time_range / (rate.windows as u64),
+            rate.rate,
+        );
+        Self {
+            rate,
+            window,
+            slots: VecDeque::new(),
+        }
+    }
+
+    fn inc(&mut self, ingest_ns: u64) -> bool {
+        if self.window.inc_t(ingest_ns).is_err() {
+            return false;
+        }
+        let slot_ns = self.rate.slot_ns();
+        let range_ns = self.rate.time_range * 1_000_000;
+        while self
+            .slots
+            .front()
+            .map_or(false, |(start, _)| start + range_ns <= ingest_ns)
+        {
+            self.slots.pop_front();
+        }
+        match self.slots.back_mut() {
+            Some((start, count)) if *start / slot_ns == ingest_ns / slot_ns => *count += 1,
+            _ => self.slots.push_back((ingest_ns, 1)),
+        }
+        true
+    }
+
+    fn snapshot(&self) -> Value<'static> {
+        let slots: Vec<Value<'static>> = self
+            .slots
+            .iter()
+            .map(|(start, count)| Value::from(vec![Value::from(*start), Value::from(*count)]))
+            .collect();
+        let mut snapshot = Object::with_capacity(2);
+        snapshot.insert("rate".into(), self.rate.snapshot());
+        snapshot.insert("slots".into(), Value::from(slots));
+        Value::from(snapshot)
+    }
+
+    /// Rebuilds the window by replaying the counted events
+    fn restore(snapshot: &Value) -> Result<Self> {
+        let rate = snapshot
+            .get("rate")
+            .and_then(Rate::from_meta)
+            .ok_or_else(invalid_snapshot)?;
+        let mut limit = Self::new(rate);
+        let slots = snapshot
+            .get("slots")
+            .and_then(Value::as_array)
+            .ok_or_else(invalid_snapshot)?;
+        for slot in slots {
+            match slot.as_array().map(Vec::as_slice) {
+                Some([start, count]) => {
+                    let start = start.as_u64().ok_or_else(invalid_snapshot)?;
+                    let count = count.as_u64().ok_or_else(invalid_snapshot)?;
+                    for _ in 0..count {
+                        limit.inc(start);
+                    }
+                }
+                _ => return Err(invalid_snapshot()),
+            }
+        }
+        Ok(limit)
+    }
 }
 
 pub struct Bucket {
-    cache: LruCache<String, TimeWindow>,
+    cache: LruCache<String, Limit>,
     pass: u64,
     overflow: u64,
 }
@@ -122,6 +218,60 @@ impl Bucket {
             overflow: 0,
         }
     }
+
+    fn snapshot(&self) -> Value<'static> {
+        let mut windows: Vec<Value<'static>> = self
+            .cache
+            .iter()
+            .map(|(dimensions, limit)| {
+                Value::from(vec![Value::from(dimensions.clone()), limit.snapshot()])
+            })
+            .collect();
+        // least recently used first so restoring keeps the order
+        windows.reverse();
+        let mut snapshot = Object::with_capacity(4);
+        snapshot.insert("cardinality".into(), Value::from(self.cache.cap()));
+        snapshot.insert("pass".into(), Value::from(self.pass));
+        snapshot.insert("overflow".into(), Value::from(self.overflow));
+        snapshot.insert("windows".into(), Value::from(windows));
+        Value::from(snapshot)
+    }
+
+    fn restore(snapshot: &Value) -> Result<Self> {
+        let cardinality = snapshot
+            .get("cardinality")
+            .and_then(Value::as_usize)
+            .ok_or_else(invalid_snapshot)?;
+        let mut bucket = Self::new(cardinality);
+        bucket.pass = snapshot
+            .get("pass")
+            .and_then(Value::as_u64)
+            .ok_or_else(invalid_snapshot)?;
+        bucket.overflow = snapshot
+            .get("overflow")
+            .and_then(Value::as_u64)
+            .ok_or_else(invalid_snapshot)?;
+        let windows = snapshot
+            .get("windows")
+            .and_then(Value::as_array)
+            .ok_or_else(invalid_snapshot)?;
+        for window in windows {
+            match window.as_array().map(Vec::as_slice) {
+                Some([dimensions, limit]) => {
+                    let dimensions = dimensions.as_str().ok_or_else(invalid_snapshot)?;
+                    bucket
+                        .cache
+                        .put(dimensions.to_string(), Limit::restore(limit)?);
+                }
+                _ => return Err(invalid_snapshot()),
+            }
+        }
+        Ok(bucket)
+    }
+}
+
+fn invalid_snapshot() -> Error {
+    ErrorKind::PipelineError("Invalid bucket snapshot".into()).into()
 }
 
 pub struct Grouper {
@@ -163,16 +313,9 @@ impl Operator for Grouper {
                     let rate = if let Some(rate) = Rate::from_meta(&meta) {
                         rate
                     } else {
-                        return Ok(vec![("error".into(), event)]);
+                        return Ok(vec![(ERROR_PORT.into(), event)]);
                     };
-                    groups.cache.put(
-                        dimensions.clone(),
-                        TimeWindow::new(
-                            rate.windows,
-                            rate.time_range / (rate.windows as u64),
-                            rate.rate,
-                        ),
-                    );
+                    groups.cache.put(dimensions.clone(), Limit::new(rate));
                     if let Some(g) = groups.cache.get_mut(&dimensions) {
                         g
                     } else {
@@ -182,7 +325,7 @@ impl Operator for Grouper {
                 }
                 Some(m) => m,
             };
-            if window.inc_t(event.ingest_ns).is_ok() {
+            if window.inc(event.ingest_ns) {
                 groups.pass += 1;
                 Ok(vec![("out".into(), event)])
             } else {
@@ -190,7 +333,7 @@ impl Operator for Grouper {
                 Ok(vec![("overflow".into(), event)])
             }
         } else {
-            Ok(vec![("error".into(), event)])
+            Ok(vec![(ERROR_PORT.into(), event)])
         }
     }
 
@@ -227,4 +370,81 @@ impl Operator for Grouper {
         }
         Ok(res)
     }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        let mut snapshot = Object::with_capacity(self.buckets.len());
+        for (class, bucket) in &self.buckets {
+            snapshot.insert(class.clone().into(), bucket.snapshot());
+        }
+        Ok(Some(Value::from(snapshot)))
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let snapshot = snapshot.as_object().ok_or_else(invalid_snapshot)?;
+        let mut buckets = HashMap::new();
+        for (class, bucket) in snapshot {
+            buckets.insert(class.to_string(), Bucket::restore(bucket)?);
+        }
+        self.buckets = buckets;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simd_json::json;
+
+    fn test_event(id: u64, ingest_ns: u64, dimension: &str) -> Event {
+        let meta = json!({
+            "class": "test",
+            "rate": 2,
+            "dimensions": [dimension]
+        });
+        Event {
+            origin_uri: None,
+            is_batch: false,
+            id,
+            ingest_ns,
+            data: (Value::null(), Value::from(meta)).into(),
+            kind: None,
+        }
+    }
+
+    fn port(op: &mut Grouper, id: u64, ingest_ns: u64, dimension: &str) -> Result<String> {
+        let mut state = Value::null();
+        let mut r = op.on_event("in", &mut state, test_event(id, ingest_ns, dimension))?;
+        let (port, _) = r.pop().ok_or_else(|| Error::from("no output"))?;
+        Ok(port.to_string())
+    }
+
+    fn grouper() -> Grouper {
+        Grouper {
+            buckets: HashMap::new(),
+            _id: "bucket".into(),
+        }
+    }
+
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        let mut op = grouper();
+        assert_eq!("out", port(&mut op, 1, 0, "snot")?);
+        assert_eq!("out", port(&mut op, 2, 1_000_000, "snot")?);
+        assert_eq!("out", port(&mut op, 3, 2_000_000, "badger")?);
+        assert_eq!("overflow", port(&mut op, 4, 3_000_000, "snot")?);
+        let snapshot = op.snapshot()?.ok_or_else(|| Error::from("no snapshot"))?;
+
+        let mut op = grouper();
+        op.restore(&snapshot)?;
+        assert_eq!(snapshot, op.snapshot()?.unwrap_or_else(Value::null));
+        // the window of `snot` is still full while `badger` has room left
+        assert_eq!("overflow", port(&mut op, 5, 4_000_000, "snot")?);
+        assert_eq!("out", port(&mut op, 6, 4_000_000, "badger")?);
+        assert_eq!("overflow", port(&mut op, 7, 5_000_000, "badger")?);
+        // once the time range passed events pass again
+        assert_eq!("out", port(&mut op, 8, 2_000_000_000, "snot")?);
+
+        assert!(op.restore(&Value::from("snot")).is_err());
+        Ok(())
+    }
 }
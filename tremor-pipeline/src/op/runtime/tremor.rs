@@ -12,12 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::op::prelude::*;
-use crate::FN_REGISTRY;
+use crate::{ERROR_PORT, FN_REGISTRY};
 use simd_json::borrowed::Value;
 use tremor_script::highlighter::Dumb as DumbHighlighter;
 use tremor_script::path::load as load_module_path;
 use tremor_script::prelude::*;
-use tremor_script::{self, AggrType, EventContext, Return, Script};
+use tremor_script::{self, AggrType, EventContext, Limits, Return, Script};
 
 op!(TremorFactory(node) {
     if let Some(map) = &node.config {
@@ -27,12 +27,14 @@ op!(TremorFactory(node) {
                &load_module_path(),
                "<operator>",
                config.script.clone(), &*FN_REGISTRY.lock()?) {
-            Ok(runtime) =>
+            Ok(mut runtime) => {
+                runtime.set_limits(config.limits);
                 Ok(Box::new(Tremor {
                     runtime,
                     config,
                     id: node.id.clone().to_string(),
-                })),
+                }))
+            }
             Err(e) => {
                 let mut h = DumbHighlighter::new();
                 if let Err(e) = tremor_script::Script::format_error_from_script(&config.script, &mut h, &e) {
@@ -51,6 +53,9 @@ op!(TremorFactory(node) {
 #[derive(Debug, Clone, Deserialize)]
 struct Config {
     script: String,
+    /// Limits the script has to stay within for every event
+    #[serde(default)]
+    limits: Limits,
 }
 impl ConfigImpl for Config {}
 
@@ -92,6 +97,8 @@ impl Operator for Tremor {
                     port.map_or_else(|| "out".into(), Cow::Owned)
                 }
                 Ok(Return::Drop) => return Ok(vec![]),
+                // Runtime errors are emitted on the error port where the
+                // error policy of the pipeline picks them up
                 Err(ref e) => {
                     let mut o = Value::from(hashmap! {
                         "error".into() => Value::from(self.runtime.format_error(&e)),
@@ -103,7 +110,7 @@ impl Operator for Tremor {
                         // ALLOW: we know this never happens since we swap the event three lines above
                         unreachable!();
                     };
-                    "error".into()
+                    ERROR_PORT.into()
                 }
             }
         };
@@ -123,6 +130,7 @@ mod test {
         let config = Config {
             script: r#"match event.a of case 1 => let event.snot = "badger" end; event;"#
                 .to_string(),
+            limits: Limits::default(),
         };
         let runtime = Script::parse(
             &ModulePath { mounts: vec![] }, // FIXME config cpp
@@ -159,10 +167,45 @@ mod test {
         )
     }
 
+    #[test]
+    fn limits() {
+        let config = Config {
+            script: r#"let x = 0; let x = x + 1; let x = x + 1; let x = x + 1; x"#.to_string(),
+            limits: Limits {
+                max_steps: Some(5),
+                ..Limits::default()
+            },
+        };
+        let mut runtime = Script::parse(
+            &ModulePath { mounts: vec![] },
+            "<test>",
+            config.script.clone(),
+            &*FN_REGISTRY.lock().expect("could not claim lock"),
+        )
+        .expect("failed to parse script");
+        runtime.set_limits(config.limits);
+        let mut op = Tremor {
+            config,
+            runtime,
+            id: "badger".into(),
+        };
+        let event = Event {
+            origin_uri: None,
+            is_batch: false,
+            id: 1,
+            ingest_ns: 1,
+            data: Value::from(json!({"a": 1})).into(),
+            kind: None,
+        };
+        let mut state = Value::null();
+        assert!(op.on_event("in", &mut state, event).is_err());
+    }
+
     #[test]
     pub fn test_how_it_handles_errors() {
         let config = Config {
             script: r#"match this is invalid code so no match case"#.to_string(),
+            limits: Limits::default(),
         };
         let _runtime = Script::parse(
             &ModulePath { mounts: vec![] }, // FIXME config cpp
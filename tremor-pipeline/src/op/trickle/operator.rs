@@ -16,26 +16,21 @@ use crate::common_cow;
 use crate::errors::missing_config;
 use crate::op::prelude::*;
 use tremor_script::prelude::*;
-
-use tremor_script::{self};
+use tremor_script::query::OwnedStmt;
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Debug)]
 pub(crate) struct TrickleOperator {
     pub id: String,
-    pub stmt: tremor_script::query::StmtRentalWrapper,
+    pub stmt: OwnedStmt,
     pub op: Box<dyn Operator>,
 }
 
 impl TrickleOperator {
     #[allow(clippy::too_many_lines)]
-    pub fn with_stmt(
-        id: String,
-        stmt_rentwrapped: tremor_script::query::StmtRentalWrapper,
-    ) -> Result<Self> {
+    pub fn with_stmt(id: String, stmt: OwnedStmt) -> Result<Self> {
         use crate::op;
-        let stmt = stmt_rentwrapped.suffix();
-        let op: Box<dyn Operator> = match stmt {
+        let op: Box<dyn Operator> = match stmt.stmt() {
             tremor_script::ast::Stmt::OperatorDecl(ref op) => {
                 match (op.kind.module.as_str(), op.kind.operation.as_str()) {
                     ("debug", "history") => {
@@ -138,6 +133,46 @@ impl TrickleOperator {
                         })
                     }
                     ("generic", "counter") => Box::new(op::generic::counter::Counter {}),
+                    ("generic", "dedup") => {
+                        use op::generic::dedup::{Config, Dedup, Mode};
+                        let params = op.params.as_ref();
+                        let key = params
+                            .and_then(|v| v.get("key"))
+                            .and_then(Value::as_str)
+                            .ok_or_else(|| missing_config("key"))?
+                            .to_string();
+                        let mode = match params.and_then(|v| v.get("mode")).and_then(Value::as_str)
+                        {
+                            None | Some("lru") => Mode::Lru,
+                            Some("bloom") => Mode::Bloom,
+                            Some(other) => {
+                                return Err(ErrorKind::BadOpConfig(format!(
+                                    "Unknown dedup mode: {}",
+                                    other
+                                ))
+                                .into())
+                            }
+                        };
+                        let capacity = params
+                            .and_then(|v| v.get("capacity"))
+                            .and_then(Value::as_usize)
+                            .unwrap_or(10_000);
+                        let ttl = params.and_then(|v| v.get("ttl")).and_then(Value::as_u64);
+                        let false_positive_rate = params
+                            .and_then(|v| v.get("false_positive_rate"))
+                            .and_then(Value::cast_f64)
+                            .unwrap_or(0.01);
+                        Box::new(Dedup::new(
+                            common_cow(&op.id),
+                            Config {
+                                key,
+                                mode,
+                                capacity,
+                                ttl,
+                                false_positive_rate,
+                            },
+                        )?)
+                    }
                     ("grouper", "bucket") => Box::new(op::grouper::bucket::Grouper {
                         buckets: HashMap::new(),
                         _id: common_cow(&op.id),
@@ -153,11 +188,7 @@ impl TrickleOperator {
             }
         };
 
-        Ok(Self {
-            id,
-            stmt: stmt_rentwrapped,
-            op,
-        })
+        Ok(Self { id, stmt, op })
     }
 }
 
@@ -170,4 +201,12 @@ impl Operator for TrickleOperator {
     ) -> Result<Vec<(Cow<'static, str>, Event)>> {
         self.op.on_event(port, state, event)
     }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        self.op.snapshot()
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        self.op.restore(snapshot)
+    }
 }
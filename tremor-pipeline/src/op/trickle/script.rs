@@ -13,73 +13,38 @@
 // limitations under the License.
 
 use crate::op::prelude::*;
+use crate::ERROR_PORT;
 use std::mem;
-use std::sync::Arc;
+use tremor_script::ast::ScriptDecl;
 use tremor_script::prelude::*;
-use tremor_script::ARGS_CONST_ID;
+use tremor_script::query::OwnedStmt;
+use tremor_script::{Limits, ARGS_CONST_ID};
 
-rental! {
-    pub mod rentals {
-        use tremor_script::query::StmtRental;
-        use std::sync::Arc;
-        use halfbrown::HashMap;
-        use serde::Serialize;
-        use super::*;
-
-        #[rental(covariant,debug)]
-        pub struct Script {
-            stmt: Arc<StmtRental>,
-            script: tremor_script::ast::ScriptDecl<'stmt>,
-        }
-    }
-}
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(clippy::module_name_repetitions)]
 pub struct TrickleScript {
     pub id: String,
-    pub defn: Arc<tremor_script::query::StmtRental>,
-    pub node: Arc<tremor_script::query::StmtRental>,
-    script: rentals::Script,
+    pub defn: OwnedStmt,
+    pub node: OwnedStmt,
+    script: ScriptDecl<'static>,
 }
 
 impl TrickleScript {
-    pub fn with_stmt(
-        id: String,
-        defn_rentwrapped: tremor_script::query::StmtRentalWrapper,
-        node_rentwrapped: tremor_script::query::StmtRentalWrapper,
-    ) -> Result<Self> {
-        // We require Value to be static here to enforce the constraint that
-        // arguments name/value pairs live at least as long as the operator nodes that have
-        // dependencies on them.
-        //
-        // Note also that definitional parameters and instance parameters have slightly
-        // different costs. The definitional paraemeters ( if not overriden ) never change
-        // but instance parameters that do override must be guaranteed as static to ensure
-        // their lifetimes don't go out of scope. We avoid this with definitional arguments
-        // as they are always available once specified.
-        //
-        // The key to why this is the case is the binding lifetime as it is associated with
-        // the definition ( from which all instances are incarnated ) not the 'create' instances.
-        // The binding association chooses the definition simply as it hosts the parsed script.
-        //
+    pub fn with_stmt(id: String, defn: OwnedStmt, node: OwnedStmt, limits: Limits) -> Result<Self> {
         let args: Value;
 
         let mut params = HashMap::new();
-        if let tremor_script::ast::query::Stmt::ScriptDecl(ref defn) = defn_rentwrapped.suffix() {
-            if let Some(p) = &defn.params {
+        if let tremor_script::ast::query::Stmt::ScriptDecl(ref decl) = defn.stmt() {
+            if let Some(p) = &decl.params {
                 // Set params from decl as meta vars
                 for (name, value) in p {
-                    // We could clone here since we bind Script to defn_rentwrapped.stmt's lifetime
                     params.insert(Cow::Owned(name.clone()), value.clone());
                 }
                 // Set params from instance as meta vars ( eg: upsert ~= override + add )
-                if let tremor_script::ast::query::Stmt::Script(instance) = node_rentwrapped.suffix()
-                {
+                if let tremor_script::ast::query::Stmt::Script(instance) = node.stmt() {
                     if let Some(map) = &instance.params {
                         for (name, value) in map {
-                            // We can not clone here since we do not bind Script to node_rentwrapped's lifetime
-                            params.insert(Cow::Owned(name.clone()), value.clone_static());
+                            params.insert(Cow::Owned(name.clone()), value.clone());
                         }
                     }
                 } else {
@@ -97,7 +62,7 @@ impl TrickleScript {
             )
             .into());
         };
-        let script = match defn_rentwrapped.suffix() {
+        let script = match defn.stmt() {
             tremor_script::ast::Stmt::ScriptDecl(ref script) => script.clone(),
             _other => {
                 return Err(ErrorKind::PipelineError(
@@ -107,23 +72,15 @@ impl TrickleScript {
             }
         };
 
-        let script = rentals::Script::new(defn_rentwrapped.stmt.clone(), move |_| unsafe {
-            use tremor_script::ast::ScriptDecl;
-            // This is sound since defn_rentwrapped.stmt is an arc by cloning
-            // it we ensure that the referenced data remains available until
-            // the rental is dropped.
-            let mut decl = mem::transmute::<ScriptDecl<'_>, ScriptDecl<'static>>(*script);
-            let args: Value<'static> = mem::transmute(args);
-
-            decl.script.consts = vec![Value::null(), Value::null(), Value::null()];
-            decl.script.consts[ARGS_CONST_ID] = args;
-            decl
-        });
+        let mut script = *script;
+        script.script.consts = vec![Value::null(), Value::null(), Value::null()];
+        script.script.consts[ARGS_CONST_ID] = args;
+        script.script.limits = limits;
 
         Ok(Self {
             id,
-            defn: defn_rentwrapped.stmt,
-            node: node_rentwrapped.stmt,
+            defn,
+            node,
             script,
         })
     }
@@ -148,7 +105,7 @@ impl Operator for TrickleScript {
             unsafe { mem::transmute(data.value()) };
         let event_meta: &'_ mut tremor_script::Value<'_> = unsafe { mem::transmute(data.meta()) };
 
-        let value = self.script.suffix().script.run(
+        let value = self.script.script.run(
             &context,
             AggrType::Emit,
             unwind_event, // event
@@ -169,15 +126,17 @@ impl Operator for TrickleScript {
                 Ok(vec![(port.map_or_else(|| "out".into(), Cow::Owned), event)])
             }
             Ok(Return::Drop) => Ok(vec![]),
+            // Runtime errors are emitted on the error port where the error
+            // policy of the pipeline picks them up
             Err(e) => {
                 let mut o = Value::from(hashmap! {
-                    "error".into() => Value::from(self.node.head().format_error(&e)),
+                    "error".into() => Value::from(self.node.format_error(&e)),
                 });
                 mem::swap(&mut o, unwind_event);
                 if let Some(error) = unwind_event.as_object_mut() {
                     error.insert("event".into(), o);
                 };
-                Ok(vec![("error".into(), event)])
+                Ok(vec![(ERROR_PORT.into(), event)])
             }
         }
     }
@@ -19,19 +19,20 @@ use crate::{Event, Operator};
 use halfbrown::HashMap;
 use simd_json::borrowed::Value;
 use std::borrow::Cow;
-use std::mem;
+use std::cmp::Ordering;
 use std::sync::Arc;
+use tremor_script::debugger::Frame;
 use tremor_script::interpreter::Env;
-use tremor_script::query::StmtRentalWrapper;
-use tremor_script::utils::sorsorted_serialize;
+use tremor_script::utils::{sorsorted_serialize, value_cmp};
 use tremor_script::{
     self,
     ast::{
-        InvokeAggrFn, Select, SelectStmt, WindowDecl, ARGS_CONST_ID, GROUP_CONST_ID,
-        WINDOW_CONST_ID,
+        InvokeAggrFn, OrderDirection, Select, SelectStmt, WindowDecl, ARGS_CONST_ID,
+        GROUP_CONST_ID, WINDOW_CONST_ID,
     },
     prelude::*,
-    query::StmtRental,
+    query::OwnedStmt,
+    Limits,
 };
 
 pub type Aggrs<'script> = Vec<InvokeAggrFn<'script>>;
@@ -43,48 +44,14 @@ pub struct GroupData<'groups> {
     aggrs: Aggrs<'groups>,
 }
 type Groups<'groups> = HashMap<String, GroupData<'groups>>;
-rental! {
-    pub mod rentals {
-        use std::sync::Arc;
-        use halfbrown::HashMap;
-        use super::*;
-
-        #[rental(covariant,debug,clone)]
-        pub struct Dims {
-            query: Arc<StmtRental>,
-            groups: Groups<'query>,
-        }
-
-        #[rental(covariant,debug)]
-        pub struct Select {
-            stmt: Arc<StmtRental>,
-            select: tremor_script::ast::SelectStmt<'stmt>,
-        }
-        #[rental(covariant, debug, clone)]
-        pub struct Window {
-            stmt: Arc<StmtRental>,
-            window: WindowDecl<'stmt>,
-        }
-    }
-}
-pub use rentals::Dims as SelectDims;
-
-impl SelectDims {
-    pub fn from_query(stmt: Arc<StmtRental>) -> Self {
-        Self::new(stmt, |_| HashMap::new())
-    }
-    #[allow(mutable_transmutes, clippy::transmute_ptr_to_ptr, clippy::mut_from_ref)]
-    unsafe fn mut_suffix(&self) -> &mut Groups<'static> {
-        std::mem::transmute(self.suffix())
-    }
-}
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TrickleSelect {
     pub id: String,
-    pub select: rentals::Select,
+    pub select: SelectStmt<'static>,
     pub windows: Vec<Window>,
+    pub limits: Limits,
 }
 
 pub trait WindowTrait: std::fmt::Debug {
@@ -92,13 +59,13 @@ pub trait WindowTrait: std::fmt::Debug {
     fn eviction_ns(&self) -> Option<u64>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Window {
     window_impl: WindowImpl,
     module: Vec<String>,
     name: String,
-    dims: SelectDims,
-    last_dims: SelectDims,
+    dims: Groups<'static>,
+    last_dims: Groups<'static>,
     next_swap: u64,
 }
 
@@ -175,6 +142,38 @@ impl From<TumblingWindowOnTime> for WindowImpl {
     }
 }
 
+impl WindowImpl {
+    /// Captures the position of the window, the configuration of
+    /// the window is not part of the snapshot.
+    fn snapshot(&self) -> Value<'static> {
+        match self {
+            Self::TumblingTimeBased(w) => w.next_window.map_or_else(Value::null, Value::from),
+            Self::TumblingCountBased(w) => Value::from(w.count),
+            Self::No(w) => Value::from(w.open),
+        }
+    }
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        match self {
+            Self::TumblingTimeBased(w) => {
+                w.next_window = if snapshot.is_null() {
+                    None
+                } else {
+                    Some(snapshot.as_u64().ok_or_else(invalid_snapshot)?)
+                }
+            }
+            Self::TumblingCountBased(w) => {
+                w.count = snapshot.as_u64().ok_or_else(invalid_snapshot)?;
+            }
+            Self::No(w) => w.open = snapshot.as_bool().ok_or_else(invalid_snapshot)?,
+        }
+        Ok(())
+    }
+}
+
+fn invalid_snapshot() -> Error {
+    ErrorKind::PipelineError("Invalid select snapshot".into()).into()
+}
+
 #[derive(Debug, PartialEq)]
 pub struct WindowEvent {
     /// New window is opened,
@@ -213,22 +212,11 @@ pub struct TumblingWindowOnTime {
     next_window: Option<u64>,
     size: u64,
     ttl: Option<u64>,
-    script: Option<rentals::Window>,
+    script: Option<Arc<WindowDecl<'static>>>,
 }
 impl TumblingWindowOnTime {
-    pub fn from_stmt(
-        size: u64,
-        ttl: Option<u64>,
-        script: Option<&WindowDecl>,
-        stmt: &StmtRentalWrapper,
-    ) -> Self {
-        let script = script.map(|s| {
-            rentals::Window::new(stmt.stmt.clone(), |_| unsafe {
-                // This is sound since stmt.stmt is an arc that holds
-                // the borrwed data
-                mem::transmute::<WindowDecl<'_>, WindowDecl<'static>>(s.clone())
-            })
-        });
+    pub fn from_stmt(size: u64, ttl: Option<u64>, script: Option<&WindowDecl<'static>>) -> Self {
+        let script = script.map(|s| Arc::new(s.clone()));
         Self {
             next_window: None,
             size,
@@ -246,7 +234,7 @@ impl WindowTrait for TumblingWindowOnTime {
         let time = self
             .script
             .as_ref()
-            .and_then(|script| script.suffix().script.as_ref())
+            .and_then(|decl| decl.script.as_ref())
             .map(|script| {
                 // TODO avoid origin_uri clone here
                 let context = EventContext::new(event.ingest_ns, event.origin_uri.clone());
@@ -294,25 +282,12 @@ pub struct TumblingWindowOnNumber {
     count: u64,
     size: u64,
     ttl: Option<u64>,
-    script: Option<rentals::Window>,
+    script: Option<Arc<WindowDecl<'static>>>,
 }
 
 impl TumblingWindowOnNumber {
-    pub fn from_stmt(
-        size: u64,
-        ttl: Option<u64>,
-        script: Option<&WindowDecl>,
-        stmt: &StmtRentalWrapper,
-    ) -> Self {
-        let script = script.map(|s| {
-            rentals::Window::new(stmt.stmt.clone(), |_| unsafe {
-                // This is safe since `stmt.stmt` is an Arc that
-                // hods the referenced data and we clone it into the rental.
-                // This ensures refferenced data isn't dropped until the rental
-                // is dropped.
-                mem::transmute::<WindowDecl<'_>, WindowDecl<'static>>(s.clone())
-            })
-        });
+    pub fn from_stmt(size: u64, ttl: Option<u64>, script: Option<&WindowDecl<'static>>) -> Self {
+        let script = script.map(|s| Arc::new(s.clone()));
         Self {
             count: 0,
             size,
@@ -329,7 +304,7 @@ impl WindowTrait for TumblingWindowOnNumber {
         let count = self
             .script
             .as_ref()
-            .and_then(|script| script.suffix().script.as_ref())
+            .and_then(|decl| decl.script.as_ref())
             .map(|script| {
                 // TODO avoid origin_uri clone here
                 let context = EventContext::new(event.ingest_ns, event.origin_uri.clone());
@@ -370,14 +345,136 @@ impl WindowTrait for TumblingWindowOnNumber {
 
 const NO_AGGRS: [InvokeAggrFn<'static>; 0] = [];
 
+/// Applies the `order by` and `limit` clauses to the results emitted by
+/// the windows during a single event. `keys` holds the index of the
+/// emitting window and the evaluated order expression for each event.
+///
+/// Results are kept grouped by window (narrowest first), ordered inside
+/// each window and truncated to `limit` results per window.
+fn order_and_limit(
+    events: Vec<(Cow<'static, str>, Event)>,
+    keys: Vec<(usize, Value<'static>)>,
+    direction: Option<OrderDirection>,
+    limit: Option<usize>,
+) -> Vec<(Cow<'static, str>, Event)> {
+    let mut tagged: Vec<_> = keys.into_iter().zip(events).collect();
+    // This is a stable sort so results with equal keys keep their emit order
+    tagged.sort_by(|((l_win, l_key), _), ((r_win, r_key), _)| {
+        l_win.cmp(r_win).then_with(|| match direction {
+            Some(OrderDirection::Asc) => value_cmp(l_key, r_key),
+            Some(OrderDirection::Desc) => value_cmp(r_key, l_key),
+            None => Ordering::Equal,
+        })
+    });
+    let mut last_win = None;
+    let mut count = 0;
+    tagged
+        .into_iter()
+        .filter_map(|((win, _), event)| {
+            if last_win != Some(win) {
+                last_win = Some(win);
+                count = 0;
+            }
+            count += 1;
+            if limit.map_or(true, |limit| count <= limit) {
+                Some(event)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Looks up the data of a group in a window, the group is moved over from
+/// the previous generation of groups or created if it isn't known yet.
+fn group_entry<'groups>(
+    groups: &'groups mut Groups<'static>,
+    last_groups: &mut Groups<'static>,
+    window_impl: &WindowImpl,
+    aggregates: &[InvokeAggrFn<'static>],
+    group_str: &str,
+    group_value: &Value,
+) -> &'groups mut GroupData<'static> {
+    let (_, group) = groups
+        .raw_entry_mut()
+        .from_key(group_str)
+        .or_insert_with(|| {
+            (
+                group_str.to_string(),
+                last_groups.remove(group_str).unwrap_or_else(|| GroupData {
+                    window: window_impl.clone(),
+                    aggrs: aggregates.to_vec(),
+                    group: group_value.clone_static(),
+                }),
+            )
+        });
+    group
+}
+
+fn snapshot_groups(groups: &Groups) -> Value<'static> {
+    let mut snapshot = HashMap::new();
+    for (group_str, data) in groups {
+        // Aggregates that can't be snapshotted are stored as `null` and
+        // start from a fresh state when restored
+        let aggrs: Vec<Value<'static>> = data
+            .aggrs
+            .iter()
+            .map(|aggr| aggr.invocable.snapshot().unwrap_or_else(Value::null))
+            .collect();
+        let mut group = HashMap::new();
+        group.insert("group".into(), data.group.clone());
+        group.insert("window".into(), data.window.snapshot());
+        group.insert("aggrs".into(), Value::from(aggrs));
+        snapshot.insert(group_str.clone().into(), Value::from(group));
+    }
+    Value::from(snapshot)
+}
+
+fn restore_groups(
+    groups: &mut Groups<'static>,
+    snapshot: &Value,
+    window_impl: &WindowImpl,
+    aggregates: &[InvokeAggrFn<'static>],
+) -> Result<()> {
+    groups.clear();
+    for (group_str, data) in snapshot.as_object().ok_or_else(invalid_snapshot)? {
+        let mut window = window_impl.clone();
+        window.restore(data.get("window").ok_or_else(invalid_snapshot)?)?;
+        let mut aggrs = aggregates.to_vec();
+        let aggr_snapshots = data
+            .get("aggrs")
+            .and_then(Value::as_array)
+            .ok_or_else(invalid_snapshot)?;
+        for (aggr, aggr_snapshot) in aggrs.iter_mut().zip(aggr_snapshots) {
+            if !aggr_snapshot.is_null() {
+                aggr.invocable
+                    .restore(aggr_snapshot)
+                    .map_err(|e| Error::from(format!("{:?}", e)))?;
+            }
+        }
+        groups.insert(
+            group_str.to_string(),
+            GroupData {
+                group: data
+                    .get("group")
+                    .ok_or_else(invalid_snapshot)?
+                    .clone_static(),
+                window,
+                aggrs,
+            },
+        );
+    }
+    Ok(())
+}
+
 impl TrickleSelect {
     pub fn with_stmt(
         id: String,
-        dims: &SelectDims,
         windows: Vec<(String, WindowImpl)>,
-        stmt_rentwrapped: &tremor_script::query::StmtRentalWrapper,
+        stmt: &OwnedStmt,
+        limits: Limits,
     ) -> Result<Self> {
-        let select = match stmt_rentwrapped.suffix() {
+        let select = match stmt.stmt() {
             tremor_script::ast::Stmt::Select(ref select) => select.clone(),
             _ => {
                 return Err(ErrorKind::PipelineError(
@@ -390,8 +487,8 @@ impl TrickleSelect {
         let windows = windows
             .into_iter()
             .map(|(fqwn, window_impl)| Window {
-                dims: dims.clone(),
-                last_dims: dims.clone(),
+                dims: HashMap::new(),
+                last_dims: HashMap::new(),
                 module: Window::module_path(&fqwn),
                 name: Window::ident_name(&fqwn),
                 window_impl,
@@ -400,14 +497,9 @@ impl TrickleSelect {
             .collect();
         Ok(Self {
             id,
+            select,
             windows,
-            select: rentals::Select::new(stmt_rentwrapped.stmt.clone(), move |_| unsafe {
-                // This is safe since `stmt_rentwrapped.stmt` is an Arc that
-                // hods the referenced data and we clone it into the rental.
-                // This ensures refferenced data isn't dropped until the rental
-                // is dropped.
-                mem::transmute::<SelectStmt<'_>, SelectStmt<'static>>(select)
-            }),
+            limits,
         })
     }
     fn opts() -> ExecOpts {
@@ -419,11 +511,7 @@ impl TrickleSelect {
 }
 
 impl Operator for TrickleSelect {
-    #[allow(
-        mutable_transmutes,
-        clippy::transmute_ptr_to_ptr,
-        clippy::too_many_lines
-    )]
+    #[allow(clippy::too_many_lines)]
     fn on_event(
         &mut self,
         _port: &str,
@@ -431,18 +519,21 @@ impl Operator for TrickleSelect {
         event: Event,
     ) -> Result<Vec<(Cow<'static, str>, Event)>> {
         let opts = Self::opts();
+        let budget = self.limits.budget();
+        let debugger = tremor_script::debugger::current();
+        // ALLOW: the guard leaves the debugger frame again once it goes out of scope
+        let _frame = debugger
+            .as_ref()
+            .map(|d| d.enter(Frame::new("select", vec![])));
         // We guarantee at compile time that select in itself can't have locals, so this is safe
-
-        // NOTE We are unwrapping our rental wrapped stmt
-
-        // FIXME: reason about soundness
         let SelectStmt {
             stmt,
             aggregates,
             consts,
             locals,
+            functions,
             node_meta,
-        }: &mut SelectStmt = unsafe { mem::transmute(self.select.suffix()) };
+        } = &mut self.select;
         let local_stack = tremor_script::interpreter::LocalStack::with_size(*locals);
         consts[WINDOW_CONST_ID] = Value::null();
         consts[GROUP_CONST_ID] = Value::null();
@@ -461,7 +552,10 @@ impl Operator for TrickleSelect {
                 consts: &consts,
                 aggrs: &NO_AGGRS,
                 meta: &node_meta,
+                functions: &functions,
                 recursion_limit: tremor_script::recursion_limit(),
+                budget: budget.as_ref(),
+                debugger: debugger.as_deref(),
             };
             let test = guard.run(opts, &env, unwind_event, state, event_meta, &local_stack)?;
             if let Some(test) = test.as_bool() {
@@ -475,6 +569,10 @@ impl Operator for TrickleSelect {
         }
 
         let mut events = vec![];
+        // The window index and order key of each windowed result, only
+        // collected if we need to order or limit them.
+        let order_results = stmt.maybe_order_by.is_some() || stmt.maybe_limit.is_some();
+        let mut order_keys: Vec<(usize, Value<'static>)> = vec![];
 
         let mut group_values = {
             let data = event.data.suffix();
@@ -503,7 +601,10 @@ impl Operator for TrickleSelect {
                 consts: &consts,
                 aggrs: &NO_AGGRS,
                 meta: &node_meta,
+                functions: &functions,
                 recursion_limit: tremor_script::recursion_limit(),
+                budget: budget.as_ref(),
+                debugger: debugger.as_deref(),
             };
             let value =
                 stmt.target
@@ -539,15 +640,8 @@ impl Operator for TrickleSelect {
             if let Some(eviction_ns) = window.window_impl.eviction_ns() {
                 if window.next_swap < event.ingest_ns {
                     window.next_swap = event.ingest_ns + eviction_ns;
-                    unsafe {
-                        // Windows are never added or deleted, we can argue that no
-                        // data allocated in one window will be accessed after
-                        // any other window is dropped
-                        let this_groups = window.dims.mut_suffix();
-                        let last_groups = window.last_dims.mut_suffix();
-                        last_groups.clear();
-                        std::mem::swap(this_groups, last_groups);
-                    }
+                    window.last_dims.clear();
+                    std::mem::swap(&mut window.dims, &mut window.last_dims);
                 }
             }
         }
@@ -560,23 +654,14 @@ impl Operator for TrickleSelect {
 
             // We first iterate through the windows and emit as far as we would have to emit.
             while let Some(this) = windows.next() {
-                // This is sound since we only add mutability to groups
-                let this_groups = unsafe { this.dims.mut_suffix() };
-                let (_, this_group) = this_groups
-                    .raw_entry_mut()
-                    .from_key(&group_str)
-                    .or_insert_with(|| {
-                        // This is sound since we only add mutability to groups
-                        let last_groups = unsafe { this.last_dims.mut_suffix() };
-                        (
-                            group_str.clone(),
-                            last_groups.remove(&group_str).unwrap_or_else(|| GroupData {
-                                window: this.window_impl.clone(),
-                                aggrs: aggregates.clone(),
-                                group: group_value.clone_static(),
-                            }),
-                        )
-                    });
+                let this_group = group_entry(
+                    &mut this.dims,
+                    &mut this.last_dims,
+                    &this.window_impl,
+                    aggregates,
+                    &group_str,
+                    &group_value,
+                );
                 let window_event = this_group.window.on_event(&event)?;
                 // The issue with the windows is the following:
                 // We emit on the first event of the next windows, this works well for the inital frame
@@ -618,7 +703,10 @@ impl Operator for TrickleSelect {
                         consts: &consts,
                         aggrs: &this_group.aggrs,
                         meta: &node_meta,
+                        functions: &functions,
                         recursion_limit: tremor_script::recursion_limit(),
+                        budget: budget.as_ref(),
+                        debugger: debugger.as_deref(),
                     };
                     let result = stmt.target.run(
                         opts,
@@ -642,6 +730,18 @@ impl Operator for TrickleSelect {
                             )?;
                         }
                     }
+                    if order_results {
+                        let key = if let Some(order_by) = &stmt.maybe_order_by {
+                            order_by
+                                .expr
+                                .run(opts, &env, &result, state, &NULL, &local_stack)?
+                                .into_owned()
+                                .into_static()
+                        } else {
+                            Value::null()
+                        };
+                        order_keys.push((emit_depth, key));
+                    }
                     let result = result.into_owned();
                     events.push((
                         "out".into(),
@@ -670,84 +770,57 @@ impl Operator for TrickleSelect {
             // Check if we are emitting all the windows, if not we need to take
             // special care in the code below
             let mut clear_all = emit_count == self.windows.len();
-            let mut windows = self
-                .windows
-                .iter_mut()
-                // We look one beyond the emit count this is required
-                // since we go 'backwards' from the widest to the narrowest.
-                // Since we do not want to clean the next not emitted
-                // frame we calcualted emit_all above.
-                .take(emit_count + 1)
-                // We reverse the iterator since we want to start with
-                // the widest window
-                .rev()
-                .peekable();
-
-            while let Some(this) = windows.next() {
+            // We look one beyond the emit count this is required
+            // since we go 'backwards' from the widest to the narrowest.
+            // Since we do not want to clean the next not emitted
+            // frame we calcualted emit_all above.
+            let window_count = (emit_count + 1).min(self.windows.len());
+            let windows = &mut self.windows[..window_count];
+
+            // We iterate in reverse since we want to start with
+            // the widest window
+            for idx in (0..window_count).rev() {
+                let (prevs, rest) = windows.split_at_mut(idx);
+                let this = &mut rest[0];
                 // First start with getting our group
-
-                // FIXME: reason about soundness
-                let this_groups = unsafe { this.dims.mut_suffix() };
-                let (_, this_group) = this_groups
-                    .raw_entry_mut()
-                    .from_key(&group_str)
-                    .or_insert_with(|| {
-                        // FIXME: reason about soundness
-                        let last_groups = unsafe { this.last_dims.mut_suffix() };
-                        (
-                            group_str.clone(),
-                            last_groups.remove(&group_str).unwrap_or_else(|| GroupData {
-                                window: this.window_impl.clone(),
-                                aggrs: aggregates.clone(),
-                                group: group_value.clone_static(),
-                            }),
-                        )
-                    });
+                let this_group = group_entry(
+                    &mut this.dims,
+                    &mut this.last_dims,
+                    &this.window_impl,
+                    aggregates,
+                    &group_str,
+                    &group_value,
+                );
 
                 // Check if we want to clear all the following window
                 // this is false for a non terminal widest window
                 if clear_all {
-                    for aggr in &this_group.aggrs {
-                        // FIXME: reason about soundness
-                        let aggr_static: &mut InvokeAggrFn<'static> =
-                            unsafe { std::mem::transmute(aggr) };
-                        aggr_static.invocable.init();
+                    for aggr in &mut this_group.aggrs {
+                        aggr.invocable.init();
                     }
                 } else {
                     // If we skipped the widest window we can clear the rest
                     clear_all = true;
                 }
 
-                // Remember we iterate backwards so we the next element in the iterator
+                // Remember we iterate backwards so we the next element
                 // is the previous window.
                 // We grab it and then merge the data from the previous window into this
                 // This ensures that in the next iteration of the leep we can clear
                 // the previous window since we already pulled all needed data out here.
-                if let Some(prev) = windows.peek() {
-                    // FIXME: reason about soundness
-                    let prev_groups = unsafe { prev.dims.mut_suffix() };
-                    let (_, prev_group) = prev_groups
-                        .raw_entry_mut()
-                        .from_key(&group_str)
-                        .or_insert_with(|| {
-                            // FIXME: reason about soundness
-                            let last_groups = unsafe { prev.last_dims.mut_suffix() };
-                            (
-                                group_str.clone(),
-                                last_groups.remove(&group_str).unwrap_or_else(|| GroupData {
-                                    window: prev.window_impl.clone(),
-                                    aggrs: aggregates.clone(),
-                                    group: group_value.clone_static(),
-                                }),
-                            )
-                        });
+                if let Some(prev) = prevs.last_mut() {
+                    let prev_group = group_entry(
+                        &mut prev.dims,
+                        &mut prev.last_dims,
+                        &prev.window_impl,
+                        aggregates,
+                        &group_str,
+                        &group_value,
+                    );
                     for (i, aggr) in prev_group.aggrs.iter().enumerate() {
-                        // FIXME: reason about soundness
-                        let aggr_static: &InvokeAggrFn<'static> =
-                            unsafe { std::mem::transmute(aggr) };
                         this_group.aggrs[i]
                             .invocable
-                            .merge(&aggr_static.invocable)
+                            .merge(&aggr.invocable)
                             .map_err(|e| {
                                 // FIXME nice error
                                 let r: Option<&Registry> = None;
@@ -758,24 +831,17 @@ impl Operator for TrickleSelect {
             }
 
             // If we had at least one window ingest the event into this window
-            if let Some(this) = self.windows.first() {
+            if let Some(this) = self.windows.first_mut() {
                 let (unwind_event, event_meta) = event.data.parts();
                 consts[WINDOW_CONST_ID] = Value::from(this.name.to_string());
-                // FIXME: reason about soundness
-                let this_groups = unsafe { this.dims.mut_suffix() };
-                let (_, this_group) = this_groups
-                    .raw_entry_mut()
-                    .from_key(&group_str)
-                    .or_insert_with(|| {
-                        (
-                            group_str.clone(),
-                            GroupData {
-                                window: this.window_impl.clone(),
-                                aggrs: aggregates.clone(),
-                                group: group_value.clone_static(),
-                            },
-                        )
-                    });
+                let this_group = group_entry(
+                    &mut this.dims,
+                    &mut this.last_dims,
+                    &this.window_impl,
+                    aggregates,
+                    &group_str,
+                    &group_value,
+                );
                 consts[GROUP_CONST_ID] = group_value.clone_static();
                 consts[GROUP_CONST_ID].push(group_str.clone()).ok();
 
@@ -784,7 +850,10 @@ impl Operator for TrickleSelect {
                     consts: &consts,
                     aggrs: &NO_AGGRS,
                     meta: &node_meta,
+                    functions: &functions,
                     recursion_limit: tremor_script::recursion_limit(),
+                    budget: budget.as_ref(),
+                    debugger: debugger.as_deref(),
                 };
                 for aggr in &mut this_group.aggrs {
                     let invocable = &mut aggr.invocable;
@@ -819,7 +888,10 @@ impl Operator for TrickleSelect {
                     consts: &consts,
                     aggrs: &NO_AGGRS,
                     meta: &node_meta,
+                    functions: &functions,
                     recursion_limit: tremor_script::recursion_limit(),
+                    budget: budget.as_ref(),
+                    debugger: debugger.as_deref(),
                 };
                 let value =
                     stmt.target
@@ -853,8 +925,54 @@ impl Operator for TrickleSelect {
                 ));
             }
         }
+        if order_results {
+            events = order_and_limit(
+                events,
+                order_keys,
+                stmt.maybe_order_by.as_ref().map(|o| o.direction),
+                stmt.maybe_limit,
+            );
+        }
         Ok(events)
     }
+
+    fn snapshot(&self) -> Result<Option<Value<'static>>> {
+        let mut windows = HashMap::new();
+        for window in &self.windows {
+            let mut snapshot = HashMap::new();
+            snapshot.insert("next_swap".into(), Value::from(window.next_swap));
+            snapshot.insert("groups".into(), snapshot_groups(&window.dims));
+            snapshot.insert("last_groups".into(), snapshot_groups(&window.last_dims));
+            windows.insert(window.name.clone().into(), Value::from(snapshot));
+        }
+        Ok(Some(Value::from(windows)))
+    }
+
+    fn restore(&mut self, snapshot: &Value) -> Result<()> {
+        let aggregates = &self.select.aggregates;
+        for window in &mut self.windows {
+            // Windows that are new since the snapshot was taken start empty
+            if let Some(snapshot) = snapshot.get(window.name.as_str()) {
+                window.next_swap = snapshot
+                    .get("next_swap")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(invalid_snapshot)?;
+                restore_groups(
+                    &mut window.dims,
+                    snapshot.get("groups").ok_or_else(invalid_snapshot)?,
+                    &window.window_impl,
+                    aggregates,
+                )?;
+                restore_groups(
+                    &mut window.last_dims,
+                    snapshot.get("last_groups").ok_or_else(invalid_snapshot)?,
+                    &window.window_impl,
+                    aggregates,
+                )?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -886,17 +1004,8 @@ mod test {
             windows: vec![],
             maybe_group_by: None,
             maybe_having: None,
-        }
-    }
-
-    fn test_query(stmt: ast::Stmt) -> ast::Query {
-        ast::Query {
-            stmts: vec![stmt.clone()],
-            node_meta: ast::NodeMetas::new(Vec::new()),
-            windows: HashMap::new(),
-            scripts: HashMap::new(),
-            operators: HashMap::new(),
-            config: HashMap::new(),
+            maybe_order_by: None,
+            maybe_limit: None,
         }
     }
 
@@ -914,10 +1023,7 @@ mod test {
         }
     }
 
-    use std::sync::Arc;
-
-    fn test_select(stmt: tremor_script::query::StmtRentalWrapper) -> Result<TrickleSelect> {
-        let groups = SelectDims::from_query(stmt.stmt.clone());
+    fn test_select(stmt: OwnedStmt) -> Result<TrickleSelect> {
         let windows = vec![
             (
                 "15s".into(),
@@ -941,7 +1047,7 @@ mod test {
             ),
         ];
         let id = "select".to_string();
-        TrickleSelect::with_stmt(id, &groups, windows, &stmt)
+        TrickleSelect::with_stmt(id, windows, &stmt, Limits::default())
     }
 
     fn try_enqueue(
@@ -992,12 +1098,10 @@ mod test {
         )
         .map_err(tremor_script::errors::CompilerError::error)?;
 
-        let stmt_rental = tremor_script::query::StmtRental::new(Arc::new(query.clone()), |q| {
-            q.suffix().stmts[0].clone()
-        });
-        let stmt = tremor_script::query::StmtRentalWrapper {
-            stmt: Arc::new(stmt_rental),
-        };
+        let stmt = OwnedStmt::new(
+            query.suffix().stmts[0].clone(),
+            Arc::new(query.source.clone()),
+        );
         Ok(test_select(stmt)?)
     }
 
@@ -1017,6 +1121,43 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_clone() -> Result<()> {
+        let mut op = parse_query(
+            "test.trickle".to_string(),
+            "select aggr::stats::sum(event.h2g2) from in into out;",
+        )?;
+        assert!(try_enqueue(&mut op, test_event(0))?.is_none());
+        assert!(try_enqueue(&mut op, test_event(1))?.is_none());
+
+        // The clone carries over the groups but runs independently
+        let mut cloned = op.clone();
+        assert!(try_enqueue(&mut op, test_event(2))?.is_none());
+        let (_, event) = try_enqueue(&mut op, test_event(15))?.expect("no event from op");
+        assert_eq!(*event.data.suffix().value(), 126.0);
+        let (_, event) = try_enqueue(&mut cloned, test_event(15))?.expect("no event from clone");
+        assert_eq!(*event.data.suffix().value(), 84.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_restore() -> Result<()> {
+        let query = "select aggr::stats::sum(event.h2g2) from in into out;";
+        let mut op = parse_query("test.trickle".to_string(), query)?;
+        assert!(try_enqueue(&mut op, test_event(0))?.is_none());
+        assert!(try_enqueue(&mut op, test_event(1))?.is_none());
+        let snapshot = op.snapshot()?.expect("select has a snapshot");
+
+        let mut op = parse_query("test.trickle".to_string(), query)?;
+        op.restore(&snapshot)?;
+        let (out, event) =
+            try_enqueue(&mut op, test_event(15))?.expect("no event emitted after aggregation");
+        assert_eq!("out", out);
+
+        assert_eq!(*event.data.suffix().value(), 84.0);
+        Ok(())
+    }
+
     #[test]
     fn test_count() -> Result<()> {
         let mut op = parse_query(
@@ -1082,25 +1223,7 @@ mod test {
         let stmt_ast = test_stmt(target);
 
         let stmt_ast = test_select_stmt(stmt_ast);
-        let script = "fake".to_string();
-        let script_box = Box::new(script.clone());
-        let query_rental = Arc::new(tremor_script::query::QueryRental::new(script_box, |_| {
-            test_query(stmt_ast.clone())
-        }));
-
-        let query = tremor_script::query::Query {
-            query: query_rental,
-            locals: 0,
-            source: script,
-            warnings: vec![],
-        };
-
-        let stmt_rental =
-            tremor_script::query::StmtRental::new(Arc::new(query.clone()), |_| stmt_ast);
-
-        let stmt = tremor_script::query::StmtRentalWrapper {
-            stmt: Arc::new(stmt_rental),
-        };
+        let stmt = OwnedStmt::new(stmt_ast, Arc::new("fake".to_string()));
 
         let mut op = test_select(stmt)?;
         assert!(try_enqueue(&mut op, test_event(0))?.is_none());
@@ -1112,6 +1235,22 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn select_limits() -> Result<()> {
+        let target = test_target();
+        let stmt_ast = test_select_stmt(test_stmt(target));
+        let stmt = OwnedStmt::new(stmt_ast, Arc::new("fake".to_string()));
+
+        let mut op = test_select(stmt)?;
+        op.limits = Limits {
+            max_steps: Some(0),
+            ..Limits::default()
+        };
+        // the where clause alone takes a step
+        assert!(try_enqueue(&mut op, test_event(0)).is_err());
+        Ok(())
+    }
+
     #[test]
     fn select_nowin_nogrp_whrt_nohav() -> Result<()> {
         let target = test_target();
@@ -1123,25 +1262,7 @@ mod test {
         }));
 
         let stmt_ast = test_select_stmt(stmt_ast);
-        let script = "fake".to_string();
-        let script_box = Box::new(script.clone());
-        let query_rental = Arc::new(tremor_script::query::QueryRental::new(script_box, |_| {
-            test_query(stmt_ast.clone())
-        }));
-
-        let query = tremor_script::query::Query {
-            query: query_rental,
-            locals: 0,
-            source: script,
-            warnings: vec![],
-        };
-
-        let stmt_rental =
-            tremor_script::query::StmtRental::new(Arc::new(query.clone()), |_| stmt_ast);
-
-        let stmt = tremor_script::query::StmtRentalWrapper {
-            stmt: Arc::new(stmt_rental),
-        };
+        let stmt = OwnedStmt::new(stmt_ast, Arc::new("fake".to_string()));
 
         let mut op = test_select(stmt)?;
 
@@ -1159,31 +1280,13 @@ mod test {
         let target = test_target();
         let mut stmt_ast = test_stmt(target);
 
-        let script = "fake".to_string();
-        let script_box = Box::new(script.clone());
         stmt_ast.maybe_where = Some(ImutExpr::from(ast::Literal {
             mid: 0,
             value: Value::from(false),
         }));
         let stmt_ast = test_select_stmt(stmt_ast);
 
-        let query_rental = Arc::new(tremor_script::query::QueryRental::new(script_box, |_| {
-            test_query(stmt_ast.clone())
-        }));
-
-        let query = tremor_script::query::Query {
-            query: query_rental,
-            locals: 0,
-            source: script,
-            warnings: vec![],
-        };
-
-        let stmt_rental =
-            tremor_script::query::StmtRental::new(Arc::new(query.clone()), |_| stmt_ast);
-
-        let stmt = tremor_script::query::StmtRentalWrapper {
-            stmt: Arc::new(stmt_rental),
-        };
+        let stmt = OwnedStmt::new(stmt_ast, Arc::new("fake".to_string()));
 
         let mut op = test_select(stmt)?;
         let next = try_enqueue(&mut op, test_event(0))?;
@@ -1201,25 +1304,7 @@ mod test {
         }));
 
         let stmt_ast = test_select_stmt(stmt_ast);
-        let script = "fake".to_string();
-        let script_box = Box::new(script.clone());
-        let query_rental = Arc::new(tremor_script::query::QueryRental::new(script_box, |_| {
-            test_query(stmt_ast.clone())
-        }));
-
-        let query = tremor_script::query::Query {
-            query: query_rental,
-            locals: 0,
-            source: script,
-            warnings: vec![],
-        };
-
-        let stmt_rental =
-            tremor_script::query::StmtRental::new(Arc::new(query.clone()), |_| stmt_ast);
-
-        let stmt = tremor_script::query::StmtRentalWrapper {
-            stmt: Arc::new(stmt_rental),
-        };
+        let stmt = OwnedStmt::new(stmt_ast, Arc::new("fake".to_string()));
 
         let mut op = test_select(stmt)?;
 
@@ -1242,25 +1327,7 @@ mod test {
         }));
 
         let stmt_ast = test_select_stmt(stmt_ast);
-        let script = "fake".to_string();
-        let script_box = Box::new(script.clone());
-        let query_rental = Arc::new(tremor_script::query::QueryRental::new(script_box, |_| {
-            test_query(stmt_ast.clone())
-        }));
-
-        let query = tremor_script::query::Query {
-            query: query_rental,
-            locals: 0,
-            source: script,
-            warnings: vec![],
-        };
-
-        let stmt_rental =
-            tremor_script::query::StmtRental::new(Arc::new(query.clone()), |_| stmt_ast);
-
-        let stmt = tremor_script::query::StmtRentalWrapper {
-            stmt: Arc::new(stmt_rental),
-        };
+        let stmt = OwnedStmt::new(stmt_ast, Arc::new("fake".to_string()));
 
         let mut op = test_select(stmt)?;
 
@@ -1288,25 +1355,7 @@ mod test {
         }));
 
         let stmt_ast = test_select_stmt(stmt_ast);
-        let script = "fake".to_string();
-        let script_box = Box::new(script.clone());
-        let query_rental = Arc::new(tremor_script::query::QueryRental::new(script_box, |_| {
-            test_query(stmt_ast.clone())
-        }));
-
-        let query = tremor_script::query::Query {
-            query: query_rental,
-            locals: 0,
-            source: script,
-            warnings: vec![],
-        };
-
-        let stmt_rental =
-            tremor_script::query::StmtRental::new(Arc::new(query.clone()), |_| stmt_ast);
-
-        let stmt = tremor_script::query::StmtRentalWrapper {
-            stmt: Arc::new(stmt_rental),
-        };
+        let stmt = OwnedStmt::new(stmt_ast, Arc::new("fake".to_string()));
 
         let mut op = test_select(stmt)?;
         let event = test_event(0);
@@ -1323,6 +1372,7 @@ mod test {
             aggregates: vec![],
             consts: vec![Value::null(), Value::null(), Value::null()],
             locals: 0,
+            functions: vec![],
             node_meta: ast::NodeMetas::new(vec![]),
         })
     }
@@ -1343,25 +1393,7 @@ mod test {
         }));
 
         let stmt_ast = test_select_stmt(stmt_ast);
-        let script = "fake".to_string();
-        let script_box = Box::new(script.clone());
-        let query_rental = Arc::new(tremor_script::query::QueryRental::new(script_box, |_| {
-            test_query(stmt_ast.clone())
-        }));
-
-        let query = tremor_script::query::Query {
-            query: query_rental,
-            locals: 0,
-            source: script,
-            warnings: vec![],
-        };
-
-        let stmt_rental =
-            tremor_script::query::StmtRental::new(Arc::new(query.clone()), |_| stmt_ast);
-
-        let stmt = tremor_script::query::StmtRentalWrapper {
-            stmt: Arc::new(stmt_rental),
-        };
+        let stmt = OwnedStmt::new(stmt_ast, Arc::new("fake".to_string()));
 
         let mut op = test_select(stmt)?;
         let event = test_event(0);
@@ -17,43 +17,29 @@
 use crate::errors::{ErrorKind, Result};
 use crate::{Event, Operator};
 use std::borrow::Cow;
+use tremor_script::debugger::Frame;
 use tremor_script::interpreter::Env;
 use tremor_script::{
     self,
     ast::{InvokeAggrFn, Select, SelectStmt},
     prelude::*,
-    query::StmtRental,
+    query::OwnedStmt,
+    Limits,
 };
 
-rental! {
-    pub mod rentals {
-        use std::sync::Arc;
-        use halfbrown::HashMap;
-        use super::*;
-
-        #[rental(covariant,debug)]
-        pub struct Select {
-            stmt: Arc<StmtRental>,
-            select: tremor_script::ast::SelectStmt<'stmt>,
-        }
-    }
-}
-
 #[allow(clippy::module_name_repetitions)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TrickleSimpleSelect {
     pub id: String,
-    pub select: rentals::Select,
+    pub select: SelectStmt<'static>,
+    pub limits: Limits,
 }
 
 const NO_AGGRS: [InvokeAggrFn<'static>; 0] = [];
 
 impl TrickleSimpleSelect {
-    pub fn with_stmt(
-        id: String,
-        stmt_rentwrapped: &tremor_script::query::StmtRentalWrapper,
-    ) -> Result<Self> {
-        let select = match stmt_rentwrapped.suffix() {
+    pub fn with_stmt(id: String, stmt: &OwnedStmt, limits: Limits) -> Result<Self> {
+        let select = match stmt.stmt() {
             tremor_script::ast::Stmt::Select(ref select) => select.clone(),
             _ => {
                 return Err(ErrorKind::PipelineError(
@@ -63,14 +49,7 @@ impl TrickleSimpleSelect {
             }
         };
 
-        Ok(Self {
-            id,
-            select: rentals::Select::new(stmt_rentwrapped.stmt.clone(), move |_| unsafe {
-                // This is sound since we keep an arc of the borrowed data in
-                // stmt
-                std::mem::transmute::<SelectStmt<'_>, SelectStmt<'static>>(select)
-            }),
-        })
+        Ok(Self { id, select, limits })
     }
     fn opts() -> ExecOpts {
         ExecOpts {
@@ -81,7 +60,6 @@ impl TrickleSimpleSelect {
 }
 
 impl Operator for TrickleSimpleSelect {
-    #[allow(mutable_transmutes, clippy::transmute_ptr_to_ptr)]
     fn on_event(
         &mut self,
         _port: &str,
@@ -89,17 +67,21 @@ impl Operator for TrickleSimpleSelect {
         event: Event,
     ) -> Result<Vec<(Cow<'static, str>, Event)>> {
         let opts = Self::opts();
+        let budget = self.limits.budget();
+        let debugger = tremor_script::debugger::current();
+        // ALLOW: the guard leaves the debugger frame again once it goes out of scope
+        let _frame = debugger
+            .as_ref()
+            .map(|d| d.enter(Frame::new("select", vec![])));
         // We guarantee at compile time that select in itself can't have locals, so this is safe
-
-        // NOTE We are unwrapping our rental wrapped stmt
-        // FIXME: add soundness reasoning
         let SelectStmt {
             stmt,
             locals,
             consts,
+            functions,
             node_meta,
             ..
-        }: &mut SelectStmt = unsafe { std::mem::transmute(self.select.suffix()) };
+        } = &self.select;
         let local_stack = tremor_script::interpreter::LocalStack::with_size(*locals);
         // TODO avoid origin_uri clone here
         let ctx = EventContext::new(event.ingest_ns, event.origin_uri.clone());
@@ -115,7 +97,10 @@ impl Operator for TrickleSimpleSelect {
                 consts: &consts,
                 aggrs: &NO_AGGRS,
                 meta: &node_meta,
+                functions: &functions,
                 recursion_limit: tremor_script::recursion_limit(),
+                budget: budget.as_ref(),
+                debugger: debugger.as_deref(),
             };
             let test = guard.run(opts, &env, unwind_event, state, event_meta, &local_stack)?;
             if let Some(test) = test.as_bool() {
@@ -135,7 +120,10 @@ impl Operator for TrickleSimpleSelect {
                 consts: &consts,
                 aggrs: &NO_AGGRS,
                 meta: &node_meta,
+                functions: &functions,
                 recursion_limit: tremor_script::recursion_limit(),
+                budget: budget.as_ref(),
+                debugger: debugger.as_deref(),
             };
             let test = guard.run(opts, &env, unwind_event, state, event_meta, &local_stack)?;
             if let Some(test) = test.as_bool() {
@@ -12,37 +12,34 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::config::{InputPort, OutputPort};
+use crate::config::{ErrorPolicy, InputPort, OutputPort};
 use crate::errors::{Error, ErrorKind, Result};
 use crate::op;
 use crate::op::trickle::select::WindowImpl;
 use crate::OperatorNode;
-use crate::{common_cow, ConfigGraph, NodeConfig, NodeKind, PortIndexMap};
+use crate::{common_cow, ConfigGraph, NodeConfig, NodeKind, PortIndexMap, ERR_OUTPUT};
 use halfbrown::HashMap;
 use indexmap::IndexMap;
 use op::identity::PassthroughFactory;
 use op::trickle::{
-    operator::TrickleOperator,
-    script::TrickleScript,
-    select::{SelectDims, TrickleSelect},
+    operator::TrickleOperator, script::TrickleScript, select::TrickleSelect,
     simple_select::TrickleSimpleSelect,
 };
 use petgraph::algo::is_cyclic_directed;
 use petgraph::dot::{Config, Dot};
 use simd_json::prelude::*;
 use std::borrow::Cow;
-use std::mem;
 use std::sync::Arc;
 use tremor_script::ast::{CompilationUnit, Ident, SelectType, Stmt, WindowDecl, WindowKind};
 use tremor_script::errors::{query_stream_not_defined, CompilerError};
 use tremor_script::highlighter::Dumb as DumbHighlighter;
 use tremor_script::path::ModulePath;
-use tremor_script::query::{StmtRental, StmtRentalWrapper};
-use tremor_script::{AggrRegistry, Registry, Value};
+use tremor_script::query::OwnedStmt;
+use tremor_script::{AggrRegistry, Limits, Registry, Value};
 
 // Legacy ops for backwards compat with pipeline.yaml at runtime in trickle / extension
 use op::debug::EventHistoryFactory;
-use op::generic::{BackpressureFactory, BatchFactory, CounterFactory};
+use op::generic::{BackpressureFactory, BatchFactory, CounterFactory, DedupFactory};
 use op::grouper::BucketGrouperFactory;
 use op::runtime::TremorFactory;
 
@@ -62,10 +59,7 @@ fn resolve_output_port(port: &(Ident, Ident)) -> OutputPort {
     }
 }
 
-fn window_decl_to_impl<'script>(
-    d: &WindowDecl<'script>,
-    stmt: &StmtRentalWrapper,
-) -> Result<WindowImpl> {
+fn window_decl_to_impl(d: &WindowDecl<'static>) -> Result<WindowImpl> {
     use op::trickle::select::{TumblingWindowOnNumber, TumblingWindowOnTime};
     match &d.kind {
         WindowKind::Sliding => Err("Sliding windows are not yet implemented".into()),
@@ -73,9 +67,9 @@ fn window_decl_to_impl<'script>(
             let script = if d.script.is_some() { Some(d) } else { None };
             let ttl = d.params.get("eviction_period").and_then(Value::as_u64);
             if let Some(interval) = d.params.get("interval").and_then(Value::as_u64) {
-                Ok(TumblingWindowOnTime::from_stmt(interval, ttl, script, stmt).into())
+                Ok(TumblingWindowOnTime::from_stmt(interval, ttl, script).into())
             } else if let Some(size) = d.params.get("size").and_then(Value::as_u64) {
-                Ok(TumblingWindowOnNumber::from_stmt(size, ttl, script, stmt).into())
+                Ok(TumblingWindowOnNumber::from_stmt(size, ttl, script).into())
             } else {
                 Err(Error::from(
                     "Bad window configuration, either `size` or `interval` is required",
@@ -122,6 +116,7 @@ impl Query {
         use std::iter;
 
         let query = self.0.suffix();
+        let source = Arc::new(self.0.source.clone());
 
         let mut pipe_graph = ConfigGraph::new();
         let mut pipe_ops = HashMap::new();
@@ -135,6 +130,27 @@ impl Query {
             .get("metrics_interval_s")
             .and_then(Value::as_u64)
             .map(|i| i * 1_000_000_000);
+        let snapshot_interval = query
+            .config
+            .get("state_snapshot_interval_s")
+            .and_then(Value::as_u64)
+            .map(|i| i * 1_000_000_000);
+        let error_policy = query
+            .config
+            .get("error_policy")
+            .and_then(ErrorPolicy::from_value)
+            .unwrap_or_default();
+        // Select and script nodes get the limits as their config
+        let limits = query
+            .config
+            .get("limits")
+            .map(Limits::from_value)
+            .unwrap_or_default();
+        let limits = if limits.is_unlimited() {
+            None
+        } else {
+            Some(serde_yaml::to_value(limits)?)
+        };
 
         // FIXME compute public streams - do not hardcode
         let in_s: Cow<'static, str> = "in".into();
@@ -152,7 +168,7 @@ impl Query {
         inputs.insert(in_s, id);
 
         // FIXME compute public streams - do not hardcode
-        let err: Cow<'static, str> = "err".into();
+        let err: Cow<'static, str> = ERR_OUTPUT.into();
         let id = pipe_graph.add_node(NodeConfig {
             id: err.clone(),
             kind: NodeKind::Output,
@@ -185,16 +201,7 @@ impl Query {
         let mut select_num = 0;
 
         for stmt in &query.stmts {
-            let stmt_rental = StmtRental::new(Arc::new(self.0.clone()), |_| unsafe {
-                // This is sound since self.0 includes an ARC of the data we
-                // so we hold on to any referenced data by including a clone
-                // of that arc inthe rental source.
-                mem::transmute::<Stmt<'_>, Stmt<'static>>(stmt.clone())
-            });
-
-            let that = tremor_script::query::StmtRentalWrapper {
-                stmt: std::sync::Arc::new(stmt_rental),
-            };
+            let that = OwnedStmt::new(stmt.clone(), source.clone());
 
             match stmt {
                 Stmt::Select(ref select) => {
@@ -274,7 +281,7 @@ impl Query {
                         id: select_in.id.clone(),
                         kind: NodeKind::Operator,
                         op_type: "trickle::select".to_string(),
-                        config: None,
+                        config: limits.clone(),
                         defn: None,
                         node: None,
                     };
@@ -282,7 +289,7 @@ impl Query {
 
                     let mut ww: HashMap<String, WindowImpl> = HashMap::new();
                     for w in &query.windows {
-                        ww.insert(w.0.clone(), window_decl_to_impl(&w.1, &that)?);
+                        ww.insert(w.0.clone(), window_decl_to_impl(&w.1)?);
                     }
                     let op = node.to_op(supported_operators, None, Some(that), Some(ww))?;
                     pipe_ops.insert(id, op);
@@ -334,23 +341,14 @@ impl Query {
                         node: None,
                     };
                     let id = pipe_graph.add_node(node.clone());
-                    let inner_stmt: tremor_script::ast::Stmt = Stmt::OperatorDecl(
+                    let inner_stmt: Stmt<'static> = Stmt::OperatorDecl(
                         query
                             .operators
                             .get(&fqon)
                             .ok_or_else(|| Error::from("operator not found"))?
                             .clone(),
                     );
-                    let stmt_rental = StmtRental::new(Arc::new(self.0.clone()), |_| unsafe {
-                        // This is sound since self.0 includes an ARC of the data we
-                        // so we hold on to any referenced data by including a clone
-                        // of that arc inthe rental source.
-                        mem::transmute::<Stmt<'_>, Stmt<'static>>(inner_stmt)
-                    });
-
-                    let that = StmtRentalWrapper {
-                        stmt: std::sync::Arc::new(stmt_rental),
-                    };
+                    let that = OwnedStmt::new(inner_stmt, source.clone());
                     let op = node.to_op(supported_operators, None, Some(that), None)?;
                     pipe_ops.insert(id, op);
                     nodes.insert(common_cow(&o.id), id);
@@ -363,31 +361,22 @@ impl Query {
                     } else {
                         format!("{}::{}", o.module.join("::"), target)
                     };
-                    let inner_stmt: tremor_script::ast::Stmt = Stmt::ScriptDecl(Box::new(
+                    let inner_stmt: Stmt<'static> = Stmt::ScriptDecl(Box::new(
                         query
                             .scripts
                             .get(&fqsn)
                             .ok_or_else(|| Error::from("script not found"))?
                             .clone(),
                     ));
-                    let stmt_rental = StmtRental::new(Arc::new(self.0.clone()), |_| unsafe {
-                        // This is sound since self.0 includes an ARC of the data we
-                        // so we hold on to any referenced data by including a clone
-                        // of that arc inthe rental source.
-                        mem::transmute::<Stmt<'_>, Stmt<'static>>(inner_stmt)
-                    });
-
-                    let that_defn = tremor_script::query::StmtRentalWrapper {
-                        stmt: std::sync::Arc::new(stmt_rental),
-                    };
+                    let that_defn = OwnedStmt::new(inner_stmt, source.clone());
 
                     let node = NodeConfig {
                         id: common_cow(&o.id),
                         kind: NodeKind::Operator,
                         op_type: "trickle::script".to_string(),
-                        config: None,
-                        defn: Some(std::sync::Arc::new(that_defn.clone())),
-                        node: Some(std::sync::Arc::new(that.clone())),
+                        config: limits.clone(),
+                        defn: Some(that_defn.clone()),
+                        node: Some(that.clone()),
                     };
 
                     let id = pipe_graph.add_node(node.clone());
@@ -510,6 +499,8 @@ impl Query {
                 contraflow,
                 signalflow,
                 metric_interval,
+                snapshot_interval,
+                error_policies: iter::repeat(error_policy).take(graph.len()).collect(),
             };
             exec.optimize();
 
@@ -518,11 +509,21 @@ impl Query {
     }
 }
 
+/// Limits the query configured for the scripts of a node
+fn node_limits(config: &NodeConfig) -> Result<Limits> {
+    Ok(config
+        .config
+        .clone()
+        .map(serde_yaml::from_value)
+        .transpose()?
+        .unwrap_or_default())
+}
+
 #[allow(clippy::implicit_hasher, clippy::too_many_lines)]
 pub(crate) fn supported_operators(
     config: &NodeConfig,
-    defn: Option<tremor_script::query::StmtRentalWrapper>,
-    node: Option<tremor_script::query::StmtRentalWrapper>,
+    defn: Option<OwnedStmt>,
+    node: Option<OwnedStmt>,
     windows: Option<HashMap<String, WindowImpl>>,
 ) -> Result<OperatorNode> {
     let name_parts: Vec<&str> = config.op_type.split("::").collect();
@@ -537,7 +538,7 @@ pub(crate) fn supported_operators(
                 )
                 .into());
             };
-            let select_type = match node.stmt.suffix() {
+            let select_type = match node.stmt() {
                 tremor_script::ast::Stmt::Select(ref select) => select.complexity(),
                 _ => {
                     return Err(ErrorKind::PipelineError(
@@ -554,9 +555,9 @@ pub(crate) fn supported_operators(
                 SelectType::Simple => Box::new(TrickleSimpleSelect::with_stmt(
                     config.id.clone().to_string(),
                     &node,
+                    node_limits(config)?,
                 )?),
                 SelectType::Normal => {
-                    let groups = SelectDims::from_query(node.stmt.clone());
                     let windows = if let Some(windows) = windows {
                         windows
                     } else {
@@ -566,7 +567,7 @@ pub(crate) fn supported_operators(
                         .into());
                     };
                     let windows: Result<Vec<(String, WindowImpl)>> =
-                        if let tremor_script::ast::Stmt::Select(s) = node.stmt.suffix() {
+                        if let tremor_script::ast::Stmt::Select(s) = node.stmt() {
                             s.stmt
                                 .windows
                                 .iter()
@@ -589,9 +590,9 @@ pub(crate) fn supported_operators(
 
                     Box::new(TrickleSelect::with_stmt(
                         config.id.clone().to_string(),
-                        &groups,
                         windows?,
                         &node,
+                        node_limits(config)?,
                     )?)
                 }
             }
@@ -623,6 +624,7 @@ pub(crate) fn supported_operators(
                 config.id.clone().to_string(),
                 defn.ok_or_else(|| Error::from("Script definition missing"))?,
                 node,
+                node_limits(config)?,
             )?)
         }
         ["passthrough"] => {
@@ -635,9 +637,8 @@ pub(crate) fn supported_operators(
         ["generic", "batch"] => BatchFactory::new_boxed().from_node(config)?,
         ["generic", "backpressure"] => BackpressureFactory::new_boxed().from_node(config)?,
         ["generic", "counter"] => CounterFactory::new_boxed().from_node(config)?,
-        [namespace, name] => {
-            return Err(ErrorKind::UnknownOp((*namespace).to_string(), (*name).to_string()).into());
-        }
+        ["generic", "dedup"] => DedupFactory::new_boxed().from_node(config)?,
+        [namespace, name] => crate::registered_op(config, namespace, name)?,
         _ => return Err(ErrorKind::UnknownNamespace(config.op_type.clone()).into()),
     };
     Ok(OperatorNode {
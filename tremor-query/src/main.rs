@@ -27,11 +27,14 @@
 
 use crate::query::Query; // {Query, Return};
 use chrono::{Timelike, Utc};
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::rc::Rc;
 use tremor_pipeline::errors::{Error, ErrorKind, Result};
+use tremor_script::debugger::{self, Console, Debugger};
+use tremor_script::formatter;
 use tremor_script::highlighter::{Highlighter, Term as TermHighlighter};
 use tremor_script::path::load as load_module_path;
 use tremor_script::{
@@ -61,6 +64,23 @@ fn main() -> Result<()> {
                 .required(true)
                 .index(1),
         )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Formats the query in place.")
+                .arg(
+                    Arg::with_name("SCRIPT")
+                        .help("The query to format")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .takes_value(false)
+                        .help("Fails if the query isn't formatted instead of formatting it."),
+                ),
+        )
         .arg(
             Arg::with_name("event")
                 .short("e")
@@ -122,8 +142,48 @@ fn main() -> Result<()> {
                 .takes_value(false)
                 .help("Do not print the result."),
         )
+        .arg(
+            Arg::with_name("check-types")
+                .long("check-types")
+                .takes_value(false)
+                .help("Checks the types in the query before running it."),
+        )
+        .arg(
+            Arg::with_name("schema")
+                .long("schema")
+                .takes_value(true)
+                .requires("check-types")
+                .help("A JSON file describing the types of the event."),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .takes_value(false)
+                .help("Pauses in the debugger before the first expression."),
+        )
+        .arg(
+            Arg::with_name("break")
+                .long("break")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Sets a debugger breakpoint on a line of the script."),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        let script_file = matches
+            .value_of("SCRIPT")
+            .ok_or_else(|| Error::from("No script file provided"))?;
+        let check = matches.is_present("check");
+        if formatter::format_file(script_file, check)? && check {
+            eprintln!("{} is not formatted", script_file);
+            // ALLOW: main.rs
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let script_file = matches
         .value_of("SCRIPT")
         .ok_or_else(|| Error::from("No script file provided"))?;
@@ -140,7 +200,8 @@ fn main() -> Result<()> {
     let aggr_reg = registry::aggr();
 
     let module_path = load_module_path();
-    let runnable = match Query::parse(&module_path, script_file, &raw, vec![], &reg, &aggr_reg) {
+    let mut runnable = match Query::parse(&module_path, script_file, &raw, vec![], &reg, &aggr_reg)
+    {
         Ok(runnable) => runnable,
         Err(e) => {
             let mut h = TermHighlighter::new();
@@ -151,8 +212,38 @@ fn main() -> Result<()> {
             std::process::exit(1);
         }
     };
+    if matches.is_present("check-types") {
+        let mut schema = Vec::new();
+        if let Some(schema_file) = matches.value_of("schema") {
+            File::open(&schema_file)?.read_to_end(&mut schema)?;
+        }
+        let schema = if schema.is_empty() {
+            None
+        } else {
+            Some(simd_json::to_borrowed_value(&mut schema)?)
+        };
+        if let Err(e) = runnable.check_types(schema.as_ref(), &reg.signatures()) {
+            let mut h = TermHighlighter::new();
+            runnable.format_error_with(&mut h, &e)?;
+            // ALLOW: main.rs
+            std::process::exit(1);
+        }
+    }
     let mut h = TermHighlighter::new();
     runnable.format_warnings_with(&mut h)?;
+    if matches.is_present("debug") || matches.is_present("break") {
+        let debugger = Debugger::new(Box::new(Console::new(script_file, &raw)));
+        if matches.is_present("debug") {
+            debugger.pause();
+        }
+        for line in matches.values_of("break").into_iter().flatten() {
+            let line = line
+                .parse()
+                .map_err(|_| Error::from(format!("Invalid breakpoint: {}", line)))?;
+            debugger.set_breakpoint(line);
+        }
+        debugger::attach(Rc::new(debugger));
+    }
     let runnable = tremor_pipeline::query::Query(runnable);
     if matches.is_present("highlight-source") {
         println!();
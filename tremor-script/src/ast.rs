@@ -13,24 +13,29 @@
 // limitations under the License.
 
 pub(crate) mod base_expr;
+mod optimizer;
 /// Query AST
 pub mod query;
 pub(crate) mod raw;
 mod support;
 mod upable;
+use crate::debugger::{self, Frame};
 use crate::errors::{error_generic, error_no_consts, error_no_locals, Result};
 use crate::impl_expr2;
-use crate::interpreter::{AggrType, Cont, Env, ExecOpts, LocalStack};
+use crate::interpreter::{AggrType, Cont, Env, ExecOpts, LocalStack, Program};
 pub use crate::lexer::CompilationUnit;
+use crate::limits::Limits;
 use crate::pos::{Location, Range};
 use crate::registry::{
-    Aggr as AggrRegistry, CustomFn, Registry, TremorAggrFnWrapper, TremorFnWrapper,
+    call_fn_value, Aggr as AggrRegistry, CustomAggr, CustomFn, Registry, TremorAggrFnWrapper,
+    TremorFnWrapper,
 };
 use crate::script::Return;
 use crate::stry;
 use crate::tilde::Extractor;
 pub use base_expr::BaseExpr;
 use halfbrown::HashMap;
+pub(crate) use optimizer::{LookupTable, Rewrite};
 pub use query::*;
 use serde::Serialize;
 use simd_json::{prelude::*, BorrowedValue as Value, KnownKey};
@@ -267,8 +272,10 @@ where
     // this could be achieved in a cleaner and faster way, and `Warning` already implements `Ord`
     // anyway.
     warnings: Vec<Warning>,
+    rewrites: Vec<Rewrite>,
     shadowed_vars: Vec<String>,
     func_vec: Vec<CustomFn<'script>>,
+    aggr_vec: HashMap<Vec<String>, TremorAggrFnWrapper>,
     pub locals: HashMap<String, usize>,
     pub functions: HashMap<Vec<String>, usize>,
     pub consts: HashMap<Vec<String>, usize>,
@@ -345,11 +352,13 @@ where
             operators: HashMap::new(),
             aggregates: Vec::new(),
             warnings: Vec::new(),
+            rewrites: Vec::new(),
             locals: HashMap::new(),
             consts: HashMap::new(),
             streams: HashMap::new(),
             functions: HashMap::new(),
             func_vec: Vec::new(),
+            aggr_vec: HashMap::new(),
             shadowed_vars: Vec::new(),
             meta: NodeMetas::new(cus),
             docs: Docs::default(),
@@ -377,6 +386,27 @@ where
         }
     }
 
+    fn register_aggr(&mut self, name: String, aggr: CustomAggr) -> Result<()> {
+        let mut ma = self.module.clone();
+        ma.push(name.clone());
+        if self.aggr_vec.contains_key(&ma) {
+            return Err(format!("aggregate function {} already defined.", name).into());
+        }
+        let module = self.module.join("::");
+        self.aggr_vec
+            .insert(ma, TremorAggrFnWrapper::new(module, name, Box::new(aggr)));
+        Ok(())
+    }
+
+    /// Finds an aggregate function declared in a module relative to the
+    /// current module
+    fn find_aggr(&self, module: &[String], fun: &str) -> Option<&TremorAggrFnWrapper> {
+        let mut ma = self.module.clone();
+        ma.extend_from_slice(module);
+        ma.push(fun.to_string());
+        self.aggr_vec.get(&ma)
+    }
+
     fn register_shadow_var(&mut self, id: &str) -> usize {
         let r = self.reserve_shadow();
         self.shadowed_vars.push(id.to_string());
@@ -440,11 +470,20 @@ pub struct Script<'script> {
     aggregates: Vec<InvokeAggrFn<'script>>,
     windows: HashMap<String, WindowDecl<'script>>,
     functions: Vec<CustomFn<'script>>,
-    locals: usize,
+    pub(crate) locals: usize,
+    /// Names of the locals by their index
+    #[serde(skip)]
+    pub(crate) local_names: Vec<String>,
     pub(crate) node_meta: NodeMetas,
     #[serde(skip)]
     /// Documentaiton from the script
     pub docs: Docs<'script>,
+    #[serde(skip)]
+    pub(crate) program: Program<'script>,
+    /// Rewrites applied by the optimizer
+    pub(crate) rewrites: Vec<Rewrite>,
+    /// Limits the script has to stay within for every event
+    pub limits: Limits,
 }
 
 impl<'input, 'run, 'script, 'event> Script<'script>
@@ -462,25 +501,68 @@ where
         state: &'run mut Value<'static>,
         meta: &'run mut Value<'event>,
     ) -> Result<Return<'event>> {
-        let mut local = LocalStack::with_size(self.locals);
-
-        let mut exprs = self.exprs.iter().peekable();
-        let opts = ExecOpts {
-            result_needed: true,
-            aggr,
-        };
-
+        let budget = self.limits.budget();
+        let debugger = debugger::current();
+        // ALLOW: the guard leaves the debugger frame again once it goes out of scope
+        let _frame = debugger
+            .as_ref()
+            .map(|d| d.enter(Frame::new("script", self.local_names.clone())));
         let env = Env {
             context,
             consts: &self.consts,
             aggrs: &self.aggregates,
             meta: &self.node_meta,
             recursion_limit: crate::recursion_limit(),
+            functions: &self.functions,
+            budget: budget.as_ref(),
+            debugger: debugger.as_deref(),
         };
+        let r = stry!(self.run_exprs(&env, aggr, event, state, meta));
+        if let (Some(budget), Some(last)) = (&budget, self.exprs.last()) {
+            match &r {
+                Return::Emit { value, .. } => {
+                    stry!(budget.finish(last, value, meta, &self.node_meta))
+                }
+                Return::EmitEvent { .. } => {
+                    stry!(budget.finish(last, event, meta, &self.node_meta))
+                }
+                Return::Drop => (),
+            }
+        }
+        Ok(r)
+    }
 
-        while let Some(expr) = exprs.next() {
+    fn run_exprs<'exec>(
+        &'script self,
+        env: &'exec Env<'exec, 'event, 'script>,
+        aggr: AggrType,
+        event: &'exec mut Value<'event>,
+        state: &'exec mut Value<'static>,
+        meta: &'exec mut Value<'event>,
+    ) -> Result<Return<'event>>
+    where
+        'event: 'exec,
+    {
+        let mut local = LocalStack::with_size(self.locals);
+
+        let mut exprs = self.exprs.iter().enumerate().peekable();
+        let opts = ExecOpts {
+            result_needed: true,
+            aggr,
+        };
+
+        while let Some((idx, expr)) = exprs.next() {
             if exprs.peek().is_none() {
-                match stry!(expr.run(opts.with_result(), &env, event, state, meta, &mut local)) {
+                match stry!(self.program.exec(
+                    idx,
+                    expr,
+                    opts.with_result(),
+                    env,
+                    event,
+                    state,
+                    meta,
+                    &mut local
+                )) {
                     Cont::Drop => return Ok(Return::Drop),
                     Cont::Emit(value, port) => return Ok(Return::Emit { value, port }),
                     Cont::EmitEvent(port) => {
@@ -494,7 +576,16 @@ where
                     }
                 }
             } else {
-                match stry!(expr.run(opts.without_result(), &env, event, state, meta, &mut local)) {
+                match stry!(self.program.exec(
+                    idx,
+                    expr,
+                    opts.without_result(),
+                    env,
+                    event,
+                    state,
+                    meta,
+                    &mut local
+                )) {
                     Cont::Drop => return Ok(Return::Drop),
                     Cont::Emit(value, port) => return Ok(Return::Emit { value, port }),
                     Cont::EmitEvent(port) => {
@@ -700,6 +791,7 @@ pub(crate) enum ImutExprInt<'script> {
     Invoke(Invoke<'script>),
     InvokeAggr(InvokeAggr),
     Recur(Recur<'script>),
+    Try(Box<Try<'script>>),
 }
 
 fn is_lit<'script>(e: &ImutExprInt<'script>) -> bool {
@@ -775,6 +867,11 @@ impl<'script> Invocable<'script> {
         'event: 'run,
     {
         match self {
+            Invocable::Intrinsic(f) if f.is_higher_order() => {
+                f.invoke_with(env.context, args, &mut |fun, args| {
+                    call_fn_value(env, fun, args)
+                })
+            }
             Invocable::Intrinsic(f) => f.invoke(env.context, args),
             Invocable::Tremor(f) => f.invoke(env, args),
         }
@@ -825,6 +922,7 @@ pub(crate) struct Match<'script> {
     pub mid: usize,
     pub target: ImutExprInt<'script>,
     pub patterns: Predicates<'script>,
+    pub tables: Vec<LookupTable<'script>>,
 }
 impl_expr2!(Match);
 
@@ -833,9 +931,20 @@ pub(crate) struct ImutMatch<'script> {
     pub mid: usize,
     pub target: ImutExprInt<'script>,
     pub patterns: ImutPredicates<'script>,
+    pub tables: Vec<LookupTable<'script>>,
 }
 impl_expr2!(ImutMatch);
 
+/// `try <expr> catch <pattern> => <exprs> end`, the patterns are matched
+/// against a record describing the error raised by `expr`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct Try<'script> {
+    pub mid: usize,
+    pub expr: ImutExprInt<'script>,
+    pub catches: ImutPredicates<'script>,
+}
+impl_expr2!(Try);
+
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub(crate) struct PredicateClause<'script> {
     pub mid: usize,
@@ -953,6 +1062,7 @@ pub(crate) enum Pattern<'script> {
     Expr(ImutExprInt<'script>),
     Assign(AssignPattern<'script>),
     Tuple(TuplePattern<'script>),
+    Bytes(BytesPattern),
     DoNotCare,
     Default,
 }
@@ -1080,6 +1190,29 @@ pub(crate) struct TuplePattern<'script> {
 }
 impl_expr2!(TuplePattern);
 
+/// A bit pattern `%<< ... >>` matched against a binary, segments are read
+/// big endian. Unless the pattern ends in `_` the binary has to be fully
+/// consumed by the segments.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct BytesPattern {
+    pub mid: usize,
+    pub segments: Vec<BytesPatternSegment>,
+    pub rest: bool,
+}
+
+impl BaseExpr for BytesPattern {
+    fn mid(&self) -> usize {
+        self.mid
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct BytesPatternSegment {
+    pub bits: u64,
+    /// the value the segment has to have, `None` for `_`
+    pub value: Option<u64>,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub(crate) enum Path<'script> {
     Const(LocalPath<'script>),
@@ -116,6 +116,7 @@ impl<'script> BaseExpr for ImutExprInt<'script> {
             ImutExprInt::Patch(e) => e.s(meta),
             ImutExprInt::Path(e) => e.s(meta),
             ImutExprInt::Record(e) => e.s(meta),
+            ImutExprInt::Try(e) => e.s(meta),
             ImutExprInt::Unary(e) => e.s(meta),
         }
     }
@@ -139,6 +140,7 @@ impl<'script> BaseExpr for ImutExprInt<'script> {
                 meta.end(*mid).unwrap_or_default()
             }
             ImutExprInt::Record(e) => e.e(meta),
+            ImutExprInt::Try(e) => e.e(meta),
             ImutExprInt::Unary(e) => e.e(meta),
         }
     }
@@ -160,6 +162,7 @@ impl<'script> BaseExpr for ImutExprInt<'script> {
             ImutExprInt::Recur(e) => e.mid(),
             ImutExprInt::Local { mid, .. } | ImutExprInt::Present { mid, .. } => *mid,
             ImutExprInt::Record(e) => e.mid(),
+            ImutExprInt::Try(e) => e.mid(),
             ImutExprInt::Unary(e) => e.mid(),
         }
     }
@@ -234,8 +237,11 @@ impl<'script> BaseExpr for ImutExprRaw<'script> {
     fn s(&self, meta: &NodeMetas) -> Location {
         match self {
             ImutExprRaw::Binary(e) => e.start,
+            ImutExprRaw::Bytes(e) => e.start,
+            ImutExprRaw::FnRef(e) => e.start,
             ImutExprRaw::Comprehension(e) => e.start,
             ImutExprRaw::Invoke(e) => e.s(meta),
+            ImutExprRaw::Lambda(e) => e.start,
             ImutExprRaw::List(e) => e.s(meta),
             ImutExprRaw::Literal(e) => e.s(meta),
             ImutExprRaw::Match(e) => e.start,
@@ -246,14 +252,18 @@ impl<'script> BaseExpr for ImutExprRaw<'script> {
             ImutExprRaw::Record(e) => e.s(meta),
             ImutExprRaw::Recur(e) => e.s(meta),
             ImutExprRaw::String(e) => e.start,
+            ImutExprRaw::Try(e) => e.start,
             ImutExprRaw::Unary(e) => e.start,
         }
     }
     fn e(&self, meta: &NodeMetas) -> Location {
         match self {
             ImutExprRaw::Binary(e) => e.end,
+            ImutExprRaw::Bytes(e) => e.end,
+            ImutExprRaw::FnRef(e) => e.end,
             ImutExprRaw::Comprehension(e) => e.end,
             ImutExprRaw::Invoke(e) => e.e(meta),
+            ImutExprRaw::Lambda(e) => e.end,
             ImutExprRaw::List(e) => e.e(meta),
             ImutExprRaw::Literal(e) => e.e(meta),
             ImutExprRaw::Match(e) => e.end,
@@ -264,6 +274,7 @@ impl<'script> BaseExpr for ImutExprRaw<'script> {
             ImutExprRaw::Record(e) => e.e(meta),
             ImutExprRaw::Recur(e) => e.e(meta),
             ImutExprRaw::String(e) => e.end,
+            ImutExprRaw::Try(e) => e.end,
             ImutExprRaw::Unary(e) => e.end,
         }
     }
@@ -0,0 +1,348 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rewrites applied while turning the raw AST into the AST.
+//!
+//! Constant expressions are folded as they are created (see
+//! `ImutExprInt::reduce`), match expressions lose arms that can never be
+//! reached and runs of arms that compare against string literals are
+//! turned into lookup tables so they don't have to be tested one by one.
+//! Every rewrite is recorded and part of the AST printed by `--print-ast`.
+
+use super::{
+    BinOpKind, Helper, ImutExprInt, ImutPredicateClause, Literal, Pattern, PredicateClause,
+    PredicatePattern,
+};
+use crate::interpreter::val_eq;
+use crate::pos::Range;
+use halfbrown::HashMap;
+use serde::Serialize;
+use simd_json::prelude::*;
+use simd_json::{BorrowedValue as Value, KnownKey};
+use std::borrow::Cow;
+
+/// Minimum number of consecutive arms that get turned into a lookup table
+const MIN_TABLE_ARMS: usize = 4;
+
+/// A rewrite the optimizer applied
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct Rewrite {
+    range: Range,
+    rewrite: String,
+}
+
+impl<'script, 'registry> Helper<'script, 'registry>
+where
+    'script: 'registry,
+{
+    pub(crate) fn rewrite<S: ToString>(&mut self, range: Range, rewrite: &S) {
+        self.rewrites.push(Rewrite {
+            range,
+            rewrite: rewrite.to_string(),
+        })
+    }
+
+    pub(crate) fn rewrite_mid<S: ToString>(&mut self, mid: usize, rewrite: &S) {
+        let range = Range(
+            self.meta.start(mid).unwrap_or_default(),
+            self.meta.end(mid).unwrap_or_default(),
+        );
+        self.rewrite(range, rewrite)
+    }
+}
+
+/// A lookup table for the arms `start..end` of a match, they all compare
+/// either the target or the same key of the target against a string.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub(crate) struct LookupTable<'script> {
+    start: usize,
+    end: usize,
+    field: Option<Cow<'script, str>>,
+    #[serde(skip)]
+    key: Option<KnownKey<'script>>,
+    arms: HashMap<String, usize>,
+}
+
+impl<'script> LookupTable<'script> {
+    /// The index of the first arm that can match `target`, this is `end`
+    /// if none of the arms in the table does.
+    pub(crate) fn lookup(&self, target: &Value) -> usize {
+        let testee = if let Some(key) = &self.key {
+            key.lookup(target)
+        } else {
+            Some(target)
+        };
+        testee
+            .and_then(Value::as_str)
+            .and_then(|s| self.arms.get(s))
+            .copied()
+            .unwrap_or(self.end)
+    }
+
+    pub(crate) fn start(&self) -> usize {
+        self.start
+    }
+
+    pub(crate) fn end(&self) -> usize {
+        self.end
+    }
+}
+
+/// The parts of a match arm the optimizer looks at
+pub(crate) trait Clause<'script> {
+    fn pattern(&self) -> &Pattern<'script>;
+    fn guard(&self) -> &Option<ImutExprInt<'script>>;
+}
+
+impl<'script> Clause<'script> for PredicateClause<'script> {
+    fn pattern(&self) -> &Pattern<'script> {
+        &self.pattern
+    }
+    fn guard(&self) -> &Option<ImutExprInt<'script>> {
+        &self.guard
+    }
+}
+
+impl<'script> Clause<'script> for ImutPredicateClause<'script> {
+    fn pattern(&self) -> &Pattern<'script> {
+        &self.pattern
+    }
+    fn guard(&self) -> &Option<ImutExprInt<'script>> {
+        &self.guard
+    }
+}
+
+/// Whether a clause matches a literal target, `None` if we can't tell
+/// without running it.
+fn matches<'script, C: Clause<'script>>(target: Option<&Value>, clause: &C) -> Option<bool> {
+    let pattern_matches = match (clause.pattern(), target) {
+        (Pattern::Default, _) | (Pattern::DoNotCare, _) => true,
+        (Pattern::Expr(ImutExprInt::Literal(Literal { value, .. })), Some(target)) => {
+            if val_eq(target, value) {
+                true
+            } else {
+                // the guard is never evaluated if the pattern doesn't match
+                return Some(false);
+            }
+        }
+        _ => return None,
+    };
+    if clause.guard().is_none() {
+        Some(pattern_matches)
+    } else {
+        None
+    }
+}
+
+/// The string a clause compares against and the key it compares, if any.
+fn table_entry<'c, 'script, C: Clause<'script>>(
+    clause: &'c C,
+) -> Option<(
+    Option<(&'c Cow<'script, str>, &'c KnownKey<'script>)>,
+    &'c str,
+)> {
+    if clause.guard().is_some() {
+        return None;
+    }
+    match clause.pattern() {
+        Pattern::Expr(ImutExprInt::Literal(Literal { value, .. })) => {
+            value.as_str().map(|s| (None, s))
+        }
+        Pattern::Record(rp) => match rp.fields.as_slice() {
+            [PredicatePattern::Bin {
+                lhs,
+                key,
+                rhs: ImutExprInt::Literal(Literal { value, .. }),
+                kind: BinOpKind::Eq,
+            }] => value.as_str().map(|s| (Some((lhs, key)), s)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Removes unreachable arms from a match and builds lookup tables for it
+pub(crate) fn optimize_match<'script, C: Clause<'script>>(
+    target: &ImutExprInt<'script>,
+    patterns: &mut Vec<C>,
+    range: Range,
+    helper: &mut Helper<'script, '_>,
+) -> Vec<LookupTable<'script>> {
+    let literal = if let ImutExprInt::Literal(Literal { value, .. }) = target {
+        Some(value)
+    } else {
+        None
+    };
+
+    let total = patterns.len();
+    let mut kept = Vec::with_capacity(total);
+    for clause in patterns.drain(..) {
+        match matches(literal, &clause) {
+            Some(false) => (),
+            Some(true) => {
+                kept.push(clause);
+                break;
+            }
+            None => kept.push(clause),
+        }
+    }
+    *patterns = kept;
+    if patterns.len() < total {
+        helper.rewrite(
+            range,
+            &format!(
+                "removed {} unreachable arm(s) from match",
+                total - patterns.len()
+            ),
+        );
+    }
+
+    let mut tables = Vec::new();
+    let mut start = 0;
+    while start < patterns.len() {
+        let field = if let Some((field, _)) = patterns.get(start).and_then(table_entry) {
+            field
+        } else {
+            start += 1;
+            continue;
+        };
+        let mut arms = HashMap::new();
+        let mut end = start;
+        while let Some((f, s)) = patterns.get(end).and_then(table_entry) {
+            if f.map(|(name, _)| name) != field.map(|(name, _)| name) {
+                break;
+            }
+            // The first arm wins, later ones with the same value never match
+            arms.entry(s.to_string()).or_insert(end);
+            end += 1;
+        }
+        if end - start >= MIN_TABLE_ARMS {
+            helper.rewrite(
+                range,
+                &format!(
+                    "compiled arms {} to {} of match into a lookup table",
+                    start,
+                    end - 1
+                ),
+            );
+            tables.push(LookupTable {
+                start,
+                end,
+                field: field.map(|(name, _)| name.clone()),
+                key: field.map(|(_, key)| key.clone()),
+                arms,
+            });
+        }
+        start = end;
+    }
+    tables
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ast::Expr;
+    use crate::path::ModulePath;
+    use crate::registry;
+    use crate::{AggrType, EventContext, Return, Script};
+    use simd_json::json;
+
+    fn parse(src: &str) -> Script {
+        let reg = registry::registry();
+        match Script::parse(&ModulePath { mounts: vec![] }, "test", src.into(), &reg) {
+            Ok(s) => s,
+            Err(e) => panic!("{:?}", e.error()),
+        }
+    }
+
+    fn run(script: &Script, event: Value<'static>) -> Value<'static> {
+        let context = EventContext::new(0, None);
+        let mut event = event;
+        let mut state = Value::null();
+        let mut meta = Value::from(simd_json::value::borrowed::Object::new());
+        match script.run(&context, AggrType::Tick, &mut event, &mut state, &mut meta) {
+            Ok(Return::Emit { value, .. }) => value.into_static(),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    fn rewrites(script: &Script) -> Vec<String> {
+        script
+            .script
+            .rewrites
+            .iter()
+            .map(|r| r.rewrite.clone())
+            .collect()
+    }
+
+    #[test]
+    fn fold() {
+        let script = parse("const c = 2; c * 3 + string::len(\"snot\")");
+        assert_eq!(run(&script, Value::null()), Value::from(10));
+        assert!(rewrites(&script)
+            .iter()
+            .any(|r| r.starts_with("folded call to constant function")));
+    }
+
+    #[test]
+    fn dead_arms() {
+        let script = parse(
+            r#"
+match "b" of
+  case "a" => 1
+  case "b" => 2
+  case "c" => 3
+  default => 4
+end"#,
+        );
+        if let Some(Expr::Match(m)) = script.script.exprs.first() {
+            assert_eq!(m.patterns.len(), 1);
+        } else {
+            panic!("not a match");
+        }
+        assert_eq!(run(&script, Value::null()), Value::from(2));
+        assert_eq!(
+            rewrites(&script),
+            vec!["removed 3 unreachable arm(s) from match"]
+        );
+    }
+
+    #[test]
+    fn lookup_table() {
+        let script = parse(
+            r#"
+match event of
+  case %{ class == "a" } => 1
+  case %{ class == "b" } => 2
+  case %{ class == "a" } => 3
+  case %{ class == "c" } => 4
+  case %{ present other } => 5
+  default => 6
+end"#,
+        );
+        if let Some(Expr::Match(m)) = script.script.exprs.first() {
+            assert_eq!(m.tables.len(), 1);
+            assert_eq!(m.tables[0].end, 4);
+        } else {
+            panic!("not a match");
+        }
+        let r = |e: simd_json::OwnedValue| run(&script, Value::from(e));
+        assert_eq!(r(json!({"class": "a"})), Value::from(1));
+        assert_eq!(r(json!({"class": "b"})), Value::from(2));
+        assert_eq!(r(json!({"class": "c"})), Value::from(4));
+        assert_eq!(r(json!({"class": "d", "other": 1})), Value::from(5));
+        assert_eq!(r(json!({"class": 1})), Value::from(6));
+        assert_eq!(r(json!("a")), Value::from(6));
+    }
+}
@@ -15,9 +15,9 @@
 pub(crate) mod raw;
 use super::raw::BaseExpr;
 use super::{
-    error_generic, error_no_consts, error_no_locals, AggrRegistry, Builder, Cow, EventPath,
-    HashMap, Helper, Ident, ImutExpr, ImutExprInt, InvokeAggrFn, Location, NodeMetas, Path,
-    Registry, Result, Script, Serialize, Stmts, Upable, Value, Warning,
+    error_generic, error_no_consts, error_no_locals, AggrRegistry, Builder, Cow, CustomFn,
+    EventPath, HashMap, Helper, Ident, ImutExpr, ImutExprInt, InvokeAggrFn, Location, NodeMetas,
+    Path, Registry, Result, Script, Serialize, Stmts, Upable, Value, Warning,
 };
 use crate::impl_expr2;
 use raw::WindowDefnRaw;
@@ -78,6 +78,8 @@ pub struct SelectStmt<'script> {
     pub consts: Vec<Value<'script>>,
     /// Number of locals
     pub locals: usize,
+    /// Functions function values in the statement refer to
+    pub functions: Vec<CustomFn<'script>>,
     /// Node metadata nodes
     pub node_meta: NodeMetas,
 }
@@ -270,9 +272,33 @@ pub struct Select<'script> {
     pub maybe_group_by: Option<GroupBy<'script>>,
     /// Window
     pub windows: Vec<WindowDefnRaw<'script>>,
+    /// Order-By clause
+    pub maybe_order_by: Option<OrderBy<'script>>,
+    /// Limit clause
+    pub maybe_limit: Option<usize>,
 }
 impl_expr2!(Select);
 
+/// The direction an order by clause sorts in
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum OrderDirection {
+    /// Smallest first
+    Asc,
+    /// Largest first
+    Desc,
+}
+
+/// An order by clause
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OrderBy<'script> {
+    pub(crate) mid: usize,
+    /// The expression the results are ordered by
+    pub expr: ImutExpr<'script>,
+    /// The direction of the ordering
+    pub direction: OrderDirection,
+}
+impl_expr2!(OrderBy);
+
 /// A group by clause
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct GroupBy<'script>(pub(crate) GroupByInt<'script>);
@@ -21,9 +21,9 @@ use super::super::raw::{
 use super::{
     error_generic, error_no_consts, error_no_locals, AggrRegistry, Builder, Cow, GroupBy,
     GroupByInt, HashMap, Helper, ImutExpr, Location, NodeMetas, OperatorDecl, OperatorKind,
-    OperatorStmt, Query, Registry, Result, ScriptDecl, ScriptStmt, Select, SelectStmt, Serialize,
-    Stmt, StreamStmt, Upable, Value, Warning, WindowDecl, WindowKind, ARGS_CONST_ID,
-    GROUP_CONST_ID, WINDOW_CONST_ID,
+    OperatorStmt, OrderBy, OrderDirection, Query, Registry, Result, ScriptDecl, ScriptStmt, Select,
+    SelectStmt, Serialize, Stmt, StreamStmt, Upable, Value, Warning, WindowDecl, WindowKind,
+    ARGS_CONST_ID, GROUP_CONST_ID, WINDOW_CONST_ID,
 };
 use crate::impl_expr;
 
@@ -50,11 +50,11 @@ pub struct QueryRaw<'script> {
     pub(crate) config: WithExprsRaw<'script>,
     pub(crate) stmts: StmtsRaw<'script>,
 }
-impl<'script> QueryRaw<'script> {
+impl QueryRaw<'static> {
     pub(crate) fn up_script<'registry>(
         self,
-        mut helper: &mut Helper<'script, 'registry>,
-    ) -> Result<(Query<'script>, usize, Vec<Warning>)> {
+        mut helper: &mut Helper<'static, 'registry>,
+    ) -> Result<(Query<'static>, usize, Vec<Warning>)> {
         let mut stmts = vec![];
         for (_i, e) in self.stmts.into_iter().enumerate() {
             match e {
@@ -137,16 +137,16 @@ impl<'script> BaseExpr for StmtRaw<'script> {
     }
 }
 
-impl<'script> Upable<'script> for StmtRaw<'script> {
-    type Target = Stmt<'script>;
-    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+impl Upable<'static> for StmtRaw<'static> {
+    type Target = Stmt<'static>;
+    fn up<'registry>(self, helper: &mut Helper<'static, 'registry>) -> Result<Self::Target> {
         match self {
             StmtRaw::Select(stmt) => {
                 let mut aggregates = Vec::new();
                 let mut consts = HashMap::new();
                 let mut locals = HashMap::new();
                 helper.swap(&mut aggregates, &mut consts, &mut locals);
-                let stmt: Select<'script> = stmt.up(helper)?;
+                let stmt: Select<'static> = stmt.up(helper)?;
                 helper.swap(&mut aggregates, &mut consts, &mut locals);
                 // We know that select statements have exactly three consts
                 let consts = vec![Value::null(), Value::null(), Value::null()];
@@ -156,12 +156,13 @@ impl<'script> Upable<'script> for StmtRaw<'script> {
                     aggregates,
                     consts,
                     locals: locals.len(),
+                    functions: helper.func_vec.clone(),
                     node_meta: helper.meta.clone(),
                 }))
             }
             StmtRaw::Stream(stmt) => Ok(Stmt::Stream(stmt.up(helper)?)),
             StmtRaw::OperatorDecl(stmt) => {
-                let stmt: OperatorDecl<'script> = stmt.up(helper)?;
+                let stmt: OperatorDecl<'static> = stmt.up(helper)?;
                 helper
                     .operators
                     .insert(stmt.fqon(&stmt.module), stmt.clone());
@@ -169,13 +170,13 @@ impl<'script> Upable<'script> for StmtRaw<'script> {
             }
             StmtRaw::Operator(stmt) => Ok(Stmt::Operator(stmt.up(helper)?)),
             StmtRaw::ScriptDecl(stmt) => {
-                let stmt: ScriptDecl<'script> = stmt.up(helper)?;
+                let stmt: ScriptDecl<'static> = stmt.up(helper)?;
                 helper.scripts.insert(stmt.fqsn(&stmt.module), stmt.clone());
                 Ok(Stmt::ScriptDecl(Box::new(stmt)))
             }
             StmtRaw::Script(stmt) => Ok(Stmt::Script(stmt.up(helper)?)),
             StmtRaw::WindowDecl(stmt) => {
-                let stmt: WindowDecl<'script> = stmt.up(helper)?;
+                let stmt: WindowDecl<'static> = stmt.up(helper)?;
                 helper.windows.insert(stmt.fqwn(&stmt.module), stmt.clone());
                 Ok(Stmt::WindowDecl(Box::new(stmt)))
             }
@@ -226,13 +227,13 @@ pub struct ModuleStmtRaw<'script> {
 }
 impl_expr!(ModuleStmtRaw);
 
-impl<'script> ModuleStmtRaw<'script> {
+impl ModuleStmtRaw<'static> {
     pub(crate) fn define<'registry>(
         self,
         reg: &'registry Registry,
         aggr_reg: &'registry AggrRegistry,
-        consts: &mut Vec<Value<'script>>,
-        mut helper: &mut Helper<'script, 'registry>,
+        consts: &mut Vec<Value<'static>>,
+        mut helper: &mut Helper<'static, 'registry>,
     ) -> Result<()> {
         helper.module.push(self.name.id.to_string());
         for e in self.stmts {
@@ -324,9 +325,9 @@ pub struct ScriptDeclRaw<'script> {
     pub(crate) script: ScriptRaw<'script>,
 }
 
-impl<'script> Upable<'script> for ScriptDeclRaw<'script> {
-    type Target = ScriptDecl<'script>;
-    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+impl Upable<'static> for ScriptDeclRaw<'static> {
+    type Target = ScriptDecl<'static>;
+    fn up<'registry>(self, helper: &mut Helper<'static, 'registry>) -> Result<Self::Target> {
         // NOTE As we can have module aliases and/or nested modules within script definitions
         // that are private to or inline with the script - multiple script definitions in the
         // same module scope can share the same relative function/const module paths.
@@ -397,9 +398,9 @@ pub struct WindowDeclRaw<'script> {
     pub(crate) script: Option<ScriptRaw<'script>>,
 }
 
-impl<'script> Upable<'script> for WindowDeclRaw<'script> {
-    type Target = WindowDecl<'script>;
-    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+impl Upable<'static> for WindowDeclRaw<'static> {
+    type Target = WindowDecl<'static>;
+    fn up<'registry>(self, helper: &mut Helper<'static, 'registry>) -> Result<Self::Target> {
         let mut maybe_script = self.script.map(|s| s.up_script(helper)).transpose()?;
         if let Some((_, ref mut warnings)) = maybe_script {
             helper.warnings.append(warnings);
@@ -459,6 +460,8 @@ pub struct SelectRaw<'script> {
     pub(crate) maybe_having: Option<ImutExprRaw<'script>>,
     pub(crate) maybe_group_by: Option<GroupByRaw<'script>>,
     pub(crate) windows: Option<Vec<WindowDefnRaw<'script>>>,
+    pub(crate) maybe_order_by: Option<OrderByRaw<'script>>,
+    pub(crate) maybe_limit: Option<LimitRaw>,
 }
 impl_expr!(SelectRaw);
 
@@ -511,6 +514,34 @@ impl<'script> Upable<'script> for SelectRaw<'script> {
 
         let windows = self.windows.unwrap_or_default();
 
+        // Ordering and limiting only make sense on the set of results a
+        // window emits, so we reject them for non windowed selects.
+        if windows.is_empty() {
+            if let Some(order_by) = &self.maybe_order_by {
+                return error_generic(
+                    order_by,
+                    order_by,
+                    &"`order by` is only supported on windowed select statements",
+                    &helper.meta,
+                );
+            }
+            if let Some(limit) = &self.maybe_limit {
+                return error_generic(
+                    limit,
+                    limit,
+                    &"`limit` is only supported on windowed select statements",
+                    &helper.meta,
+                );
+            }
+        }
+        let maybe_order_by = self.maybe_order_by.up(helper)?;
+        if helper.has_locals() {
+            if let Some(definitely) = maybe_order_by {
+                return error_no_locals(&(self.start, self.end), &definitely, &helper.meta);
+            }
+        };
+        let maybe_limit = self.maybe_limit.up(helper)?;
+
         let from = match self.from {
             (stream, None) => {
                 let mut port = stream.clone();
@@ -536,10 +567,68 @@ impl<'script> Upable<'script> for SelectRaw<'script> {
             maybe_having: maybe_having.map(ImutExpr),
             maybe_group_by,
             windows,
+            maybe_order_by,
+            maybe_limit,
+        })
+    }
+}
+
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OrderByRaw<'script> {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) expr: ImutExprRaw<'script>,
+    pub(crate) direction: OrderDirection,
+}
+impl_expr!(OrderByRaw);
+
+impl<'script> Upable<'script> for OrderByRaw<'script> {
+    type Target = OrderBy<'script>;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        Ok(OrderBy {
+            mid: helper.add_meta(self.start, self.end),
+            expr: ImutExpr(self.expr.up(helper)?),
+            direction: self.direction,
         })
     }
 }
 
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LimitRaw {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) limit: i64,
+}
+impl BaseExpr for LimitRaw {
+    fn s(&self, _meta: &NodeMetas) -> Location {
+        self.start
+    }
+    fn e(&self, _meta: &NodeMetas) -> Location {
+        self.end
+    }
+    fn mid(&self) -> usize {
+        0
+    }
+}
+
+impl<'script> Upable<'script> for LimitRaw {
+    type Target = usize;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        if self.limit < 1 {
+            return error_generic(
+                &self,
+                &self,
+                &"`limit` has to be a positive integer",
+                &helper.meta,
+            );
+        }
+        #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+        Ok(self.limit as usize)
+    }
+}
+
 /// we're forced to make this pub because of lalrpop
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum GroupByRaw<'script> {
@@ -15,22 +15,25 @@
 #![doc(hidden)]
 // We want to keep the names here
 #![allow(clippy::module_name_repetitions)]
+use super::optimizer::optimize_match;
 use super::upable::Upable;
 use super::{
     base_expr, is_lit, path_eq, query, replace_last_shadow_use, ArrayPattern,
-    ArrayPredicatePattern, AssignPattern, BinExpr, BinOpKind, Comprehension, ComprehensionCase,
-    ConstDoc, EmitExpr, Env, EventPath, Expr, Field, FnDecl, FnDoc, Helper, Ident,
-    ImutComprehension, ImutComprehensionCase, ImutExpr, ImutExprInt, ImutMatch,
-    ImutPredicateClause, Invocable, Invoke, InvokeAggr, InvokeAggrFn, List, Literal, LocalPath,
-    Match, Merge, MetadataPath, ModDoc, NodeMetas, Patch, PatchOperation, Path, Pattern,
+    ArrayPredicatePattern, AssignPattern, BinExpr, BinOpKind, BytesPattern, BytesPatternSegment,
+    Comprehension, ComprehensionCase, ConstDoc, EmitExpr, Env, EventPath, Expr, Field, FnDecl,
+    FnDoc, Helper, Ident, ImutComprehension, ImutComprehensionCase, ImutExpr, ImutExprInt,
+    ImutMatch, ImutPredicateClause, Invocable, Invoke, InvokeAggr, InvokeAggrFn, List, Literal,
+    LocalPath, Match, Merge, MetadataPath, ModDoc, NodeMetas, Patch, PatchOperation, Path, Pattern,
     PredicateClause, PredicatePattern, Predicates, Record, RecordPattern, Recur, Script, Segment,
     StatePath, TestExpr, TuplePattern, UnaryExpr, UnaryOpKind, Warning,
 };
+use crate::binary::MAX_SEGMENT_BITS;
 use crate::errors::{error_generic, error_oops, ErrorKind, Result};
 use crate::impl_expr;
-use crate::interpreter::{exec_binary, exec_unary};
+use crate::interpreter::{exec_binary, exec_unary, Program};
+use crate::limits::Limits;
 use crate::pos::{Location, Range};
-use crate::registry::CustomFn;
+use crate::registry::{fn_value, AggrFns, CustomAggr, CustomFn};
 use crate::tilde::Extractor;
 use crate::EventContext;
 pub use base_expr::BaseExpr;
@@ -40,6 +43,7 @@ use serde::Serialize;
 use simd_json::value::borrowed;
 use simd_json::{prelude::*, BorrowedValue as Value, KnownKey};
 use std::borrow::Cow;
+use std::convert::TryFrom;
 
 const NO_AGGRS: [InvokeAggrFn<'static>; 0] = [];
 const NO_CONSTS: Vec<Value<'static>> = Vec::new();
@@ -55,11 +59,16 @@ impl<'script> ScriptRaw<'script> {
     pub(crate) fn new(exprs: ExprsRaw<'script>, doc: Option<Vec<Cow<'script, str>>>) -> Self {
         Self { exprs, doc }
     }
+}
+
+// Scripts are parsed from owned tokens, so the aggregate functions declared
+// in their modules can be kept by the registry.
+impl ScriptRaw<'static> {
     #[allow(clippy::too_many_lines)]
     pub(crate) fn up_script<'registry>(
         self,
-        mut helper: &mut Helper<'script, 'registry>,
-    ) -> Result<(Script<'script>, Vec<Warning>)> {
+        mut helper: &mut Helper<'static, 'registry>,
+    ) -> Result<(Script<'static>, Vec<Warning>)> {
         helper
             .consts
             .insert(vec!["window".to_owned()], WINDOW_CONST_ID);
@@ -106,7 +115,7 @@ impl<'script> ScriptRaw<'script> {
                             mid: helper.add_meta_w_name(start, end, &name),
                         }))
                     }
-                    let expr = expr.reduce(&helper)?;
+                    let expr = expr.reduce(&mut helper)?;
 
                     let v = reduce2(expr, &helper)?;
                     let value_type = v.value_type();
@@ -173,6 +182,13 @@ impl<'script> ScriptRaw<'script> {
                 .map(|d| d.iter().map(|l| l.trim()).collect::<Vec<_>>().join("\n")),
         });
 
+        let program = Program::compile(&exprs);
+        let mut local_names = vec![String::new(); helper.locals.len()];
+        for (name, idx) in &helper.locals {
+            if let Some(local_name) = local_names.get_mut(*idx) {
+                *local_name = name.clone();
+            }
+        }
         Ok((
             Script {
                 imports: vec![], // Compiled out
@@ -181,9 +197,13 @@ impl<'script> ScriptRaw<'script> {
                 aggregates: helper.aggregates.clone(),
                 windows: helper.windows.clone(),
                 locals: helper.locals.len(),
+                local_names,
                 node_meta: helper.meta.clone(),
                 functions: helper.func_vec.clone(),
                 docs: helper.docs.clone(),
+                program,
+                rewrites: helper.rewrites.clone(),
+                limits: Limits::default(),
             },
             helper.warnings.clone(),
         ))
@@ -201,8 +221,8 @@ pub struct ModuleRaw<'script> {
 }
 impl_expr!(ModuleRaw);
 
-impl<'script> ModuleRaw<'script> {
-    pub(crate) fn define<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<()> {
+impl ModuleRaw<'static> {
+    pub(crate) fn define<'registry>(self, helper: &mut Helper<'static, 'registry>) -> Result<()> {
         helper.module.push(self.name.id.to_string());
         for e in self.exprs {
             match e {
@@ -249,6 +269,9 @@ impl<'script> ModuleRaw<'script> {
 
                     helper.register_fun(f)?;
                 }
+                ExprRaw::AggrDecl(a) => {
+                    a.define(helper)?;
+                }
                 e => {
                     return error_generic(
                         &e,
@@ -369,6 +392,109 @@ pub struct StringLitRaw<'script> {
     pub(crate) exprs: ImutExprsRaw<'script>,
 }
 
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BytesRaw<'script> {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) segments: Vec<BytesSegmentRaw<'script>>,
+}
+
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BytesSegmentRaw<'script> {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) value: ImutExprRaw<'script>,
+    pub(crate) bits: Option<i64>,
+}
+
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LambdaRaw<'script> {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) args: Vec<IdentRaw<'script>>,
+    pub(crate) body: ExprsRaw<'script>,
+}
+
+/// Lambdas are compiled like any other function of the script, the
+/// expression itself only holds a reference to it. As their body is
+/// a function body they can not capture the locals around them.
+impl<'script> Upable<'script> for LambdaRaw<'script> {
+    type Target = Literal<'script>;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        let (is_open, fn_argc) = (helper.is_open, helper.fn_argc);
+        let f = FnDeclRaw {
+            start: self.start,
+            end: self.end,
+            name: IdentRaw {
+                start: self.start,
+                end: self.end,
+                id: "lambda".into(),
+            },
+            args: self.args,
+            body: self.body,
+            doc: None,
+            open: false,
+            inline: false,
+        }
+        .up(helper)?;
+        helper.is_open = is_open;
+        helper.fn_argc = fn_argc;
+        // inner lambdas get registered while we up the body
+        let name = format!("lambda#{}", helper.func_vec.len());
+        let id = helper.register_fun(CustomFn {
+            name: name.clone().into(),
+            args: f.args.iter().map(|i| i.id.to_string()).collect(),
+            locals: f.locals,
+            body: f.body,
+            is_const: false,
+            open: false,
+            inline: false,
+        })?;
+        Ok(Literal {
+            mid: helper.add_meta_w_name(self.start, self.end, &name),
+            value: helper
+                .func_vec
+                .get(id)
+                .map_or_else(Value::null, |f| fn_value(f, id)),
+        })
+    }
+}
+
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FnRefRaw {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) module: Vec<String>,
+    pub(crate) fun: String,
+}
+
+impl<'script> Upable<'script> for FnRefRaw {
+    type Target = Literal<'script>;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        let mut abs_module = helper.module.clone();
+        abs_module.extend_from_slice(&self.module);
+        abs_module.push(self.fun.clone());
+        let value = helper
+            .functions
+            .get(&abs_module)
+            .and_then(|id| helper.func_vec.get(*id).map(|f| fn_value(f, *id)));
+        if let Some(value) = value {
+            Ok(Literal {
+                mid: helper.add_meta_w_name(self.start, self.end, &abs_module.join("::")),
+                value,
+            })
+        } else {
+            let inner: Range = (self.start, self.end).into();
+            let outer: Range = inner.expand_lines(3);
+            Err(ErrorKind::MissingFunction(outer, inner, self.module, self.fun, None).into())
+        }
+    }
+}
+
 /// we're forced to make this pub because of lalrpop
 pub struct StrLitElements<'script>(
     pub(crate) Vec<Cow<'script, str>>,
@@ -419,7 +545,7 @@ pub(crate) fn reduce2<'script>(
 
 impl<'script> ImutExprInt<'script> {
     #[allow(clippy::too_many_lines)]
-    pub(crate) fn reduce(self, helper: &Helper<'script, '_>) -> Result<Self> {
+    pub(crate) fn reduce(self, helper: &mut Helper<'script, '_>) -> Result<Self> {
         match self {
             ImutExprInt::Unary(u) => match *u {
                 u1
@@ -462,6 +588,7 @@ impl<'script> ImutExprInt<'script> {
                         // TODO remove duplicate params?
                         let value =
                             exec_binary(&b1, &b1, &helper.meta, b1.kind, &lhs, &rhs)?.into_owned();
+                        helper.rewrite_mid(b1.mid, &"folded constant binary expression");
                         let lit = Literal { mid: b1.mid, value };
                         Ok(ImutExprInt::Literal(lit))
                     }
@@ -512,6 +639,7 @@ impl<'script> ImutExprInt<'script> {
                         mid,
                         value: v.clone(),
                     };
+                    helper.rewrite_mid(mid, &"inlined constant");
                     Ok(ImutExprInt::Literal(lit))
                 } else {
                     error_generic(
@@ -549,6 +677,9 @@ impl<'script> ImutExprInt<'script> {
                         aggrs: &NO_AGGRS,
                         meta: &helper.meta,
                         recursion_limit: crate::recursion_limit(),
+                        functions: &[],
+                        budget: None,
+                        debugger: None,
                     };
 
                     let v = i
@@ -556,6 +687,14 @@ impl<'script> ImutExprInt<'script> {
                         .invoke(&env, &args2)
                         .map_err(|e| e.into_err(&ex, &ex, Some(&helper.reg), &helper.meta))?
                         .into_static();
+                    helper.rewrite_mid(
+                        i.mid,
+                        &format!(
+                            "folded call to constant function {}::{}",
+                            i.module.join("::"),
+                            i.fun
+                        ),
+                    );
                     Ok(ImutExprInt::Literal(Literal {
                         value: v,
                         mid: i.mid,
@@ -610,6 +749,8 @@ pub enum ExprRaw<'script> {
     /// we're forced to make this pub because of lalrpop
     FnDecl(AnyFnRaw<'script>),
     /// we're forced to make this pub because of lalrpop
+    AggrDecl(Box<AggrDeclRaw<'script>>),
+    /// we're forced to make this pub because of lalrpop
     Imut(ImutExprRaw<'script>),
 }
 
@@ -698,6 +839,15 @@ impl<'script> Upable<'script> for ExprRaw<'script> {
                 )
                 .into());
             }
+            ExprRaw::AggrDecl(a) => {
+                // Same as above, aggregates can only be declared in modules
+                #[cfg_attr(tarpaulin, skip)]
+                return Err(ErrorKind::InvalidFn(
+                    a.extent(&helper.meta).expand_lines(2),
+                    a.extent(&helper.meta),
+                )
+                .into());
+            }
         })
     }
 }
@@ -715,6 +865,7 @@ impl<'script> BaseExpr for ExprRaw<'script> {
             ExprRaw::Comprehension(e) => e.s(meta),
             ExprRaw::Emit(e) => e.s(meta),
             ExprRaw::FnDecl(e) => e.s(meta),
+            ExprRaw::AggrDecl(e) => e.s(meta),
             ExprRaw::Imut(e) => e.s(meta),
         }
     }
@@ -727,6 +878,7 @@ impl<'script> BaseExpr for ExprRaw<'script> {
             ExprRaw::Comprehension(e) => e.e(meta),
             ExprRaw::Emit(e) => e.e(meta),
             ExprRaw::FnDecl(e) => e.e(meta),
+            ExprRaw::AggrDecl(e) => e.e(meta),
             ExprRaw::Imut(e) => e.e(meta),
         }
     }
@@ -844,6 +996,125 @@ impl<'script> BaseExpr for AnyFnRaw<'script> {
     }
 }
 
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AggrDeclRaw<'script> {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) name: IdentRaw<'script>,
+    pub(crate) args: Vec<IdentRaw<'script>>,
+    pub(crate) fns: Vec<AnyFnRaw<'script>>,
+    pub(crate) doc: Option<Vec<Cow<'script, str>>>,
+}
+impl_expr!(AggrDeclRaw);
+
+impl AggrDeclRaw<'static> {
+    /// Turns the functions of the aggregate into a `CustomAggr` and
+    /// registers it under the current module
+    pub(crate) fn define<'registry>(self, helper: &mut Helper<'static, 'registry>) -> Result<()> {
+        let argc = self.args.len();
+        let mut init = None;
+        let mut accumulate = None;
+        let mut compensate = None;
+        let mut merge = None;
+        let mut emit = None;
+        for f in self.fns {
+            let (name, expected) = match &f {
+                AnyFnRaw::Normal(FnDeclRaw { name, .. })
+                | AnyFnRaw::Match(MatchFnDeclRaw { name, .. }) => match &*name.id {
+                    "init" => ("init", 0),
+                    "accumulate" => ("accumulate", argc + 1),
+                    "compensate" => ("compensate", argc + 1),
+                    "merge" => ("merge", 2),
+                    "emit" => ("emit", 1),
+                    other => {
+                        return error_generic(
+                            &f,
+                            &f,
+                            &format!(
+                                "Unknown aggregate function `{}`, expected one of `init`, `accumulate`, `compensate`, `merge` or `emit`",
+                                other
+                            ),
+                            &helper.meta,
+                        )
+                    }
+                },
+            };
+            let outer = f.extent(&helper.meta);
+            let f = f.up(helper)?;
+            if f.open || f.args.len() != expected {
+                return error_generic(
+                    &outer,
+                    &outer,
+                    &format!("`{}` has to take exactly {} argument(s)", name, expected),
+                    &helper.meta,
+                );
+            }
+            let f = CustomFn {
+                name: f.name.id,
+                args: f.args.iter().map(|i| i.id.to_string()).collect(),
+                locals: f.locals,
+                body: f.body,
+                is_const: false,
+                open: false,
+                inline: false,
+            };
+            let slot = match name {
+                "init" => &mut init,
+                "accumulate" => &mut accumulate,
+                "compensate" => &mut compensate,
+                "merge" => &mut merge,
+                _ => &mut emit,
+            };
+            if slot.replace(f).is_some() {
+                return error_generic(
+                    &outer,
+                    &outer,
+                    &format!("`{}` is declared twice", name),
+                    &helper.meta,
+                );
+            }
+        }
+        let extent = Range::from((self.start, self.end));
+        let (init, accumulate, merge, emit) = match (init, accumulate, merge, emit) {
+            (Some(init), Some(accumulate), Some(merge), Some(emit)) => {
+                (init, accumulate, merge, emit)
+            }
+            _ => {
+                return error_generic(
+                    &extent,
+                    &extent,
+                    &"An aggregate function needs `init`, `accumulate`, `merge` and `emit`",
+                    &helper.meta,
+                )
+            }
+        };
+
+        let mut consts: Vec<Value<'static>> = helper
+            .const_values
+            .iter()
+            .map(|v| v.clone_static())
+            .collect();
+        if consts.len() <= ARGS_CONST_ID {
+            consts.resize(ARGS_CONST_ID + 1, Value::null());
+        }
+        let aggr = CustomAggr::new(
+            AggrFns {
+                init,
+                accumulate,
+                compensate,
+                merge,
+                emit,
+                functions: helper.func_vec.clone(),
+            },
+            consts,
+            helper.meta.clone(),
+            argc,
+        );
+        helper.register_aggr(self.name.id.to_string(), aggr)
+    }
+}
+
 /// we're forced to make this pub because of lalrpop
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct MatchFnDeclRaw<'script> {
@@ -1014,7 +1285,15 @@ pub enum ImutExprRaw<'script> {
     /// we're forced to make this pub because of lalrpop
     String(StringLitRaw<'script>),
     /// we're forced to make this pub because of lalrpop
+    Bytes(BytesRaw<'script>),
+    /// we're forced to make this pub because of lalrpop
     Recur(RecurRaw<'script>),
+    /// we're forced to make this pub because of lalrpop
+    Try(Box<TryRaw<'script>>),
+    /// we're forced to make this pub because of lalrpop
+    Lambda(Box<LambdaRaw<'script>>),
+    /// we're forced to make this pub because of lalrpop
+    FnRef(FnRefRaw),
 }
 
 impl<'script> Upable<'script> for ImutExprRaw<'script> {
@@ -1054,6 +1333,26 @@ impl<'script> Upable<'script> for ImutExprRaw<'script> {
                     .reduce(helper)?
                 }
             }
+            ImutExprRaw::Bytes(b) => {
+                let mut args = Vec::with_capacity(b.segments.len() * 2);
+                for segment in b.segments {
+                    args.push(segment.value);
+                    args.push(ImutExprRaw::Literal(LiteralRaw {
+                        start: segment.start,
+                        end: segment.end,
+                        value: segment.bits.map_or_else(Value::null, Value::from),
+                    }));
+                }
+                ImutExprRaw::Invoke(InvokeRaw {
+                    start: b.start,
+                    end: b.end,
+                    module: vec!["core".into(), "binary".into()],
+                    fun: "pack".into(),
+                    args,
+                })
+                .up(helper)?
+                .reduce(helper)?
+            }
             ImutExprRaw::Record(r) => ImutExprInt::Record(r.up(helper)?).reduce(helper)?,
             ImutExprRaw::List(l) => ImutExprInt::List(l.up(helper)?).reduce(helper)?,
             ImutExprRaw::Patch(p) => ImutExprInt::Patch(Box::new(p.up(helper)?)).reduce(helper)?,
@@ -1098,6 +1397,9 @@ impl<'script> Upable<'script> for ImutExprRaw<'script> {
                 ImutExprInt::Match(Box::new(m.up(helper)?))
             }
             ImutExprRaw::Comprehension(c) => ImutExprInt::Comprehension(Box::new(c.up(helper)?)),
+            ImutExprRaw::Try(t) => ImutExprInt::Try(Box::new(t.up(helper)?)),
+            ImutExprRaw::Lambda(l) => ImutExprInt::Literal(l.up(helper)?),
+            ImutExprRaw::FnRef(r) => ImutExprInt::Literal(r.up(helper)?),
         });
         helper.possible_leaf = was_leaf;
         r
@@ -1538,6 +1840,8 @@ pub enum PatternRaw<'script> {
     /// we're forced to make this pub because of lalrpop
     Tuple(TuplePatternRaw<'script>),
     /// we're forced to make this pub because of lalrpop
+    Bytes(BytesPatternRaw),
+    /// we're forced to make this pub because of lalrpop
     Expr(ImutExprRaw<'script>),
     /// we're forced to make this pub because of lalrpop
     Assign(AssignPatternRaw<'script>),
@@ -1550,12 +1854,13 @@ pub enum PatternRaw<'script> {
 impl<'script> Upable<'script> for PatternRaw<'script> {
     type Target = Pattern<'script>;
     fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
-        use PatternRaw::{Array, Assign, Default, DoNotCare, Expr, Record, Tuple};
+        use PatternRaw::{Array, Assign, Bytes, Default, DoNotCare, Expr, Record, Tuple};
         Ok(match self {
             //Predicate(pp) => Pattern::Predicate(pp.up(helper)?),
             Record(rp) => Pattern::Record(rp.up(helper)?),
             Array(ap) => Pattern::Array(ap.up(helper)?),
             Tuple(tp) => Pattern::Tuple(tp.up(helper)?),
+            Bytes(bp) => Pattern::Bytes(bp.up(helper)?),
             Expr(expr) => Pattern::Expr(expr.up(helper)?),
             Assign(ap) => Pattern::Assign(ap.up(helper)?),
             DoNotCare => Pattern::DoNotCare,
@@ -1794,6 +2099,80 @@ impl<'script> Upable<'script> for TuplePatternRaw<'script> {
     }
 }
 
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BytesPatternRaw {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) segments: Vec<BytesPatternSegmentRaw>,
+    pub(crate) rest: bool,
+}
+
+impl<'script> Upable<'script> for BytesPatternRaw {
+    type Target = BytesPattern;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        let outer = Range(self.start, self.end);
+        let mut segments = Vec::with_capacity(self.segments.len());
+        let mut total = 0;
+        for segment in self.segments {
+            let inner = Range(segment.start, segment.end);
+            let bits = match u64::try_from(segment.bits) {
+                Ok(bits) if bits > 0 && bits <= MAX_SEGMENT_BITS => bits,
+                _ => {
+                    return error_generic(
+                        &outer,
+                        &inner,
+                        &format!(
+                            "segment sizes have to be between 1 and {} bits",
+                            MAX_SEGMENT_BITS
+                        ),
+                        &helper.meta,
+                    )
+                }
+            };
+            let value = if let Some(value) = segment.value {
+                match u64::try_from(value) {
+                    Ok(value) if bits == MAX_SEGMENT_BITS || value < 1 << bits => Some(value),
+                    _ => {
+                        return error_generic(
+                            &outer,
+                            &inner,
+                            &format!("{} does not fit into {} bits", value, bits),
+                            &helper.meta,
+                        )
+                    }
+                }
+            } else {
+                None
+            };
+            total += bits;
+            segments.push(BytesPatternSegment { bits, value });
+        }
+        if total % 8 != 0 {
+            return error_generic(
+                &outer,
+                &outer,
+                &"the segments of a binary pattern have to add up to whole bytes",
+                &helper.meta,
+            );
+        }
+        Ok(BytesPattern {
+            mid: helper.add_meta(self.start, self.end),
+            segments,
+            rest: self.rest,
+        })
+    }
+}
+
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BytesPatternSegmentRaw {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) value: Option<i64>,
+    pub(crate) bits: i64,
+}
+
 pub(crate) const FN_RES_NAME: &str = "__fn_assign_this_is_ugly";
 
 /// we're forced to make this pub because of lalrpop
@@ -2237,10 +2616,14 @@ impl<'script> Upable<'script> for MatchRaw<'script> {
             _ => ()
         }
 
+        let mut patterns = patterns;
+        let target = self.target.up(helper)?;
+        let tables = optimize_match(&target, &mut patterns, Range(self.start, self.end), helper);
         Ok(Match {
             mid: helper.add_meta(self.start, self.end),
-            target: self.target.up(helper)?,
+            target,
             patterns,
+            tables,
         })
     }
 }
@@ -2275,16 +2658,42 @@ impl<'script> Upable<'script> for ImutMatchRaw<'script> {
         }
         let was_leaf = helper.possible_leaf;
         helper.possible_leaf = false;
+        let mut patterns = patterns;
+        let target = self.target.up(helper)?;
+        let tables = optimize_match(&target, &mut patterns, Range(self.start, self.end), helper);
         let r = Ok(ImutMatch {
             mid: helper.add_meta(self.start, self.end),
-            target: self.target.up(helper)?,
+            target,
             patterns,
+            tables,
         });
         helper.possible_leaf = was_leaf;
         r
     }
 }
 
+/// we're forced to make this pub because of lalrpop
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TryRaw<'script> {
+    pub(crate) start: Location,
+    pub(crate) end: Location,
+    pub(crate) expr: ImutExprRaw<'script>,
+    pub(crate) catches: ImutPredicatesRaw<'script>,
+}
+
+impl<'script> Upable<'script> for TryRaw<'script> {
+    type Target = Try<'script>;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        let expr = self.expr.up(helper)?;
+        let catches = self.catches.up(helper)?;
+        Ok(Try {
+            mid: helper.add_meta(self.start, self.end),
+            expr,
+            catches,
+        })
+    }
+}
+
 /// we're forced to make this pub because of lalrpop
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct InvokeRaw<'script> {
@@ -2354,16 +2763,17 @@ impl<'script> Upable<'script> for InvokeRaw<'script> {
 
 impl<'script> InvokeRaw<'script> {
     fn is_aggregate<'registry>(&self, helper: &mut Helper<'script, 'registry>) -> bool {
-        if self.module.get(0) == Some(&String::from("aggr")) && self.module.len() == 2 {
-            let module = self.module.get(1).cloned().unwrap_or_default();
-            helper.aggr_reg.find(&module, &self.fun).is_ok()
+        if self.module.get(0) == Some(&String::from("aggr")) && self.module.len() >= 2 {
+            let module = self.module.get(1..).unwrap_or_default();
+            (module.len() == 1 && helper.aggr_reg.find(&module[0], &self.fun).is_ok())
+                || helper.find_aggr(module, &self.fun).is_some()
         } else {
             false
         }
     }
 
     fn into_aggregate(self) -> InvokeAggrRaw<'script> {
-        let module = self.module.get(1).cloned().unwrap_or_default();
+        let module = self.module.get(1..).unwrap_or_default().join("::");
         InvokeAggrRaw {
             start: self.start,
             end: self.end,
@@ -2396,11 +2806,18 @@ impl<'script> Upable<'script> for InvokeAggrRaw<'script> {
             .into());
         };
         helper.is_in_aggr = true;
-        let invocable = helper
-            .aggr_reg
-            .find(&self.module, &self.fun)
-            .map_err(|e| e.into_err(&self, &self, Some(&helper.reg), &helper.meta))?
-            .clone();
+        let invocable = match helper.aggr_reg.find(&self.module, &self.fun) {
+            Ok(invocable) => invocable.clone(),
+            Err(e) => {
+                // aggregate functions declared in tremor-script modules
+                let module: Vec<String> = self.module.split("::").map(String::from).collect();
+                if let Some(invocable) = helper.find_aggr(&module, &self.fun) {
+                    invocable.clone()
+                } else {
+                    return Err(e.into_err(&self, &self, Some(&helper.reg), &helper.meta));
+                }
+            }
+        };
         if !invocable.valid_arity(self.args.len()) {
             return Err(ErrorKind::BadArity(
                 self.extent(&helper.meta),
@@ -0,0 +1,364 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Binary data in tremor-script.
+//!
+//! Values have no dedicated type for raw bytes so a binary is an array of
+//! its bytes that starts with a tag: a NaN with a payload that neither
+//! arithmetic nor any of our decoders hand out, so event data can't pass
+//! for a binary and an array of small integers stays an array. Function
+//! values are tagged the same way. Indexing and ranges like `bin[2:4]`
+//! work on the bytes, binary literals `<< ... >>`, bit patterns
+//! `%<< ... >>` and the `binary` module create and take them apart.
+//! Codecs with a native type for raw bytes like msgpack decode it into a
+//! binary and encode binaries back into it, everything else gets a
+//! portable copy where binaries are plain arrays of integers.
+
+use simd_json::prelude::*;
+use simd_json::BorrowedValue as Value;
+use simd_json::StaticNode;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+/// Largest size of a single integer segment
+pub(crate) const MAX_SEGMENT_BITS: u64 = 64;
+
+/// NaN payload marking a binary
+const BINARY_TAG: u64 = 0x7ff8_0000_0062_696e;
+
+/// NaN payload marking a function value
+pub(crate) const FUNCTION_TAG: u64 = 0x7ff8_0000_0000_666e;
+
+/// The value marking an array with the given NaN payload
+pub(crate) fn tag(bits: u64) -> Value<'static> {
+    Value::Static(StaticNode::F64(f64::from_bits(bits)))
+}
+
+/// The tag of a tagged array and the elements after it
+pub(crate) fn untag<'value, 'event>(
+    value: &'value Value<'event>,
+) -> Option<(u64, &'value [Value<'event>])> {
+    match value.as_array()?.split_first()? {
+        (Value::Static(StaticNode::F64(f)), rest)
+            if f.to_bits() == BINARY_TAG || f.to_bits() == FUNCTION_TAG =>
+        {
+            Some((f.to_bits(), rest))
+        }
+        _ => None,
+    }
+}
+
+/// If the value is an array marked with the given NaN payload
+pub(crate) fn is_tagged(value: &Value, bits: u64) -> bool {
+    untag(value).map_or(false, |(tag, _)| tag == bits)
+}
+
+/// Floats coming from outside pass through here so a NaN can't carry
+/// one of our tags
+#[must_use]
+pub fn sanitize_f64(f: f64) -> f64 {
+    if f.is_nan() {
+        std::f64::NAN
+    } else {
+        f
+    }
+}
+
+/// If the value is a binary
+pub fn is_binary(value: &Value) -> bool {
+    is_tagged(value, BINARY_TAG)
+}
+
+/// The bytes of a binary, `None` if the value isn't one
+pub fn as_bytes(value: &Value) -> Option<Vec<u8>> {
+    match untag(value)? {
+        (BINARY_TAG, bytes) => bytes
+            .iter()
+            .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Turns bytes into a binary
+pub fn from_bytes(bytes: &[u8]) -> Value<'static> {
+    let mut binary = Vec::with_capacity(bytes.len() + 1);
+    binary.push(tag(BINARY_TAG));
+    binary.extend(bytes.iter().map(|b| Value::from(*b)));
+    Value::Array(binary)
+}
+
+/// What indexes and ranges look at: the elements of an array or the
+/// bytes of a binary
+pub(crate) fn elements<'value, 'event>(
+    value: &'value Value<'event>,
+) -> Option<&'value [Value<'event>]> {
+    match untag(value) {
+        Some((BINARY_TAG, bytes)) => Some(bytes),
+        Some(_) => None,
+        None => value.as_array().map(Vec::as_slice),
+    }
+}
+
+/// Turns a range of `value` back into a binary or an array
+pub(crate) fn slice<'event>(value: &Value<'event>, range: &[Value<'event>]) -> Value<'event> {
+    if is_binary(value) {
+        let mut binary = Vec::with_capacity(range.len() + 1);
+        binary.push(tag(BINARY_TAG));
+        binary.extend_from_slice(range);
+        Value::Array(binary)
+    } else {
+        Value::from(range.to_vec())
+    }
+}
+
+fn is_portable(value: &Value) -> bool {
+    match value {
+        Value::Array(a) => untag(value).is_none() && a.iter().all(is_portable),
+        Value::Object(o) => o.values().all(is_portable),
+        _ => true,
+    }
+}
+
+fn make_portable(value: &mut Value) {
+    let portable = match untag(value) {
+        Some((BINARY_TAG, bytes)) => Some(Value::from(bytes.to_vec())),
+        // function values turn into the name of the function
+        Some((_, function)) => Some(function.get(1).cloned().unwrap_or_else(Value::null)),
+        None => None,
+    };
+    if let Some(portable) = portable {
+        *value = portable;
+        return;
+    }
+    match value {
+        Value::Array(a) => a.iter_mut().for_each(make_portable),
+        Value::Object(o) => o.values_mut().for_each(make_portable),
+        _ => (),
+    }
+}
+
+/// A copy of the value for formats without a native type for raw bytes,
+/// binaries become arrays of integers and function values their name.
+pub fn portable<'value, 'event>(value: &'value Value<'event>) -> Cow<'value, Value<'event>> {
+    if is_portable(value) {
+        Cow::Borrowed(value)
+    } else {
+        let mut value = value.clone();
+        make_portable(&mut value);
+        Cow::Owned(value)
+    }
+}
+
+/// Writes big endian bit segments into a binary
+#[derive(Debug, Default)]
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn push_bit(&mut self, bit: bool) {
+        let offset = self.bits % 8;
+        if offset == 0 {
+            self.bytes.push(0);
+        }
+        if bit {
+            if let Some(last) = self.bytes.last_mut() {
+                *last |= 0x80 >> offset;
+            }
+        }
+        self.bits += 1;
+    }
+
+    /// Writes the lowest `bits` bits of `value`
+    pub(crate) fn push(&mut self, value: u64, bits: u64) {
+        for i in (0..bits).rev() {
+            self.push_bit((value >> i) & 1 == 1)
+        }
+    }
+
+    /// Writes whole bytes
+    pub(crate) fn push_bytes(&mut self, bytes: &[u8]) {
+        if self.is_aligned() {
+            self.bytes.extend_from_slice(bytes);
+            self.bits += bytes.len() * 8;
+        } else {
+            for b in bytes {
+                self.push(u64::from(*b), 8);
+            }
+        }
+    }
+
+    /// If everything written so far adds up to whole bytes
+    pub(crate) fn is_aligned(&self) -> bool {
+        self.bits % 8 == 0
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads big endian bit segments from a binary
+#[derive(Debug)]
+pub(crate) struct BitReader<'bytes> {
+    bytes: &'bytes [u8],
+    bits: usize,
+}
+
+impl<'bytes> BitReader<'bytes> {
+    pub(crate) fn new(bytes: &'bytes [u8]) -> Self {
+        Self { bytes, bits: 0 }
+    }
+
+    /// Reads the next `bits` bits, `None` if there aren't enough left
+    pub(crate) fn read(&mut self, bits: u64) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self.bytes.get(self.bits / 8)?;
+            let bit = (byte >> (7 - self.bits % 8)) & 1;
+            value = (value << 1) | u64::from(bit);
+            self.bits += 1;
+        }
+        Some(value)
+    }
+
+    /// The bytes not read yet, `None` if we stopped in the middle of a byte
+    pub(crate) fn rest(&self) -> Option<&'bytes [u8]> {
+        if self.bits % 8 == 0 {
+            self.bytes.get(self.bits / 8..)
+        } else {
+            None
+        }
+    }
+}
+
+/// Builds a binary out of `value, bits` pairs where `bits` is `null`
+/// unless a size was given for the segment. Integers default to 8 bits,
+/// strings and binaries are added as they are.
+pub(crate) fn pack(args: &[&Value]) -> std::result::Result<Vec<u8>, String> {
+    if args.len() % 2 != 0 {
+        return Err("binary segments have to come as value and size pairs".into());
+    }
+    let mut writer = BitWriter::default();
+    for segment in args.chunks(2) {
+        let (value, bits) = (segment[0], segment[1]);
+        let bits = if bits.is_null() {
+            None
+        } else {
+            Some(
+                bits.as_u64()
+                    .filter(|b| *b > 0 && *b <= MAX_SEGMENT_BITS)
+                    .ok_or_else(|| {
+                        format!(
+                            "segment sizes have to be between 1 and {} bits",
+                            MAX_SEGMENT_BITS
+                        )
+                    })?,
+            )
+        };
+        if let Some(s) = value.as_str() {
+            if bits.is_some() {
+                return Err("only integer segments can have a size".into());
+            }
+            writer.push_bytes(s.as_bytes())
+        } else if value.is_i64() || value.is_u64() {
+            let bits = bits.unwrap_or(8);
+            let int = value
+                .as_u64()
+                .filter(|i| bits == MAX_SEGMENT_BITS || *i < 1 << bits)
+                .ok_or_else(|| format!("{} does not fit into {} bits", value.encode(), bits))?;
+            writer.push(int, bits)
+        } else if let Some(bytes) = as_bytes(value) {
+            if bits.is_some() {
+                return Err("only integer segments can have a size".into());
+            }
+            writer.push_bytes(&bytes)
+        } else {
+            return Err(format!(
+                "binary segments have to be integers, strings or binaries but got {}",
+                value.encode()
+            ));
+        }
+    }
+    if writer.is_aligned() {
+        Ok(writer.into_bytes())
+    } else {
+        Err("the segments of a binary have to add up to whole bytes".into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bits() {
+        let mut w = BitWriter::default();
+        w.push(0b101, 3);
+        w.push(0b0_0001, 5);
+        w.push(0xbeef, 16);
+        assert!(w.is_aligned());
+        let bytes = w.into_bytes();
+        assert_eq!(bytes, vec![0b1010_0001, 0xbe, 0xef]);
+
+        let mut r = BitReader::new(&bytes);
+        assert_eq!(r.read(3), Some(0b101));
+        assert_eq!(r.rest(), None);
+        assert_eq!(r.read(5), Some(1));
+        assert_eq!(r.rest(), Some(&[0xbe, 0xef][..]));
+        assert_eq!(r.read(16), Some(0xbeef));
+        assert_eq!(r.rest(), Some(&[][..]));
+        assert_eq!(r.read(1), None);
+    }
+
+    #[test]
+    fn packing() {
+        let null = Value::null();
+        let v = |v: Value<'static>| v;
+        let (one, four, twelve) = (v(1.into()), v(4.into()), v(12.into()));
+        let s = v("ab".into());
+        assert_eq!(
+            pack(&[&one, &four, &one, &twelve, &s, &null]),
+            Ok(vec![0x10, 0x01, b'a', b'b'])
+        );
+        assert!(pack(&[&one, &four]).is_err());
+        assert!(pack(&[&twelve, &four]).is_err());
+        assert!(pack(&[&s, &four]).is_err());
+        assert!(pack(&[&one]).is_err());
+        let bin = from_bytes(&[1, 2]);
+        assert_eq!(as_bytes(&bin), Some(vec![1, 2]));
+        assert_eq!(pack(&[&bin, &null]), Ok(vec![1, 2]));
+        assert_eq!(as_bytes(&Value::from(vec![256])), None);
+    }
+
+    #[test]
+    fn tagging() {
+        let bin = from_bytes(&[1, 2, 3]);
+        assert!(is_binary(&bin));
+        assert!(!is_binary(&Value::from(vec![1, 2, 3])));
+        assert!(!is_binary(&Value::from(vec![sanitize_f64(
+            f64::from_bits(BINARY_TAG)
+        )])));
+        assert_eq!(as_bytes(&Value::from(vec![1, 2, 3])), None);
+        let bytes = elements(&bin).map(<[Value]>::to_vec);
+        assert_eq!(bytes, Some(vec![1.into(), 2.into(), 3.into()]));
+        assert_eq!(
+            as_bytes(&slice(&bin, &bytes.unwrap_or_default()[1..])),
+            Some(vec![2, 3])
+        );
+        assert_eq!(portable(&bin).into_owned(), Value::from(vec![1, 2, 3]));
+    }
+}
@@ -0,0 +1,579 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interactive debugger for scripts and queries.
+//!
+//! A `Debugger` attached to a thread sees every expression the scripts and
+//! queries running on that thread evaluate. It pauses on breakpoints set on
+//! lines of the main file, or on every expression while stepping, and hands
+//! control to its `Frontend` to inspect the event, its metadata, the state,
+//! the locals and the window and group of a select.
+//!
+//! While a debugger is attached statements are never run as bytecode, so
+//! no expression goes unseen.
+
+use crate::ast::query::{GROUP_CONST_ID, WINDOW_CONST_ID};
+use crate::ast::BaseExpr;
+use crate::errors::{ErrorKind, Result};
+use crate::highlighter::{Error as Callout, ErrorLevel, Highlighter, Term as TermHighlighter};
+use crate::interpreter::{Env, LocalStack};
+use crate::lexer::Tokenizer;
+use crate::pos::{Location, Range};
+use simd_json::prelude::*;
+use simd_json::BorrowedValue as Value;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+thread_local! {
+    static DEBUGGER: RefCell<Option<Rc<Debugger>>> = RefCell::new(None);
+}
+
+/// Attaches a debugger to the current thread
+pub fn attach(debugger: Rc<Debugger>) {
+    DEBUGGER.with(|d| *d.borrow_mut() = Some(debugger));
+}
+
+/// Detaches the debugger from the current thread
+pub fn detach() -> Option<Rc<Debugger>> {
+    DEBUGGER.with(|d| d.borrow_mut().take())
+}
+
+/// The debugger attached to the current thread, if any
+pub fn current() -> Option<Rc<Debugger>> {
+    DEBUGGER.with(|d| d.borrow().clone())
+}
+
+/// How to carry on after a pause
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resume {
+    /// Run until the next breakpoint
+    Continue,
+    /// Pause on the next expression
+    Step,
+    /// Pause on the next line, stepping over function calls
+    Next,
+    /// Abort the script with an error
+    Abort,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Run,
+    Step,
+    Next { depth: usize, line: usize },
+}
+
+/// A script, function or select the debugger can pause in
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Name of the script, function or select
+    pub name: String,
+    /// Names of the locals by their index, as far as they are known
+    pub locals: Vec<String>,
+}
+
+impl Frame {
+    /// Creates a new frame
+    pub fn new(name: &str, locals: Vec<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            locals,
+        }
+    }
+}
+
+/// Leaves its frame when dropped
+pub struct FrameGuard<'debugger> {
+    debugger: &'debugger Debugger,
+}
+
+impl<'debugger> Drop for FrameGuard<'debugger> {
+    fn drop(&mut self) {
+        self.debugger.frames.borrow_mut().pop();
+    }
+}
+
+/// Where the debugger paused and what it can see there
+pub struct Pause<'pause, 'event> {
+    /// Start of the expression the debugger paused on
+    pub start: Location,
+    /// End of the expression the debugger paused on
+    pub end: Location,
+    /// Frames the expression is nested in, innermost last
+    pub frames: &'pause [Frame],
+    /// The event
+    pub event: &'pause Value<'event>,
+    /// The metadata of the event
+    pub meta: &'pause Value<'event>,
+    /// The state
+    pub state: &'pause Value<'static>,
+    local: &'pause LocalStack<'event>,
+    consts: &'pause [Value<'event>],
+}
+
+impl<'pause, 'event> Pause<'pause, 'event> {
+    /// The locals that are set, named `#<index>` if their name is not known
+    pub fn locals(&self) -> Vec<(String, &'pause Value<'event>)> {
+        let names = self.frames.last().map_or(&[][..], |f| f.locals.as_slice());
+        self.local
+            .values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, v)| {
+                let name = names
+                    .get(i)
+                    .filter(|n| !n.is_empty())
+                    .cloned()
+                    .unwrap_or_else(|| format!("#{}", i));
+                v.as_ref().map(|v| (name, v))
+            })
+            .collect()
+    }
+
+    /// The local named `name`, if it is set
+    pub fn local(&self, name: &str) -> Option<&'pause Value<'event>> {
+        self.locals()
+            .into_iter()
+            .find_map(|(n, v)| if n == name { Some(v) } else { None })
+    }
+
+    /// The window a select is evaluated for, if any
+    pub fn window(&self) -> Option<&'pause Value<'event>> {
+        self.consts.get(WINDOW_CONST_ID).filter(|v| !v.is_null())
+    }
+
+    /// The group a select is evaluated for, if any
+    pub fn group(&self) -> Option<&'pause Value<'event>> {
+        self.consts.get(GROUP_CONST_ID).filter(|v| !v.is_null())
+    }
+}
+
+/// Interacts with the user while the debugger is paused
+pub trait Frontend {
+    /// Called whenever the debugger pauses, returns how to carry on
+    fn paused(&mut self, debugger: &Debugger, pause: &Pause) -> Resume;
+}
+
+/// The debugger, keeping track of breakpoints and frames
+pub struct Debugger {
+    frontend: RefCell<Box<dyn Frontend>>,
+    breakpoints: RefCell<BTreeSet<usize>>,
+    mode: Cell<Mode>,
+    /// Line of the main file the last expression was on, `0` for none
+    line: Cell<usize>,
+    frames: RefCell<Vec<Frame>>,
+}
+
+impl Debugger {
+    /// Creates a debugger that runs until it hits a breakpoint
+    pub fn new(frontend: Box<dyn Frontend>) -> Self {
+        Self {
+            frontend: RefCell::new(frontend),
+            breakpoints: RefCell::new(BTreeSet::new()),
+            mode: Cell::new(Mode::Run),
+            line: Cell::new(0),
+            frames: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Sets a breakpoint on a line of the main file
+    pub fn set_breakpoint(&self, line: usize) {
+        self.breakpoints.borrow_mut().insert(line);
+    }
+
+    /// Removes the breakpoint on a line, returns if there was one
+    pub fn remove_breakpoint(&self, line: usize) -> bool {
+        self.breakpoints.borrow_mut().remove(&line)
+    }
+
+    /// The lines with breakpoints in order
+    pub fn breakpoints(&self) -> Vec<usize> {
+        self.breakpoints.borrow().iter().copied().collect()
+    }
+
+    /// Pauses on the next expression
+    pub fn pause(&self) {
+        self.mode.set(Mode::Step);
+    }
+
+    /// Enters a frame until the returned guard is dropped
+    pub fn enter(&self, frame: Frame) -> FrameGuard<'_> {
+        let mut frames = self.frames.borrow_mut();
+        // Every event starts on a fresh line
+        if frames.is_empty() {
+            self.line.set(0);
+        }
+        frames.push(frame);
+        FrameGuard { debugger: self }
+    }
+
+    /// Called before `expr` gets evaluated, pauses if there is a reason to
+    pub(crate) fn on_expr<'run, 'event, 'script, E: BaseExpr>(
+        &self,
+        expr: &E,
+        env: &Env<'run, 'event, 'script>,
+        event: &Value<'event>,
+        state: &Value<'static>,
+        meta: &Value<'event>,
+        local: &LocalStack<'event>,
+    ) -> Result<()>
+    where
+        'script: 'event,
+        'event: 'run,
+    {
+        let range = expr.extent(env.meta);
+        let Range(start, end) = range;
+        // Breakpoints are only hit when entering their line
+        let in_main = start.unit_id == 0;
+        let new_line = in_main && self.line.replace(start.line) != start.line;
+        let frames = self.frames.borrow();
+        let pause = match self.mode.get() {
+            Mode::Run => false,
+            Mode::Step => true,
+            Mode::Next { depth, line } => in_main && frames.len() <= depth && start.line != line,
+        } || (new_line && self.breakpoints.borrow().contains(&start.line));
+        if !pause {
+            return Ok(());
+        }
+        let pause = Pause {
+            start,
+            end,
+            frames: &frames,
+            event,
+            meta,
+            state,
+            local,
+            consts: env.consts,
+        };
+        let resume = self.frontend.borrow_mut().paused(self, &pause);
+        self.mode.set(match resume {
+            Resume::Continue | Resume::Abort => Mode::Run,
+            Resume::Step => Mode::Step,
+            Resume::Next => Mode::Next {
+                depth: frames.len(),
+                line: start.line,
+            },
+        });
+        if resume == Resume::Abort {
+            Err(ErrorKind::Aborted(range.expand_lines(2), range).into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// What to show of a pause
+#[derive(Clone, Debug, PartialEq)]
+enum Inspect {
+    Event,
+    Meta,
+    State,
+    Locals,
+    Window,
+    Group,
+    Local(String),
+}
+
+/// A command typed into the console
+#[derive(Clone, Debug, PartialEq)]
+enum Command {
+    Resume(Resume),
+    Break(usize),
+    Delete(usize),
+    Breakpoints,
+    Print(Inspect),
+    List,
+    Backtrace,
+    Help,
+}
+
+const HELP: &str = r#"Commands:
+  c, continue       run until the next breakpoint
+  s, step           pause on the next expression
+  n, next           pause on the next line, stepping over function calls
+  b, break <line>   set a breakpoint on a line
+  d, delete <line>  remove the breakpoint on a line
+  i, breakpoints    list the breakpoints
+  p, print <what>   print the `event`, `meta` (or `$`), `state`, `locals`,
+                    `window`, `group` or a local by its name
+  l, list           show where the script is paused
+  bt, backtrace     show the frames the script is paused in
+  q, quit           abort the script
+  h, help           show this help"#;
+
+impl Command {
+    fn parse(input: &str) -> std::result::Result<Self, String> {
+        let mut words = input.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let arg = words.next();
+        let line = || {
+            arg.and_then(|l| l.parse().ok())
+                .ok_or_else(|| format!("`{}` needs a line number", command))
+        };
+        Ok(match command {
+            "c" | "continue" => Self::Resume(Resume::Continue),
+            "s" | "step" => Self::Resume(Resume::Step),
+            "n" | "next" => Self::Resume(Resume::Next),
+            "q" | "quit" => Self::Resume(Resume::Abort),
+            "b" | "break" => Self::Break(line()?),
+            "d" | "delete" => Self::Delete(line()?),
+            "i" | "breakpoints" => Self::Breakpoints,
+            "p" | "print" => Self::Print(match arg {
+                Some("event") => Inspect::Event,
+                Some("meta") | Some("$") => Inspect::Meta,
+                Some("state") => Inspect::State,
+                Some("locals") | None => Inspect::Locals,
+                Some("window") => Inspect::Window,
+                Some("group") => Inspect::Group,
+                Some(name) => Inspect::Local(name.to_string()),
+            }),
+            "l" | "list" => Self::List,
+            "bt" | "backtrace" => Self::Backtrace,
+            "h" | "help" | "" => Self::Help,
+            other => return Err(format!("Unknown command `{}`, try `help`", other)),
+        })
+    }
+}
+
+/// A frontend reading commands from stdin and highlighting the line the
+/// script is paused on
+pub struct Console {
+    file: String,
+    source: String,
+}
+
+impl Console {
+    /// Creates a console for the main file `file` containing `source`
+    pub fn new(file: &str, source: &str) -> Self {
+        Self {
+            file: file.to_string(),
+            source: source.to_string(),
+        }
+    }
+
+    fn show(&self, pause: &Pause) -> io::Result<()> {
+        let name = pause.frames.last().map_or("<query>", |f| f.name.as_str());
+        println!(
+            "Paused in {} at {}:{}:{}",
+            name, self.file, pause.start.line, pause.start.column
+        );
+        // We only have the source of the main file
+        if pause.start.unit_id != 0 {
+            return Ok(());
+        }
+        let tokens: Vec<_> = Tokenizer::new(&self.source)
+            .filter_map(std::result::Result::ok)
+            .collect();
+        let context = Range(pause.start, pause.end).expand_lines(2);
+        let callout = Callout::new(
+            pause.start,
+            pause.end,
+            "paused here".to_string(),
+            ErrorLevel::Hint,
+        );
+        let mut h = TermHighlighter::new();
+        h.highlight_runtime_error(None, &tokens, context.0, context.1, Some(callout))?;
+        h.finalize()
+    }
+
+    fn print(pause: &Pause, what: &Inspect) -> Result<()> {
+        let value = match what {
+            Inspect::Event => Some(pause.event),
+            Inspect::Meta => Some(pause.meta),
+            Inspect::State => Some(pause.state),
+            Inspect::Window => pause.window(),
+            Inspect::Group => pause.group(),
+            Inspect::Local(name) => pause.local(name),
+            Inspect::Locals => {
+                for (name, value) in pause.locals() {
+                    println!("{} = {}", name, value.encode());
+                }
+                return Ok(());
+            }
+        };
+        if let Some(value) = value {
+            println!("{}", simd_json::to_string_pretty(value)?);
+        } else {
+            println!("not set");
+        }
+        Ok(())
+    }
+}
+
+impl Frontend for Console {
+    fn paused(&mut self, debugger: &Debugger, pause: &Pause) -> Resume {
+        if let Err(e) = self.show(pause) {
+            eprintln!("Error: {}", e);
+        }
+        let stdin = io::stdin();
+        loop {
+            print!("(debug) ");
+            io::stdout().flush().ok();
+            let mut input = String::new();
+            match stdin.lock().read_line(&mut input) {
+                // Without anyone to ask there is no point in carrying on
+                Ok(0) | Err(_) => return Resume::Abort,
+                Ok(_) => (),
+            }
+            match Command::parse(&input) {
+                Ok(Command::Resume(resume)) => return resume,
+                Ok(Command::Break(line)) => {
+                    debugger.set_breakpoint(line);
+                    println!("Breakpoint set on line {}", line);
+                }
+                Ok(Command::Delete(line)) => {
+                    if debugger.remove_breakpoint(line) {
+                        println!("Breakpoint on line {} removed", line);
+                    } else {
+                        println!("No breakpoint on line {}", line);
+                    }
+                }
+                Ok(Command::Breakpoints) => {
+                    for line in debugger.breakpoints() {
+                        println!("{}:{}", self.file, line);
+                    }
+                }
+                Ok(Command::Print(what)) => {
+                    if let Err(e) = Self::print(pause, &what) {
+                        eprintln!("Error: {}", e);
+                    }
+                }
+                Ok(Command::List) => {
+                    if let Err(e) = self.show(pause) {
+                        eprintln!("Error: {}", e);
+                    }
+                }
+                Ok(Command::Backtrace) => {
+                    for frame in pause.frames.iter().rev() {
+                        println!("  {}", frame.name);
+                    }
+                }
+                Ok(Command::Help) => println!("{}", HELP),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::path::ModulePath;
+    use crate::registry;
+    use crate::{AggrType, EventContext, Script};
+
+    /// Records where it paused and what `x` was, resuming as told
+    struct Scripted {
+        resumes: Vec<Resume>,
+        paused: Rc<RefCell<Vec<(usize, Option<String>)>>>,
+    }
+
+    impl Frontend for Scripted {
+        fn paused(&mut self, _debugger: &Debugger, pause: &Pause) -> Resume {
+            let x = pause.local("x").map(|v| v.encode());
+            self.paused.borrow_mut().push((pause.start.line, x));
+            if self.resumes.is_empty() {
+                Resume::Continue
+            } else {
+                self.resumes.remove(0)
+            }
+        }
+    }
+
+    fn debug(
+        src: &str,
+        breakpoints: &[usize],
+        resumes: Vec<Resume>,
+    ) -> (Result<()>, Vec<(usize, Option<String>)>) {
+        let reg = registry::registry();
+        let script = Script::parse(
+            &ModulePath { mounts: vec![] },
+            "<test>",
+            src.to_string(),
+            &reg,
+        )
+        .expect("failed to parse script");
+        let paused = Rc::new(RefCell::new(Vec::new()));
+        let debugger = Rc::new(Debugger::new(Box::new(Scripted {
+            resumes,
+            paused: paused.clone(),
+        })));
+        for line in breakpoints {
+            debugger.set_breakpoint(*line);
+        }
+        attach(debugger);
+        let mut event = Value::object();
+        let mut meta = Value::object();
+        let mut state = Value::null();
+        let r = script
+            .run(
+                &EventContext::new(0, None),
+                AggrType::Emit,
+                &mut event,
+                &mut state,
+                &mut meta,
+            )
+            .map(|_| ());
+        detach();
+        let paused = paused.borrow().clone();
+        (r, paused)
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Command::parse("c\n"), Ok(Command::Resume(Resume::Continue)));
+        assert_eq!(Command::parse("break 3"), Ok(Command::Break(3)));
+        assert_eq!(Command::parse("d 7"), Ok(Command::Delete(7)));
+        assert_eq!(Command::parse("p $"), Ok(Command::Print(Inspect::Meta)));
+        assert_eq!(
+            Command::parse("print snot"),
+            Ok(Command::Print(Inspect::Local("snot".into())))
+        );
+        assert!(Command::parse("break").is_err());
+        assert!(Command::parse("badger").is_err());
+    }
+
+    #[test]
+    fn breakpoints() {
+        let src = "let x = 1;\nlet x = x + 1;\nlet x = x + 1;\nx";
+        let (r, paused) = debug(src, &[2, 4], vec![]);
+        assert!(r.is_ok());
+        assert_eq!(paused, vec![(2, Some("1".into())), (4, Some("3".into()))]);
+    }
+
+    #[test]
+    fn next() {
+        let src = "let x = 1;\nlet x = x + 1;\nlet x = x + 1;\nx";
+        let (r, paused) = debug(src, &[2], vec![Resume::Next, Resume::Next]);
+        assert!(r.is_ok());
+        assert_eq!(
+            paused,
+            vec![
+                (2, Some("1".into())),
+                (3, Some("2".into())),
+                (4, Some("3".into()))
+            ]
+        );
+    }
+
+    #[test]
+    fn abort() {
+        let src = "let x = 1;\nlet y = try\n  x + 1\ncatch\n  e => 0\nend;\ny";
+        let (r, paused) = debug(src, &[3], vec![Resume::Abort]);
+        assert_eq!(paused, vec![(3, Some("1".into()))]);
+        assert!(r.err().map_or(false, |e| e.is_aborted()));
+    }
+}
@@ -124,7 +124,7 @@ impl<'screw_lalrpop> From<ParserError<'screw_lalrpop>> for Error {
 }
 
 // We need this since we call objects records
-fn t2s(t: ValueType) -> &'static str {
+pub(crate) fn t2s(t: ValueType) -> &'static str {
     match t {
         ValueType::Null => "null",
         ValueType::Bool => "boolean",
@@ -155,23 +155,25 @@ impl ErrorKind {
     }
     pub(crate) fn expr(&self) -> ErrorLocation {
         use ErrorKind::{
-            AggrInAggr, ArrayOutOfRange, AssignIntoArray, AssignToConst, BadAccessInEvent,
+            Aborted, AggrInAggr, ArrayOutOfRange, AssignIntoArray, AssignToConst, BadAccessInEvent,
             BadAccessInGlobal, BadAccessInLocal, BadAccessInState, BadArity, BadArrayIndex,
             BadType, BinaryDrop, BinaryEmit, DecreasingRange, DoubleConst, DoubleStream,
             EmptyScript, ExtraToken, Generic, Grok, InvalidAssign, InvalidBinary, InvalidBitshift,
             InvalidConst, InvalidDrop, InvalidEmit, InvalidExtractor, InvalidFloatLiteral,
             InvalidFn, InvalidHexLiteral, InvalidInfluxData, InvalidIntLiteral, InvalidMod,
-            InvalidRecur, InvalidToken, InvalidUnary, Io, JSONError, MergeTypeConflict,
-            MissingEffectors, MissingFunction, MissingModule, ModuleNotFound, Msg, NoClauseHit,
-            NoConstsAllowed, NoLocalsAllowed, NoObjectError, NotConstant, NotFound, Oops,
-            ParseIntError, ParserError, PatchKeyExists, PreprocessorError, QueryStreamNotDefined,
-            RuntimeError, TailingHereDoc, TypeConflict, UnexpectedCharacter, UnexpectedEndOfStream,
-            UnexpectedEscapeCode, UnrecognizedToken, UnterminatedExtractor, UnterminatedHereDoc,
-            UnterminatedIdentLiteral, UnterminatedStringLiteral, UpdateKeyMissing, Utf8Error,
+            InvalidRecur, InvalidToken, InvalidUnary, Io, JSONError, LimitExceeded,
+            MergeTypeConflict, MissingEffectors, MissingFunction, MissingModule, ModuleNotFound,
+            Msg, NoClauseHit, NoConstsAllowed, NoLocalsAllowed, NoObjectError, NotConstant,
+            NotFound, Oops, ParseIntError, ParserError, PatchKeyExists, PreprocessorError,
+            QueryStreamNotDefined, RuntimeError, TailingHereDoc, TypeConflict, UnexpectedCharacter,
+            UnexpectedEndOfStream, UnexpectedEscapeCode, UnrecognizedToken, UnterminatedExtractor,
+            UnterminatedHereDoc, UnterminatedIdentLiteral, UnterminatedStringLiteral,
+            UpdateKeyMissing, Utf8Error,
         };
         match self {
             NoClauseHit(outer) | Oops(outer, _, _) => (Some(outer.expand_lines(2)), Some(*outer)),
             QueryStreamNotDefined(outer, inner, _)
+            | Aborted(outer, inner)
             | ArrayOutOfRange(outer, inner, _, _)
             | AssignIntoArray(outer, inner)
             | BadAccessInEvent(outer, inner, _, _)
@@ -204,6 +206,7 @@ impl ErrorKind {
             | InvalidIntLiteral(outer, inner)
             | InvalidToken(outer, inner)
             | InvalidUnary(outer, inner, _, _)
+            | LimitExceeded(outer, inner, _, _)
             | MergeTypeConflict(outer, inner, _, _)
             | MissingEffectors(outer, inner)
             | MissingFunction(outer, inner, _, _, _)
@@ -257,8 +260,8 @@ impl ErrorKind {
     }
     pub(crate) fn hint(&self) -> Option<String> {
         use ErrorKind::{
-            BadAccessInEvent, BadAccessInGlobal, BadAccessInLocal, MissingFunction, MissingModule,
-            NoClauseHit, Oops, TypeConflict, UnrecognizedToken,
+            BadAccessInEvent, BadAccessInGlobal, BadAccessInLocal, LimitExceeded, MissingFunction,
+            MissingModule, NoClauseHit, Oops, TypeConflict, UnrecognizedToken,
         };
         match self {
             UnrecognizedToken(outer, inner, t, _) if t == "" && inner.0.absolute == outer.1.absolute => Some("It looks like a `;` is missing at the end of the script".into()),
@@ -303,6 +306,7 @@ impl ErrorKind {
             MissingModule(_, _, m, _) if m == "object" => Some("Did you mean to use the `record` module".into()),
             MissingModule(_, _, _, Some((_, suggestion))) | MissingFunction(_, _, _, _, Some((_, suggestion))) => Some(format!("Did you mean `{}`?", suggestion)),
 
+            LimitExceeded(_, _, limit, _) => Some(format!("The `{}` limit is set in the `limits` of the script configuration", limit)),
             NoClauseHit(_) => Some("Consider adding a `default => null` clause at the end of your match or validate full coverage beforehand.".into()),
             Oops(_, id, _) => Some(format!("Please take the error output script and test data and open a ticket, this should not happen.\nhttps://github.com/wayfair-tremor/tremor-runtime/issues/new?labels=bug&template=bug_report.md&title=Opps%20{}", id)),
             _ => None,
@@ -326,6 +330,76 @@ impl Error {
     pub(crate) fn token(&self) -> Option<String> {
         self.0.token()
     }
+
+    /// If the error was raised by a script exceeding one of its limits
+    pub fn is_limit_exceeded(&self) -> bool {
+        if let ErrorKind::LimitExceeded(..) = self.0 {
+            true
+        } else {
+            false
+        }
+    }
+
+    /// If the error was raised by the debugger aborting a script
+    pub fn is_aborted(&self) -> bool {
+        if let ErrorKind::Aborted(..) = self.0 {
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The error as the record bound by the `catch` clauses of a `try`
+    /// expression, `mfa` is only set for errors raised by functions.
+    pub(crate) fn to_value(&self) -> Value<'static> {
+        use ErrorKind::{
+            ArrayOutOfRange, BadAccessInEvent, BadAccessInGlobal, BadAccessInLocal,
+            BadAccessInState, BadArity, BadArrayIndex, BadType, InvalidBinary, InvalidUnary,
+            MergeTypeConflict, MissingFunction, MissingModule, NoClauseHit, PatchKeyExists,
+            RuntimeError, TypeConflict, UpdateKeyMissing,
+        };
+        let mut message = self.to_string();
+        let mut mfa = None;
+        let kind = match &self.0 {
+            BadArity(_, _, m, f, a, _) => {
+                mfa = Some((m, f, *a.start()));
+                "bad_arity"
+            }
+            BadType(_, _, m, f, a) => {
+                mfa = Some((m, f, *a));
+                "bad_type"
+            }
+            RuntimeError(_, _, m, f, a, error) => {
+                mfa = Some((m, f, *a));
+                message = error.clone();
+                "runtime_error"
+            }
+            MissingModule(..) | MissingFunction(..) => "missing_function",
+            BadAccessInEvent(..)
+            | BadAccessInState(..)
+            | BadAccessInGlobal(..)
+            | BadAccessInLocal(..) => "bad_access",
+            BadArrayIndex(..) | ArrayOutOfRange(..) => "bad_index",
+            InvalidBinary(..) | InvalidUnary(..) | TypeConflict(..) | MergeTypeConflict(..) => {
+                "type_conflict"
+            }
+            PatchKeyExists(..) | UpdateKeyMissing(..) => "bad_patch",
+            NoClauseHit(..) => "no_clause_hit",
+            _ => "error",
+        };
+        let mut record = simd_json::borrowed::Object::with_capacity(3);
+        record.insert("kind".into(), Value::from(kind));
+        record.insert("message".into(), Value::from(message));
+        let mfa = mfa.map_or_else(Value::null, |(m, f, a)| {
+            let mut mfa = simd_json::borrowed::Object::with_capacity(3);
+            mfa.insert("module".into(), Value::from(m.clone()));
+            mfa.insert("function".into(), Value::from(f.clone()));
+            mfa.insert("arity".into(), Value::from(a));
+            Value::from(mfa)
+        });
+        record.insert("mfa".into(), mfa);
+        Value::from(record)
+    }
 }
 
 fn choices<T>(choices: &[T]) -> String
@@ -682,6 +756,20 @@ error_chain! {
             description("Local variables are not allowed here")
                 display("Local variables are not allowed here")
         }
+        /*
+         * Limits
+         */
+        LimitExceeded(expr: Range, inner: Range, limit: String, max: u64) {
+            description("Script limit exceeded")
+                display("The script exceeded its `{}` limit of {}", limit, max)
+        }
+        /*
+         * Debugger
+         */
+        Aborted(expr: Range, inner: Range) {
+            description("Script aborted")
+                display("The script was aborted by the debugger")
+        }
     }
 }
 
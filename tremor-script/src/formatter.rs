@@ -0,0 +1,801 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Canonical source formatter for scripts and queries.
+//!
+//! The formatter works on the token stream of the lexer rather than on the
+//! AST so comments, doc comments and module comments survive. It only ever
+//! changes whitespace: tokens are kept as written, spaces between them are
+//! normalised and lines are indented two spaces for every open block.
+//!
+//! Line breaks don't depend on where the author put them. Every statement,
+//! block body and `case` starts a line, `end` and `catch` start one too and
+//! brackets are broken up, one element per line, only if they hold a block
+//! or a comment or don't fit in `MAX_WIDTH`. Comments stay on their own line
+//! or at the end of the line they were on, and a single blank line is kept
+//! where the author had any in front of something that starts a line.
+//!
+//! As the layout only depends on the tokens, the comments and those blank
+//! lines formatting is idempotent.
+
+use crate::errors::Result;
+use crate::lexer::{Token, Tokenizer};
+use crate::pos::Location;
+use std::fs;
+
+/// Width of one level of indentation
+const INDENT: &str = "  ";
+
+/// Brackets that would make a line wider than this are broken up
+const MAX_WIDTH: usize = 80;
+
+/// A token together with its text as it is written out
+struct Lexeme<'input> {
+    token: Token<'input>,
+    text: String,
+    /// Number of line breaks in front of it in the source
+    breaks: usize,
+}
+
+/// Something that is closed again later on, a bracket or a block
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Paren,
+    Bracket,
+    Brace,
+    Binary,
+    Match,
+    Patch,
+    Merge,
+    For,
+    Try,
+    /// A `with` block of a function, module or query definition
+    With,
+    /// The cases of a function defined with `of`
+    Of,
+    /// The `script` block of a script definition
+    Script,
+}
+
+#[derive(Clone, Debug)]
+struct Open {
+    kind: Kind,
+    /// Indentation of the lines inside
+    indent: usize,
+    /// For `match`, `patch`, `merge` and `for` that haven't seen their `of`
+    /// yet
+    header: bool,
+    /// If we are past the first `case` so case bodies get indented
+    in_case: bool,
+    /// For brackets, if they are broken up with one element per line
+    broken: bool,
+}
+
+impl Open {
+    fn is_bracket(&self) -> bool {
+        match self.kind {
+            Kind::Paren | Kind::Bracket | Kind::Brace | Kind::Binary => true,
+            _ => false,
+        }
+    }
+    fn has_cases(&self) -> bool {
+        !self.header
+            && (self.kind == Kind::Match || self.kind == Kind::For || self.kind == Kind::Of)
+    }
+}
+
+/// Formats a script or query in the canonical style
+pub fn format(src: &str) -> Result<String> {
+    let mut src = src.to_string();
+    // The lexer cuts the last character of a comment at the end of the input
+    if !src.ends_with('\n') {
+        src.push('\n');
+    }
+    let lexemes = lexemes(&src)?;
+    Ok(Layout::new(&lexemes).format())
+}
+
+/// Formats the file at `path` in place, with `check` it is left alone.
+/// Returns whether the file was not formatted.
+pub fn format_file(path: &str, check: bool) -> Result<bool> {
+    let src = fs::read_to_string(path)?;
+    let formatted = format(&src)?;
+    if formatted == src {
+        Ok(false)
+    } else {
+        if !check {
+            fs::write(path, formatted)?;
+        }
+        Ok(true)
+    }
+}
+
+/// Turns the source into lexemes, whitespace dropped and strings as one
+/// lexeme each.
+fn lexemes(src: &str) -> Result<Vec<Lexeme>> {
+    // Locations count characters, we need bytes to slice
+    let offsets: Vec<usize> = src
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(src.len()))
+        .collect();
+    let offset = |l: Location| offsets.get(l.absolute).copied().unwrap_or(src.len());
+    let slice = |start: Location, end: Location| src.get(offset(start)..offset(end)).unwrap_or("");
+
+    let mut lexemes = Vec::new();
+    // Strings can contain interpolated code which can contain strings, we
+    // keep track of the nesting to find the end of the outermost string.
+    // `None` stands for a string, `Some(n)` for interpolated code with `n`
+    // open braces.
+    let mut strings: Vec<Option<usize>> = Vec::new();
+    let mut string_start = Location::default();
+    let mut breaks = 0;
+    for token in Tokenizer::new(src) {
+        let token = token?;
+        let (start, end) = (token.span.start(), token.span.end());
+        if !strings.is_empty() {
+            match (&token.value, strings.last().copied()) {
+                (Token::DQuote, Some(None)) | (Token::RBrace, Some(Some(0))) => {
+                    strings.pop();
+                }
+                (Token::DQuote, _) => strings.push(None),
+                (Token::LBrace, Some(None)) => strings.push(Some(0)),
+                (Token::LBrace, Some(Some(n))) => {
+                    strings.pop();
+                    strings.push(Some(n + 1));
+                }
+                (Token::RBrace, Some(Some(n))) => {
+                    strings.pop();
+                    strings.push(Some(n - 1));
+                }
+                _ => (),
+            }
+            if strings.is_empty() {
+                lexemes.push(Lexeme {
+                    token: Token::DQuote,
+                    text: slice(string_start, end).to_string(),
+                    breaks,
+                });
+                breaks = 0;
+            }
+            continue;
+        }
+        let text = match &token.value {
+            Token::Whitespace(_) | Token::EndOfStream => continue,
+            Token::NewLine => {
+                breaks += 1;
+                continue;
+            }
+            Token::Bad(bad) => {
+                return Err(format!(
+                    "Unexpected `{}` at {}:{}, only valid code can be formatted",
+                    bad, start.line, start.column
+                )
+                .into())
+            }
+            Token::DQuote => {
+                strings.push(None);
+                string_start = start;
+                continue;
+            }
+            Token::SingleLineComment(_) | Token::DocComment(_) | Token::ModComment(_) => {
+                // Comments run to the end of the line
+                let rest = src.get(offset(start)..).unwrap_or("");
+                rest.lines().next().unwrap_or("").trim_end().to_string()
+            }
+            Token::IntLiteral(_)
+            | Token::FloatLiteral(_, _)
+            | Token::HereDoc(_, _)
+            | Token::TestLiteral(_, _) => slice(start, end).to_string(),
+            Token::ConfigDirective => token.value.to_string().trim_end().to_string(),
+            other => other.to_string(),
+        };
+        lexemes.push(Lexeme {
+            token: token.value,
+            text,
+            breaks,
+        });
+        breaks = 0;
+    }
+    Ok(lexemes)
+}
+
+fn is_comment(token: &Token) -> bool {
+    match token {
+        Token::SingleLineComment(_) | Token::DocComment(_) | Token::ModComment(_) => true,
+        _ => false,
+    }
+}
+
+/// If the token can end an operand, so an operator following it is binary
+fn ends_operand(token: &Token) -> bool {
+    match token {
+        Token::Ident(_, _)
+        | Token::IntLiteral(_)
+        | Token::FloatLiteral(_, _)
+        | Token::BoolLiteral(_)
+        | Token::Nil
+        | Token::DQuote
+        | Token::HereDoc(_, _)
+        | Token::TestLiteral(_, _)
+        | Token::RParen
+        | Token::RBracket
+        | Token::RBrace
+        | Token::Event
+        | Token::State
+        | Token::Args
+        | Token::Dollar
+        | Token::DontCare
+        | Token::Group
+        | Token::Window
+        | Token::End => true,
+        _ => false,
+    }
+}
+
+/// If a line ending in the token is done, otherwise the next one continues
+/// it and gets indented one level more
+fn ends_line(token: &Token) -> bool {
+    match token {
+        Token::Semi
+        | Token::Comma
+        | Token::LParen
+        | Token::LPatParen
+        | Token::LBracket
+        | Token::LPatBracket
+        | Token::LBrace
+        | Token::LPatBrace
+        | Token::LBitShift
+        | Token::Of
+        | Token::With
+        | Token::EqArrow
+        | Token::Script
+        | Token::Try
+        | Token::End => true,
+        _ => false,
+    }
+}
+
+/// Brackets that can be broken up
+fn opens_bracket(token: &Token) -> bool {
+    match token {
+        Token::LParen
+        | Token::LPatParen
+        | Token::LBracket
+        | Token::LPatBracket
+        | Token::LBrace
+        | Token::LPatBrace => true,
+        _ => false,
+    }
+}
+
+fn closes_bracket(token: &Token) -> bool {
+    match token {
+        Token::RParen | Token::RBracket | Token::RBrace => true,
+        _ => false,
+    }
+}
+
+/// Tokens that start a query statement after a `#!config` directive
+fn starts_statement(token: &Token) -> bool {
+    match token {
+        Token::ConfigDirective | Token::Select | Token::Define | Token::Create | Token::Module => {
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The open brackets and blocks and what we know about the last token
+#[derive(Clone, Default)]
+struct Blocks<'input> {
+    stack: Vec<Open>,
+    /// The last token that wasn't a comment
+    prev: Option<Token<'input>>,
+    /// If the last token ended an operand
+    operand: bool,
+    /// If the next token goes right after the last one
+    glue: bool,
+}
+
+impl<'input> Blocks<'input> {
+    fn prev_is(&self, tokens: &[Token]) -> bool {
+        self.prev.as_ref().map_or(false, |p| tokens.contains(p))
+    }
+
+    fn top_is(&self, kind: Kind) -> bool {
+        self.stack.last().map_or(false, |o| o.kind == kind)
+    }
+
+    fn top_is_broken(&self) -> bool {
+        self.stack
+            .last()
+            .map_or(false, |o| o.is_bracket() && o.broken)
+    }
+
+    /// If a `script` starts the script of a definition with a `with` block
+    fn script_in_with(&self) -> bool {
+        self.top_is(Kind::With) && !self.prev_is(&[Token::Define, Token::Create])
+    }
+
+    /// If no space goes between `last` and `next` on a line
+    fn glues(&self, last: &Token, next: &Token) -> bool {
+        if is_comment(next) {
+            return false;
+        }
+        // `--` isn't a thing for the lexer
+        if (*last == Token::Sub || *last == Token::Add)
+            && (*next == Token::Sub || *next == Token::Add)
+        {
+            return false;
+        }
+        if self.glue {
+            return true;
+        }
+        match next {
+            Token::RParen
+            | Token::RBracket
+            | Token::RBrace
+            | Token::Comma
+            | Token::Semi
+            | Token::Colon
+            | Token::Dot
+            | Token::ColonColon => true,
+            Token::LParen => match last {
+                Token::Ident(_, _) | Token::Recur | Token::Set | Token::Each => true,
+                _ => false,
+            },
+            Token::LBracket => self.operand,
+            Token::TestLiteral(_, _) => match last {
+                Token::Ident(_, _) => true,
+                _ => false,
+            },
+            Token::Ident(_, _) => *last == Token::Dollar,
+            Token::LBitShift => *last == Token::Mod && !self.operand,
+            _ => false,
+        }
+    }
+
+    /// Keeps track of brackets and blocks, what gets opened is indented
+    /// one level more than `indent`
+    fn feed(&mut self, token: &Token<'input>, indent: usize, broken: bool) {
+        let was_operand = self.operand;
+        let mut operand = ends_operand(token);
+        self.glue = match token {
+            Token::LParen
+            | Token::LPatParen
+            | Token::LBracket
+            | Token::LPatBracket
+            | Token::LBrace
+            | Token::LPatBrace
+            | Token::Dot
+            | Token::ColonColon => true,
+            // Unary operators and the `%` of a binary pattern
+            Token::Sub | Token::Add | Token::Mod => !was_operand,
+            // `<< 1:8 >>` and `bin[0:1]`
+            Token::Colon => self.top_is(Kind::Binary) || self.top_is(Kind::Bracket),
+            _ => false,
+        };
+        match token {
+            Token::LParen | Token::LPatParen => self.open(Kind::Paren, indent, broken),
+            Token::LBracket | Token::LPatBracket => self.open(Kind::Bracket, indent, broken),
+            Token::LBrace | Token::LPatBrace => self.open(Kind::Brace, indent, broken),
+            Token::LBitShift if !was_operand => self.open(Kind::Binary, indent, false),
+            Token::RParen | Token::RBracket | Token::RBrace => {
+                if self.stack.last().map_or(false, Open::is_bracket) {
+                    self.stack.pop();
+                }
+            }
+            Token::RBitShiftSigned if self.top_is(Kind::Binary) => {
+                self.stack.pop();
+                operand = true;
+            }
+            Token::Match => self.open(Kind::Match, indent, false),
+            Token::Patch => self.open(Kind::Patch, indent, false),
+            Token::For => self.open(Kind::For, indent, false),
+            Token::Try => self.open(Kind::Try, indent, false),
+            // `merge` is also an operation of `patch`
+            Token::Merge
+                if !(self.top_is(Kind::Patch) && self.prev_is(&[Token::Of, Token::Comma])) =>
+            {
+                self.open(Kind::Merge, indent, false)
+            }
+            Token::Of => {
+                if let Some(top) = self.stack.last_mut().filter(|o| o.header) {
+                    top.header = false;
+                } else {
+                    self.open(Kind::Of, indent, false)
+                }
+            }
+            Token::With => self.open(Kind::With, indent, false),
+            Token::Script
+                if !self.prev_is(&[Token::Define, Token::Create]) && !self.script_in_with() =>
+            {
+                self.open(Kind::Script, indent, false)
+            }
+            Token::Case | Token::Default => {
+                if let Some(top) = self.stack.last_mut().filter(|o| o.has_cases()) {
+                    top.in_case = true;
+                }
+            }
+            Token::End => {
+                while let Some(open) = self.stack.pop() {
+                    if !open.is_bracket() {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+        self.operand = operand;
+        self.prev = Some(token.clone());
+    }
+
+    fn open(&mut self, kind: Kind, indent: usize, broken: bool) {
+        let header = match kind {
+            Kind::Match | Kind::Patch | Kind::Merge | Kind::For => true,
+            _ => false,
+        };
+        self.stack.push(Open {
+            kind,
+            indent: indent + 1,
+            header,
+            in_case: false,
+            broken,
+        });
+    }
+}
+
+struct Layout<'lexemes, 'input> {
+    lexemes: &'lexemes [Lexeme<'input>],
+    /// The index of the closing bracket for every opening one
+    closing: Vec<Option<usize>>,
+    blocks: Blocks<'input>,
+    lines: Vec<String>,
+    /// The current line without its indentation
+    line: String,
+    /// Indentation of the current line
+    indent: usize,
+    /// If a blank line goes before the current line
+    blank: bool,
+    /// The last token on the current line
+    last: Option<&'lexemes Token<'input>>,
+    /// If the next token has to start a new line
+    newline: bool,
+    /// If the last line wasn't done
+    continued: bool,
+    /// If we are in the expression of a `#!config` directive
+    config: bool,
+    /// Comment lines waiting for the next line of code to know their
+    /// indentation, with whether there was a blank line before them
+    comments: Vec<(bool, String)>,
+}
+
+impl<'lexemes, 'input> Layout<'lexemes, 'input> {
+    fn new(lexemes: &'lexemes [Lexeme<'input>]) -> Self {
+        let mut closing = vec![None; lexemes.len()];
+        let mut open = Vec::new();
+        for (i, lexeme) in lexemes.iter().enumerate() {
+            if opens_bracket(&lexeme.token) {
+                open.push(i);
+            } else if closes_bracket(&lexeme.token) {
+                if let Some(o) = open.pop().and_then(|o| closing.get_mut(o)) {
+                    *o = Some(i);
+                }
+            }
+        }
+        Self {
+            lexemes,
+            closing,
+            blocks: Blocks::default(),
+            lines: Vec::new(),
+            line: String::new(),
+            indent: 0,
+            blank: false,
+            last: None,
+            newline: false,
+            continued: false,
+            config: false,
+            comments: Vec::new(),
+        }
+    }
+
+    fn format(mut self) -> String {
+        let lexemes = self.lexemes;
+        for (i, lexeme) in lexemes.iter().enumerate() {
+            if is_comment(&lexeme.token) {
+                self.comment(lexeme);
+            } else {
+                self.token(i, lexeme);
+            }
+        }
+        if self.last.is_some() {
+            self.flush();
+        }
+        // Comments at the very end stay at the level of the code before them
+        self.indent = self.blocks.stack.last().map_or(0, |o| o.indent);
+        for (blank, comment) in std::mem::take(&mut self.comments) {
+            self.push(blank, &comment);
+        }
+        let mut out = self.lines.join("\n");
+        out.push('\n');
+        out
+    }
+
+    fn comment(&mut self, lexeme: &Lexeme) {
+        if lexeme.breaks > 0 || self.last.is_none() {
+            self.comments.push((lexeme.breaks > 1, lexeme.text.clone()));
+        } else {
+            self.line.push(' ');
+            self.line.push_str(&lexeme.text);
+        }
+        self.newline = true;
+    }
+
+    fn token(&mut self, i: usize, lexeme: &'lexemes Lexeme<'input>) {
+        let token = &lexeme.token;
+        let statement = self.config && starts_statement(token);
+        if self.last.is_some() && (self.newline || statement || self.breaks_before(token)) {
+            self.continued = !statement && !self.blocks.prev.as_ref().map_or(true, ends_line);
+            self.config = false;
+            self.flush();
+        }
+        if let Some(last) = self.last {
+            if !self.blocks.glues(last, token) {
+                self.line.push(' ');
+            }
+        } else {
+            self.indent = self.indent_of(token);
+            for (blank, comment) in std::mem::take(&mut self.comments) {
+                self.push(blank, &comment);
+            }
+            self.blank = lexeme.breaks > 1;
+        }
+        let broken = self
+            .closing
+            .get(i)
+            .copied()
+            .flatten()
+            .map_or(false, |close| self.breaks_up(i, close));
+        let opens_script =
+            *token == Token::Script && !self.blocks.prev_is(&[Token::Define, Token::Create]);
+        self.line.push_str(&lexeme.text);
+        self.blocks.feed(token, self.indent, broken);
+        self.last = Some(token);
+        self.config |= *token == Token::ConfigDirective;
+        self.newline = match token {
+            Token::Semi | Token::With | Token::Of | Token::Try => true,
+            Token::Script => opens_script,
+            Token::EqArrow => self.has_statements(i),
+            Token::Comma => {
+                self.blocks.top_is(Kind::With)
+                    || self.blocks.top_is(Kind::Patch)
+                    || self.blocks.top_is_broken()
+            }
+            _ => broken,
+        };
+    }
+
+    /// If the token starts a new line no matter what comes before it
+    fn breaks_before(&self, token: &Token) -> bool {
+        match token {
+            Token::End | Token::Catch => true,
+            Token::Case | Token::Default => self.blocks.stack.last().map_or(false, Open::has_cases),
+            Token::Script => self.blocks.script_in_with(),
+            Token::RParen | Token::RBracket | Token::RBrace => self.blocks.top_is_broken(),
+            _ => false,
+        }
+    }
+
+    /// If the brackets opened at `open` and closed at `close` have to be
+    /// broken up
+    fn breaks_up(&self, open: usize, close: usize) -> bool {
+        let inside = self.lexemes.get(open..=close).unwrap_or(&[]);
+        let blocks = inside.iter().any(|l| match l.token {
+            Token::Semi | Token::End => true,
+            ref other => is_comment(other),
+        });
+        // Everything is counted with a space in front, more than needed
+        let width = INDENT.len() * self.indent
+            + self.line.chars().count()
+            + inside
+                .iter()
+                .map(|l| l.text.chars().count() + 1)
+                .sum::<usize>();
+        blocks || width > MAX_WIDTH
+    }
+
+    /// If the `=>` at `i` is followed by more than one statement
+    fn has_statements(&self, i: usize) -> bool {
+        let mut blocks = self.blocks.clone();
+        let depth = blocks.stack.len();
+        let rest = self.lexemes.get(i + 1..).unwrap_or(&[]);
+        for lexeme in rest.iter().filter(|l| !is_comment(&l.token)) {
+            if blocks.stack.len() == depth {
+                match lexeme.token {
+                    Token::Semi => return true,
+                    Token::Case | Token::Default | Token::Catch | Token::Comma => return false,
+                    _ => (),
+                }
+            }
+            blocks.feed(&lexeme.token, 0, false);
+            if blocks.stack.len() < depth {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn flush(&mut self) {
+        let line = std::mem::take(&mut self.line);
+        self.push(self.blank, &line);
+        self.blank = false;
+        self.last = None;
+        self.newline = false;
+    }
+
+    fn push(&mut self, blank: bool, text: &str) {
+        if blank && !self.lines.is_empty() {
+            self.lines.push(String::new());
+        }
+        self.lines
+            .push(format!("{}{}", INDENT.repeat(self.indent), text));
+    }
+
+    fn indent_of(&self, first: &Token) -> usize {
+        let top = self.blocks.stack.last();
+        let base = top.map_or(0, |o| o.indent);
+        let header = top.map_or(false, |o| o.header);
+        match first {
+            Token::RParen | Token::RBracket | Token::RBrace | Token::End => base.saturating_sub(1),
+            Token::RBitShiftSigned if self.blocks.top_is(Kind::Binary) => base.saturating_sub(1),
+            Token::Of if header => base.saturating_sub(1),
+            Token::Catch if self.blocks.top_is(Kind::Try) => base.saturating_sub(1),
+            Token::Script if self.blocks.script_in_with() => base.saturating_sub(1),
+            Token::Case | Token::Default if top.map_or(false, Open::has_cases) => base,
+            Token::With | Token::Of => base,
+            _ => {
+                let in_case = top.map_or(false, |o| o.has_cases() && o.in_case);
+                base + usize::from(in_case) + usize::from(self.continued)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_formats(src: &str, expected: &str) {
+        let formatted = format(src).expect("failed to format");
+        assert_eq!(expected, formatted);
+        assert_eq!(expected, format(&formatted).expect("failed to format"));
+    }
+
+    #[test]
+    fn spacing() {
+        assert_formats(
+            "let   a=event . b [ 1 ]  ;\nlet b = std::string::format( \"{}\" ,  - a ) ;",
+            "let a = event.b[1];\nlet b = std::string::format(\"{}\", -a);\n",
+        );
+        assert_formats(
+            "{ \"a\" : [ 1,2 ], \"b\":$meta , \"c\": << 1:8 , x : 4 >> }",
+            "{\"a\": [1, 2], \"b\": $meta, \"c\": << 1:8, x:4 >>}\n",
+        );
+        assert_formats("1 -  - 2", "1 - -2\n");
+    }
+
+    #[test]
+    fn strings() {
+        assert_formats(
+            "let s = \"a  {  event.b  } c\"  ;\nlet e = \"\";",
+            "let s = \"a  {  event.b  } c\";\nlet e = \"\";\n",
+        );
+        assert_formats(
+            "  let h = \"\"\"\n    snot\n  badger\n  \"\"\";\n",
+            "let h = \"\"\"\n    snot\n  badger\n  \"\"\";\n",
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        assert_formats(
+            "match event of\ncase %{ a == 1 } => \"one\"\ncase _ =>\nlet x = 2;\nx\nend",
+            "match event of\n  case %{a == 1} => \"one\"\n  case _ =>\n    let x = 2;\n    x\nend\n",
+        );
+        assert_formats(
+            "fn f(a) with\nmatch a of\ndefault => patch a of\ninsert \"b\" => 1,\nmerge => {}\nend\nend\nend;\n",
+            "fn f(a) with\n  match a of\n    default => patch a of\n      insert \"b\" => 1,\n      merge => {}\n    end\n  end\nend;\n",
+        );
+        assert_formats(
+            "try\nx + 1\ncatch e => 0\nend",
+            "try\n  x + 1\ncatch e => 0\nend\n",
+        );
+        assert_formats(
+            "let a = {\n\"b\": [\n1,\n2\n]\n};\nlet c = 1 +\n2;",
+            "let a = {\"b\": [1, 2]};\nlet c = 1 + 2;\n",
+        );
+        assert_formats(
+            "let r = {\"snot\": \"badger\", \"long_key_number_one\": 12345, \"another_rather_long_key\": [1, 2, 3]};",
+            "let r = {\n  \"snot\": \"badger\",\n  \"long_key_number_one\": 12345,\n  \"another_rather_long_key\": [1, 2, 3]\n};\n",
+        );
+        assert_formats(
+            "f(1, fn (x) with x end)",
+            "f(\n  1,\n  fn (x) with\n    x\n  end\n)\n",
+        );
+    }
+
+    #[test]
+    fn queries() {
+        assert_formats(
+            "define tumbling window w\nwith\nsize = 3\nend;\ndefine script s\nwith\na = 1\nscript\nlet event.a = args.a;\nevent\nend;\ncreate script s;\nselect event from in[w]\ninto out;",
+            "define tumbling window w with\n  size = 3\nend;\ndefine script s with\n  a = 1\nscript\n  let event.a = args.a;\n  event\nend;\ncreate script s;\nselect event from in[w] into out;\n",
+        );
+        assert_formats(
+            "#!config a = 1 #!config b = 2 select event from in into out;",
+            "#!config a = 1\n#!config b = 2\nselect event from in into out;\n",
+        );
+    }
+
+    #[test]
+    fn canonical() {
+        let expected = "fn f(a) with\n  match a of\n    case 1 =>\n      let b = 2;\n      b\n    default => patch a of\n      insert \"b\" => 1,\n      merge => {}\n    end\n  end\nend;\nlet c = [1, 2];\n";
+        let layouts = [
+            "fn f(a) with match a of case 1 => let b = 2; b default => patch a of insert \"b\" => 1, merge => {} end end end; let c = [1, 2];",
+            "fn f(a)\nwith\nmatch a\nof\ncase 1\n=>\nlet b = 2;\nb\ndefault =>\npatch a of insert \"b\" => 1,\nmerge => {} end\nend\nend;\nlet c = [\n1,\n2\n];",
+            "fn f(a) with\n      match a of\n  case 1 => let b = 2;   b\n default => patch a of\n insert \"b\" => 1, merge => {}\n end end\nend; let c =\n[1, 2];\n",
+            expected,
+        ];
+        for src in &layouts {
+            assert_formats(src, expected);
+        }
+    }
+
+    #[test]
+    fn idempotent() {
+        let mut files = Vec::new();
+        for dir in &["../tests/scripts", "../tests/queries"] {
+            for entry in fs::read_dir(dir).expect("no test directory") {
+                let path = entry.expect("bad directory entry").path();
+                files.push(path.join("script.tremor"));
+                files.push(path.join("query.trickle"));
+            }
+        }
+        for file in files.iter().filter(|f| f.exists()) {
+            let src = fs::read_to_string(file).expect("failed to read");
+            if let Ok(formatted) = format(&src) {
+                assert_eq!(
+                    formatted,
+                    format(&formatted).expect("failed to format"),
+                    "{} formats differently the second time",
+                    file.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn comments() {
+        assert_formats(
+            "### module docs\n\n\n## doc\nfn f() with   # trailing   \n   # inside\n1\nend;\n\n# last",
+            "### module docs\n\n## doc\nfn f() with # trailing\n  # inside\n  1\nend;\n\n# last\n",
+        );
+    }
+
+    #[test]
+    fn bad() {
+        assert!(format("let a = 1 ?? 2").is_err());
+        assert!(format("let a = \"snot").is_err());
+    }
+}
@@ -52,6 +52,18 @@ pub struct Error {
 }
 
 impl Error {
+    /// A callout without a hint spanning from `start` to `end`
+    pub fn new(start: Location, end: Location, callout: String, level: ErrorLevel) -> Self {
+        Self {
+            start,
+            end,
+            callout,
+            hint: None,
+            level,
+            token: None,
+        }
+    }
+
     /// Get start location of the error
     pub fn start(&self) -> Location {
         self.start
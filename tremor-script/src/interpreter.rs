@@ -32,19 +32,25 @@
 
 mod expr;
 mod imut_expr;
+mod vm;
 
 pub(crate) use self::expr::Cont;
+pub(crate) use self::vm::Program;
 use crate::ast::{
-    ArrayPattern, ArrayPredicatePattern, BaseExpr, BinOpKind, GroupBy, GroupByInt, ImutExprInt,
-    InvokeAggrFn, NodeMetas, Patch, PatchOperation, Path, Pattern, PredicatePattern, RecordPattern,
-    Segment, TuplePattern, UnaryOpKind,
+    ArrayPattern, ArrayPredicatePattern, BaseExpr, BinOpKind, BytesPattern, GroupBy, GroupByInt,
+    ImutExprInt, InvokeAggrFn, NodeMetas, Patch, PatchOperation, Path, Pattern, PredicatePattern,
+    RecordPattern, Segment, TuplePattern, UnaryOpKind,
 };
+use crate::binary::{as_bytes, elements, from_bytes, slice, untag, BitReader};
+use crate::debugger::Debugger;
 use crate::errors::{
     error_array_out_of_bound, error_bad_array_index, error_bad_key, error_decreasing_range,
     error_guard_not_bool, error_invalid_binary, error_invalid_bitshift, error_need_arr,
     error_need_int, error_need_obj, error_need_str, error_oops, error_patch_key_exists,
     error_patch_merge_type_conflict, error_patch_update_key_missing, Result,
 };
+use crate::limits::Budget;
+use crate::registry::CustomFn;
 use crate::stry;
 use crate::EventContext;
 use simd_json::borrowed::Value;
@@ -91,6 +97,12 @@ where
     pub meta: &'run NodeMetas,
     /// Maximal recursion depth in custom functions
     pub recursion_limit: u32,
+    /// Functions of the script function values refer to
+    pub functions: &'script [CustomFn<'script>],
+    /// What is left of the limits of the script, `None` if it has none
+    pub budget: Option<&'run Budget>,
+    /// The debugger attached to the thread the script runs on, if any
+    pub debugger: Option<&'run Debugger>,
 }
 
 /// Local variable stack
@@ -139,12 +151,17 @@ impl ExecOpts {
 
 #[inline]
 #[allow(clippy::cast_precision_loss)]
-fn val_eq<'event>(lhs: &Value<'event>, rhs: &Value<'event>) -> bool {
+pub(crate) fn val_eq<'event>(lhs: &Value<'event>, rhs: &Value<'event>) -> bool {
     // FIXME Consider Tony Garnock-Jones perserves w.r.t. forcing a total ordering
     // across builtin types if/when extending for 'lt' and 'gt' variants
     //
     use Value::{Array, Object, Static, String};
     let error = std::f64::EPSILON;
+    // binaries and function values are equal if they have the same tag
+    // and their elements are equal
+    if let (Some((l_tag, l)), Some((r_tag, r))) = (untag(lhs), untag(rhs)) {
+        return l_tag == r_tag && l.len() == r.len() && l.iter().zip(r).all(|(l, r)| val_eq(l, r));
+    }
     match (lhs, rhs) {
         (Object(l), Object(r)) => {
             if l.len() == r.len() {
@@ -489,8 +506,8 @@ where
             }
             // Next segment is an index: index into `current`, if it's an array
             Segment::Idx { idx, .. } => {
-                if let Some(a) = current.as_array() {
-                    let range_to_consider = subrange.unwrap_or_else(|| a.as_slice());
+                if let Some(a) = elements(current) {
+                    let range_to_consider = subrange.unwrap_or(a);
                     let idx = *idx;
 
                     if let Some(c) = range_to_consider.get(idx) {
@@ -517,8 +534,8 @@ where
                 range_end,
                 ..
             } => {
-                if let Some(a) = current.as_array() {
-                    let array = subrange.unwrap_or_else(|| a.as_slice());
+                if let Some(a) = elements(current) {
+                    let array = subrange.unwrap_or(a);
                     let start_idx = stry!(range_start
                         .eval_to_index(outer, opts, env, event, state, meta, local, path, &array));
                     let end_idx = stry!(range_end
@@ -572,8 +589,8 @@ where
                         return error_need_str(outer, segment, other.value_type(), &env.meta)
                     }
                     // If `current` is an array, the segment has to be an index
-                    (Value::Array(a), idx) => {
-                        let array = subrange.unwrap_or_else(|| a.as_slice());
+                    (Value::Array(_), idx) => {
+                        let array = subrange.or_else(|| elements(current)).unwrap_or_default();
                         let idx = value_to_index(outer, segment, idx, env, path, array)?;
 
                         if let Some(v) = array.get(idx) {
@@ -607,7 +624,7 @@ where
     }
 
     if let Some(range_to_consider) = subrange {
-        Ok(Cow::Owned(Value::from(range_to_consider.to_vec())))
+        Ok(Cow::Owned(slice(current, range_to_consider)))
     } else {
         Ok(Cow::Borrowed(current))
     }
@@ -836,6 +853,13 @@ where
                 Ok(false)
             }
         }
+        Pattern::Bytes(ref bp) => {
+            if match_bytes_pattern(target, bp).is_some() {
+                test_guard(outer, opts, env, event, state, meta, local, guard)
+            } else {
+                Ok(false)
+            }
+        }
         Pattern::Record(ref rp) => {
             if stry!(match_rp_expr(
                 outer,
@@ -886,6 +910,13 @@ where
         Pattern::Assign(ref a) => {
             match *a.pattern {
                 Pattern::DoNotCare => {
+                    stry!(set_local_shadow(
+                        outer,
+                        local,
+                        &env.meta,
+                        a.idx,
+                        target.clone()
+                    ));
                     test_guard(outer, opts, env, event, state, meta, local, guard)
                 }
                 Pattern::Array(ref ap) => {
@@ -964,6 +995,16 @@ where
                         Ok(false)
                     }
                 }
+                Pattern::Bytes(ref bp) => {
+                    if let Some(v) = match_bytes_pattern(target, bp) {
+                        // we need to assign prior to the guard so we can check
+                        // against the pattern expressions
+                        stry!(set_local_shadow(outer, local, &env.meta, a.idx, v));
+                        test_guard(outer, opts, env, event, state, meta, local, guard)
+                    } else {
+                        Ok(false)
+                    }
+                }
                 Pattern::Assign(_) => {
                     error_oops(outer, 0xdead_0004, "nested assign pattern", &env.meta)
                 }
@@ -1171,6 +1212,28 @@ where
     }
 }
 
+/// Matches a binary against a bit pattern, returns the values of the
+/// segments followed by the rest of the binary if the pattern has one.
+fn match_bytes_pattern(target: &Value, bp: &BytesPattern) -> Option<Value<'static>> {
+    let bytes = as_bytes(target)?;
+    let mut reader = BitReader::new(&bytes);
+    let mut values = Vec::with_capacity(bp.segments.len() + 1);
+    for segment in &bp.segments {
+        let value = reader.read(segment.bits)?;
+        if segment.value.map_or(false, |expected| expected != value) {
+            return None;
+        }
+        values.push(Value::from(value));
+    }
+    let rest = reader.rest()?;
+    if bp.rest {
+        values.push(from_bytes(rest));
+    } else if !rest.is_empty() {
+        return None;
+    }
+    Some(Value::Array(values))
+}
+
 #[inline]
 fn match_tp_expr<'run, 'event, 'script, Expr>(
     outer: &'script Expr,
@@ -1326,6 +1389,9 @@ impl<'script> GroupByInt<'script> {
             aggrs: &NO_AGGRS,
             meta: node_meta,
             recursion_limit: crate::recursion_limit(),
+            functions: &[],
+            budget: None,
+            debugger: None,
         };
         match self {
             GroupByInt::Expr { expr, .. } => {
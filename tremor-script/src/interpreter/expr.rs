@@ -20,6 +20,7 @@ use crate::ast::{
     BaseExpr, Comprehension, EmitExpr, EventPath, Expr, ImutExprInt, Match, Merge, Patch, Path,
     Segment,
 };
+use crate::binary::elements;
 use crate::errors::{
     error_assign_array, error_assign_to_const, error_bad_key, error_invalid_assign_target,
     error_missing_effector, error_need_obj, error_no_clause_hit, error_oops, Result,
@@ -95,7 +96,16 @@ where
     ) -> Result<Cont<'run, 'event>> {
         let target = stry!(expr.target.run(opts, env, event, state, meta, local));
 
-        for predicate in &expr.patterns {
+        let mut tables = expr.tables.iter().peekable();
+        let mut i = 0;
+        while let Some(predicate) = expr.patterns.get(i) {
+            if let Some(table) = tables.peek() {
+                if table.start() == i {
+                    i = table.lookup(&target);
+                    tables.next();
+                    continue;
+                }
+            }
             if stry!(test_predicate_expr(
                 self,
                 opts,
@@ -119,6 +129,7 @@ where
                     &predicate.exprs,
                 );
             }
+            i += 1;
         }
         error_no_clause_hit(self, &env.meta)
     }
@@ -270,7 +281,7 @@ where
                     }
                 }
             }
-        } else if let Some(target_array) = target_value.as_array() {
+        } else if let Some(target_array) = elements(&target_value) {
             // Array comprehension case
 
             value_vec.reserve(if opts.result_needed {
@@ -285,7 +296,7 @@ where
             // If we restruct mutation in the future we could get rid of this.
 
             let mut count = 0;
-            'comp_array_outer: for x in target_array.clone() {
+            'comp_array_outer: for x in target_array.to_vec() {
                 let k = count.into();
                 stry!(set_local_shadow(self, local, &env.meta, expr.key_id, k));
                 stry!(set_local_shadow(self, local, &env.meta, expr.val_id, x));
@@ -475,6 +486,12 @@ where
         meta: &'run mut Value<'event>,
         local: &'run mut LocalStack<'event>,
     ) -> Result<Cont<'run, 'event>> {
+        if let Some(budget) = env.budget {
+            stry!(budget.step(self, &env.meta));
+        }
+        if let Some(debugger) = env.debugger {
+            stry!(debugger.on_expr(self, env, event, state, meta, local));
+        }
         match self {
             Expr::Emit(expr) => match expr.borrow() {
                 EmitExpr {
@@ -19,8 +19,9 @@ use super::{
 
 use crate::ast::{
     BaseExpr, BinExpr, ImutComprehension, ImutExpr, ImutExprInt, ImutMatch, Invoke, InvokeAggr,
-    LocalPath, Merge, Patch, Path, Recur, Segment, UnaryExpr, ARGS_CONST_ID,
+    LocalPath, Merge, Patch, Path, Recur, Segment, Try, UnaryExpr, ARGS_CONST_ID,
 };
+use crate::binary::elements;
 use crate::errors::{
     error_bad_key, error_decreasing_range, error_invalid_unary, error_missing_effector,
     error_need_obj, error_need_str, error_no_clause_hit, error_oops, Result,
@@ -102,7 +103,8 @@ where
         value_to_index(outer, self, val.borrow(), env, path, array)
     }
 
-    #[allow(clippy::too_many_lines)]
+    /// Evaluates the expression, taking a step out of the budget of the
+    /// script if it has one and letting an attached debugger pause on it.
     #[inline]
     pub fn run(
         &'script self,
@@ -112,6 +114,33 @@ where
         state: &'run Value<'static>,
         meta: &'run Value<'event>,
         local: &'run LocalStack<'event>,
+    ) -> Result<Cow<'run, Value<'event>>> {
+        if let Some(debugger) = env.debugger {
+            stry!(debugger.on_expr(self, env, event, state, meta, local));
+        }
+        if let Some(budget) = env.budget {
+            stry!(budget.step(self, &env.meta));
+            let r = stry!(self.run_expr(opts, env, event, state, meta, local));
+            // Only owned values were created by the script
+            if let Cow::Owned(v) = &r {
+                stry!(budget.check_value(self, v, &env.meta));
+            }
+            Ok(r)
+        } else {
+            self.run_expr(opts, env, event, state, meta, local)
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    #[inline]
+    fn run_expr(
+        &'script self,
+        opts: ExecOpts,
+        env: &'run Env<'run, 'event, 'script>,
+        event: &'run Value<'event>,
+        state: &'run Value<'static>,
+        meta: &'run Value<'event>,
+        local: &'run LocalStack<'event>,
     ) -> Result<Cow<'run, Value<'event>>> {
         match self {
             ImutExprInt::Recur(Recur { exprs, argc, .. }) => {
@@ -223,7 +252,55 @@ where
             ImutExprInt::Comprehension(ref expr) => {
                 self.comprehension(opts, env, event, state, meta, local, expr)
             }
+            ImutExprInt::Try(ref expr) => self.try_expr(opts, env, event, state, meta, local, expr),
+        }
+    }
+
+    fn try_expr(
+        &'script self,
+        opts: ExecOpts,
+        env: &'run Env<'run, 'event, 'script>,
+        event: &'run Value<'event>,
+        state: &'run Value<'static>,
+        meta: &'run Value<'event>,
+        local: &'run LocalStack<'event>,
+        expr: &'script Try,
+    ) -> Result<Cow<'run, Value<'event>>> {
+        let e = match expr.expr.run(opts, env, event, state, meta, local) {
+            Ok(v) => return Ok(v),
+            // running out of budget or being aborted is not for the script
+            // to handle
+            Err(e) if e.is_limit_exceeded() || e.is_aborted() => return Err(e),
+            Err(e) => e,
+        };
+        let error = e.to_value();
+        for predicate in &expr.catches {
+            if stry!(test_predicate_expr(
+                self,
+                opts,
+                env,
+                event,
+                state,
+                meta,
+                local,
+                &error,
+                &predicate.pattern,
+                &predicate.guard,
+            )) {
+                return self.execute_effectors(
+                    opts,
+                    env,
+                    event,
+                    state,
+                    meta,
+                    local,
+                    predicate,
+                    &predicate.exprs,
+                );
+            }
         }
+        // None of the clauses handle the error so we pass it on
+        Err(e)
     }
 
     fn comprehension(
@@ -264,7 +341,7 @@ where
                     }
                 }
             }
-        } else if let Some(target_array) = target_value.as_array() {
+        } else if let Some(target_array) = elements(&target_value) {
             // Array comprehension case
 
             value_vec.reserve(target_array.len());
@@ -326,7 +403,16 @@ where
     ) -> Result<Cow<'run, Value<'event>>> {
         let target = stry!(expr.target.run(opts, env, event, state, meta, local));
 
-        for predicate in &expr.patterns {
+        let mut tables = expr.tables.iter().peekable();
+        let mut i = 0;
+        while let Some(predicate) = expr.patterns.get(i) {
+            if let Some(table) = tables.peek() {
+                if table.start() == i {
+                    i = table.lookup(&target);
+                    tables.next();
+                    continue;
+                }
+            }
             if stry!(test_predicate_expr(
                 self,
                 opts,
@@ -350,6 +436,7 @@ where
                     &predicate.exprs,
                 );
             }
+            i += 1;
         }
         error_no_clause_hit(self, &env.meta)
     }
@@ -441,8 +528,8 @@ where
                 }
                 // Next segment is an index: index into `current`, if it's an array
                 Segment::Idx { idx, .. } => {
-                    if let Some(a) = current.as_array() {
-                        let range_to_consider = subrange.unwrap_or_else(|| a.as_slice());
+                    if let Some(a) = elements(current) {
+                        let range_to_consider = subrange.unwrap_or(a);
                         let idx = *idx;
 
                         if let Some(c) = range_to_consider.get(idx) {
@@ -463,8 +550,8 @@ where
                     range_end,
                     ..
                 } => {
-                    if let Some(a) = current.as_array() {
-                        let array = subrange.unwrap_or_else(|| a.as_slice());
+                    if let Some(a) = elements(current) {
+                        let array = subrange.unwrap_or(a);
                         let start_idx = stry!(range_start.eval_to_index(
                             self, opts, env, event, state, meta, local, path, &array,
                         ));
@@ -495,8 +582,8 @@ where
                         // The segment resolved to an identifier, and `current` is an object: lookup
                         (Value::Object(o), Value::String(id)) => o.get(id),
                         // If `current` is an array, the segment has to be an index
-                        (Value::Array(a), idx) => {
-                            let array = subrange.unwrap_or_else(|| a.as_slice());
+                        (Value::Array(_), idx) => {
+                            let array = subrange.or_else(|| elements(current)).unwrap_or_default();
                             let idx = stry!(value_to_index(self, segment, idx, env, path, array));
                             array.get(idx)
                         }
@@ -0,0 +1,1035 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A small stack based bytecode for the hot paths of a script.
+//!
+//! When a script is created its top level expressions are lowered into
+//! bytecode: literals go into a constant pool, local variables and paths
+//! are resolved to slots and key lookups, and function calls point
+//! directly to the function they invoke. Matches that only compare the
+//! target against expressions become conditional jumps, their lookup
+//! tables are kept as jump tables. Everything we have no bytecode for
+//! (patches, comprehensions, record or array patterns, ...) is kept as a
+//! tree and evaluated by the tree walking interpreter, the same happens
+//! whenever a path lookup misses so errors are reported exactly as before.
+
+use super::{exec_binary, exec_unary, val_eq, Cont, Env, ExecOpts, LocalStack, NULL};
+use crate::ast::{
+    BaseExpr, BinOpKind, EmitExpr, EventPath, Expr, ImutExprInt, Invocable, LocalPath, LookupTable,
+    Path, Pattern, Segment, UnaryOpKind, ARGS_CONST_ID,
+};
+use crate::binary::elements;
+use crate::errors::{
+    error_guard_not_bool, error_invalid_unary, error_need_str, error_no_clause_hit, error_oops,
+    Result,
+};
+use crate::registry::{Registry, RECUR_PTR};
+use crate::stry;
+use simd_json::prelude::*;
+use simd_json::value::borrowed::{Object, Value};
+use simd_json::KnownKey;
+use std::borrow::{Borrow, Cow};
+use std::fmt;
+use std::ops::Range;
+
+/// Points errors raised by the bytecode to the node it was compiled from
+#[derive(Clone, Copy)]
+struct At(usize);
+
+impl BaseExpr for At {
+    fn mid(&self) -> usize {
+        self.0
+    }
+}
+
+/// Where a path lookup starts
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Root {
+    Local(usize),
+    Const(usize),
+    Event,
+    Meta,
+    State,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Seg<'script> {
+    Key(KnownKey<'script>),
+    Idx(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    /// Pushes a value from the constant pool
+    Lit(usize),
+    /// Pushes the value at `root` followed by `len` segments starting at
+    /// `segments`, if it does not exist the tree `expr` is evaluated
+    Path {
+        root: Root,
+        segments: usize,
+        len: usize,
+        expr: usize,
+    },
+    /// Replaces the two topmost values with the result of the operation
+    Binary { kind: BinOpKind, mid: usize },
+    /// Replaces the topmost value with the result of the operation
+    Unary {
+        kind: UnaryOpKind,
+        mid: usize,
+        operand: usize,
+    },
+    /// Ensures the topmost value is a string
+    Str(usize),
+    /// Replaces `n` pairs of value and name with a record
+    Record(usize),
+    /// Replaces `n` values with a list
+    List(usize),
+    /// Replaces `argc` arguments with the result of the function
+    Invoke { fun: usize, argc: usize, mid: usize },
+    /// Makes the topmost value independent of the arguments it might borrow from
+    Static,
+    /// Evaluates the tree `expr` with the interpreter
+    Tree(usize),
+    /// Jumps to the arm the lookup table `n` picks for the target of a match
+    Lookup(usize),
+    /// Replaces the topmost value with nothing and jumps to `next` unless
+    /// it is equal to the target of the match below it
+    Case(usize),
+    /// Replaces the topmost value with nothing and jumps to `next` if it
+    /// is false, it has to be a boolean
+    Guard {
+        next: usize,
+        mid: usize,
+        guard: usize,
+    },
+    /// Replaces the target of a match with nothing
+    Pop,
+    /// Pushes `null` and jumps to `end` if the result isn't needed
+    Needed(usize),
+    /// Continues at `end`
+    Jump(usize),
+    /// Fails a match `mid` none of the arms of which matched
+    NoClause(usize),
+}
+
+/// A lookup table of a match and where each of its arms starts
+#[derive(Clone, Debug)]
+struct Lookup<'script> {
+    table: LookupTable<'script>,
+    jumps: Vec<usize>,
+}
+
+/// The parts of a match arm the bytecode handles
+struct Arm<'arm, 'script> {
+    pattern: &'arm Pattern<'script>,
+    guard: &'arm Option<ImutExprInt<'script>>,
+    body: &'arm ImutExprInt<'script>,
+}
+
+impl<'arm, 'script> Arm<'arm, 'script> {
+    /// `None` if the pattern needs the interpreter or there is no body
+    fn new(
+        pattern: &'arm Pattern<'script>,
+        guard: &'arm Option<ImutExprInt<'script>>,
+        body: Option<&'arm ImutExprInt<'script>>,
+    ) -> Option<Self> {
+        match pattern {
+            Pattern::Expr(_) | Pattern::DoNotCare | Pattern::Default => Some(Self {
+                pattern,
+                guard,
+                body: body?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// How a top level expression is executed
+#[derive(Clone, Debug, PartialEq)]
+enum Stmt {
+    /// Run the expression with the interpreter
+    Tree,
+    Drop,
+    Imut(Range<usize>),
+    /// A match evaluated for its last effector, the others are immutable
+    /// and never evaluated
+    Match(Range<usize>),
+    Assign {
+        idx: usize,
+        code: Range<usize>,
+    },
+    Emit {
+        code: Range<usize>,
+        port: Option<(Range<usize>, usize)>,
+    },
+    EmitEvent {
+        port: Option<(Range<usize>, usize)>,
+    },
+}
+
+impl Stmt {
+    /// Number of operations the statement executes outside of the
+    /// interpreter
+    fn ops(&self) -> u64 {
+        let ops = match self {
+            Stmt::Tree => 0,
+            Stmt::Drop => 1,
+            Stmt::Imut(code) | Stmt::Match(code) | Stmt::Assign { code, .. } => code.len(),
+            Stmt::Emit { code, port } => code.len() + port.as_ref().map_or(0, |(p, _)| p.len()),
+            Stmt::EmitEvent { port } => 1 + port.as_ref().map_or(0, |(p, _)| p.len()),
+        };
+        ops as u64
+    }
+}
+
+/// The bytecode for a script
+#[derive(Clone, Default)]
+pub(crate) struct Program<'script> {
+    stmts: Vec<Stmt>,
+    code: Vec<Op>,
+    consts: Vec<Value<'script>>,
+    segments: Vec<Seg<'script>>,
+    fns: Vec<Invocable<'script>>,
+    exprs: Vec<ImutExprInt<'script>>,
+    lookups: Vec<Lookup<'script>>,
+}
+
+impl<'script> fmt::Debug for Program<'script> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Program")
+            .field("stmts", &self.stmts)
+            .field("code", &self.code)
+            .field("consts", &self.consts)
+            .finish()
+    }
+}
+
+// The program is derived from the expressions of a script so comparing
+// the bytecode is enough.
+impl<'script> PartialEq for Program<'script> {
+    fn eq(&self, other: &Self) -> bool {
+        self.stmts == other.stmts && self.code == other.code && self.consts == other.consts
+    }
+}
+
+fn is_args(expr: &ImutExprInt) -> bool {
+    if let ImutExprInt::Path(Path::Const(LocalPath { idx, .. })) = expr {
+        *idx == ARGS_CONST_ID
+    } else {
+        false
+    }
+}
+
+impl<'script> Program<'script> {
+    /// Compiles the top level expressions of a script
+    pub(crate) fn compile(exprs: &[Expr<'script>]) -> Self {
+        let mut p = Self::default();
+        for expr in exprs {
+            let stmt = p.stmt(expr);
+            p.stmts.push(stmt);
+        }
+        p
+    }
+
+    fn stmt(&mut self, expr: &Expr<'script>) -> Stmt {
+        match expr {
+            Expr::Drop { .. } => Stmt::Drop,
+            Expr::Imut(e) => Stmt::Imut(self.block(e)),
+            Expr::Assign {
+                path: Path::Local(LocalPath { idx, segments, .. }),
+                expr,
+                ..
+            } if segments.is_empty() => {
+                if let Expr::Imut(e) = expr.as_ref() {
+                    Stmt::Assign {
+                        idx: *idx,
+                        code: self.block(e),
+                    }
+                } else {
+                    Stmt::Tree
+                }
+            }
+            Expr::Emit(emit) => {
+                let EmitExpr { expr, port, .. } = emit.as_ref();
+                let port = port.as_ref().map(|p| (self.block(p), p.mid()));
+                match expr {
+                    ImutExprInt::Path(Path::Event(EventPath { segments, .. }))
+                        if segments.is_empty() =>
+                    {
+                        Stmt::EmitEvent { port }
+                    }
+                    expr => Stmt::Emit {
+                        code: self.block(expr),
+                        port,
+                    },
+                }
+            }
+            Expr::Match(m) => {
+                let arms: Option<Vec<Arm>> = m
+                    .patterns
+                    .iter()
+                    .map(|c| {
+                        let body = match c.exprs.as_slice() {
+                            [effectors @ .., Expr::Imut(last)]
+                                if effectors.iter().all(|e| matches!(e, Expr::Imut(_))) =>
+                            {
+                                Some(last)
+                            }
+                            _ => None,
+                        };
+                        Arm::new(&c.pattern, &c.guard, body)
+                    })
+                    .collect();
+                if let Some(arms) = arms {
+                    let start = self.code.len();
+                    self.arms(m.mid, &m.target, &arms, &m.tables, true);
+                    Stmt::Match(start..self.code.len())
+                } else {
+                    Stmt::Tree
+                }
+            }
+            _ => Stmt::Tree,
+        }
+    }
+
+    /// Lowers the arms of a match, the target stays on the stack until an
+    /// arm matches. With `needed` the body is skipped if the result of the
+    /// match isn't needed.
+    fn arms(
+        &mut self,
+        mid: usize,
+        target: &ImutExprInt<'script>,
+        arms: &[Arm<'_, 'script>],
+        tables: &[LookupTable<'script>],
+        needed: bool,
+    ) {
+        self.imut(target);
+        let mut tables = tables.iter().peekable();
+        // where each arm starts and the lookups that jump to them
+        let mut entries = Vec::with_capacity(arms.len() + 1);
+        let mut lookups = Vec::new();
+        // the jumps to the next arm and to the end of the match
+        let mut next = Vec::new();
+        let mut end = Vec::new();
+        for (i, arm) in arms.iter().enumerate() {
+            entries.push(self.code.len());
+            self.patch(&mut next);
+            if let Some(table) = tables.peek().filter(|t| t.start() == i) {
+                lookups.push(self.lookups.len());
+                self.code.push(Op::Lookup(self.lookups.len()));
+                self.lookups.push(Lookup {
+                    table: (*table).clone(),
+                    jumps: Vec::new(),
+                });
+                tables.next();
+            }
+            if let Pattern::Expr(e) = arm.pattern {
+                self.imut(e);
+                next.push(self.code.len());
+                self.code.push(Op::Case(0));
+            }
+            if let Some(guard) = arm.guard {
+                self.imut(guard);
+                next.push(self.code.len());
+                self.code.push(Op::Guard {
+                    next: 0,
+                    mid,
+                    guard: guard.mid(),
+                });
+            }
+            self.code.push(Op::Pop);
+            if needed {
+                end.push(self.code.len());
+                self.code.push(Op::Needed(0));
+            }
+            self.imut(arm.body);
+            end.push(self.code.len());
+            self.code.push(Op::Jump(0));
+        }
+        entries.push(self.code.len());
+        self.patch(&mut next);
+        self.code.push(Op::NoClause(mid));
+        self.patch(&mut end);
+        for idx in lookups {
+            if let Some(lookup) = self.lookups.get_mut(idx) {
+                let start = lookup.table.start();
+                // The lookup is done once, when it picks the arm it starts
+                // at we continue right after it
+                lookup.jumps = (start..=lookup.table.end())
+                    .map(|i| {
+                        if i == start {
+                            entries[i] + 1
+                        } else {
+                            entries[i]
+                        }
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    /// Points the jumps at `jumps` to the current end of the code
+    fn patch(&mut self, jumps: &mut Vec<usize>) {
+        let to = self.code.len();
+        for at in jumps.drain(..) {
+            if let Some(Op::Case(next))
+            | Some(Op::Guard { next, .. })
+            | Some(Op::Needed(next))
+            | Some(Op::Jump(next)) = self.code.get_mut(at)
+            {
+                *next = to;
+            }
+        }
+    }
+
+    fn block(&mut self, expr: &ImutExprInt<'script>) -> Range<usize> {
+        let start = self.code.len();
+        self.imut(expr);
+        start..self.code.len()
+    }
+
+    fn tree(&mut self, expr: &ImutExprInt<'script>) -> usize {
+        self.exprs.push(expr.clone());
+        self.exprs.len() - 1
+    }
+
+    fn path(&mut self, root: Root, segments: &[Segment<'script>], expr: &ImutExprInt<'script>) {
+        let start = self.segments.len();
+        for s in segments {
+            match s {
+                Segment::Id { key, .. } => self.segments.push(Seg::Key(key.clone())),
+                Segment::Idx { idx, .. } => self.segments.push(Seg::Idx(*idx)),
+                Segment::Element { .. } | Segment::Range { .. } => {
+                    self.segments.truncate(start);
+                    let expr = self.tree(expr);
+                    self.code.push(Op::Tree(expr));
+                    return;
+                }
+            }
+        }
+        let expr = self.tree(expr);
+        self.code.push(Op::Path {
+            root,
+            segments: start,
+            len: segments.len(),
+            expr,
+        });
+    }
+
+    fn imut(&mut self, expr: &ImutExprInt<'script>) {
+        match expr {
+            ImutExprInt::Literal(l) => {
+                self.consts.push(l.value.clone());
+                self.code.push(Op::Lit(self.consts.len() - 1));
+            }
+            ImutExprInt::Local { idx, is_const, .. } => {
+                let root = if *is_const {
+                    Root::Const(*idx)
+                } else {
+                    Root::Local(*idx)
+                };
+                self.path(root, &[], expr)
+            }
+            ImutExprInt::Path(path) => {
+                let root = match path {
+                    Path::Local(p) => Root::Local(p.idx),
+                    Path::Const(p) => Root::Const(p.idx),
+                    Path::Event(_) => Root::Event,
+                    Path::Meta(_) => Root::Meta,
+                    Path::State(_) => Root::State,
+                };
+                self.path(root, path.segments(), expr)
+            }
+            ImutExprInt::Binary(b) => {
+                self.imut(&b.lhs);
+                self.imut(&b.rhs);
+                self.code.push(Op::Binary {
+                    kind: b.kind,
+                    mid: b.mid,
+                });
+            }
+            ImutExprInt::Unary(u) => {
+                self.imut(&u.expr);
+                self.code.push(Op::Unary {
+                    kind: u.kind,
+                    mid: u.mid,
+                    operand: u.expr.mid(),
+                });
+            }
+            ImutExprInt::Record(r) => {
+                for field in &r.fields {
+                    self.imut(&field.value);
+                    self.imut(&field.name);
+                    self.code.push(Op::Str(field.name.mid()));
+                }
+                self.code.push(Op::Record(r.fields.len()));
+            }
+            ImutExprInt::List(l) => {
+                for e in &l.exprs {
+                    self.imut(e);
+                }
+                self.code.push(Op::List(l.exprs.len()));
+            }
+            ImutExprInt::Match(m) => {
+                let arms: Option<Vec<Arm>> = m
+                    .patterns
+                    .iter()
+                    .map(|c| Arm::new(&c.pattern, &c.guard, c.exprs.last().map(|e| &e.0)))
+                    .collect();
+                if let Some(arms) = arms {
+                    // Immutable effectors have no side effects so only the
+                    // last one is evaluated
+                    self.arms(m.mid, &m.target, &arms, &m.tables, false);
+                } else {
+                    let expr = self.tree(expr);
+                    self.code.push(Op::Tree(expr));
+                }
+            }
+            ImutExprInt::Invoke1(i)
+            | ImutExprInt::Invoke2(i)
+            | ImutExprInt::Invoke3(i)
+            | ImutExprInt::Invoke(i) => {
+                for arg in &i.args {
+                    self.imut(&arg.0);
+                    if is_args(&arg.0) {
+                        self.code.push(Op::Static);
+                    }
+                }
+                self.fns.push(i.invocable.clone());
+                self.code.push(Op::Invoke {
+                    fun: self.fns.len() - 1,
+                    argc: i.args.len(),
+                    mid: i.mid,
+                });
+            }
+            _ => {
+                let expr = self.tree(expr);
+                self.code.push(Op::Tree(expr));
+            }
+        }
+    }
+
+    /// Executes the top level expression `idx`, `expr` is the expression it
+    /// was compiled from.
+    pub(crate) fn exec<'run, 'event>(
+        &'script self,
+        idx: usize,
+        expr: &'script Expr<'script>,
+        opts: ExecOpts,
+        env: &'run Env<'run, 'event, 'script>,
+        event: &'run mut Value<'event>,
+        state: &'run mut Value<'static>,
+        meta: &'run mut Value<'event>,
+        local: &'run mut LocalStack<'event>,
+    ) -> Result<Cont<'run, 'event>>
+    where
+        'script: 'event,
+        'event: 'run,
+    {
+        // The debugger needs to see every expression
+        let stmt = match self.stmts.get(idx) {
+            Some(stmt) if env.debugger.is_none() => stmt,
+            _ => return expr.run(opts, env, event, state, meta, local),
+        };
+        if let Some(budget) = env.budget {
+            stry!(budget.steps(expr, stmt.ops(), &env.meta));
+        }
+        match stmt {
+            Stmt::Tree => expr.run(opts, env, event, state, meta, local),
+            Stmt::Drop => Ok(Cont::Drop),
+            Stmt::Imut(code) => {
+                // If we don't need the result of a immutable value then we
+                // don't need to evaluate it.
+                let r = if opts.result_needed {
+                    stry!(self.eval(code, expr.mid(), opts, env, event, state, meta, local))
+                } else {
+                    Cow::Borrowed(&NULL)
+                };
+                if let Cow::Borrowed(v) = r {
+                    if v.as_str().map(str::as_ptr) == RECUR_PTR {
+                        return Ok(Cont::Drop);
+                    }
+                }
+                Ok(Cont::Cont(r))
+            }
+            Stmt::Match(code) => {
+                let r = stry!(self.eval(code, expr.mid(), opts, env, event, state, meta, local));
+                if let Cow::Borrowed(v) = r {
+                    if v.as_str().map(str::as_ptr) == RECUR_PTR {
+                        return Ok(Cont::Drop);
+                    }
+                }
+                Ok(Cont::Cont(r))
+            }
+            Stmt::Assign { idx: slot, code } => {
+                let value = stry!(self.eval(
+                    code,
+                    expr.mid(),
+                    opts.with_result(),
+                    env,
+                    event,
+                    state,
+                    meta,
+                    local
+                ))
+                .into_owned();
+                match local.values.get_mut(*slot) {
+                    Some(slot) => {
+                        *slot = Some(value);
+                        if let Some(l) = slot {
+                            Ok(Cont::Cont(Cow::Borrowed(l)))
+                        } else {
+                            error_oops(expr, 0xdead_0009, "Unreacable code", &env.meta)
+                        }
+                    }
+                    None => error_oops(expr, 0xdead_000a, "Unknown local varialbe", &env.meta),
+                }
+            }
+            Stmt::Emit { code, port } => {
+                let port = if let Some((port, mid)) = port {
+                    Some(stry!(
+                        self.eval_port(port, *mid, opts, env, event, state, meta, local)
+                    ))
+                } else {
+                    None
+                };
+                Ok(Cont::Emit(
+                    stry!(self.eval(code, expr.mid(), opts, env, event, state, meta, local))
+                        .into_owned(),
+                    port,
+                ))
+            }
+            Stmt::EmitEvent { port } => {
+                let port = if let Some((port, mid)) = port {
+                    Some(stry!(
+                        self.eval_port(port, *mid, opts, env, event, state, meta, local)
+                    ))
+                } else {
+                    None
+                };
+                Ok(Cont::EmitEvent(port))
+            }
+        }
+    }
+
+    fn eval_port<'run, 'event>(
+        &'script self,
+        code: &Range<usize>,
+        mid: usize,
+        opts: ExecOpts,
+        env: &'run Env<'run, 'event, 'script>,
+        event: &'run Value<'event>,
+        state: &'run Value<'static>,
+        meta: &'run Value<'event>,
+        local: &'run LocalStack<'event>,
+    ) -> Result<String>
+    where
+        'script: 'event,
+        'event: 'run,
+    {
+        let value = stry!(self.eval(code, mid, opts, env, event, state, meta, local));
+        if let Some(s) = value.as_str() {
+            Ok(s.to_string())
+        } else {
+            error_need_str(&At(mid), &At(mid), value.value_type(), &env.meta)
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn eval<'run, 'event>(
+        &'script self,
+        code: &Range<usize>,
+        mid: usize,
+        opts: ExecOpts,
+        env: &'run Env<'run, 'event, 'script>,
+        event: &'run Value<'event>,
+        state: &'run Value<'static>,
+        meta: &'run Value<'event>,
+        local: &'run LocalStack<'event>,
+    ) -> Result<Cow<'run, Value<'event>>>
+    where
+        'script: 'event,
+        'event: 'run,
+    {
+        // Checks the values the bytecode creates against the budget
+        let check = |mid: usize, value: &Value| -> Result<()> {
+            env.budget.map_or(Ok(()), |budget| {
+                budget.check_value(&At(mid), value, &env.meta)
+            })
+        };
+        let mut stack: Vec<Cow<'run, Value<'event>>> = Vec::with_capacity(8);
+        let mut pc = code.start;
+        while pc < code.end {
+            let op = self.code[pc];
+            pc += 1;
+            match op {
+                Op::Lit(idx) => stack.push(Cow::Borrowed(&self.consts[idx])),
+                Op::Path {
+                    root,
+                    segments,
+                    len,
+                    expr,
+                } => {
+                    let base: Option<&'run Value<'event>> = match root {
+                        Root::Local(idx) => local.values.get(idx).and_then(Option::as_ref),
+                        Root::Const(idx) => env.consts.get(idx),
+                        Root::Event => Some(event),
+                        Root::Meta => Some(meta),
+                        Root::State => Some(state),
+                    };
+                    let found = base.and_then(|base| {
+                        self.segments[segments..segments + len].iter().try_fold(
+                            base,
+                            |current, segment| match segment {
+                                Seg::Key(key) => key.lookup(current),
+                                Seg::Idx(idx) => elements(current).and_then(|a| a.get(*idx)),
+                            },
+                        )
+                    });
+                    if let Some(v) = found {
+                        stack.push(Cow::Borrowed(v));
+                    } else {
+                        // Let the interpreter produce the error
+                        stack.push(stry!(
+                            self.exprs[expr].run(opts, env, event, state, meta, local)
+                        ));
+                    }
+                }
+                Op::Binary { kind, mid } => {
+                    let rhs = stry!(pop(&mut stack, env));
+                    let lhs = stry!(pop(&mut stack, env));
+                    let r = stry!(exec_binary(&At(mid), &At(mid), &env.meta, kind, &lhs, &rhs))
+                        .into_owned();
+                    stry!(check(mid, &r));
+                    stack.push(Cow::Owned(r));
+                }
+                Op::Unary { kind, mid, operand } => {
+                    let rhs = stry!(pop(&mut stack, env));
+                    match exec_unary(kind, &rhs) {
+                        Some(v) => stack.push(Cow::Owned(v.into_owned())),
+                        None => {
+                            return error_invalid_unary(
+                                &At(mid),
+                                &At(operand),
+                                kind,
+                                &rhs,
+                                &env.meta,
+                            )
+                        }
+                    }
+                }
+                Op::Str(mid) => {
+                    if let Some(v) = stack.last() {
+                        if !v.is_str() {
+                            return error_need_str(&At(mid), &At(mid), v.value_type(), &env.meta);
+                        }
+                    }
+                }
+                Op::Record(n) => {
+                    let fields = stry!(split(&mut stack, n * 2, env));
+                    let mut object: Object = Object::with_capacity(n);
+                    let mut fields = fields.into_iter();
+                    while let (Some(value), Some(name)) = (fields.next(), fields.next()) {
+                        let name = name.as_str().unwrap_or_default().to_string();
+                        object.insert(name.into(), value.into_owned());
+                    }
+                    let object = Value::from(object);
+                    stry!(check(mid, &object));
+                    stack.push(Cow::Owned(object));
+                }
+                Op::List(n) => {
+                    let values = stry!(split(&mut stack, n, env));
+                    let values: Vec<Value<'event>> =
+                        values.into_iter().map(Cow::into_owned).collect();
+                    let values = Value::from(values);
+                    stry!(check(mid, &values));
+                    stack.push(Cow::Owned(values));
+                }
+                Op::Invoke { fun, argc, mid } => {
+                    let args = stry!(split(&mut stack, argc, env));
+                    let argv: Vec<&Value<'event>> = args.iter().map(Cow::borrow).collect();
+                    let v = stry!(self.fns[fun].invoke(env, &argv).map_err(|e| {
+                        let r: Option<&Registry> = None;
+                        e.into_err(&At(mid), &At(mid), r, &env.meta)
+                    }));
+                    stry!(check(mid, &v));
+                    stack.push(Cow::Owned(v));
+                }
+                Op::Static => {
+                    let v = stry!(pop(&mut stack, env));
+                    stack.push(Cow::Owned(v.clone_static()));
+                }
+                Op::Tree(expr) => {
+                    stack.push(stry!(
+                        self.exprs[expr].run(opts, env, event, state, meta, local)
+                    ));
+                }
+                Op::Lookup(idx) => {
+                    let Lookup { table, jumps } = &self.lookups[idx];
+                    if let Some(target) = stack.last() {
+                        pc = jumps[table.lookup(target) - table.start()];
+                    }
+                }
+                Op::Case(next) => {
+                    let pattern = stry!(pop(&mut stack, env));
+                    if !stack
+                        .last()
+                        .map_or(false, |target| val_eq(target, &pattern))
+                    {
+                        pc = next;
+                    }
+                }
+                Op::Guard { next, mid, guard } => {
+                    let test = stry!(pop(&mut stack, env));
+                    match test.as_bool() {
+                        Some(true) => (),
+                        Some(false) => pc = next,
+                        None => {
+                            return error_guard_not_bool(&At(mid), &At(guard), &test, &env.meta)
+                        }
+                    }
+                }
+                Op::Pop => {
+                    stry!(pop(&mut stack, env));
+                }
+                Op::Needed(end) => {
+                    if !opts.result_needed {
+                        stack.push(Cow::Borrowed(&NULL));
+                        pc = end;
+                    }
+                }
+                Op::Jump(to) => pc = to,
+                Op::NoClause(mid) => return error_no_clause_hit(&At(mid), &env.meta),
+            }
+        }
+        pop(&mut stack, env)
+    }
+}
+
+#[inline]
+fn pop<'run, 'event>(
+    stack: &mut Vec<Cow<'run, Value<'event>>>,
+    env: &Env,
+) -> Result<Cow<'run, Value<'event>>> {
+    if let Some(v) = stack.pop() {
+        Ok(v)
+    } else {
+        error_oops(&At(0), 0xdead_0012, "Bytecode stack underflow", &env.meta)
+    }
+}
+
+#[inline]
+fn split<'run, 'event>(
+    stack: &mut Vec<Cow<'run, Value<'event>>>,
+    n: usize,
+    env: &Env,
+) -> Result<Vec<Cow<'run, Value<'event>>>> {
+    if let Some(at) = stack.len().checked_sub(n) {
+        Ok(stack.split_off(at))
+    } else {
+        error_oops(&At(0), 0xdead_0012, "Bytecode stack underflow", &env.meta)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::path::ModulePath;
+    use crate::registry;
+    use crate::{AggrType, EventContext, Return, Script};
+    use simd_json::json;
+
+    fn run(script: &Script, event: &Value) -> std::result::Result<Return<'static>, String> {
+        let context = EventContext::new(0, None);
+        let mut event = event.clone_static();
+        let mut state = Value::null();
+        let mut meta = Value::from(Object::new());
+        script
+            .run(&context, AggrType::Tick, &mut event, &mut state, &mut meta)
+            .map(|r| match r {
+                Return::Emit { value, port } => Return::Emit {
+                    value: value.clone_static(),
+                    port,
+                },
+                Return::Drop => Return::Drop,
+                Return::EmitEvent { port } => Return::EmitEvent { port },
+            })
+            .map_err(|e| script.format_error(&e))
+    }
+
+    // Runs the script once from bytecode and once with the interpreter
+    fn conforms(src: &str, event: &Value) -> std::result::Result<Return<'static>, String> {
+        let reg = registry::registry();
+        let mut script = Script::parse(&ModulePath { mounts: vec![] }, "test", src.into(), &reg)
+            .map_err(|e| format!("{:?}", e))?;
+        let compiled = run(&script, event);
+        let n = script.script.exprs.len();
+        script.script.program = Program {
+            stmts: vec![Stmt::Tree; n],
+            ..Program::default()
+        };
+        let interpreted = run(&script, event);
+        assert_eq!(compiled, interpreted);
+        compiled
+    }
+
+    #[test]
+    fn compiles_to_bytecode() -> std::result::Result<(), String> {
+        let reg = registry::registry();
+        let script = Script::parse(
+            &ModulePath { mounts: vec![] },
+            "test",
+            "let a = event.a + 1; match a of case 2 => \"two\" default => a end".into(),
+            &reg,
+        )
+        .map_err(|e| format!("{:?}", e))?;
+        let program = &script.script.program;
+        assert!(matches!(program.stmts[0], Stmt::Assign { .. }));
+        assert!(matches!(program.stmts[1], Stmt::Match(_)));
+        assert!(program
+            .code
+            .iter()
+            .any(|op| matches!(op, Op::Binary { .. })));
+        assert!(program.code.iter().any(|op| matches!(op, Op::Case(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn match_conformance() -> std::result::Result<(), String> {
+        let event = Value::from(json!({"a": 1, "b": [1, 2, {"c": "snot"}], "s": "badger"}));
+        let ok = |v: Value<'static>| {
+            Ok(Return::Emit {
+                value: v,
+                port: None,
+            })
+        };
+        assert_eq!(
+            conforms(
+                "match event.a of case 1 => \"one\" default => \"other\" end",
+                &event
+            ),
+            ok(Value::from("one"))
+        );
+        assert_eq!(
+            conforms(
+                "match event.a of case 2 => \"two\" case _ when event.a > 0 => \"positive\" default => \"other\" end",
+                &event
+            ),
+            ok(Value::from("positive"))
+        );
+        // lookup tables
+        let strings =
+            "case \"snot\" => 1 case \"badger\" => 2 case \"ferret\" => 3 case \"weasel\" => 4";
+        assert_eq!(
+            conforms(
+                &format!("{{\"m\": match event.s of {} default => 0 end}}", strings),
+                &event
+            ),
+            ok(Value::from(json!({"m": 2})))
+        );
+        assert_eq!(
+            conforms(
+                &format!("match event.b[2].c of {} default => 0 end", strings),
+                &event
+            ),
+            ok(Value::from(1))
+        );
+        assert_eq!(
+            conforms(
+                &format!("match event.a of {} default => 0 end", strings),
+                &event
+            ),
+            ok(Value::from(0))
+        );
+        // nested matches
+        assert_eq!(
+            conforms(
+                "match match event.a of case 1 => event.s default => 1 end of case \"badger\" => [match event.a of case _ => true end] default => false end",
+                &event
+            ),
+            ok(Value::from(json!([true])))
+        );
+        // the result of a match that isn't the last expression is never evaluated
+        assert_eq!(
+            conforms(
+                "match event.a of case 1 => \"unused\", event.snot default => 0 end; event.a",
+                &event
+            ),
+            ok(Value::from(1))
+        );
+        // record patterns are left to the interpreter
+        assert_eq!(
+            conforms(
+                "match event of case %{a == 1} => 1 default => 2 end",
+                &event
+            ),
+            ok(Value::from(1))
+        );
+        // errors are the same as well
+        assert!(conforms("match event.a of case 2 => 1 end", &event).is_err());
+        assert!(conforms(
+            "match event.a of case _ when event.s => 1 default => 2 end",
+            &event
+        )
+        .is_err());
+        assert!(conforms(
+            "match event.a of case 1 => event.snot default => 0 end",
+            &event
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn conformance() -> std::result::Result<(), String> {
+        let event = Value::from(json!({"a": 1, "b": [1, 2, {"c": "snot"}], "s": "badger"}));
+        let ok = |v: Value<'static>| {
+            Ok(Return::Emit {
+                value: v,
+                port: None,
+            })
+        };
+        assert_eq!(conforms("event.a + 1", &event), ok(Value::from(2)));
+        assert_eq!(conforms("event.b[2].c", &event), ok(Value::from("snot")));
+        assert_eq!(
+            conforms("let x = event.a; {\"x\": x, \"y\": [x, -x]}", &event),
+            ok(Value::from(json!({"x": 1, "y": [1, -1]})))
+        );
+        assert_eq!(
+            conforms("string::format(\"{}-{}\", event.s, event.a)", &event),
+            ok(Value::from("badger-1"))
+        );
+        assert_eq!(
+            conforms("emit event => \"out\"", &event),
+            Ok(Return::EmitEvent {
+                port: Some("out".into())
+            })
+        );
+        assert_eq!(
+            conforms("emit event.a => \"out\"", &event),
+            Ok(Return::Emit {
+                value: Value::from(1),
+                port: Some("out".into())
+            })
+        );
+        assert_eq!(conforms("drop", &event), Ok(Return::Drop));
+        // errors are the same as well
+        assert!(conforms("event.snot", &event).is_err());
+        assert!(conforms("event.b[7]", &event).is_err());
+        assert!(conforms("event.s + 1", &event).is_err());
+        assert!(conforms("-event.s", &event).is_err());
+        assert!(conforms("emit event => event.a", &event).is_err());
+        Ok(())
+    }
+}
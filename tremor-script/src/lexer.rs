@@ -90,15 +90,15 @@ fn is_hex(ch: char) -> bool {
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token<'input> {
     ///  Ignorable when parsing, significant when highlighting
-    Whitespace(&'input str),
+    Whitespace(Cow<'input, str>),
     /// a new line
     NewLine,
     /// a singe line comment
-    SingleLineComment(&'input str),
+    SingleLineComment(Cow<'input, str>),
     /// a mod comment
-    ModComment(&'input str),
+    ModComment(Cow<'input, str>),
     /// a doc comment
-    DocComment(&'input str),
+    DocComment(Cow<'input, str>),
     /// a BAD TOKEN
     Bad(String), // Mark bad tokens in lexical stream
 
@@ -178,6 +178,12 @@ pub enum Token<'input> {
     Fun,
     /// the `intrinsic` keyword
     Intrinsic,
+    /// the `aggregate` keyword
+    Aggregate,
+    /// the `try` keyword
+    Try,
+    /// the `catch` keyword
+    Catch,
     /// the `mod` keyword
     Module,
     /// the `_` token
@@ -285,6 +291,12 @@ pub enum Token<'input> {
     With,
     /// The `order` keyword
     Order,
+    /// The `asc` keyword
+    Asc,
+    /// The `desc` keyword
+    Desc,
+    /// The `limit` keyword
+    Limit,
     /// the `group` keyword
     Group,
     /// The `by` keyword
@@ -337,7 +349,7 @@ impl<'input> Token<'input> {
     /// Is the token ignorable except when syntax or error highlighting.
     /// Is the token insignificant when parsing ( a correct ... ) source.
     #[cfg_attr(tarpaulin, skip)]
-    pub(crate) fn is_ignorable(&self) -> bool {
+    pub fn is_ignorable(&self) -> bool {
         match *self {
             Token::SingleLineComment(_)
             | Token::Whitespace(_)
@@ -349,10 +361,11 @@ impl<'input> Token<'input> {
 
     /// Is the token a keyword, excluding keyword literals ( eg: true, nil )
     #[cfg_attr(tarpaulin, skip)]
-    pub(crate) fn is_keyword(&self) -> bool {
+    pub fn is_keyword(&self) -> bool {
         match *self {
             Token::Absent
             | Token::Args
+            | Token::Asc
             | Token::By
             | Token::Case
             | Token::Const
@@ -360,6 +373,7 @@ impl<'input> Token<'input> {
             | Token::Create
             | Token::Default
             | Token::Define
+            | Token::Desc
             | Token::Drop
             | Token::Each
             | Token::Emit
@@ -374,7 +388,11 @@ impl<'input> Token<'input> {
             | Token::Insert
             | Token::Into
             | Token::Intrinsic
+            | Token::Aggregate
+            | Token::Try
+            | Token::Catch
             | Token::Let
+            | Token::Limit
             | Token::Match
             | Token::Merge
             | Token::Module
@@ -405,7 +423,7 @@ impl<'input> Token<'input> {
 
     /// Is the token a literal, excluding list and record literals
     #[cfg_attr(tarpaulin, skip)]
-    pub(crate) fn is_literal(&self) -> bool {
+    pub fn is_literal(&self) -> bool {
         match *self {
             Token::DontCare
             | Token::Nil
@@ -416,9 +434,9 @@ impl<'input> Token<'input> {
         }
     }
 
-    // It's text-like or string-like notation such as String, char, regex ...
+    /// It's text-like or string-like notation such as String, char, regex ...
     #[cfg_attr(tarpaulin, skip)]
-    pub(crate) fn is_string_like(&self) -> bool {
+    pub fn is_string_like(&self) -> bool {
         match *self {
             Token::StringLiteral(_)
             | Token::DQuote
@@ -430,7 +448,7 @@ impl<'input> Token<'input> {
 
     /// Is the token a builtin delimiter symbol
     #[cfg_attr(tarpaulin, skip)]
-    pub(crate) fn is_symbol(&self) -> bool {
+    pub fn is_symbol(&self) -> bool {
         match *self {
             Token::BSlash
             | Token::Colon
@@ -456,7 +474,7 @@ impl<'input> Token<'input> {
 
     /// Is the token a builtin expression operator ( excludes forms such as 'match', 'let'
     #[cfg_attr(tarpaulin, skip)]
-    pub(crate) fn is_operator(&self) -> bool {
+    pub fn is_operator(&self) -> bool {
         match *self {
             Token::Not
             | Token::BitNot
@@ -490,6 +508,142 @@ impl<'input> Token<'input> {
     }
 }
 
+impl Token<'_> {
+    /// Turns the token into an owned token that no longer borrows from the
+    /// source, this allows the parser to produce an AST that owns its data.
+    #[allow(clippy::too_many_lines)]
+    pub(crate) fn into_static(self) -> Token<'static> {
+        match self {
+            Token::Whitespace(s) => Token::Whitespace(Cow::Owned(s.into_owned())),
+            Token::NewLine => Token::NewLine,
+            Token::SingleLineComment(s) => Token::SingleLineComment(Cow::Owned(s.into_owned())),
+            Token::ModComment(s) => Token::ModComment(Cow::Owned(s.into_owned())),
+            Token::DocComment(s) => Token::DocComment(Cow::Owned(s.into_owned())),
+            Token::Bad(bad) => Token::Bad(bad),
+            Token::Ident(id, escaped) => Token::Ident(Cow::Owned(id.into_owned()), escaped),
+            Token::Dollar => Token::Dollar,
+            Token::Dot => Token::Dot,
+            Token::Nil => Token::Nil,
+            Token::BoolLiteral(b) => Token::BoolLiteral(b),
+            Token::IntLiteral(i) => Token::IntLiteral(i),
+            Token::FloatLiteral(f, txt) => Token::FloatLiteral(f, txt),
+            Token::TestLiteral(indent, lines) => Token::TestLiteral(indent, lines),
+            Token::HereDoc(indent, lines) => Token::HereDoc(indent, lines),
+            Token::DQuote => Token::DQuote,
+            Token::StringLiteral(s) => Token::StringLiteral(Cow::Owned(s.into_owned())),
+            Token::Let => Token::Let,
+            Token::Const => Token::Const,
+            Token::Match => Token::Match,
+            Token::Case => Token::Case,
+            Token::Of => Token::Of,
+            Token::When => Token::When,
+            Token::End => Token::End,
+            Token::Patch => Token::Patch,
+            Token::Insert => Token::Insert,
+            Token::Upsert => Token::Upsert,
+            Token::Update => Token::Update,
+            Token::Erase => Token::Erase,
+            Token::Move => Token::Move,
+            Token::Copy => Token::Copy,
+            Token::Merge => Token::Merge,
+            Token::Drop => Token::Drop,
+            Token::Default => Token::Default,
+            Token::Emit => Token::Emit,
+            Token::For => Token::For,
+            Token::Event => Token::Event,
+            Token::State => Token::State,
+            Token::Present => Token::Present,
+            Token::Absent => Token::Absent,
+            Token::Fun => Token::Fun,
+            Token::Intrinsic => Token::Intrinsic,
+            Token::Aggregate => Token::Aggregate,
+            Token::Try => Token::Try,
+            Token::Catch => Token::Catch,
+            Token::Module => Token::Module,
+            Token::DontCare => Token::DontCare,
+            Token::Recur => Token::Recur,
+            Token::BSlash => Token::BSlash,
+            Token::Comma => Token::Comma,
+            Token::Not => Token::Not,
+            Token::BitNot => Token::BitNot,
+            Token::And => Token::And,
+            Token::Or => Token::Or,
+            Token::Xor => Token::Xor,
+            Token::BitAnd => Token::BitAnd,
+            Token::BitOr => Token::BitOr,
+            Token::BitXor => Token::BitXor,
+            Token::Eq => Token::Eq,
+            Token::EqEq => Token::EqEq,
+            Token::NotEq => Token::NotEq,
+            Token::TildeEq => Token::TildeEq,
+            Token::Tilde => Token::Tilde,
+            Token::Gte => Token::Gte,
+            Token::Gt => Token::Gt,
+            Token::Lte => Token::Lte,
+            Token::Lt => Token::Lt,
+            Token::RBitShiftSigned => Token::RBitShiftSigned,
+            Token::RBitShiftUnsigned => Token::RBitShiftUnsigned,
+            Token::LBitShift => Token::LBitShift,
+            Token::Add => Token::Add,
+            Token::Sub => Token::Sub,
+            Token::Mul => Token::Mul,
+            Token::Div => Token::Div,
+            Token::Mod => Token::Mod,
+            Token::Colon => Token::Colon,
+            Token::ColonColon => Token::ColonColon,
+            Token::EqArrow => Token::EqArrow,
+            Token::Semi => Token::Semi,
+            Token::LPatParen => Token::LPatParen,
+            Token::LParen => Token::LParen,
+            Token::RParen => Token::RParen,
+            Token::LBrace => Token::LBrace,
+            Token::LPatBrace => Token::LPatBrace,
+            Token::RBrace => Token::RBrace,
+            Token::LBracket => Token::LBracket,
+            Token::LPatBracket => Token::LPatBracket,
+            Token::RBracket => Token::RBracket,
+            Token::EndOfStream => Token::EndOfStream,
+            Token::Select => Token::Select,
+            Token::From => Token::From,
+            Token::Where => Token::Where,
+            Token::With => Token::With,
+            Token::Order => Token::Order,
+            Token::Asc => Token::Asc,
+            Token::Desc => Token::Desc,
+            Token::Limit => Token::Limit,
+            Token::Group => Token::Group,
+            Token::By => Token::By,
+            Token::Having => Token::Having,
+            Token::Into => Token::Into,
+            Token::Create => Token::Create,
+            Token::Tumbling => Token::Tumbling,
+            Token::Sliding => Token::Sliding,
+            Token::Window => Token::Window,
+            Token::Stream => Token::Stream,
+            Token::Operator => Token::Operator,
+            Token::Script => Token::Script,
+            Token::Set => Token::Set,
+            Token::Each => Token::Each,
+            Token::Define => Token::Define,
+            Token::Args => Token::Args,
+            Token::Use => Token::Use,
+            Token::As => Token::As,
+            Token::LineDirective(l, file) => Token::LineDirective(l, Cow::Owned(file.into_owned())),
+            Token::ConfigDirective => Token::ConfigDirective,
+        }
+    }
+}
+
+impl Spanned<Token<'_>> {
+    /// Turns the spanned token into an owned spanned token
+    pub(crate) fn into_static(self) -> TokenSpan<'static> {
+        Spanned {
+            span: self.span,
+            value: self.value.into_static(),
+        }
+    }
+}
+
 // LALRPOP requires a means to convert spanned tokens to triple form
 impl<'input> __ToTriple<'input> for Spanned<Token<'input>> {
     fn to_triple(
@@ -580,6 +734,9 @@ impl<'input> fmt::Display for Token<'input> {
             Token::When => write!(f, "when"),
             Token::Default => write!(f, "default"),
             Token::Intrinsic => write!(f, "intrinsic"),
+            Token::Aggregate => write!(f, "aggregate"),
+            Token::Try => write!(f, "try"),
+            Token::Catch => write!(f, "catch"),
             Token::Module => write!(f, "mod"),
             Token::BSlash => write!(f, "\\"),
             Token::Colon => write!(f, ":"),
@@ -636,6 +793,9 @@ impl<'input> fmt::Display for Token<'input> {
             Token::Where => write!(f, "where"),
             Token::With => write!(f, "with"),
             Token::Order => write!(f, "order"),
+            Token::Asc => write!(f, "asc"),
+            Token::Desc => write!(f, "desc"),
+            Token::Limit => write!(f, "limit"),
             Token::Group => write!(f, "group"),
             Token::By => write!(f, "by"),
             Token::Having => write!(f, "having"),
@@ -1334,10 +1494,10 @@ impl<'input> Lexer<'input> {
             let (end, lexeme) = self.take_until(start, |ch| ch == '\n');
 
             if lexeme.starts_with("###") {
-                let doc = Token::ModComment(&lexeme[3..]);
+                let doc = Token::ModComment(Cow::from(&lexeme[3..]));
                 Ok(self.spanned2(start, end, doc))
             } else if lexeme.starts_with("##") {
-                let doc = Token::DocComment(&lexeme[2..]);
+                let doc = Token::DocComment(Cow::from(&lexeme[2..]));
                 Ok(self.spanned2(start, end, doc))
             } else if lexeme.starts_with("#!line") {
                 let directive = &lexeme[6..];
@@ -1365,7 +1525,11 @@ impl<'input> Lexer<'input> {
                     Err("Snot!".into())
                 }
             } else {
-                Ok(self.spanned2(start, end, Token::SingleLineComment(&lexeme[1..])))
+                Ok(self.spanned2(
+                    start,
+                    end,
+                    Token::SingleLineComment(Cow::from(&lexeme[1..])),
+                ))
             }
         }
     }
@@ -1466,6 +1630,9 @@ impl<'input> Lexer<'input> {
 
         let token = match ident {
             "intrinsic" => Token::Intrinsic,
+            "aggregate" => Token::Aggregate,
+            "try" => Token::Try,
+            "catch" => Token::Catch,
             "mod" => Token::Module,
             "const" => Token::Const,
             "let" => Token::Let,
@@ -1503,6 +1670,9 @@ impl<'input> Lexer<'input> {
             "where" => Token::Where,
             "with" => Token::With,
             "order" => Token::Order,
+            "asc" => Token::Asc,
+            "desc" => Token::Desc,
+            "limit" => Token::Limit,
             "group" => Token::Group,
             "by" => Token::By,
             "having" => Token::Having,
@@ -2045,7 +2215,7 @@ impl<'input> Lexer<'input> {
     /// Consume whitespace
     fn ws(&mut self, start: Location) -> Result<TokenSpan<'input>> {
         let (end, src) = self.take_while(start, is_ws);
-        Ok(self.spanned2(start, end, Token::Whitespace(src)))
+        Ok(self.spanned2(start, end, Token::Whitespace(src.into())))
     }
 }
 
@@ -2255,6 +2425,9 @@ mod tests {
         lex_ok! { " set ", " ~~~~~~ " => Token::Set, };
         lex_ok! { " each ", " ~~~~~~ " => Token::Each, };
         lex_ok! { " intrinsic ", " ~~~~~~~~~ " => Token::Intrinsic, };
+        lex_ok! { " aggregate ", " ~~~~~~~~~ " => Token::Aggregate, };
+        lex_ok! { " try ", " ~~~ " => Token::Try, };
+        lex_ok! { " catch ", " ~~~~~ " => Token::Catch, };
         Ok(())
     }
 
@@ -28,13 +28,19 @@
 
 /// The Tremor Script AST
 pub mod ast;
+/// Binary data
+pub mod binary;
 mod compat;
 mod ctx;
 mod datetime;
+/// Interactive debugger for scripts and queries
+pub mod debugger;
 /// Tremor script function doc helper
 pub mod docs;
 /// Errors
 pub mod errors;
+/// Canonical source formatter
+pub mod formatter;
 /// Grok implementation
 pub mod grok;
 /// Tremor Script highlighter
@@ -43,6 +49,8 @@ pub mod highlighter;
 pub mod interpreter;
 /// The Tremor Script Lexer
 pub mod lexer;
+/// Sandbox limits for scripts
+pub mod limits;
 // We need this because of lalrpop
 #[allow(unused)]
 pub(crate) mod parser;
@@ -60,6 +68,7 @@ pub mod registry;
 pub mod script;
 mod std_lib;
 mod tilde;
+mod types;
 /// Utility functions
 pub mod utils;
 
@@ -75,6 +84,7 @@ use simd_json::prelude::*;
 
 pub use crate::ast::query::{SelectType, ARGS_CONST_ID};
 pub use crate::ctx::{EventContext, EventOriginUri};
+pub use crate::limits::Limits;
 pub use crate::query::Query;
 pub use crate::registry::{
     aggr as aggr_registry, registry, Aggr as AggrRegistry, Registry, TremorAggrFn,
@@ -0,0 +1,288 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sandbox limits for scripts.
+//!
+//! A script gets a fresh `Budget` for every event it processes. The
+//! interpreter takes a step out of it for every expression it evaluates and
+//! checks the size of every value it creates against it. Exceeding any of
+//! the limits aborts the script with a `LimitExceeded` error that `try`
+//! expressions can not catch.
+//!
+//! Looking at the clock is comparatively expensive, so the time limit is
+//! only checked every 1024 steps and once the script is done with an event.
+//! A script can run for up to 1024 steps past its deadline before it gets
+//! aborted.
+//!
+//! Function calls are a single step and are never interrupted. A regular
+//! expression or any other function applied to a huge string runs to
+//! completion even if it takes longer than the time limit, so the size of
+//! such inputs has to be bounded before they reach the script.
+
+use crate::ast::{BaseExpr, NodeMetas};
+use crate::errors::{ErrorKind, Result};
+use simd_json::prelude::*;
+use simd_json::BorrowedValue as Value;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Number of steps between two looks at the clock
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// Limits for a script processing a single event, `None` means unlimited
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Maximal number of expressions evaluated
+    pub max_steps: Option<u64>,
+    /// Maximal wall clock time in milliseconds, checked every 1024 steps
+    /// and once the script is done
+    pub timeout_ms: Option<u64>,
+    /// Maximal size of a value created by the script, this counts the
+    /// elements of all arrays and records nested in it as well as the
+    /// bytes of all strings and keys
+    pub max_value_size: Option<u64>,
+    /// Maximal size in bytes of the event and its metadata once the script
+    /// is done with them
+    pub max_output_size: Option<u64>,
+}
+
+impl Limits {
+    /// If none of the limits are set
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Starts the budget for a new event, `None` if there is nothing to
+    /// limit
+    pub fn budget(&self) -> Option<Budget> {
+        if self.is_unlimited() {
+            None
+        } else {
+            Some(Budget::new(*self))
+        }
+    }
+
+    /// Reads limits from a query config record, limits that are missing
+    /// or not a number stay unlimited
+    pub fn from_value(value: &Value) -> Self {
+        Self {
+            max_steps: value.get("max_steps").and_then(ValueTrait::as_u64),
+            timeout_ms: value.get("timeout_ms").and_then(ValueTrait::as_u64),
+            max_value_size: value.get("max_value_size").and_then(ValueTrait::as_u64),
+            max_output_size: value.get("max_output_size").and_then(ValueTrait::as_u64),
+        }
+    }
+}
+
+/// What is left of the limits of a script while it processes an event
+#[derive(Debug)]
+pub struct Budget {
+    limits: Limits,
+    steps: Cell<u64>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// Starts the budget for a new event
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            steps: Cell::new(0),
+            deadline: limits
+                .timeout_ms
+                .map(|ms| Instant::now() + Duration::from_millis(ms)),
+        }
+    }
+
+    /// Takes a step for evaluating `expr`
+    #[inline]
+    pub fn step<E: BaseExpr>(&self, expr: &E, meta: &NodeMetas) -> Result<()> {
+        self.steps(expr, 1, meta)
+    }
+
+    /// Takes `n` steps for evaluating `expr`
+    pub fn steps<E: BaseExpr>(&self, expr: &E, n: u64, meta: &NodeMetas) -> Result<()> {
+        let before = self.steps.get();
+        let steps = before.saturating_add(n);
+        self.steps.set(steps);
+        if let Some(max) = self.limits.max_steps {
+            if steps > max {
+                return error_limit_exceeded(expr, "max_steps", max, meta);
+            }
+        }
+        // We only look at the clock whenever we pass another interval
+        if steps / CLOCK_INTERVAL != before / CLOCK_INTERVAL {
+            self.check_time(expr, meta)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks the size of a value `expr` created
+    #[inline]
+    pub fn check_value<E: BaseExpr>(
+        &self,
+        expr: &E,
+        value: &Value,
+        meta: &NodeMetas,
+    ) -> Result<()> {
+        if let Some(max) = self.limits.max_value_size {
+            if value_size(value, max) > max {
+                return error_limit_exceeded(expr, "max_value_size", max, meta);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the event and its metadata once the script is done with
+    /// them, as well as the time it took.
+    pub fn finish<E: BaseExpr>(
+        &self,
+        expr: &E,
+        event: &Value,
+        event_meta: &Value,
+        meta: &NodeMetas,
+    ) -> Result<()> {
+        self.check_time(expr, meta)?;
+        if let Some(max) = self.limits.max_output_size {
+            if (output_size(event) + output_size(event_meta)) as u64 > max {
+                return error_limit_exceeded(expr, "max_output_size", max, meta);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_time<E: BaseExpr>(&self, expr: &E, meta: &NodeMetas) -> Result<()> {
+        match (self.deadline, self.limits.timeout_ms) {
+            (Some(deadline), Some(max)) if Instant::now() > deadline => {
+                error_limit_exceeded(expr, "timeout_ms", max, meta)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Size of a value as limited by `max_value_size`, counting stops once it
+/// passes `max` so huge values don't have to be walked completely
+fn value_size(value: &Value, max: u64) -> u64 {
+    match value {
+        Value::Static(_) => 0,
+        Value::String(s) => s.len() as u64,
+        Value::Array(a) => {
+            let mut size = a.len() as u64;
+            for v in a.iter() {
+                if size > max {
+                    break;
+                }
+                size += value_size(v, max - size);
+            }
+            size
+        }
+        Value::Object(o) => {
+            let mut size = o.len() as u64;
+            for (k, v) in o.iter() {
+                if size > max {
+                    break;
+                }
+                size += k.len() as u64;
+                size += value_size(v, max.saturating_sub(size));
+            }
+            size
+        }
+    }
+}
+
+/// Approximate size of a value in bytes
+fn output_size(value: &Value) -> usize {
+    match value {
+        Value::Static(_) => 8,
+        Value::String(s) => s.len(),
+        Value::Array(a) => a.iter().map(output_size).sum(),
+        Value::Object(o) => o.iter().map(|(k, v)| k.len() + output_size(v)).sum(),
+    }
+}
+
+fn error_limit_exceeded<T, E: BaseExpr>(
+    expr: &E,
+    limit: &str,
+    max: u64,
+    meta: &NodeMetas,
+) -> Result<T> {
+    let r = expr.extent(meta);
+    Err(ErrorKind::LimitExceeded(r.expand_lines(2), r, limit.to_string(), max).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pos::Location;
+    use simd_json::prelude::*;
+
+    fn at() -> (Location, Location) {
+        (Location::default(), Location::default())
+    }
+
+    #[test]
+    fn steps() {
+        let meta = NodeMetas::new(vec![]);
+        let budget = Budget::new(Limits {
+            max_steps: Some(3),
+            ..Limits::default()
+        });
+        assert!(budget.step(&at(), &meta).is_ok());
+        assert!(budget.steps(&at(), 2, &meta).is_ok());
+        assert!(budget.step(&at(), &meta).is_err());
+        let unlimited = Budget::new(Limits::default());
+        assert!(unlimited.steps(&at(), u64::max_value(), &meta).is_ok());
+        assert!(unlimited.step(&at(), &meta).is_ok());
+    }
+
+    #[test]
+    fn timeout() {
+        let meta = NodeMetas::new(vec![]);
+        let budget = Budget::new(Limits {
+            timeout_ms: Some(0),
+            ..Limits::default()
+        });
+        std::thread::sleep(Duration::from_millis(1));
+        // we don't look at the clock on every step
+        assert!(budget.step(&at(), &meta).is_ok());
+        assert!(budget.steps(&at(), CLOCK_INTERVAL, &meta).is_err());
+        assert!(budget
+            .finish(&at(), &Value::null(), &Value::null(), &meta)
+            .is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        let meta = NodeMetas::new(vec![]);
+        let budget = Budget::new(Limits {
+            max_value_size: Some(3),
+            max_output_size: Some(16),
+            ..Limits::default()
+        });
+        let small = Value::from(vec![1, 2, 3]);
+        let big = Value::from("snot badger");
+        assert!(budget.check_value(&at(), &small, &meta).is_ok());
+        assert!(budget.check_value(&at(), &big, &meta).is_err());
+        // nested values count as a whole
+        let nested = Value::from(vec![vec![1], vec![2]]);
+        assert!(budget.check_value(&at(), &nested, &meta).is_err());
+        // 3 numbers and a null add up to 32 bytes
+        assert!(budget.finish(&at(), &small, &Value::null(), &meta).is_err());
+        assert!(budget
+            .finish(&at(), &Value::from("snot"), &Value::null(), &meta)
+            .is_ok());
+    }
+}
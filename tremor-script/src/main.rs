@@ -28,13 +28,18 @@
 #![allow(clippy::must_use_candidate, clippy::missing_errors_doc)]
 
 mod ast;
+mod binary;
 mod ctx;
 mod datetime;
+mod debugger;
+mod docs;
 mod errors;
+mod formatter;
 mod grok;
 mod highlighter;
 mod interpreter;
 mod lexer;
+mod limits;
 mod parser;
 mod path;
 mod pos;
@@ -42,17 +47,17 @@ mod registry;
 mod script;
 mod std_lib;
 mod tilde;
+mod types;
 mod utils;
-#[macro_use]
-extern crate rental;
 
+use crate::debugger::{Console, Debugger};
 use crate::errors::{Error, ErrorKind, Result};
 use crate::highlighter::{Highlighter, Term as TermHighlighter};
 use crate::path::load as load_module_path;
 use crate::pos::{Span, Spanned};
 use crate::script::{AggrType, Return, Script};
 use chrono::{Timelike, Utc};
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
 use ctx::{EventContext, EventOriginUri};
 use halfbrown::hashmap;
 use simd_json::borrowed::{Object, Value};
@@ -61,6 +66,7 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::io::{BufReader, Read};
 use std::iter::FromIterator;
+use std::rc::Rc;
 
 #[macro_use]
 extern crate serde_derive;
@@ -99,6 +105,23 @@ fn main() -> Result<()> {
                 .required(true)
                 .index(1),
         )
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Formats the script in place.")
+                .arg(
+                    Arg::with_name("SCRIPT")
+                        .help("The script to format")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .takes_value(false)
+                        .help("Fails if the script isn't formatted instead of formatting it."),
+                ),
+        )
         .arg(
             Arg::with_name("process")
                 .long("process")
@@ -177,8 +200,48 @@ fn main() -> Result<()> {
                 .takes_value(true)
                 .help("Prints docs for a script."),
         )
+        .arg(
+            Arg::with_name("check-types")
+                .long("check-types")
+                .takes_value(false)
+                .help("Checks the types in the script before running it."),
+        )
+        .arg(
+            Arg::with_name("schema")
+                .long("schema")
+                .takes_value(true)
+                .requires("check-types")
+                .help("A JSON file describing the types of the event."),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .takes_value(false)
+                .help("Pauses in the debugger before the first expression."),
+        )
+        .arg(
+            Arg::with_name("break")
+                .long("break")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Sets a debugger breakpoint on a line of the script."),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        let script_file = matches
+            .value_of("SCRIPT")
+            .ok_or_else(|| Error::from("No script file provided"))?;
+        let check = matches.is_present("check");
+        if formatter::format_file(script_file, check)? && check {
+            eprintln!("{} is not formatted", script_file);
+            // ALLOW: main.rs
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let script_file = matches
         .value_of("SCRIPT")
         .ok_or_else(|| Error::from("No script file provided"))?;
@@ -240,9 +303,39 @@ fn main() -> Result<()> {
     }
 
     match Script::parse(&mp, script_file, raw.clone(), &reg) {
-        Ok(runnable) => {
+        Ok(mut runnable) => {
+            if matches.is_present("check-types") {
+                let mut schema = Vec::new();
+                if let Some(schema_file) = matches.value_of("schema") {
+                    File::open(&schema_file)?.read_to_end(&mut schema)?;
+                }
+                let schema = if schema.is_empty() {
+                    None
+                } else {
+                    Some(simd_json::to_borrowed_value(&mut schema)?)
+                };
+                if let Err(e) = runnable.check_types(schema.as_ref(), &reg.signatures()) {
+                    let mut h = TermHighlighter::new();
+                    runnable.format_error_with(&mut h, &e)?;
+                    // ALLOW: main.rs
+                    std::process::exit(1);
+                }
+            }
             let mut h = TermHighlighter::new();
             runnable.format_warnings_with(&mut h)?;
+            if matches.is_present("debug") || matches.is_present("break") {
+                let debugger = Debugger::new(Box::new(Console::new(script_file, &raw)));
+                if matches.is_present("debug") {
+                    debugger.pause();
+                }
+                for line in matches.values_of("break").into_iter().flatten() {
+                    let line = line
+                        .parse()
+                        .map_err(|_| Error::from(format!("Invalid breakpoint: {}", line)))?;
+                    debugger.set_breakpoint(line);
+                }
+                debugger::attach(Rc::new(debugger));
+            }
 
             if matches.is_present("process") {
                 let mut state = Value::null();
@@ -355,13 +448,13 @@ fn main() -> Result<()> {
             }
 
             if matches.is_present("print-ast") {
-                let ast = simd_json::to_string_pretty(&runnable.script.suffix())?;
+                let ast = simd_json::to_string_pretty(&runnable.script)?;
                 println!();
                 let mut h = TermHighlighter::new();
                 Script::highlight_script_with(&ast, &mut h)?;
             }
             if matches.is_present("print-ast-raw") {
-                let ast = simd_json::to_string_pretty(&runnable.script.suffix())?;
+                let ast = simd_json::to_string_pretty(&runnable.script)?;
                 println!();
                 println!("{}", ast);
             }
@@ -13,67 +13,63 @@
 // limitations under the License.
 
 use crate::ast::{self, Warning};
+use crate::docs::FunctionSignatureDoc;
 use crate::errors::{CompilerError, Error, Result};
 use crate::highlighter::{Dumb as DumbHighlighter, Highlighter};
 use crate::lexer;
 use crate::path::ModulePath;
 use crate::pos::Range;
 use crate::prelude::*;
-use rental::rental;
-use std::boxed::Box;
+use crate::types::Checker;
 use std::io::Write;
 use std::sync::Arc;
 
-/// Rental wrapper
-#[derive(Debug, PartialEq, PartialOrd, Eq, Clone)]
-pub struct StmtRentalWrapper {
+/// A query statement, it owns all of its data so it can be handed to
+/// operators and shared between threads.
+#[derive(Debug, Clone)]
+pub struct OwnedStmt {
     /// Statement
-    pub stmt: Arc<rentals::Stmt>,
-}
-impl StmtRentalWrapper {
-    /// Gets the wrapped statement
-    pub fn suffix(&self) -> &ast::Stmt {
-        self.stmt.suffix()
-    }
+    pub stmt: Arc<ast::Stmt<'static>>,
+    /// Source of the query the statement is part of
+    pub source: Arc<String>,
 }
 
-rental! {
-    mod rentals {
-        use crate::ast;
-        use std::borrow::Cow;
-        use serde::Serialize;
-        use std::sync::Arc;
-
-        /// rental around Query
-        #[rental_mut(covariant,debug)]
-        pub struct Query {
-            script: Box<String>,
-            query: ast::Query<'script>,
+impl OwnedStmt {
+    /// Creates a statement from a statement of a query and its source
+    pub fn new(stmt: ast::Stmt<'static>, source: Arc<String>) -> Self {
+        Self {
+            stmt: Arc::new(stmt),
+            source,
         }
+    }
+
+    /// Gets the statement
+    pub fn stmt(&self) -> &ast::Stmt<'static> {
+        &self.stmt
+    }
 
-        /// rental around Stmt
-        #[rental(covariant,debug)]
-        pub struct Stmt {
-            query: Arc<super::Query>,
-            stmt: ast::Stmt<'query>,
+    /// Formats an error within the query this statement is part of
+    pub fn format_error(&self, e: &Error) -> String {
+        let mut h = DumbHighlighter::default();
+        if Query::format_error_from_script(&self.source, &mut h, e).is_ok() {
+            h.to_string()
+        } else {
+            format!("Failed to extract code for error: {}", e)
         }
     }
 }
 
-pub use rentals::Query as QueryRental;
-pub use rentals::Stmt as StmtRental;
-
 #[cfg_attr(tarpaulin, skip)]
-impl PartialEq for rentals::Stmt {
+impl PartialEq for OwnedStmt {
     fn eq(&self, other: &Self) -> bool {
-        self.suffix() == other.suffix()
+        self.stmt == other.stmt
     }
 }
 
-impl Eq for rentals::Stmt {}
+impl Eq for OwnedStmt {}
 
 #[cfg_attr(tarpaulin, skip)]
-impl PartialOrd for rentals::Stmt {
+impl PartialOrd for OwnedStmt {
     fn partial_cmp(&self, _other: &Self) -> Option<std::cmp::Ordering> {
         None // NOTE Here be dragons FIXME
     }
@@ -83,7 +79,7 @@ impl PartialOrd for rentals::Stmt {
 #[derive(Debug, Clone)]
 pub struct Query {
     /// The query
-    pub query: Arc<rentals::Query>,
+    pub query: Arc<ast::Query<'static>>,
     /// Source of the query
     pub source: String,
     /// Warnings emitted by the script
@@ -98,8 +94,8 @@ where
     'event: 'run,
 {
     /// Borrows the query
-    pub fn suffix(&self) -> &ast::Query {
-        self.query.suffix()
+    pub fn suffix(&self) -> &ast::Query<'static> {
+        &self.query
     }
     /// Parses a string into a query
     pub fn parse(
@@ -112,39 +108,33 @@ where
     ) -> std::result::Result<Self, CompilerError> {
         let mut source = script.to_string();
 
-        let mut warnings = vec![];
-        let mut locals = 0;
-
         // FIXME make lexer EOS tolerant to avoid this kludge
         source.push('\n');
 
         let mut include_stack = lexer::IncludeStack::default();
 
         let r = |include_stack: &mut lexer::IncludeStack| -> Result<Self> {
-            let query = rentals::Query::try_new(Box::new(source.clone()), |src: &mut String| {
-                let mut helper = ast::Helper::new(reg, aggr_reg, cus);
-                let cu = include_stack.push(&file_name)?;
-                let lexemes: Vec<_> = lexer::Preprocessor::preprocess(
-                    module_path,
-                    file_name,
-                    src,
-                    cu,
-                    include_stack,
-                )?;
-                let filtered_tokens = lexemes
-                    .into_iter()
-                    .filter_map(Result::ok)
-                    .filter(|t| !t.value.is_ignorable());
-
-                let (script, local_count, ws) = crate::parser::g::QueryParser::new()
-                    .parse(filtered_tokens)?
-                    .up_script(&mut helper)?;
-
-                warnings = ws;
-                locals = local_count;
-                Ok(script)
-            })
-            .map_err(|e: rental::RentalError<Error, Box<String>>| e.0)?;
+            let mut helper = ast::Helper::new(reg, aggr_reg, cus);
+            let cu = include_stack.push(&file_name)?;
+            let mut src = source.clone();
+            let lexemes: Vec<_> = lexer::Preprocessor::preprocess(
+                module_path,
+                file_name,
+                &mut src,
+                cu,
+                include_stack,
+            )?;
+            // The tokens are turned into owned tokens so the resulting
+            // query does not borrow from the source.
+            let filtered_tokens = lexemes
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|t| !t.value.is_ignorable())
+                .map(lexer::Spanned::into_static);
+
+            let (query, locals, warnings) = crate::parser::g::QueryParser::new()
+                .parse(filtered_tokens)?
+                .up_script(&mut helper)?;
 
             Ok(Self {
                 query: Arc::new(query),
@@ -159,6 +149,45 @@ where
         })
     }
 
+    /// Infers and checks the types in the select statements and script
+    /// declarations of the query, definite mismatches are returned as
+    /// errors, possible ones are added to the warnings.
+    ///
+    /// `schema` optionally describes the incoming events, `signatures` are
+    /// the documented signatures of functions.
+    pub fn check_types(
+        &mut self,
+        schema: Option<&Value>,
+        signatures: &[FunctionSignatureDoc],
+    ) -> Result<()> {
+        for stmt in &self.query.stmts {
+            match stmt {
+                ast::Stmt::Select(s) => {
+                    let mut checker =
+                        Checker::new(&s.node_meta, &s.consts, s.locals, schema, signatures)?;
+                    if let Some(w) = &s.stmt.maybe_where {
+                        checker.imut(&w.0)?;
+                    }
+                    // `having` runs on the result of the select
+                    checker.event = checker.imut(&s.stmt.target.0)?;
+                    if let Some(h) = &s.stmt.maybe_having {
+                        checker.imut(&h.0)?;
+                    }
+                    self.warnings.append(&mut checker.warnings);
+                }
+                ast::Stmt::ScriptDecl(d) => {
+                    let s = &d.script;
+                    let mut checker =
+                        Checker::new(&s.node_meta, &s.consts, s.locals, schema, signatures)?;
+                    checker.exprs(&s.exprs)?;
+                    self.warnings.append(&mut checker.warnings);
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
     /// Highlights a script with a given highlighter.
     #[cfg_attr(tarpaulin, skip)]
     pub fn highlight_script_with<H: Highlighter>(script: &str, h: &mut H) -> std::io::Result<()> {
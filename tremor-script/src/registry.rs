@@ -12,15 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod custom_aggr;
 mod custom_fn;
-pub(crate) use self::custom_fn::{CustomFn, RECUR, RECUR_PTR};
+pub(crate) use self::custom_aggr::{AggrFns, CustomAggr};
+pub(crate) use self::custom_fn::{call_fn_value, fn_value, CustomFn, RECUR, RECUR_PTR};
 use crate::ast::{BaseExpr, NodeMetas};
+use crate::docs::FunctionSignatureDoc;
 use crate::errors::{best_hint, Error, ErrorKind, Result};
 use crate::utils::hostname as get_hostname;
 use crate::{tremor_fn, EventContext};
 use downcast_rs::{impl_downcast, DowncastSync};
 use halfbrown::HashMap;
-use simd_json::BorrowedValue as Value;
+use simd_json::{BorrowedValue as Value, ValueType};
 use std::default::Default;
 use std::fmt;
 use std::ops::RangeInclusive;
@@ -58,6 +61,15 @@ pub trait TremorAggrFn: DowncastSync + Sync + Send {
     fn warning(&self) -> Option<String> {
         None
     }
+    /// Captures the accumulated state of the function so it can be
+    /// persisted, returns `None` if the function can't be snapshotted.
+    fn snapshot(&self) -> Option<Value<'static>> {
+        None
+    }
+    /// Restores the accumulated state from a value created by `snapshot`
+    fn restore(&mut self, _snapshot: &Value) -> FResult<()> {
+        Ok(())
+    }
 }
 impl_downcast!(sync TremorAggrFn);
 
@@ -81,10 +93,33 @@ pub trait TremorFn: Sync + Send {
     fn is_const(&self) -> bool {
         false
     }
+    /// The types of the arguments if the function declares them
+    fn arg_types(&self) -> Option<Vec<ValueType>> {
+        None
+    }
+    /// returns if this function takes function values as arguments,
+    /// those functions get invoked through `invoke_with`.
+    fn is_higher_order(&self) -> bool {
+        false
+    }
+    /// Invoke the function with a callback into the interpreter that
+    /// calls the function values passed to it
+    fn invoke_with<'event>(
+        &self,
+        ctx: &EventContext,
+        args: &[&Value<'event>],
+        _call: &mut Callback<'_, 'event>,
+    ) -> FResult<Value<'event>> {
+        self.invoke(ctx, args)
+    }
 }
 /// The result of a function
 pub type FResult<T> = std::result::Result<T, FunctionError>;
 
+/// Calls a function value with the given arguments
+pub type Callback<'call, 'event> =
+    dyn FnMut(&Value<'event>, &[&Value<'event>]) -> FResult<Value<'event>> + 'call;
+
 /// Creates a new function registry and inserts some placeholder
 /// functions.
 #[allow(unused_variables)]
@@ -261,6 +296,14 @@ impl TremorFnWrapper {
     pub fn new(module: String, name: String, fun: Box<dyn TremorFn>) -> Self {
         Self { module, name, fun }
     }
+    /// Name of the module the function is in
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+    /// Name of the function
+    pub fn name(&self) -> &str {
+        &self.name
+    }
     /// Invokes the function
     pub fn invoke<'event>(
         &self,
@@ -283,6 +326,27 @@ impl TremorFnWrapper {
     pub fn is_const(&self) -> bool {
         self.fun.is_const()
     }
+
+    /// Returns the types of the arguments if the function declares them
+    pub fn arg_types(&self) -> Option<Vec<ValueType>> {
+        self.fun.arg_types()
+    }
+
+    /// Returns if the function takes function values as arguments
+    pub fn is_higher_order(&self) -> bool {
+        self.fun.is_higher_order()
+    }
+
+    /// Invokes the function with a callback for the function values
+    /// passed to it
+    pub fn invoke_with<'event>(
+        &self,
+        context: &EventContext,
+        args: &[&Value<'event>],
+        call: &mut Callback<'_, 'event>,
+    ) -> FResult<Value<'event>> {
+        self.fun.invoke_with(context, args, call)
+    }
 }
 
 impl Clone for TremorFnWrapper {
@@ -305,6 +369,79 @@ impl PartialEq for TremorFnWrapper {
     }
 }
 
+/// The implementation of a higher order function, it gets the arguments
+/// and a callback to call the function values among them.
+pub(crate) type HigherOrderBody = for<'event, 'call> fn(
+    &[&Value<'event>],
+    &mut Callback<'call, 'event>,
+) -> FResult<Value<'event>>;
+
+/// A function taking function values as arguments
+#[derive(Clone)]
+pub(crate) struct HigherOrderFn {
+    module: &'static str,
+    name: &'static str,
+    arity: usize,
+    body: HigherOrderBody,
+}
+
+impl HigherOrderFn {
+    pub(crate) fn wrap(
+        module: &'static str,
+        name: &'static str,
+        arity: usize,
+        body: HigherOrderBody,
+    ) -> TremorFnWrapper {
+        TremorFnWrapper::new(
+            module.to_string(),
+            name.to_string(),
+            Box::new(Self {
+                module,
+                name,
+                arity,
+                body,
+            }),
+        )
+    }
+}
+
+impl TremorFn for HigherOrderFn {
+    fn invoke<'event>(
+        &self,
+        _ctx: &EventContext,
+        args: &[&Value<'event>],
+    ) -> FResult<Value<'event>> {
+        Err(FunctionError::RuntimeError {
+            mfa: mfa(self.module, self.name, args.len()),
+            error: "function values can only be called from within a script".into(),
+        })
+    }
+    fn invoke_with<'event>(
+        &self,
+        _ctx: &EventContext,
+        args: &[&Value<'event>],
+        call: &mut Callback<'_, 'event>,
+    ) -> FResult<Value<'event>> {
+        if args.len() == self.arity {
+            (self.body)(args, call)
+        } else {
+            Err(FunctionError::BadArity {
+                mfa: mfa(self.module, self.name, self.arity),
+                calling_a: args.len(),
+            })
+        }
+    }
+    fn snot_clone(&self) -> Box<dyn TremorFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        self.arity..=self.arity
+    }
+    fn is_higher_order(&self) -> bool {
+        true
+    }
+}
+
 /// Tremor Function registry
 #[derive(Debug, Clone)]
 pub struct Registry {
@@ -484,6 +621,15 @@ macro_rules! tremor_fn_ {
                 fn is_const(&self) -> bool {
                     $const
                 }
+                fn arg_types(&self) -> Option<Vec<simd_json::ValueType>> {
+                    macro_rules! value_type {
+                        (String) => { Some(simd_json::ValueType::String) };
+                        (Array) => { Some(simd_json::ValueType::Array) };
+                        (Object) => { Some(simd_json::ValueType::Object) };
+                        ($_other:ident) => { None };
+                    }
+                    vec![$(value_type!($type)),*].into_iter().collect()
+                }
             }
 
             TremorFnWrapper::new(
@@ -599,6 +745,45 @@ impl Registry {
     pub fn find_module(&self, module: &str) -> Option<&HashMap<String, TremorFnWrapper>> {
         self.functions.get(module)
     }
+
+    /// Signatures of all registered functions, arguments are typed where
+    /// the function declares its argument types
+    pub fn signatures(&self) -> Vec<FunctionSignatureDoc> {
+        self.functions
+            .values()
+            .flat_map(HashMap::values)
+            .map(|f| {
+                let args = if let Some(types) = f.arg_types() {
+                    types
+                        .iter()
+                        .enumerate()
+                        .map(|(i, t)| format!("arg{}: {}", i, type_name(*t)))
+                        .collect()
+                } else {
+                    (0..*f.arity().start())
+                        .map(|i| format!("arg{}", i))
+                        .collect()
+                };
+                FunctionSignatureDoc {
+                    full_name: format!("{}::{}", f.module, f.name),
+                    args,
+                    result: String::new(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn type_name(t: ValueType) -> &'static str {
+    match t {
+        ValueType::Null => "null",
+        ValueType::Bool => "bool",
+        ValueType::I64 | ValueType::U64 => "int",
+        ValueType::F64 => "float",
+        ValueType::String => "string",
+        ValueType::Array => "array",
+        _ => "record",
+    }
 }
 
 /// Wrapper around an aggregate function
@@ -665,6 +850,16 @@ impl TremorAggrFnWrapper {
         use std::borrow::Borrow;
         self.fun.merge(src.fun.borrow())
     }
+
+    /// Captures the accumulated state of the function
+    pub fn snapshot(&self) -> Option<Value<'static>> {
+        self.fun.snapshot()
+    }
+
+    /// Restores the accumulated state of the function
+    pub fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.fun.restore(snapshot)
+    }
 }
 
 impl fmt::Debug for TremorAggrFnWrapper {
@@ -734,7 +929,7 @@ impl Aggr {
 }
 
 #[cfg(test)]
-pub use tests::fun;
+pub use tests::{fun, fun_with};
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -748,6 +943,19 @@ mod tests {
             .clone();
         move |args: &[&Value]| -> FResult<Value> { f.invoke(&EventContext::new(0, None), &args) }
     }
+    // Test utility to grab a higher order function from the registry
+    pub fn fun_with<'event>(
+        m: &str,
+        f: &str,
+    ) -> impl Fn(&[&Value<'event>], &mut Callback<'_, 'event>) -> FResult<Value<'event>> {
+        let f = registry()
+            .find(m, f)
+            .expect("could not find function")
+            .clone();
+        move |args: &[&Value<'event>], call: &mut Callback<'_, 'event>| {
+            f.invoke_with(&EventContext::new(0, None), args, call)
+        }
+    }
     #[test]
     pub fn call_a_function_from_a_registry_works() {
         let max = fun("math", "max");
@@ -756,6 +964,16 @@ mod tests {
         assert_eq!(Ok(Value::from(2)), max(&[&one, &two]));
     }
 
+    #[test]
+    pub fn signatures() {
+        let sigs = registry().signatures();
+        let len = sigs
+            .iter()
+            .find(|s| s.full_name == "string::len")
+            .expect("no signature for string::len");
+        assert_eq!(len.args, vec!["arg0: string".to_string()]);
+    }
+
     #[test]
     pub fn bad_arity() {
         let f = tremor_fn! (module::name(_context, _a){
@@ -0,0 +1,174 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Aggregate functions declared in tremor-script modules:
+//!
+//! ```tremor
+//! mod m with
+//!   aggregate fn sum(x) with
+//!     fn init() with 0 end;
+//!     fn accumulate(state, x) with state + x end;
+//!     fn merge(state, other) with state + other end;
+//!     fn emit(state) with state end
+//!   end
+//! end
+//! ```
+//!
+//! The state is a plain value that is threaded through the functions,
+//! `compensate(state, ...)` is optional.
+
+use super::{CustomFn, FResult, FunctionError, TremorAggrFn};
+use crate::ast::{InvokeAggrFn, NodeMetas};
+use crate::interpreter::Env;
+use crate::EventContext;
+use simd_json::prelude::*;
+use simd_json::BorrowedValue as Value;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+const NO_AGGRS: [InvokeAggrFn<'static>; 0] = [];
+
+/// The functions making up an aggregate function
+#[derive(Debug)]
+pub(crate) struct AggrFns<'script> {
+    pub init: CustomFn<'script>,
+    pub accumulate: CustomFn<'script>,
+    pub compensate: Option<CustomFn<'script>>,
+    pub merge: CustomFn<'script>,
+    pub emit: CustomFn<'script>,
+    /// the functions of the module function values can refer to
+    pub functions: Vec<CustomFn<'script>>,
+}
+
+#[derive(Clone)]
+pub(crate) struct CustomAggr {
+    fns: Arc<AggrFns<'static>>,
+    consts: Vec<Value<'static>>,
+    meta: Arc<NodeMetas>,
+    argc: usize,
+    // `None` until the first value is accumulated so we only run `init`
+    // when needed.
+    state: Option<Value<'static>>,
+}
+
+impl CustomAggr {
+    pub(crate) fn new(
+        fns: AggrFns<'static>,
+        consts: Vec<Value<'static>>,
+        meta: NodeMetas,
+        argc: usize,
+    ) -> Self {
+        Self {
+            fns: Arc::new(fns),
+            consts,
+            meta: Arc::new(meta),
+            argc,
+            state: None,
+        }
+    }
+
+    fn call(&self, f: &CustomFn<'static>, args: &[&Value<'static>]) -> FResult<Value<'static>> {
+        let context = EventContext::new(0, None);
+        let env = Env {
+            context: &context,
+            consts: &self.consts,
+            aggrs: &NO_AGGRS,
+            meta: &self.meta,
+            recursion_limit: crate::recursion_limit(),
+            functions: &self.fns.functions,
+            budget: None,
+            debugger: None,
+        };
+        f.invoke(&env, args).map(|v| v.clone_static())
+    }
+
+    fn take_state(&mut self) -> FResult<Value<'static>> {
+        if let Some(state) = self.state.take() {
+            Ok(state)
+        } else {
+            self.call(&self.fns.init, &[])
+        }
+    }
+
+    fn apply(&mut self, f: &CustomFn<'static>, args: &[&Value]) -> FResult<()> {
+        if args.len() != self.argc {
+            return Err(FunctionError::Error(Box::new(
+                format!(
+                    "aggregate function expects {} argument(s) but got {}",
+                    self.argc,
+                    args.len()
+                )
+                .into(),
+            )));
+        }
+        let mut argv = Vec::with_capacity(args.len() + 1);
+        argv.push(self.take_state()?);
+        argv.extend(args.iter().map(|v| v.clone_static()));
+        let argv: Vec<&Value<'static>> = argv.iter().collect();
+        self.state = Some(self.call(f, &argv)?);
+        Ok(())
+    }
+}
+
+impl TremorAggrFn for CustomAggr {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        let fns = self.fns.clone();
+        self.apply(&fns.accumulate, args)
+    }
+    fn compensate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        let fns = self.fns.clone();
+        if let Some(compensate) = &fns.compensate {
+            self.apply(compensate, args)
+        } else {
+            Ok(())
+        }
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        let fns = self.fns.clone();
+        let state = self.take_state()?;
+        let r = self.call(&fns.emit, &[&state]);
+        self.state = Some(state);
+        r
+    }
+    fn init(&mut self) {
+        self.state = None;
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            if let Some(other_state) = &other.state {
+                let fns = self.fns.clone();
+                let state = self.take_state()?;
+                self.state = Some(self.call(&fns.merge, &[&state, other_state])?);
+            }
+        }
+        Ok(())
+    }
+    fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        self.argc..=self.argc
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let mut m = simd_json::borrowed::Object::new();
+        if let Some(state) = &self.state {
+            m.insert("state".into(), state.clone());
+        }
+        Some(Value::from(m))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.state = snapshot.get("state").map(|s| s.clone_static());
+        Ok(())
+    }
+}
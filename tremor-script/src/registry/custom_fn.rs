@@ -18,6 +18,7 @@
 use super::{FResult, FunctionError, Result};
 use crate::ast::query::ARGS_CONST_ID;
 use crate::ast::{Expr, Exprs, ImutExpr, ImutExprInt, ImutExprs, InvokeAggrFn};
+use crate::binary::{portable, tag, untag, FUNCTION_TAG};
 use crate::interpreter::{AggrType, Cont, Env, ExecOpts, LocalStack};
 use simd_json::prelude::*;
 use simd_json::BorrowedValue as Value;
@@ -27,16 +28,67 @@ const RECUR_STR: &str = "recur";
 pub(crate) const RECUR_PTR: Option<*const u8> = Some(RECUR_STR.as_ptr());
 pub(crate) const RECUR: Value<'static> = Value::String(Cow::Borrowed(RECUR_STR));
 
+/// A function declared in tremor-script
 #[derive(Debug, Clone, PartialEq, Serialize)]
-pub(crate) struct CustomFn<'script> {
+pub struct CustomFn<'script> {
     //module: Arc<Script>,
-    pub name: Cow<'script, str>,
-    pub body: Exprs<'script>,
-    pub args: Vec<String>,
-    pub open: bool,
-    pub locals: usize,
-    pub is_const: bool,
-    pub inline: bool,
+    pub(crate) name: Cow<'script, str>,
+    pub(crate) body: Exprs<'script>,
+    pub(crate) args: Vec<String>,
+    pub(crate) open: bool,
+    pub(crate) locals: usize,
+    pub(crate) is_const: bool,
+    pub(crate) inline: bool,
+}
+
+/// The value of a reference to the function `id` of a script, function
+/// values are arrays of the form `[tag, id, name]`.
+///
+/// The tag is a NaN no event data can carry so function values can't be
+/// built from events, see `binary`.
+pub(crate) fn fn_value(f: &CustomFn, id: usize) -> Value<'static> {
+    Value::Array(vec![
+        tag(FUNCTION_TAG),
+        Value::from(id),
+        Value::from(f.name.to_string()),
+    ])
+}
+
+/// Calls a function value with the functions of the script it was created
+/// in.
+pub(crate) fn call_fn_value<'run, 'event, 'script>(
+    env: &'run Env<'run, 'event, 'script>,
+    fun: &Value<'event>,
+    args: &[&Value<'event>],
+) -> FResult<Value<'event>>
+where
+    'script: 'event,
+    'event: 'run,
+{
+    let f = match untag(fun) {
+        Some((FUNCTION_TAG, [id, name])) => id
+            .as_usize()
+            .and_then(|id| env.functions.get(id))
+            .filter(|f| name.as_str().map_or(false, |name| f.name == name)),
+        _ => None,
+    };
+    let f = f.ok_or_else(|| {
+        FunctionError::Error(Box::new(
+            format!("{} is not a function", portable(fun).encode()).into(),
+        ))
+    })?;
+    if args.len() < f.args.len() || (args.len() > f.args.len() && !f.open) {
+        return Err(FunctionError::Error(Box::new(
+            format!(
+                "function {} takes {} argument(s) but got {}",
+                f.name,
+                f.args.len(),
+                args.len()
+            )
+            .into(),
+        )));
+    }
+    f.invoke(env, args)
 }
 
 impl<'script> CustomFn<'script> {
@@ -144,12 +196,19 @@ impl<'script> CustomFn<'script> {
             result_needed: false,
             aggr: AggrType::Tick,
         };
+        // ALLOW: the guard leaves the debugger frame again once it goes out of scope
+        let _frame = env
+            .debugger
+            .map(|d| d.enter(Frame::new(&self.name, self.args.clone())));
         let env = Env {
             context: env.context,
             consts: env.consts,
             aggrs: &NO_AGGRS,
             meta: env.meta,
             recursion_limit: env.recursion_limit,
+            functions: env.functions,
+            budget: env.budget,
+            debugger: env.debugger,
         };
         let mut recursion_depth = 0;
         'recur: loop {
@@ -13,17 +13,20 @@
 // limitations under the License.
 
 use crate::ast::Helper;
-use crate::ast::{Docs, Warning};
+use crate::ast::{self, Docs, Warning};
 use crate::ctx::EventContext;
+use crate::docs::FunctionSignatureDoc;
 use crate::errors::{CompilerError, Error, Result};
 use crate::highlighter::{Dumb as DumbHighlighter, Highlighter};
 pub use crate::interpreter::AggrType;
 use crate::interpreter::Cont;
 use crate::lexer::{self};
+use crate::limits::Limits;
 use crate::parser::g as grammar;
 use crate::path::ModulePath;
 use crate::pos::Range;
 use crate::registry::{Aggr as AggrRegistry, Registry};
+use crate::types::Checker;
 use serde::Serialize;
 use simd_json::borrowed::Value;
 use std::io::{self, Write};
@@ -72,7 +75,7 @@ where
 #[derive(Debug)]
 pub struct Script {
     // TODO: This should probably be pulled out to allow people wrapping it themselves
-    pub(crate) script: rentals::Script,
+    pub(crate) script: ast::Script<'static>,
     source: String,
     warnings: Vec<Warning>,
 }
@@ -82,21 +85,10 @@ impl Script {
     pub fn warnings(&self) -> &Vec<Warning> {
         &self.warnings
     }
-}
-
-rental! {
-    mod rentals {
-        use crate::ast;
-        use std::borrow::Cow;
-        use serde::Serialize;
-        use std::sync::Arc;
-        use std::marker::Send;
 
-        #[rental_mut(covariant,debug)]
-        pub(crate) struct Script{
-            script: Box<String>, // Fake
-            parsed: ast::Script<'script>
-        }
+    /// Sets the limits the script has to stay within for every event
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.script.limits = limits;
     }
 }
 
@@ -115,35 +107,30 @@ where
     ) -> std::result::Result<Self, CompilerError> {
         let mut include_stack = lexer::IncludeStack::default();
         let r = |include_stack: &mut lexer::IncludeStack| -> Result<Self> {
-            let mut warnings = vec![];
-
-            let rented_script =
-                rentals::Script::try_new(Box::new(script.clone()), |script: &mut String| {
-                    let cu = include_stack.push(file_name)?;
-                    let lexemes: Vec<_> = lexer::Preprocessor::preprocess(
-                        module_path,
-                        file_name,
-                        script,
-                        cu,
-                        include_stack,
-                    )?;
-                    let filtered_tokens = lexemes
-                        .into_iter()
-                        .filter_map(Result::ok)
-                        .filter(|t| !t.value.is_ignorable());
-
-                    let script_raw = grammar::ScriptParser::new().parse(filtered_tokens)?;
-                    let fake_aggr_reg = AggrRegistry::default();
-                    let mut helper = Helper::new(&reg, &fake_aggr_reg, include_stack.cus.clone());
-                    let (screw_rust, ws) = script_raw.up_script(&mut helper)?;
+            let cu = include_stack.push(file_name)?;
+            let mut src = script.clone();
+            let lexemes: Vec<_> = lexer::Preprocessor::preprocess(
+                module_path,
+                file_name,
+                &mut src,
+                cu,
+                include_stack,
+            )?;
+            // The tokens are turned into owned tokens so the resulting
+            // script does not borrow from the source.
+            let filtered_tokens = lexemes
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|t| !t.value.is_ignorable())
+                .map(lexer::Spanned::into_static);
 
-                    warnings = ws;
-                    Ok(screw_rust)
-                })
-                .map_err(|e: rental::RentalError<Error, Box<String>>| e.0)?;
+            let script_raw = grammar::ScriptParser::new().parse(filtered_tokens)?;
+            let fake_aggr_reg = AggrRegistry::default();
+            let mut helper = Helper::new(&reg, &fake_aggr_reg, include_stack.cus.clone());
+            let (parsed, warnings) = script_raw.up_script(&mut helper)?;
 
             Ok(Self {
-                script: rented_script,
+                script: parsed,
                 source: script,
                 warnings,
             })
@@ -156,7 +143,24 @@ where
 
     /// Returns the documentation for the script
     pub fn docs(&self) -> &Docs<'_> {
-        &self.script.suffix().docs
+        &self.script.docs
+    }
+
+    /// Infers and checks the types in the script, definite mismatches are
+    /// returned as errors, possible ones are added to the warnings.
+    ///
+    /// `schema` optionally describes the event, `signatures` are the
+    /// documented signatures of functions.
+    pub fn check_types(
+        &mut self,
+        schema: Option<&Value>,
+        signatures: &[FunctionSignatureDoc],
+    ) -> Result<()> {
+        let s = &self.script;
+        let mut checker = Checker::new(&s.node_meta, &s.consts, s.locals, schema, signatures)?;
+        checker.exprs(&s.exprs)?;
+        self.warnings.append(&mut checker.warnings);
+        Ok(())
     }
 
     /// Highlights a script with a given highlighter.
@@ -262,12 +266,7 @@ where
 
     /// Formats an error within this script using a given highlighter
     pub fn format_error_with<H: Highlighter>(&self, h: &mut H, e: &Error) -> io::Result<()> {
-        Self::format_error_from_script_and_cus(
-            &self.source,
-            h,
-            e,
-            &self.script.suffix().node_meta.cus,
-        )
+        Self::format_error_from_script_and_cus(&self.source, h, e, &self.script.node_meta.cus)
     }
 
     /// Runs an event through this script
@@ -279,6 +278,6 @@ where
         state: &'run mut Value<'static>,
         meta: &'run mut Value<'event>,
     ) -> Result<Return<'event>> {
-        self.script.suffix().run(context, aggr, event, state, meta)
+        self.script.run(context, aggr, event, state, meta)
     }
 }
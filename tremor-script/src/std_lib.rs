@@ -20,6 +20,7 @@ macro_rules! assert_val {
 }
 
 mod array;
+mod binary;
 mod chash;
 mod datetime;
 mod dummy;
@@ -44,6 +45,7 @@ use crate::registry::{Aggr as AggrRegistry, Registry};
 
 pub fn load(registry: &mut Registry) {
     array::load(registry);
+    binary::load(registry);
     chash::load(registry);
     datetime::load(registry);
     dummy::load(registry);
@@ -12,8 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::registry::Registry;
+use crate::registry::{mfa, Callback, FResult, FunctionError, HigherOrderFn, Registry};
 use crate::tremor_const_fn;
+use crate::utils::value_cmp;
 use simd_json::{prelude::*, BorrowedValue as Value};
 
 pub fn load(registry: &mut Registry) {
@@ -86,7 +87,92 @@ pub fn load(registry: &mut Registry) {
             }else {
                 Some(v.clone())
             }).collect::<Vec<_>>()))
-        }));
+        }))
+        .insert(HigherOrderFn::wrap("array", "map", 2, map))
+        .insert(HigherOrderFn::wrap("array", "filter", 2, filter))
+        .insert(HigherOrderFn::wrap("array", "reduce", 3, reduce))
+        .insert(HigherOrderFn::wrap("array", "sort_by", 2, sort_by));
+}
+
+fn map<'event>(args: &[&Value<'event>], call: &mut Callback<'_, 'event>) -> FResult<Value<'event>> {
+    if let [Value::Array(input), fun] = args {
+        input
+            .iter()
+            .map(|v| call(*fun, &[v]))
+            .collect::<FResult<Vec<_>>>()
+            .map(Value::from)
+    } else {
+        Err(FunctionError::BadType {
+            mfa: mfa("array", "map", 2),
+        })
+    }
+}
+
+fn filter<'event>(
+    args: &[&Value<'event>],
+    call: &mut Callback<'_, 'event>,
+) -> FResult<Value<'event>> {
+    if let [Value::Array(input), fun] = args {
+        let mut output = Vec::with_capacity(input.len());
+        for v in input {
+            match call(*fun, &[v])?.as_bool() {
+                Some(true) => output.push(v.clone()),
+                Some(false) => (),
+                None => {
+                    return Err(FunctionError::RuntimeError {
+                        mfa: mfa("array", "filter", 2),
+                        error: "the filter function has to return a boolean".into(),
+                    })
+                }
+            }
+        }
+        Ok(Value::from(output))
+    } else {
+        Err(FunctionError::BadType {
+            mfa: mfa("array", "filter", 2),
+        })
+    }
+}
+
+fn reduce<'event>(
+    args: &[&Value<'event>],
+    call: &mut Callback<'_, 'event>,
+) -> FResult<Value<'event>> {
+    if let [Value::Array(input), initial, fun] = args {
+        let mut acc: Value<'event> = (*initial).clone();
+        for v in input {
+            acc = call(*fun, &[&acc, v])?;
+        }
+        Ok(acc)
+    } else {
+        Err(FunctionError::BadType {
+            mfa: mfa("array", "reduce", 3),
+        })
+    }
+}
+
+fn sort_by<'event>(
+    args: &[&Value<'event>],
+    call: &mut Callback<'_, 'event>,
+) -> FResult<Value<'event>> {
+    if let [Value::Array(input), fun] = args {
+        let mut keyed = input
+            .iter()
+            .map(|v| Ok((call(*fun, &[v])?, v)))
+            .collect::<FResult<Vec<_>>>()?;
+        // the sort is stable so elements with the same key keep their order
+        keyed.sort_by(|(l, _), (r, _)| value_cmp(l, r));
+        Ok(Value::from(
+            keyed
+                .into_iter()
+                .map(|(_, v)| v.clone())
+                .collect::<Vec<_>>(),
+        ))
+    } else {
+        Err(FunctionError::BadType {
+            mfa: mfa("array", "sort_by", 2),
+        })
+    }
 }
 
 //TODO this is not very nice
@@ -100,7 +186,7 @@ fn flatten_value<'event>(v: &Value<'event>) -> Vec<Value<'event>> {
 
 #[cfg(test)]
 mod test {
-    use crate::registry::fun;
+    use crate::registry::{fun, fun_with};
     use simd_json::prelude::*;
     use simd_json::BorrowedValue as Value;
 
@@ -225,4 +311,60 @@ mod test {
             ])
         );
     }
+
+    #[test]
+    fn map() {
+        let f = fun_with("array", "map");
+        let v = Value::from(vec![1, 2, 3]);
+        let fun = Value::from("double");
+        assert_val!(
+            f(&[&v, &fun], &mut |fun, args| {
+                assert_eq!(fun, &Value::from("double"));
+                Ok(Value::from(args[0].as_i64().unwrap_or_default() * 2))
+            }),
+            vec![2, 4, 6]
+        );
+        assert!(f(&[&fun, &fun], &mut |_, _| Ok(Value::null())).is_err());
+    }
+
+    #[test]
+    fn filter() {
+        let f = fun_with("array", "filter");
+        let v = Value::from(vec![1, 2, 3, 4]);
+        let fun = Value::null();
+        assert_val!(
+            f(&[&v, &fun], &mut |_, args| Ok(Value::from(
+                args[0].as_i64().unwrap_or_default() % 2 == 0
+            ))),
+            vec![2, 4]
+        );
+        assert!(f(&[&v, &fun], &mut |_, _| Ok(Value::null())).is_err());
+    }
+
+    #[test]
+    fn reduce() {
+        let f = fun_with("array", "reduce");
+        let v = Value::from(vec![1, 2, 3, 4]);
+        let (initial, fun) = (Value::from(10), Value::null());
+        assert_val!(
+            f(&[&v, &initial, &fun], &mut |_, args| Ok(Value::from(
+                args[0].as_i64().unwrap_or_default() + args[1].as_i64().unwrap_or_default()
+            ))),
+            20
+        );
+        assert!(f(&[&v, &fun], &mut |_, _| Ok(Value::null())).is_err());
+    }
+
+    #[test]
+    fn sort_by() {
+        let f = fun_with("array", "sort_by");
+        let v = Value::from(vec!["snot", "a", "badger", "aa"]);
+        let fun = Value::null();
+        assert_val!(
+            f(&[&v, &fun], &mut |_, args| Ok(Value::from(
+                args[0].as_str().map(str::len).unwrap_or_default()
+            ))),
+            vec!["a", "aa", "snot", "badger"]
+        );
+    }
 }
@@ -0,0 +1,166 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::binary::{as_bytes, from_bytes, is_binary, pack};
+use crate::registry::{mfa, FResult, FunctionError, Registry, TremorFn, TremorFnWrapper};
+use crate::tremor_const_fn;
+use crate::EventContext;
+use simd_json::BorrowedValue as Value;
+use std::fmt::Write;
+
+macro_rules! bytes {
+    ($input:expr) => {
+        if let Some(bytes) = as_bytes($input) {
+            bytes
+        } else {
+            return Err(FunctionError::BadType { mfa: this_mfa() });
+        }
+    };
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+pub fn load(registry: &mut Registry) {
+    #[derive(Clone, Debug, Default)]
+    struct BinaryPack {}
+    impl TremorFn for BinaryPack {
+        fn invoke<'event, 'c>(
+            &self,
+            _ctx: &'c EventContext,
+            args: &[&Value<'event>],
+        ) -> FResult<Value<'event>> {
+            pack(args)
+                .map(|b| from_bytes(&b))
+                .map_err(|error| FunctionError::RuntimeError {
+                    mfa: mfa("binary", "pack", args.len()),
+                    error,
+                })
+        }
+        fn snot_clone(&self) -> Box<dyn TremorFn> {
+            Box::new(self.clone())
+        }
+        fn arity(&self) -> std::ops::RangeInclusive<usize> {
+            0..=usize::max_value()
+        }
+        fn is_const(&self) -> bool {
+            true
+        }
+    }
+
+    registry
+        .insert(tremor_const_fn! (binary::is_binary(_context, _input) {
+            Ok(Value::from(is_binary(_input)))
+        }))
+        .insert(tremor_const_fn! (binary::len(_context, _input) {
+            Ok(Value::from(bytes!(_input).len()))
+        }))
+        .insert(tremor_const_fn! (binary::concat(_context, _left, _right) {
+            let mut bytes = bytes!(_left);
+            bytes.append(&mut bytes!(_right));
+            Ok(from_bytes(&bytes))
+        }))
+        .insert(tremor_const_fn! (binary::to_hex(_context, _input) {
+            let bytes = bytes!(_input);
+            let mut hex = String::with_capacity(bytes.len() * 2);
+            for b in bytes {
+                write!(hex, "{:02x}", b).map_err(to_runtime_error)?;
+            }
+            Ok(Value::from(hex))
+        }))
+        .insert(tremor_const_fn! (binary::from_hex(_context, _input: String) {
+            from_hex(_input)
+                .map(|b| from_bytes(&b))
+                .ok_or_else(|| to_runtime_error(format!("`{}` is not a valid hex string", _input)))
+        }))
+        .insert(tremor_const_fn! (binary::to_base64(_context, _input) {
+            Ok(Value::from(base64::encode(&bytes!(_input))))
+        }))
+        .insert(tremor_const_fn! (binary::from_base64(_context, _input: String) {
+            base64::decode(_input.as_bytes())
+                .map(|b| from_bytes(&b))
+                .map_err(to_runtime_error)
+        }))
+        .insert(tremor_const_fn! (binary::to_string(_context, _input) {
+            String::from_utf8(bytes!(_input))
+                .map(Value::from)
+                .map_err(to_runtime_error)
+        }))
+        .insert(tremor_const_fn! (binary::from_string(_context, _input: String) {
+            Ok(from_bytes(_input.as_bytes()))
+        }))
+        .insert(TremorFnWrapper::new(
+            "binary".to_string(),
+            "pack".to_string(),
+            Box::new(BinaryPack::default()),
+        ));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::binary::from_bytes;
+    use crate::registry::fun;
+    use simd_json::BorrowedValue as Value;
+
+    #[test]
+    fn len() {
+        let f = fun("binary", "len");
+        assert_val!(f(&[&from_bytes(&[1, 2, 3])]), 3);
+        assert_val!(f(&[&from_bytes(&[])]), 0);
+        assert!(f(&[&Value::from(vec![1, 2])]).is_err());
+        assert!(f(&[&Value::from("snot")]).is_err());
+    }
+
+    #[test]
+    fn concat() {
+        let f = fun("binary", "concat");
+        let (a, b) = (from_bytes(&[1, 2]), from_bytes(&[3]));
+        assert_val!(f(&[&a, &b]), from_bytes(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn hex() {
+        let f = fun("binary", "to_hex");
+        assert_val!(f(&[&from_bytes(&[0, 15, 171])]), "000fab");
+        let f = fun("binary", "from_hex");
+        assert_val!(f(&[&Value::from("000fAB")]), from_bytes(&[0, 15, 171]));
+        assert!(f(&[&Value::from("abc")]).is_err());
+        assert!(f(&[&Value::from("zz")]).is_err());
+    }
+
+    #[test]
+    fn base64() {
+        let f = fun("binary", "to_base64");
+        assert_val!(f(&[&from_bytes(b"snot")]), "c25vdA==");
+        let f = fun("binary", "from_base64");
+        assert_val!(f(&[&Value::from("c25vdA==")]), from_bytes(b"snot"));
+        assert!(f(&[&Value::from("!")]).is_err());
+    }
+
+    #[test]
+    fn string() {
+        let f = fun("binary", "to_string");
+        assert_val!(f(&[&from_bytes(b"badger")]), "badger");
+        assert!(f(&[&from_bytes(&[0xff])]).is_err());
+        let f = fun("binary", "from_string");
+        assert_val!(f(&[&Value::from("badger")]), from_bytes(b"badger"));
+    }
+}
@@ -14,16 +14,15 @@
 #![allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
 
 use crate::registry::Registry;
-use crate::{tremor_const_fn, utils::sorsorted_serialize};
+use crate::tremor_const_fn;
+use crate::utils::{jump_hash, sorsorted_serialize};
 use simd_json::prelude::*;
 
 pub fn load(registry: &mut Registry) {
     registry.insert(
         tremor_const_fn! (chash::jump(_context, _key, _slot_count) {
             if let (Some(key), Some(slot_count)) =  (_key.as_str(), _slot_count.as_u32()) {
-                // This is 'tremor\0\0'  and '\0\0tremor' as integers
-                let jh = jumphash::JumpHasher::new_with_keys(8_390_880_576_440_238_080, 128_034_676_764_530);
-                Ok(jh.slot(&key, slot_count).into())
+                Ok(jump_hash(key, slot_count).into())
             } else {
                  Err(FunctionError::BadType{mfa: this_mfa()})
             }
@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::registry::Registry;
+use crate::registry::{mfa, Callback, FResult, FunctionError, HigherOrderFn, Registry};
 use crate::tremor_const_fn;
 use simd_json::prelude::*;
 use simd_json::value::borrowed::Object;
+use simd_json::BorrowedValue as Value;
+
 pub fn load(registry: &mut Registry) {
     registry
         .insert(tremor_const_fn! (record::len(_context, _input: Object) {
@@ -81,12 +83,59 @@ pub fn load(registry: &mut Registry) {
             } else {
                 (k.clone(), v.clone())
             }).collect::<Object>()))
-        }));
+        }))
+        .insert(HigherOrderFn::wrap("record", "map_values", 2, map_values))
+        .insert(HigherOrderFn::wrap("record", "filter", 2, filter));
+}
+
+fn map_values<'event>(
+    args: &[&Value<'event>],
+    call: &mut Callback<'_, 'event>,
+) -> FResult<Value<'event>> {
+    if let [Value::Object(input), fun] = args {
+        input
+            .iter()
+            .map(|(k, v)| Ok((k.clone(), call(*fun, &[v])?)))
+            .collect::<FResult<Object>>()
+            .map(Value::from)
+    } else {
+        Err(FunctionError::BadType {
+            mfa: mfa("record", "map_values", 2),
+        })
+    }
+}
+
+fn filter<'event>(
+    args: &[&Value<'event>],
+    call: &mut Callback<'_, 'event>,
+) -> FResult<Value<'event>> {
+    if let [Value::Object(input), fun] = args {
+        let mut output = Object::with_capacity(input.len());
+        for (k, v) in &**input {
+            match call(*fun, &[&Value::String(k.clone()), v])?.as_bool() {
+                Some(true) => {
+                    output.insert(k.clone(), v.clone());
+                }
+                Some(false) => (),
+                None => {
+                    return Err(FunctionError::RuntimeError {
+                        mfa: mfa("record", "filter", 2),
+                        error: "the filter function has to return a boolean".into(),
+                    })
+                }
+            }
+        }
+        Ok(Value::from(output))
+    } else {
+        Err(FunctionError::BadType {
+            mfa: mfa("record", "filter", 2),
+        })
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::registry::fun;
+    use crate::registry::{fun, fun_with};
     use halfbrown::hashmap;
     use simd_json::BorrowedValue as Value;
 
@@ -211,4 +260,48 @@ mod test {
             })
         );
     }
+
+    #[test]
+    fn map_values() {
+        let f = fun_with("record", "map_values");
+        let v = Value::from(hashmap! {
+            "snot".into() => Value::from(1),
+            "badger".into() => Value::from(2),
+        });
+        let fun = Value::null();
+        assert_val!(
+            f(&[&v, &fun], &mut |_, args| Ok(Value::from(
+                args[0].as_i64().unwrap_or_default() * 2
+            ))),
+            hashmap! {
+                "snot".into() => Value::from(2),
+                "badger".into() => Value::from(4),
+            }
+        );
+    }
+
+    #[test]
+    fn filter() {
+        let f = fun_with("record", "filter");
+        let v = Value::from(hashmap! {
+            "snot".into() => Value::from(1),
+            "badger".into() => Value::from(2),
+        });
+        let fun = Value::null();
+        assert_val!(
+            f(&[&v, &fun], &mut |_, args| Ok(Value::from(
+                *args[0] == "snot" || *args[1] == 2
+            ))),
+            v.clone()
+        );
+        assert_val!(
+            f(&[&v, &fun], &mut |_, args| Ok(Value::from(
+                *args[0] == "snot"
+            ))),
+            hashmap! {
+                "snot".into() => Value::from(1),
+            }
+        );
+        assert!(f(&[&v, &fun], &mut |_, _| Ok(Value::null())).is_err());
+    }
 }
@@ -16,6 +16,7 @@
 use crate::registry::{
     mfa, Aggr as AggrRegistry, FResult, FunctionError, TremorAggrFn, TremorAggrFnWrapper,
 };
+use crate::utils::sorsorted_serialize;
 use halfbrown::hashmap;
 use hdrhistogram::Histogram;
 use simd_json::prelude::*;
@@ -45,6 +46,71 @@ pub fn ceil(value: f64, scale: i8) -> f64 {
     (value * multiplier).ceil() / multiplier
 }
 
+fn bad_snapshot(f: &str, arity: usize) -> FunctionError {
+    FunctionError::RuntimeError {
+        mfa: mfa("stats", f, arity),
+        error: "invalid snapshot".to_string(),
+    }
+}
+
+fn restore_option_f64(snapshot: &Value, f: &str) -> FResult<Option<f64>> {
+    if snapshot.is_null() {
+        Ok(None)
+    } else {
+        snapshot
+            .cast_f64()
+            .map(Some)
+            .ok_or_else(|| bad_snapshot(f, 1))
+    }
+}
+
+fn snapshot_percentiles(percentiles: &[(String, f64)]) -> Value<'static> {
+    Value::from(
+        percentiles
+            .iter()
+            .map(|(pcn, _)| Value::from(pcn.clone()))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn restore_percentiles(snapshot: &Value, f: &str) -> FResult<Vec<(String, f64)>> {
+    snapshot
+        .as_array()
+        .ok_or_else(|| bad_snapshot(f, 2))?
+        .iter()
+        .map(|v| {
+            let pcn = v.as_str().ok_or_else(|| bad_snapshot(f, 2))?;
+            let p = pcn.parse().map_err(|_| bad_snapshot(f, 2))?;
+            Ok((pcn.to_string(), p))
+        })
+        .collect()
+}
+
+/// Reads the `[value, count]` pairs of a histogram snapshot
+fn restore_recorded<T, F>(snapshot: &Value, f: &str, value: F) -> FResult<(Vec<(T, u64)>, u64)>
+where
+    F: Fn(&Value) -> Option<T>,
+{
+    let values = snapshot
+        .get("values")
+        .and_then(Value::as_array)
+        .ok_or_else(|| bad_snapshot(f, 2))?;
+    let mut total: u64 = 0;
+    let mut recorded = Vec::with_capacity(values.len());
+    for v in values {
+        match v.as_array().map(Vec::as_slice) {
+            Some([v, count]) => {
+                let v = value(v).ok_or_else(|| bad_snapshot(f, 2))?;
+                let count = count.as_u64().ok_or_else(|| bad_snapshot(f, 2))?;
+                total += count;
+                recorded.push((v, count));
+            }
+            _ => return Err(bad_snapshot(f, 2)),
+        }
+    }
+    Ok((recorded, total))
+}
+
 #[derive(Clone, Debug, Default)]
 struct Count(i64);
 impl TremorAggrFn for Count {
@@ -70,6 +136,13 @@ impl TremorAggrFn for Count {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(self.0))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0 = snapshot.as_i64().ok_or_else(|| bad_snapshot("count", 0))?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -115,6 +188,13 @@ impl TremorAggrFn for Sum {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(self.0))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0 = snapshot.cast_f64().ok_or_else(|| bad_snapshot("sum", 1))?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -168,6 +248,19 @@ impl TremorAggrFn for Mean {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(vec![Value::from(self.0), Value::from(self.1)]))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        match snapshot.as_array().map(Vec::as_slice) {
+            Some([n, sum]) => {
+                self.0 = n.as_i64().ok_or_else(|| bad_snapshot("mean", 1))?;
+                self.1 = sum.cast_f64().ok_or_else(|| bad_snapshot("mean", 1))?;
+                Ok(())
+            }
+            _ => Err(bad_snapshot("mean", 1)),
+        }
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -225,6 +318,13 @@ impl TremorAggrFn for Min {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(self.0.map_or_else(Value::null, Value::from))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0 = restore_option_f64(snapshot, "min")?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -268,6 +368,13 @@ impl TremorAggrFn for Max {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(self.0.map_or_else(Value::null, Value::from))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0 = restore_option_f64(snapshot, "max")?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -337,6 +444,30 @@ impl TremorAggrFn for Var {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(hashmap! {
+            "n".into() => Value::from(self.n),
+            "k".into() => Value::from(self.k),
+            "ex".into() => Value::from(self.ex),
+            "ex2".into() => Value::from(self.ex2),
+        }))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        let field = |name: &str| {
+            snapshot
+                .get(name)
+                .and_then(Value::cast_f64)
+                .ok_or_else(|| bad_snapshot("var", 1))
+        };
+        self.n = snapshot
+            .get("n")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| bad_snapshot("var", 1))?;
+        self.k = field("k")?;
+        self.ex = field("ex")?;
+        self.ex2 = field("ex2")?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -375,6 +506,12 @@ impl TremorAggrFn for Stdev {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        self.0.snapshot()
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0.restore(snapshot)
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -660,6 +797,59 @@ impl TremorAggrFn for Dds {
         self.histo = None;
         self.cache.clear();
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        // the sketch can't be serialized so it is stored as `[value, count]`
+        // pairs sampled from its quantiles, a restored sketch is only as
+        // accurate as the sketch itself. Cached values are stored exactly.
+        let values: Vec<Value<'static>> = if let Some(histo) = &self.histo {
+            dds_samples(histo)
+                .into_iter()
+                .map(|(v, count)| Value::from(vec![Value::from(v), Value::from(count)]))
+                .collect()
+        } else {
+            self.cache
+                .iter()
+                .map(|v| Value::from(vec![Value::from(*v), Value::from(1_u64)]))
+                .collect()
+        };
+        Some(Value::from(hashmap! {
+            "values".into() => Value::from(values),
+            "percentiles".into() => snapshot_percentiles(&self.percentiles),
+            "percentiles_set".into() => Value::from(self.percentiles_set),
+        }))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.init();
+        self.percentiles_set = snapshot
+            .get("percentiles_set")
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        if self.percentiles_set {
+            self.percentiles = restore_percentiles(
+                snapshot
+                    .get("percentiles")
+                    .ok_or_else(|| bad_snapshot("dds", 2))?,
+                "dds",
+            )?;
+        }
+        let (recorded, total) = restore_recorded(snapshot, "dds", |v| v.cast_f64())?;
+        if total < HIST_MAX_CACHE_SIZE as u64 {
+            for (value, count) in recorded {
+                for _ in 0..count {
+                    self.cache.push(value);
+                }
+            }
+        } else {
+            let mut histo: DDSketch = DDSketch::new(DDSketchConfig::defaults());
+            for (value, count) in recorded {
+                for _ in 0..count {
+                    histo.add(value);
+                }
+            }
+            self.histo = Some(histo);
+        }
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -668,6 +858,32 @@ impl TremorAggrFn for Dds {
     }
 }
 
+/// Number of quantiles a `dds` sketch is sampled at for a snapshot
+const DDS_SNAPSHOT_SAMPLES: u64 = 1024;
+
+/// Samples a sketch as `[value, count]` pairs, the minimum and maximum are
+/// kept exactly and the values in between are spread over evenly spaced
+/// quantiles.
+fn dds_samples(histo: &DDSketch) -> Vec<(f64, u64)> {
+    let count = histo.count() as u64;
+    let mut samples = Vec::new();
+    if let (Some(min), Some(max)) = (histo.min(), histo.max()) {
+        samples.push((min, 1));
+        let inner = count.saturating_sub(2);
+        let n = inner.min(DDS_SNAPSHOT_SAMPLES);
+        for i in 0..n {
+            let q = (i as f64 + 0.5) / n as f64;
+            if let Ok(Some(v)) = histo.quantile(q) {
+                samples.push((v, inner * (i + 1) / n - inner * i / n));
+            }
+        }
+        if count > 1 {
+            samples.push((max, 1));
+        }
+    }
+    samples
+}
+
 #[derive(Clone)]
 struct Hdr {
     histo: Option<Histogram<u64>>,
@@ -900,6 +1116,452 @@ impl TremorAggrFn for Hdr {
         self.max = 0;
         self.cache.clear();
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        // we store the recorded values as `[value, count]` pairs so the
+        // snapshot is independent of the histograms internal layout
+        let values: Vec<Value<'static>> = if let Some(histo) = &self.histo {
+            histo
+                .iter_recorded()
+                .map(|v| {
+                    Value::from(vec![
+                        Value::from(v.value_iterated_to()),
+                        Value::from(v.count_at_value()),
+                    ])
+                })
+                .collect()
+        } else {
+            self.cache
+                .iter()
+                .map(|v| Value::from(vec![Value::from(*v), Value::from(1_u64)]))
+                .collect()
+        };
+        Some(Value::from(hashmap! {
+            "values".into() => Value::from(values),
+            "max".into() => Value::from(self.max),
+            "percentiles".into() => snapshot_percentiles(&self.percentiles),
+            "percentiles_set".into() => Value::from(self.percentiles_set),
+        }))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.init();
+        self.max = snapshot
+            .get("max")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| bad_snapshot("hdr", 2))?;
+        self.percentiles_set = snapshot
+            .get("percentiles_set")
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        if self.percentiles_set {
+            self.percentiles = restore_percentiles(
+                snapshot
+                    .get("percentiles")
+                    .ok_or_else(|| bad_snapshot("hdr", 2))?,
+                "hdr",
+            )?;
+        }
+        let (recorded, total) = restore_recorded(snapshot, "hdr", |v| v.as_u64())?;
+        if total < HIST_MAX_CACHE_SIZE as u64 {
+            for (value, count) in recorded {
+                for _ in 0..count {
+                    self.cache.push(value);
+                }
+            }
+        } else {
+            let mut histo: Histogram<u64> =
+                Histogram::new_with_bounds(1, self.max(), 2).map_err(|e| {
+                    FunctionError::RuntimeError {
+                        mfa: mfa("stats", "hdr", 2),
+                        error: format!("failed to allocate hdr storage: {:?}", e),
+                    }
+                })?;
+            histo.auto(true);
+            for (value, count) in recorded {
+                histo
+                    .record_n(value, count)
+                    .map_err(|e| FunctionError::RuntimeError {
+                        mfa: mfa("stats", "hdr", 2),
+                        error: format!("failed to record value: {:?}", e),
+                    })?;
+            }
+            self.histo = Some(histo);
+        }
+        Ok(())
+    }
+    fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=2
+    }
+}
+
+/// Precision of the `approx_distinct` sketch, `2^14` registers give a
+/// standard error of about 0.8%
+const HLL_PRECISION: u32 = 14;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// Stable 64 bit hash of a value, FNV-1a over its sorted serialisation
+/// followed by the murmur3 finaliser to spread the bits.
+fn hash_value(v: &Value) -> FResult<u64> {
+    let s = sorsorted_serialize(v).map_err(|e| FunctionError::RuntimeError {
+        mfa: mfa("stats", "approx_distinct", 1),
+        error: format!("failed to serialize value: {}", e),
+    })?;
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in s.as_bytes() {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    Ok(h)
+}
+
+/// HyperLogLog estimate of the number of distinct values
+#[derive(Clone, Debug, Default)]
+struct ApproxDistinct {
+    // empty until the first value is seen to keep idle groups cheap
+    registers: Vec<u8>,
+}
+
+impl ApproxDistinct {
+    #[allow(clippy::cast_possible_truncation)]
+    fn add(&mut self, h: u64) {
+        if self.registers.is_empty() {
+            self.registers = vec![0; HLL_REGISTERS];
+        }
+        let idx = (h >> (64 - HLL_PRECISION)) as usize;
+        // the guard bit bounds the rank to `64 - precision + 1`
+        let w = (h << HLL_PRECISION) | (1 << (HLL_PRECISION - 1));
+        let rank = (w.leading_zeros() + 1) as u8;
+        if let Some(r) = self.registers.get_mut(idx) {
+            if rank > *r {
+                *r = rank;
+            }
+        }
+    }
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn estimate(&self) -> u64 {
+        if self.registers.is_empty() {
+            return 0;
+        }
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let mut sum = 0.0;
+        let mut zeros = 0;
+        for r in &self.registers {
+            sum += 2_f64.powi(-i32::from(*r));
+            if *r == 0 {
+                zeros += 1;
+            }
+        }
+        let mut e = alpha * m * m / sum;
+        if e <= 2.5 * m && zeros > 0 {
+            // linear counting for small cardinalities
+            e = m * (m / f64::from(zeros)).ln();
+        }
+        e.round() as u64
+    }
+}
+
+impl TremorAggrFn for ApproxDistinct {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        let h = hash_value(args[0])?;
+        self.add(h);
+        Ok(())
+    }
+    fn compensate<'event>(&mut self, _args: &[&Value<'event>]) -> FResult<()> {
+        // A register only keeps the highest rank it has seen so removing a
+        // value would need all the values that share its register.
+        Err(FunctionError::RuntimeError {
+            mfa: mfa("stats", "approx_distinct", 1),
+            error: "approx_distinct can't forget values and can't be used in sliding windows"
+                .to_string(),
+        })
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        Ok(Value::from(self.estimate()))
+    }
+    fn init(&mut self) {
+        self.registers.clear();
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            if self.registers.is_empty() {
+                self.registers = other.registers.clone();
+            } else if !other.registers.is_empty() {
+                for (r, o) in self.registers.iter_mut().zip(&other.registers) {
+                    if *o > *r {
+                        *r = *o;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        if self.registers.is_empty() {
+            Some(Value::null())
+        } else {
+            Some(Value::from(base64::encode(&self.registers)))
+        }
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.init();
+        if !snapshot.is_null() {
+            let registers = snapshot
+                .as_str()
+                .and_then(|s| base64::decode(s).ok())
+                .ok_or_else(|| bad_snapshot("approx_distinct", 1))?;
+            if registers.len() != HLL_REGISTERS {
+                return Err(bad_snapshot("approx_distinct", 1));
+            }
+            self.registers = registers;
+        }
+        Ok(())
+    }
+    fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        1..=1
+    }
+}
+
+/// Counters kept by `top_k` for every reported entry, more counters
+/// make the counts of the reported entries more accurate
+const TOP_K_COUNTERS_PER_ENTRY: usize = 4;
+const TOP_K_DEFAULT: usize = 10;
+
+#[derive(Clone, Debug)]
+struct TopKEntry {
+    value: Value<'static>,
+    count: u64,
+    // maximal overestimation of `count`
+    error: u64,
+}
+
+/// Space saving heavy hitters, the `k` most frequent values with
+/// their counts
+#[derive(Clone, Debug)]
+struct TopK {
+    k: usize,
+    k_set: bool,
+    entries: halfbrown::HashMap<String, TopKEntry>,
+}
+
+impl Default for TopK {
+    fn default() -> Self {
+        Self {
+            k: TOP_K_DEFAULT,
+            k_set: false,
+            entries: halfbrown::HashMap::new(),
+        }
+    }
+}
+
+impl TopK {
+    fn capacity(&self) -> usize {
+        self.k * TOP_K_COUNTERS_PER_ENTRY
+    }
+    fn min_count(&self) -> u64 {
+        self.entries.values().map(|e| e.count).min().unwrap_or(0)
+    }
+    fn set_k(&mut self, k: Option<&Value>) -> FResult<()> {
+        if let Some(k) = k {
+            if !self.k_set {
+                self.k =
+                    k.as_usize()
+                        .filter(|k| *k > 0)
+                        .ok_or_else(|| FunctionError::RuntimeError {
+                            mfa: mfa("stats", "top_k", 2),
+                            error: "k needs to be a positive integer".to_string(),
+                        })?;
+                self.k_set = true;
+            }
+        }
+        Ok(())
+    }
+    /// Sorted by count, ties are broken by the serialised value so the
+    /// order is stable
+    fn sorted(&self) -> Vec<(&String, &TopKEntry)> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|(ka, a), (kb, b)| b.count.cmp(&a.count).then_with(|| ka.cmp(kb)));
+        entries
+    }
+}
+
+impl TremorAggrFn for TopK {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        self.set_k(args.get(1).copied())?;
+        let key = sorsorted_serialize(args[0]).map_err(|e| FunctionError::RuntimeError {
+            mfa: mfa("stats", "top_k", 2),
+            error: format!("failed to serialize value: {}", e),
+        })?;
+        if let Some(e) = self.entries.get_mut(&key) {
+            e.count += 1;
+        } else if self.entries.len() < self.capacity() {
+            self.entries.insert(
+                key,
+                TopKEntry {
+                    value: args[0].clone_static(),
+                    count: 1,
+                    error: 0,
+                },
+            );
+        } else {
+            // replace the entry with the smallest count
+            let min = self.sorted().last().map(|(k, e)| ((*k).clone(), e.count));
+            if let Some((min_key, min_count)) = min {
+                self.entries.remove(&min_key);
+                self.entries.insert(
+                    key,
+                    TopKEntry {
+                        value: args[0].clone_static(),
+                        count: min_count + 1,
+                        error: min_count,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+    fn compensate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        let key = sorsorted_serialize(args[0]).map_err(|e| FunctionError::RuntimeError {
+            mfa: mfa("stats", "top_k", 2),
+            error: format!("failed to serialize value: {}", e),
+        })?;
+        if let Some(e) = self.entries.get_mut(&key) {
+            e.count = e.count.saturating_sub(1);
+            if e.count == 0 {
+                self.entries.remove(&key);
+            }
+        }
+        Ok(())
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        let top: Vec<Value> = self
+            .sorted()
+            .into_iter()
+            .take(self.k)
+            .map(|(_, e)| {
+                Value::from(hashmap! {
+                    "value".into() => e.value.clone(),
+                    "count".into() => Value::from(e.count),
+                })
+            })
+            .collect();
+        Ok(Value::from(top))
+    }
+    fn init(&mut self) {
+        self.entries.clear();
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            if !self.k_set {
+                self.k = other.k;
+                self.k_set = other.k_set;
+            }
+            // A value missing from a full summary may have been seen up to
+            // its smallest count times
+            let self_min = if self.entries.len() < self.capacity() {
+                0
+            } else {
+                self.min_count()
+            };
+            let other_min = if other.entries.len() < other.capacity() {
+                0
+            } else {
+                other.min_count()
+            };
+            for e in self.entries.values_mut() {
+                e.count += other_min;
+                e.error += other_min;
+            }
+            for (key, o) in &other.entries {
+                if let Some(e) = self.entries.get_mut(key) {
+                    e.count = e.count - other_min + o.count;
+                    e.error = e.error - other_min + o.error;
+                } else {
+                    self.entries.insert(
+                        key.clone(),
+                        TopKEntry {
+                            value: o.value.clone(),
+                            count: o.count + self_min,
+                            error: o.error + self_min,
+                        },
+                    );
+                }
+            }
+            let capacity = self.capacity();
+            if self.entries.len() > capacity {
+                let drop: Vec<String> = self
+                    .sorted()
+                    .into_iter()
+                    .skip(capacity)
+                    .map(|(k, _)| k.clone())
+                    .collect();
+                for k in drop {
+                    self.entries.remove(&k);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let entries: Vec<Value<'static>> = self
+            .sorted()
+            .into_iter()
+            .map(|(_, e)| {
+                Value::from(vec![
+                    e.value.clone(),
+                    Value::from(e.count),
+                    Value::from(e.error),
+                ])
+            })
+            .collect();
+        Some(Value::from(hashmap! {
+            "k".into() => Value::from(self.k),
+            "k_set".into() => Value::from(self.k_set),
+            "entries".into() => Value::from(entries),
+        }))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.init();
+        self.k = snapshot
+            .get("k")
+            .and_then(Value::as_usize)
+            .ok_or_else(|| bad_snapshot("top_k", 2))?;
+        self.k_set = snapshot
+            .get("k_set")
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+        let entries = snapshot
+            .get("entries")
+            .and_then(Value::as_array)
+            .ok_or_else(|| bad_snapshot("top_k", 2))?;
+        for e in entries {
+            match e.as_array().map(Vec::as_slice) {
+                Some([value, count, error]) => {
+                    let key = sorsorted_serialize(value).map_err(|_| bad_snapshot("top_k", 2))?;
+                    self.entries.insert(
+                        key,
+                        TopKEntry {
+                            value: value.clone_static(),
+                            count: count.as_u64().ok_or_else(|| bad_snapshot("top_k", 2))?,
+                            error: error.as_u64().ok_or_else(|| bad_snapshot("top_k", 2))?,
+                        },
+                    );
+                }
+                _ => return Err(bad_snapshot("top_k", 2)),
+            }
+        }
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -908,6 +1570,174 @@ impl TremorAggrFn for Hdr {
     }
 }
 
+/// Counts of values per bucket, a value is counted in the first bucket
+/// whose upper bound is greater or equal to it, values above the last
+/// bound are counted as `overflow`.
+#[derive(Clone, Debug, Default)]
+struct Hist {
+    bounds: Vec<f64>,
+    counts: Vec<u64>,
+    overflow: u64,
+    count: u64,
+    sum: f64,
+}
+
+impl Hist {
+    fn set_bounds(&mut self, bounds: &Value) -> FResult<()> {
+        if !self.bounds.is_empty() {
+            return Ok(());
+        }
+        let err = || FunctionError::RuntimeError {
+            mfa: mfa("stats", "histogram", 2),
+            error: "bucket bounds need to be an ascending array of numbers".to_string(),
+        };
+        let bounds: Vec<f64> = bounds
+            .as_array()
+            .ok_or_else(err)?
+            .iter()
+            .map(|b| b.cast_f64().ok_or_else(err))
+            .collect::<FResult<_>>()?;
+        if bounds.is_empty() || bounds.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(err());
+        }
+        self.counts = vec![0; bounds.len()];
+        self.bounds = bounds;
+        Ok(())
+    }
+    fn bucket(&mut self, v: f64) -> &mut u64 {
+        match self.bounds.iter().position(|b| v <= *b) {
+            Some(i) if i < self.counts.len() => &mut self.counts[i],
+            _ => &mut self.overflow,
+        }
+    }
+}
+
+impl TremorAggrFn for Hist {
+    fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        self.set_bounds(args[1])?;
+        if let Some(v) = args[0].cast_f64() {
+            *self.bucket(v) += 1;
+            self.count += 1;
+            self.sum += v;
+            Ok(())
+        } else {
+            Err(FunctionError::BadType {
+                mfa: mfa("stats", "histogram", 2),
+            })
+        }
+    }
+    fn compensate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
+        if let Some(v) = args[0].cast_f64() {
+            let c = self.bucket(v);
+            *c = c.saturating_sub(1);
+            self.count = self.count.saturating_sub(1);
+            self.sum -= v;
+            Ok(())
+        } else {
+            Err(FunctionError::BadType {
+                mfa: mfa("stats", "histogram", 2),
+            })
+        }
+    }
+    fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        let buckets: Vec<Value> = self
+            .bounds
+            .iter()
+            .zip(&self.counts)
+            .map(|(b, c)| {
+                Value::from(hashmap! {
+                    "le".into() => Value::from(*b),
+                    "count".into() => Value::from(*c),
+                })
+            })
+            .collect();
+        Ok(Value::from(hashmap! {
+            "count".into() => Value::from(self.count),
+            "sum".into() => Value::from(self.sum),
+            "buckets".into() => Value::from(buckets),
+            "overflow".into() => Value::from(self.overflow),
+        }))
+    }
+    fn init(&mut self) {
+        for c in &mut self.counts {
+            *c = 0;
+        }
+        self.overflow = 0;
+        self.count = 0;
+        self.sum = 0.0;
+    }
+    fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
+        if let Some(other) = src.downcast_ref::<Self>() {
+            if self.bounds.is_empty() {
+                self.bounds = other.bounds.clone();
+                self.counts = vec![0; self.bounds.len()];
+            }
+            if !other.bounds.is_empty() && other.bounds != self.bounds {
+                return Err(FunctionError::RuntimeError {
+                    mfa: mfa("stats", "histogram", 2),
+                    error: "can't merge histograms with different buckets".to_string(),
+                });
+            }
+            for (c, o) in self.counts.iter_mut().zip(&other.counts) {
+                *c += o;
+            }
+            self.overflow += other.overflow;
+            self.count += other.count;
+            self.sum += other.sum;
+        }
+        Ok(())
+    }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let bounds: Vec<Value<'static>> = self.bounds.iter().copied().map(Value::from).collect();
+        let counts: Vec<Value<'static>> = self.counts.iter().copied().map(Value::from).collect();
+        Some(Value::from(hashmap! {
+            "bounds".into() => Value::from(bounds),
+            "counts".into() => Value::from(counts),
+            "overflow".into() => Value::from(self.overflow),
+            "count".into() => Value::from(self.count),
+            "sum".into() => Value::from(self.sum),
+        }))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        let list = |name: &str| {
+            snapshot
+                .get(name)
+                .and_then(Value::as_array)
+                .ok_or_else(|| bad_snapshot("histogram", 2))
+        };
+        let field = |name: &str| {
+            snapshot
+                .get(name)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| bad_snapshot("histogram", 2))
+        };
+        self.bounds = list("bounds")?
+            .iter()
+            .map(|b| b.cast_f64().ok_or_else(|| bad_snapshot("histogram", 2)))
+            .collect::<FResult<_>>()?;
+        self.counts = list("counts")?
+            .iter()
+            .map(|c| c.as_u64().ok_or_else(|| bad_snapshot("histogram", 2)))
+            .collect::<FResult<_>>()?;
+        if self.bounds.len() != self.counts.len() {
+            return Err(bad_snapshot("histogram", 2));
+        }
+        self.overflow = field("overflow")?;
+        self.count = field("count")?;
+        self.sum = snapshot
+            .get("sum")
+            .and_then(Value::cast_f64)
+            .ok_or_else(|| bad_snapshot("histogram", 2))?;
+        Ok(())
+    }
+    fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
+        Box::new(self.clone())
+    }
+    fn arity(&self) -> RangeInclusive<usize> {
+        2..=2
+    }
+}
+
 pub fn load_aggr(registry: &mut AggrRegistry) {
     registry
         .insert(TremorAggrFnWrapper::new(
@@ -954,6 +1784,21 @@ pub fn load_aggr(registry: &mut AggrRegistry) {
             "stats".to_string(),
             "dds".to_string(),
             Box::new(Dds::default()),
+        ))
+        .insert(TremorAggrFnWrapper::new(
+            "stats".to_string(),
+            "approx_distinct".to_string(),
+            Box::new(ApproxDistinct::default()),
+        ))
+        .insert(TremorAggrFnWrapper::new(
+            "stats".to_string(),
+            "top_k".to_string(),
+            Box::new(TopK::default()),
+        ))
+        .insert(TremorAggrFnWrapper::new(
+            "stats".to_string(),
+            "histogram".to_string(),
+            Box::new(Hist::default()),
         ));
 }
 
@@ -1169,4 +2014,245 @@ mod test {
         assert_eq!(v, e);
         Ok(())
     }
+
+    #[test]
+    fn approx_distinct() -> Result<()> {
+        let mut a = ApproxDistinct::default();
+        a.init();
+        assert_eq!(a.emit()?, 0);
+        for i in 0..10_000 {
+            a.accumulate(&[&Value::from(i)])?;
+            // duplicates don't count
+            a.accumulate(&[&Value::from(i)])?;
+        }
+        let r = a.emit()?.as_u64().expect("not a number");
+        assert!(r > 9_800 && r < 10_200, "{}", r);
+
+        let mut b = ApproxDistinct::default();
+        b.init();
+        for i in 5_000..15_000 {
+            b.accumulate(&[&Value::from(i)])?;
+        }
+        a.merge(&b)?;
+        let r = a.emit()?.as_u64().expect("not a number");
+        assert!(r > 14_700 && r < 15_300, "{}", r);
+
+        let mut c = ApproxDistinct::default();
+        c.init();
+        for s in &["snot", "badger", "snot"] {
+            c.accumulate(&[&Value::from(*s)])?;
+        }
+        assert_eq!(c.emit()?, 2);
+        // the sketch can't forget values
+        assert!(c.compensate(&[&Value::from("snot")]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn top_k() -> Result<()> {
+        let k = Value::from(2);
+        let mut a = TopK::default();
+        a.init();
+        for (v, n) in &[("snot", 5), ("badger", 3), ("boo", 1)] {
+            for _ in 0..*n {
+                a.accumulate(&[&Value::from(*v), &k])?;
+            }
+        }
+        let e: Value = json!([
+            {"value": "snot", "count": 5},
+            {"value": "badger", "count": 3}
+        ])
+        .into();
+        assert_eq!(a.emit()?, e);
+
+        let mut b = TopK::default();
+        b.init();
+        for _ in 0..4 {
+            b.accumulate(&[&Value::from("boo"), &k])?;
+            b.accumulate(&[&Value::from("badger"), &k])?;
+        }
+        a.merge(&b)?;
+        let e: Value = json!([
+            {"value": "badger", "count": 7},
+            {"value": "boo", "count": 5}
+        ])
+        .into();
+        assert_eq!(a.emit()?, e);
+
+        a.compensate(&[&Value::from("badger"), &k])?;
+        a.compensate(&[&Value::from("badger"), &k])?;
+        a.compensate(&[&Value::from("badger"), &k])?;
+        // ties are ordered by value
+        let e: Value = json!([
+            {"value": "boo", "count": 5},
+            {"value": "snot", "count": 5}
+        ])
+        .into();
+        assert_eq!(a.emit()?, e);
+
+        assert!(TopK::default()
+            .accumulate(&[&Value::from("snot"), &Value::from(0)])
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn top_k_evicts() -> Result<()> {
+        let k = Value::from(1);
+        let mut a = TopK::default();
+        a.init();
+        // 4 counters, the 5th distinct value replaces the smallest one
+        for v in &["a", "b", "c", "d", "a", "e"] {
+            a.accumulate(&[&Value::from(*v), &k])?;
+        }
+        assert_eq!(a.entries.len(), 4);
+        let e: Value = json!([{"value": "a", "count": 2}]).into();
+        assert_eq!(a.emit()?, e);
+        assert_eq!(a.entries.get("\"e\"").map(|e| e.error), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn histogram() -> Result<()> {
+        let bounds = Value::from(vec![1, 5, 10]);
+        let mut a = Hist::default();
+        a.init();
+        for v in &[0, 1, 2, 5, 7, 20] {
+            a.accumulate(&[&Value::from(*v), &bounds])?;
+        }
+        let e: Value = json!({
+            "count": 6,
+            "sum": 35.0,
+            "buckets": [
+                {"le": 1.0, "count": 2},
+                {"le": 5.0, "count": 2},
+                {"le": 10.0, "count": 1}
+            ],
+            "overflow": 1
+        })
+        .into();
+        assert_eq!(a.emit()?, e);
+
+        let mut b = Hist::default();
+        b.init();
+        b.accumulate(&[&Value::from(3), &bounds])?;
+        b.merge(&a)?;
+        a.compensate(&[&Value::from(20), &bounds])?;
+        let e: Value = json!({
+            "count": 7,
+            "sum": 38.0,
+            "buckets": [
+                {"le": 1.0, "count": 2},
+                {"le": 5.0, "count": 3},
+                {"le": 10.0, "count": 1}
+            ],
+            "overflow": 1
+        })
+        .into();
+        assert_eq!(b.emit()?, e);
+        assert_eq!(a.emit()?.get("overflow"), Some(&Value::from(0)));
+
+        let mut c = Hist::default();
+        c.accumulate(&[&Value::from(3), &Value::from(vec![2, 4])])?;
+        assert!(c.merge(&a).is_err());
+        assert!(Hist::default()
+            .accumulate(&[&Value::from(3), &Value::from(vec![4, 2])])
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        let mut a = Mean::default();
+        a.init();
+        let one = Value::from(1);
+        let three = Value::from(3);
+        a.accumulate(&[&one])?;
+        a.accumulate(&[&three])?;
+        let snapshot = a.snapshot().expect("mean can be snapshotted");
+        let mut b = Mean::default();
+        b.restore(&snapshot)?;
+        assert_eq!(b.emit()?, 2.0);
+
+        let mut a = Hdr::default();
+        a.init();
+        for i in 1..=100 {
+            a.accumulate(&[&Value::from(i)])?;
+        }
+        let snapshot = a.snapshot().expect("hdr can be snapshotted");
+        let mut b = Hdr::default();
+        b.restore(&snapshot)?;
+        assert_eq!(a.emit()?, b.emit()?);
+
+        let mut a = ApproxDistinct::default();
+        a.init();
+        for i in 1..=100 {
+            a.accumulate(&[&Value::from(i)])?;
+        }
+        let snapshot = a.snapshot().expect("approx_distinct can be snapshotted");
+        let mut b = ApproxDistinct::default();
+        b.restore(&snapshot)?;
+        assert_eq!(a.emit()?, b.emit()?);
+
+        let mut a = TopK::default();
+        a.init();
+        for v in &["snot", "snot", "badger"] {
+            a.accumulate(&[&Value::from(*v)])?;
+        }
+        let snapshot = a.snapshot().expect("top_k can be snapshotted");
+        let mut b = TopK::default();
+        b.restore(&snapshot)?;
+        assert_eq!(a.emit()?, b.emit()?);
+
+        let bounds = Value::from(vec![1, 5]);
+        let mut a = Hist::default();
+        a.init();
+        for v in &[0, 3, 7] {
+            a.accumulate(&[&Value::from(*v), &bounds])?;
+        }
+        let snapshot = a.snapshot().expect("histogram can be snapshotted");
+        let mut b = Hist::default();
+        b.restore(&snapshot)?;
+        assert_eq!(a.emit()?, b.emit()?);
+
+        let mut a = Dds::default();
+        a.init();
+        for i in 1..=100 {
+            a.accumulate(&[&Value::from(i)])?;
+        }
+        let snapshot = a.snapshot().expect("dds can be snapshotted");
+        let mut b = Dds::default();
+        b.restore(&snapshot)?;
+        assert_eq!(a.emit()?, b.emit()?);
+
+        // sketches are restored from samples of their quantiles
+        let mut a = Dds::default();
+        a.init();
+        for i in 1..=5_000 {
+            a.accumulate(&[&Value::from(i)])?;
+        }
+        let mut other = Dds::default();
+        other.init();
+        for i in 5_001..=10_000 {
+            other.accumulate(&[&Value::from(i)])?;
+        }
+        a.merge(&other)?;
+        let snapshot = a.snapshot().expect("dds can be snapshotted");
+        let mut b = Dds::default();
+        b.restore(&snapshot)?;
+        let (a, b) = (a.emit()?, b.emit()?);
+        assert_eq!(a.get("count"), b.get("count"));
+        assert_eq!(a.get("min"), b.get("min"));
+        assert_eq!(a.get("max"), b.get("max"));
+        let median = |v: &Value| {
+            v.get("percentiles")
+                .and_then(|p| p.get("0.5"))
+                .and_then(Value::cast_f64)
+                .unwrap_or_default()
+        };
+        assert!((median(&a) - median(&b)).abs() / median(&a) < 0.02);
+
+        assert!(b.restore(&Value::from("snot")).is_err());
+        Ok(())
+    }
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::binary::{is_binary, is_tagged, FUNCTION_TAG};
 use crate::registry::Registry;
 use crate::tremor_const_fn;
 use simd_json::prelude::*;
@@ -36,9 +37,17 @@ pub fn load(registry: &mut Registry) {
         .insert(map_function!(is_integer, is_i64))
         .insert(map_function!(is_float, is_f64))
         .insert(map_function!(is_string, is_str))
-        .insert(map_function!(is_array))
+        .insert(tremor_const_fn! (type::is_array(_context, _input) {
+            Ok(Value::from(_input.is_array() && !is_binary(_input) && !is_tagged(_input, FUNCTION_TAG)))
+        }))
         .insert(map_function!(is_record, is_object))
         .insert(tremor_const_fn! (type::as_string(_context, _input) {
+            if is_binary(_input) {
+                return Ok(Value::from("binary"));
+            }
+            if is_tagged(_input, FUNCTION_TAG) {
+                return Ok(Value::from("function"));
+            }
             Ok(match _input.value_type() {
                 ValueType::Null => Value::from("null"),
                 ValueType::Bool => Value::from("bool"),
@@ -59,6 +68,7 @@ pub fn load(registry: &mut Registry) {
 
 #[cfg(test)]
 mod test {
+    use crate::binary::from_bytes;
     use crate::registry::fun;
     use simd_json::prelude::*;
     use simd_json::BorrowedValue as Value;
@@ -125,6 +135,7 @@ mod test {
         assert_val!(f(&[&v]), false);
         let v = Value::Array(vec![]);
         assert_val!(f(&[&v]), true);
+        assert_val!(f(&[&from_bytes(&[1])]), false);
     }
 
     #[test]
@@ -153,5 +164,6 @@ mod test {
         assert_val!(f(&[&v]), "array");
         let v = Value::object();
         assert_val!(f(&[&v]), "record");
+        assert_val!(f(&[&from_bytes(&[1])]), "binary");
     }
 }
@@ -12,13 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::registry::{Aggr as AggrRegistry, FResult, TremorAggrFn, TremorAggrFnWrapper};
+use crate::registry::{
+    mfa, Aggr as AggrRegistry, FResult, FunctionError, TremorAggrFn, TremorAggrFnWrapper,
+};
 
 use simd_json::prelude::*;
 use simd_json::BorrowedValue as Value;
 
 use std::ops::RangeInclusive;
 
+fn bad_snapshot(f: &str) -> FunctionError {
+    FunctionError::RuntimeError {
+        mfa: mfa("win", f, 1),
+        error: "invalid snapshot".to_string(),
+    }
+}
+
+// Optional values are stored as an empty or single element array so a
+// captured `null` can be told apart from no value at all
+fn snapshot_option(v: &Option<Value<'static>>) -> Value<'static> {
+    Value::from(v.iter().cloned().collect::<Vec<_>>())
+}
+
+fn restore_option(snapshot: &Value, f: &str) -> FResult<Option<Value<'static>>> {
+    match snapshot.as_array().map(Vec::as_slice) {
+        Some([]) => Ok(None),
+        Some([v]) => Ok(Some(v.clone_static())),
+        _ => Err(bad_snapshot(f)),
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 struct First(Option<Value<'static>>);
 impl TremorAggrFn for First {
@@ -61,6 +84,13 @@ impl TremorAggrFn for First {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(snapshot_option(&self.0))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0 = restore_option(snapshot, "first")?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -109,6 +139,13 @@ impl TremorAggrFn for Last {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(snapshot_option(&self.0))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0 = restore_option(snapshot, "last")?;
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -148,6 +185,18 @@ impl TremorAggrFn for CollectFlattened {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(self.0.clone()))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0 = snapshot
+            .as_array()
+            .ok_or_else(|| bad_snapshot("collect_flattened"))?
+            .iter()
+            .map(Value::clone_static)
+            .collect();
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -192,6 +241,18 @@ impl TremorAggrFn for CollectNested {
         }
         Ok(())
     }
+    fn snapshot(&self) -> Option<Value<'static>> {
+        Some(Value::from(self.0.clone()))
+    }
+    fn restore(&mut self, snapshot: &Value) -> FResult<()> {
+        self.0 = snapshot
+            .as_array()
+            .ok_or_else(|| bad_snapshot("collect_nested"))?
+            .iter()
+            .map(Value::clone_static)
+            .collect();
+        Ok(())
+    }
     fn snot_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -293,4 +354,32 @@ mod test {
         assert_eq!(a.emit_and_init()?, 2);
         Ok(())
     }
+
+    #[test]
+    fn snapshot_restore() -> Result<()> {
+        let mut a = Last::default();
+        a.init();
+        let snapshot = a.snapshot().expect("last can be snapshotted");
+        let mut b = Last::default();
+        b.restore(&snapshot)?;
+        assert_eq!(b.emit()?, Value::null());
+
+        let null = Value::null();
+        a.accumulate(&[&null])?;
+        let snapshot = a.snapshot().expect("last can be snapshotted");
+        b.restore(&snapshot)?;
+        assert!(b.0.is_some());
+
+        let mut a = CollectNested::default();
+        a.init();
+        let one = Value::from(1);
+        let two = Value::from(2);
+        a.accumulate(&[&one])?;
+        a.accumulate(&[&two])?;
+        let snapshot = a.snapshot().expect("collect can be snapshotted");
+        let mut b = CollectNested::default();
+        b.restore(&snapshot)?;
+        assert_eq!(a.emit()?, b.emit()?);
+        Ok(())
+    }
 }
@@ -0,0 +1,903 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Static type inference and checking.
+//!
+//! The checker walks the AST once and infers the set of types every
+//! expression can evaluate to. It knows the types of literals, the types
+//! of the arguments and results of functions (from the registry and their
+//! documented signatures) and, optionally, the shape of the event from a
+//! schema. Operations that can never succeed are reported as errors,
+//! operations that fail for some of the inferred types as warnings.
+//!
+//! A schema is a value describing the event, leaves are type names and
+//! records describe records with known fields:
+//!
+//! ```json
+//! {"name": "string", "tags": "array", "metrics": {"count": "integer", "load": "number"}}
+//! ```
+
+use crate::ast::{
+    BaseExpr, BinOpKind, Expr, ImutExprInt, Invocable, Invoke, NodeMetas, PatchOperation, Path,
+    Pattern, Segment, UnaryOpKind, Warning,
+};
+use crate::docs::FunctionSignatureDoc;
+use crate::errors::{error_type_conflict_mult, t2s, ErrorKind, Result};
+use halfbrown::HashMap;
+use simd_json::prelude::*;
+use simd_json::{BorrowedValue as Value, ValueType};
+use std::fmt;
+
+/// A set of types a value can have
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Type(u8);
+
+impl Type {
+    pub(crate) const NONE: Self = Self(0);
+    pub(crate) const NULL: Self = Self(1);
+    pub(crate) const BOOL: Self = Self(1 << 1);
+    pub(crate) const INT: Self = Self(1 << 2);
+    pub(crate) const FLOAT: Self = Self(1 << 3);
+    pub(crate) const STRING: Self = Self(1 << 4);
+    pub(crate) const ARRAY: Self = Self(1 << 5);
+    pub(crate) const RECORD: Self = Self(1 << 6);
+    pub(crate) const ANY: Self = Self(0x7f);
+
+    /// The type of a value
+    pub(crate) fn of(value: &Value) -> Self {
+        Self::from(value.value_type())
+    }
+
+    /// Parses a type name as used in schemas and function signatures
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        Some(match name.trim() {
+            "null" => Self::NULL,
+            "bool" | "boolean" => Self::BOOL,
+            "int" | "integer" => Self::INT,
+            "float" => Self::FLOAT,
+            "number" => Self::INT | Self::FLOAT,
+            "string" => Self::STRING,
+            "array" => Self::ARRAY,
+            "record" | "object" => Self::RECORD,
+            "any" => Self::ANY,
+            _ => return None,
+        })
+    }
+
+    pub(crate) fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub(crate) fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// The single types in this set
+    fn each(self) -> impl Iterator<Item = Self> {
+        (0..7)
+            .map(|bit| Self(1 << bit))
+            .filter(move |t| self.intersects(*t))
+    }
+
+    /// The value type of the first type in this set, used in errors
+    fn value_type(self) -> ValueType {
+        self.value_types()
+            .into_iter()
+            .next()
+            .unwrap_or(ValueType::Null)
+    }
+
+    fn value_types(self) -> Vec<ValueType> {
+        self.each()
+            .map(|t| match t {
+                Self::NULL => ValueType::Null,
+                Self::BOOL => ValueType::Bool,
+                Self::INT => ValueType::I64,
+                Self::FLOAT => ValueType::F64,
+                Self::STRING => ValueType::String,
+                Self::ARRAY => ValueType::Array,
+                _ => ValueType::Object,
+            })
+            .collect()
+    }
+}
+
+impl From<ValueType> for Type {
+    fn from(t: ValueType) -> Self {
+        match t {
+            ValueType::Null => Self::NULL,
+            ValueType::Bool => Self::BOOL,
+            ValueType::I64 | ValueType::U64 => Self::INT,
+            ValueType::F64 => Self::FLOAT,
+            ValueType::String => Self::STRING,
+            ValueType::Array => Self::ARRAY,
+            ValueType::Object => Self::RECORD,
+        }
+    }
+}
+
+impl std::ops::BitOr for Type {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == Self::ANY {
+            return write!(f, "any");
+        }
+        let names: Vec<&str> = self.value_types().into_iter().map(t2s).collect();
+        write!(f, "{}", names.join(" or "))
+    }
+}
+
+/// The inferred type of a value and, for records, of its known fields
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Shape {
+    pub(crate) ty: Type,
+    fields: Option<HashMap<String, Shape>>,
+}
+
+impl Shape {
+    pub(crate) fn any() -> Self {
+        Self::from(Type::ANY)
+    }
+
+    pub(crate) fn of(value: &Value) -> Self {
+        if let Some(o) = value.as_object() {
+            Self {
+                ty: Type::RECORD,
+                fields: Some(
+                    o.iter()
+                        .map(|(k, v)| (k.to_string(), Self::of(v)))
+                        .collect(),
+                ),
+            }
+        } else {
+            Self::from(Type::of(value))
+        }
+    }
+
+    /// The shape of the error record bound by `try ... catch`
+    fn error() -> Self {
+        let mut fields = HashMap::new();
+        fields.insert("kind".to_string(), Self::from(Type::STRING));
+        fields.insert("message".to_string(), Self::from(Type::STRING));
+        fields.insert("mfa".to_string(), Self::from(Type::RECORD | Type::NULL));
+        Self {
+            ty: Type::RECORD,
+            fields: Some(fields),
+        }
+    }
+
+    /// Reads the shape described by a schema
+    pub(crate) fn from_schema(schema: &Value) -> Result<Self> {
+        if let Some(o) = schema.as_object() {
+            let mut fields = HashMap::new();
+            for (k, v) in o {
+                fields.insert(k.to_string(), Self::from_schema(v)?);
+            }
+            Ok(Self {
+                ty: Type::RECORD,
+                fields: Some(fields),
+            })
+        } else if let Some(ty) = schema.as_str().and_then(Type::parse) {
+            Ok(Self::from(ty))
+        } else {
+            Err(format!("Invalid type `{}` in schema", schema.encode()).into())
+        }
+    }
+
+    /// The shape that can be either of two shapes
+    pub(crate) fn join(&self, other: &Self) -> Self {
+        let fields = match (&self.fields, &other.fields) {
+            (Some(a), Some(b)) => Some(
+                a.iter()
+                    .filter_map(|(k, v)| b.get(k).map(|o| (k.clone(), v.join(o))))
+                    .collect(),
+            ),
+            _ => None,
+        };
+        Self {
+            ty: self.ty | other.ty,
+            fields,
+        }
+    }
+
+    /// The shape of a field, anything if it isn't known
+    pub(crate) fn field(&self, name: &str) -> Self {
+        self.fields
+            .as_ref()
+            .and_then(|f| f.get(name))
+            .cloned()
+            .unwrap_or_else(Self::any)
+    }
+
+    fn set(&mut self, path: &[String], shape: Self) {
+        if let Some((name, rest)) = path.split_first() {
+            if let Some(fields) = &mut self.fields {
+                let field = fields.entry(name.clone()).or_insert_with(Self::any);
+                if rest.is_empty() {
+                    *field = shape;
+                } else {
+                    field.set(rest, shape);
+                }
+            }
+        } else {
+            *self = shape;
+        }
+    }
+}
+
+impl From<Type> for Shape {
+    fn from(ty: Type) -> Self {
+        Self { ty, fields: None }
+    }
+}
+
+/// The argument and result types of a function
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Signature {
+    args: Option<Vec<Type>>,
+    result: Option<Type>,
+}
+
+impl From<&FunctionSignatureDoc> for Signature {
+    fn from(doc: &FunctionSignatureDoc) -> Self {
+        // Arguments are documented as either `name: type` or `name`
+        let args = doc
+            .args
+            .iter()
+            .map(|a| {
+                a.rsplit(':')
+                    .next()
+                    .and_then(Type::parse)
+                    .unwrap_or(Type::ANY)
+            })
+            .collect();
+        Self {
+            args: Some(args),
+            result: Type::parse(&doc.result),
+        }
+    }
+}
+
+/// Result type of a binary operation on two single types, `None` if
+/// the operation isn't defined for them
+fn binary(kind: BinOpKind, lhs: Type, rhs: Type) -> Option<Type> {
+    use BinOpKind::*;
+    let num = Type::INT | Type::FLOAT;
+    match kind {
+        Eq | NotEq => Some(Type::BOOL),
+        And | Or | Xor if lhs == Type::BOOL && rhs == Type::BOOL => Some(Type::BOOL),
+        BitAnd | BitOr | BitXor if lhs == rhs && (lhs == Type::BOOL || lhs == Type::INT) => {
+            Some(lhs)
+        }
+        RBitShiftSigned | RBitShiftUnsigned | LBitShift | Mod
+            if lhs == Type::INT && rhs == Type::INT =>
+        {
+            Some(Type::INT)
+        }
+        Gt | Gte | Lt | Lte
+            if (lhs == Type::STRING && rhs == Type::STRING)
+                || (num.contains(lhs) && num.contains(rhs)) =>
+        {
+            Some(Type::BOOL)
+        }
+        Add if lhs == Type::STRING && rhs == Type::STRING => Some(Type::STRING),
+        Add | Sub | Mul if lhs == Type::INT && rhs == Type::INT => Some(Type::INT),
+        Add | Sub | Mul | Div if num.contains(lhs) && num.contains(rhs) => Some(Type::FLOAT),
+        _ => None,
+    }
+}
+
+/// Result type of a unary operation on a single type
+fn unary(kind: UnaryOpKind, t: Type) -> Option<Type> {
+    use UnaryOpKind::*;
+    match kind {
+        Plus | Minus if t == Type::INT || t == Type::FLOAT => Some(t),
+        BitNot if t == Type::INT || t == Type::BOOL => Some(t),
+        Not if t == Type::BOOL => Some(t),
+        _ => None,
+    }
+}
+
+/// Infers and checks the types of a script
+pub(crate) struct Checker<'c, 'script> {
+    meta: &'c NodeMetas,
+    consts: &'c [Value<'script>],
+    signatures: HashMap<String, Signature>,
+    pub(crate) event: Shape,
+    locals: Vec<Shape>,
+    pub(crate) warnings: Vec<Warning>,
+}
+
+impl<'c, 'script> Checker<'c, 'script> {
+    pub(crate) fn new(
+        meta: &'c NodeMetas,
+        consts: &'c [Value<'script>],
+        locals: usize,
+        schema: Option<&Value>,
+        signatures: &[FunctionSignatureDoc],
+    ) -> Result<Self> {
+        let event = if let Some(schema) = schema {
+            Shape::from_schema(schema)?
+        } else {
+            Shape::any()
+        };
+        Ok(Self {
+            meta,
+            consts,
+            signatures: signatures
+                .iter()
+                .map(|s| (s.full_name.clone(), Signature::from(s)))
+                .collect(),
+            event,
+            locals: vec![Shape::any(); locals],
+            warnings: Vec::new(),
+        })
+    }
+
+    fn warn<O: BaseExpr, I: BaseExpr>(&mut self, outer: &O, inner: &I, msg: String) {
+        self.warnings.push(Warning {
+            outer: outer.extent(self.meta),
+            inner: inner.extent(self.meta),
+            msg,
+        })
+    }
+
+    /// Requires `got` to overlap with `expected`, warns if it only does
+    /// for some of its types.
+    fn require<O: BaseExpr, I: BaseExpr>(
+        &mut self,
+        outer: &O,
+        inner: &I,
+        got: Type,
+        expected: Type,
+    ) -> Result<()> {
+        if !got.intersects(expected) {
+            error_type_conflict_mult(
+                outer,
+                inner,
+                got.value_type(),
+                expected.value_types(),
+                self.meta,
+            )
+        } else {
+            if !expected.contains(got) && got != Type::ANY {
+                self.warn(
+                    outer,
+                    inner,
+                    format!("Expected {} but this can be {}", expected, got),
+                );
+            }
+            Ok(())
+        }
+    }
+
+    /// Checks a sequence of expressions, returns the shape of the last one
+    pub(crate) fn exprs(&mut self, exprs: &[Expr<'script>]) -> Result<Shape> {
+        let mut last = Shape::from(Type::NULL);
+        for e in exprs {
+            last = self.expr(e)?;
+        }
+        Ok(last)
+    }
+
+    fn imut_exprs<'e, I>(&mut self, exprs: I) -> Result<Shape>
+    where
+        I: IntoIterator<Item = &'e ImutExprInt<'script>>,
+        'script: 'e,
+    {
+        let mut last = Shape::from(Type::NULL);
+        for e in exprs {
+            last = self.imut(e)?;
+        }
+        Ok(last)
+    }
+
+    /// Checks the arms of a match and joins their shapes and the locals
+    /// they leave behind.
+    fn arms<'e, F, I>(&mut self, target: Shape, arms: I, mut body: F) -> Result<Shape>
+    where
+        I: IntoIterator<
+            Item = (
+                &'e Pattern<'script>,
+                &'e Option<ImutExprInt<'script>>,
+                usize,
+            ),
+        >,
+        F: FnMut(&mut Self, usize) -> Result<Shape>,
+        'script: 'e,
+    {
+        let before = self.locals.clone();
+        let mut after = before.clone();
+        let mut result: Option<Shape> = None;
+        for (pattern, guard, i) in arms {
+            self.locals = before.clone();
+            match pattern {
+                Pattern::Assign(a) => {
+                    if let Some(local) = self.locals.get_mut(a.idx) {
+                        *local = match a.pattern.as_ref() {
+                            Pattern::Record(_) => Shape::from(Type::RECORD),
+                            Pattern::Array(_) | Pattern::Bytes(_) => Shape::from(Type::ARRAY),
+                            _ => target.clone(),
+                        };
+                    }
+                }
+                Pattern::Expr(e) => {
+                    self.imut(e)?;
+                }
+                _ => (),
+            }
+            if let Some(guard) = guard {
+                let t = self.imut(guard)?.ty;
+                self.require(guard, guard, t, Type::BOOL)?;
+            }
+            let shape = body(self, i)?;
+            result = Some(result.map_or_else(|| shape.clone(), |r| r.join(&shape)));
+            after = after
+                .iter()
+                .zip(self.locals.iter())
+                .map(|(a, b)| a.join(b))
+                .collect();
+        }
+        self.locals = after;
+        Ok(result.unwrap_or_else(Shape::any))
+    }
+
+    /// Checks the cases of a comprehension
+    fn cases<'e, F, I>(
+        &mut self,
+        target: &ImutExprInt<'script>,
+        key_id: usize,
+        val_id: usize,
+        guards: I,
+        mut body: F,
+    ) -> Result<Shape>
+    where
+        I: IntoIterator<Item = (&'e Option<ImutExprInt<'script>>, usize)>,
+        F: FnMut(&mut Self, usize) -> Result<()>,
+        'script: 'e,
+    {
+        let t = self.imut(target)?;
+        self.require(target, target, t.ty, Type::RECORD | Type::ARRAY)?;
+        let key = if t.ty == Type::ARRAY {
+            Type::INT
+        } else if t.ty == Type::RECORD {
+            Type::STRING
+        } else {
+            Type::INT | Type::STRING
+        };
+        let before = self.locals.clone();
+        let mut after = before.clone();
+        for (guard, i) in guards {
+            self.locals = before.clone();
+            if let Some(k) = self.locals.get_mut(key_id) {
+                *k = Shape::from(key);
+            }
+            if let Some(v) = self.locals.get_mut(val_id) {
+                *v = Shape::any();
+            }
+            if let Some(guard) = guard {
+                self.imut(guard)?;
+            }
+            body(self, i)?;
+            after = after
+                .iter()
+                .zip(self.locals.iter())
+                .map(|(a, b)| a.join(b))
+                .collect();
+        }
+        self.locals = after;
+        Ok(Shape::from(Type::ARRAY))
+    }
+
+    fn record_target<E: BaseExpr>(
+        &mut self,
+        outer: &E,
+        target: &ImutExprInt<'script>,
+    ) -> Result<()> {
+        let t = self.imut(target)?.ty;
+        self.require(outer, target, t, Type::RECORD)
+    }
+
+    fn patch(
+        &mut self,
+        outer: &impl BaseExpr,
+        target: &ImutExprInt<'script>,
+        operations: &[PatchOperation<'script>],
+    ) -> Result<Shape> {
+        self.record_target(outer, target)?;
+        for op in operations {
+            match op {
+                PatchOperation::Insert { ident, expr }
+                | PatchOperation::Upsert { ident, expr }
+                | PatchOperation::Update { ident, expr } => {
+                    let t = self.imut(ident)?.ty;
+                    self.require(outer, ident, t, Type::STRING)?;
+                    self.imut(expr)?;
+                }
+                PatchOperation::Merge { ident, expr } => {
+                    let t = self.imut(ident)?.ty;
+                    self.require(outer, ident, t, Type::STRING)?;
+                    self.record_target(outer, expr)?;
+                }
+                PatchOperation::Erase { ident } => {
+                    let t = self.imut(ident)?.ty;
+                    self.require(outer, ident, t, Type::STRING)?;
+                }
+                PatchOperation::Copy { from, to } | PatchOperation::Move { from, to } => {
+                    for e in &[from, to] {
+                        let t = self.imut(e)?.ty;
+                        self.require(outer, *e, t, Type::STRING)?;
+                    }
+                }
+                PatchOperation::TupleMerge { expr } => self.record_target(outer, expr)?,
+            }
+        }
+        Ok(Shape::from(Type::RECORD))
+    }
+
+    pub(crate) fn expr(&mut self, expr: &Expr<'script>) -> Result<Shape> {
+        match expr {
+            Expr::Match(m) => {
+                let arms = m
+                    .patterns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| (&c.pattern, &c.guard, i));
+                let target = self.imut(&m.target)?;
+                self.arms(target, arms, |this, i| this.exprs(&m.patterns[i].exprs))
+            }
+            Expr::PatchInPlace(p) => self.patch(p.as_ref(), &p.target, &p.operations),
+            Expr::MergeInPlace(m) => {
+                self.record_target(m.as_ref(), &m.target)?;
+                self.record_target(m.as_ref(), &m.expr)?;
+                Ok(Shape::from(Type::RECORD))
+            }
+            Expr::Assign { path, expr, .. } => {
+                let shape = self.expr(expr)?;
+                self.assign(path, shape.clone());
+                Ok(shape)
+            }
+            Expr::AssignMoveLocal { path, idx, .. } => {
+                let shape = self.locals.get(*idx).cloned().unwrap_or_else(Shape::any);
+                self.assign(path, shape.clone());
+                Ok(shape)
+            }
+            Expr::Comprehension(c) => {
+                let guards = c.cases.iter().enumerate().map(|(i, c)| (&c.guard, i));
+                self.cases(&c.target, c.key_id, c.val_id, guards, |this, i| {
+                    this.exprs(&c.cases[i].exprs).map(|_| ())
+                })
+            }
+            Expr::Drop { .. } => Ok(Shape::any()),
+            Expr::Emit(e) => {
+                self.imut(&e.expr)?;
+                if let Some(port) = &e.port {
+                    let t = self.imut(port)?.ty;
+                    self.require(e.as_ref(), port, t, Type::STRING)?;
+                }
+                Ok(Shape::any())
+            }
+            Expr::Imut(e) => self.imut(e),
+        }
+    }
+
+    fn assign(&mut self, path: &Path<'script>, shape: Shape) {
+        let names: Option<Vec<String>> = path
+            .segments()
+            .iter()
+            .map(|s| match s {
+                Segment::Id { mid, .. } => Some(self.meta.name_dflt(*mid)),
+                _ => None,
+            })
+            .collect();
+        let root = match path {
+            Path::Local(p) => self.locals.get_mut(p.idx),
+            Path::Event(_) => Some(&mut self.event),
+            Path::Const(_) | Path::State(_) | Path::Meta(_) => None,
+        };
+        if let Some(root) = root {
+            if let Some(names) = names {
+                root.set(&names, shape);
+            } else {
+                // we can't tell which part of it got changed
+                *root = Shape::any();
+            }
+        }
+    }
+
+    fn path(&mut self, path: &Path<'script>) -> Result<Shape> {
+        let mut shape = match path {
+            Path::Const(p) => self.consts.get(p.idx).map_or_else(Shape::any, Shape::of),
+            Path::Local(p) => self.locals.get(p.idx).cloned().unwrap_or_else(Shape::any),
+            Path::Event(_) => self.event.clone(),
+            Path::State(_) | Path::Meta(_) => Shape::any(),
+        };
+        for segment in path.segments() {
+            shape = match segment {
+                Segment::Id { mid, .. } => {
+                    self.require(path, segment, shape.ty, Type::RECORD)?;
+                    shape.field(&self.meta.name_dflt(*mid))
+                }
+                Segment::Idx { .. } => {
+                    self.require(path, segment, shape.ty, Type::ARRAY)?;
+                    Shape::any()
+                }
+                Segment::Element { expr, .. } => {
+                    self.require(path, segment, shape.ty, Type::RECORD | Type::ARRAY)?;
+                    let t = self.imut(expr)?.ty;
+                    self.require(path, expr, t, Type::STRING | Type::INT)?;
+                    Shape::any()
+                }
+                Segment::Range {
+                    range_start,
+                    range_end,
+                    ..
+                } => {
+                    self.require(path, segment, shape.ty, Type::ARRAY)?;
+                    for e in &[range_start.as_ref(), range_end.as_ref()] {
+                        let t = self.imut(e)?.ty;
+                        self.require(path, *e, t, Type::INT)?;
+                    }
+                    Shape::from(Type::ARRAY)
+                }
+            };
+        }
+        Ok(shape)
+    }
+
+    fn invoke(&mut self, i: &Invoke<'script>) -> Result<Shape> {
+        let mut args = Vec::with_capacity(i.args.len());
+        for a in &i.args {
+            args.push(self.imut(&a.0)?.ty);
+        }
+        let name = format!("{}::{}", i.module.join("::"), i.fun);
+        let mut sig = self.signatures.get(&name).cloned().unwrap_or_default();
+        if let Invocable::Intrinsic(f) = &i.invocable {
+            if let Some(types) = f.arg_types() {
+                sig.args = Some(types.into_iter().map(Type::from).collect());
+            }
+        } else {
+            // tremor functions are untyped
+            return Ok(Shape::any());
+        }
+        if let Some(expected) = sig.args {
+            for ((got, expected), arg) in args.into_iter().zip(expected).zip(&i.args) {
+                self.require(i, &arg.0, got, expected)?;
+            }
+        }
+        Ok(Shape::from(sig.result.unwrap_or(Type::ANY)))
+    }
+
+    pub(crate) fn imut(&mut self, expr: &ImutExprInt<'script>) -> Result<Shape> {
+        Ok(match expr {
+            ImutExprInt::Record(r) => {
+                let mut fields = HashMap::new();
+                let mut known = true;
+                for f in &r.fields {
+                    self.imut(&f.name)?;
+                    let shape = self.imut(&f.value)?;
+                    if let ImutExprInt::Literal(l) = &f.name {
+                        if let Some(name) = l.value.as_str() {
+                            fields.insert(name.to_string(), shape);
+                            continue;
+                        }
+                    }
+                    known = false;
+                }
+                Shape {
+                    ty: Type::RECORD,
+                    fields: if known { Some(fields) } else { None },
+                }
+            }
+            ImutExprInt::List(l) => {
+                self.imut_exprs(l.exprs.iter().map(|e| &e.0))?;
+                Shape::from(Type::ARRAY)
+            }
+            ImutExprInt::Binary(b) => {
+                let lhs = self.imut(&b.lhs)?.ty;
+                let rhs = self.imut(&b.rhs)?.ty;
+                let mut result = Type::NONE;
+                let mut partial = false;
+                for l in lhs.each() {
+                    for r in rhs.each() {
+                        if let Some(t) = binary(b.kind, l, r) {
+                            result = result | t;
+                        } else {
+                            partial = true;
+                        }
+                    }
+                }
+                if result == Type::NONE {
+                    return Err(ErrorKind::InvalidBinary(
+                        b.extent(self.meta).expand_lines(2),
+                        b.extent(self.meta),
+                        b.kind,
+                        lhs.value_type(),
+                        rhs.value_type(),
+                    )
+                    .into());
+                } else if partial && lhs != Type::ANY && rhs != Type::ANY {
+                    self.warn(
+                        b.as_ref(),
+                        b.as_ref(),
+                        format!(
+                            "The binary operation `{}` is not defined for all of {} and {}",
+                            b.kind, lhs, rhs
+                        ),
+                    );
+                }
+                Shape::from(result)
+            }
+            ImutExprInt::Unary(u) => {
+                let t = self.imut(&u.expr)?.ty;
+                let valid: Vec<Type> = t.each().filter_map(|t| unary(u.kind, t)).collect();
+                if valid.is_empty() {
+                    return Err(ErrorKind::InvalidUnary(
+                        u.extent(self.meta).expand_lines(2),
+                        u.expr.extent(self.meta),
+                        u.kind,
+                        t.value_type(),
+                    )
+                    .into());
+                } else if valid.len() < t.each().count() && t != Type::ANY {
+                    self.warn(
+                        u.as_ref(),
+                        &u.expr,
+                        format!(
+                            "The unary operation `{}` is not defined for all of {}",
+                            u.kind, t
+                        ),
+                    );
+                }
+                Shape::from(valid.into_iter().fold(Type::NONE, |a, b| a | b))
+            }
+            ImutExprInt::Patch(p) => self.patch(p.as_ref(), &p.target, &p.operations)?,
+            ImutExprInt::Match(m) => {
+                let arms = m
+                    .patterns
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| (&c.pattern, &c.guard, i));
+                let target = self.imut(&m.target)?;
+                self.arms(target, arms, |this, i| {
+                    this.imut_exprs(m.patterns[i].exprs.iter().map(|e| &e.0))
+                })?
+            }
+            ImutExprInt::Comprehension(c) => {
+                let guards = c.cases.iter().enumerate().map(|(i, c)| (&c.guard, i));
+                self.cases(&c.target, c.key_id, c.val_id, guards, |this, i| {
+                    this.imut_exprs(c.cases[i].exprs.iter().map(|e| &e.0))
+                        .map(|_| ())
+                })?
+            }
+            ImutExprInt::Merge(m) => {
+                self.record_target(m.as_ref(), &m.target)?;
+                self.record_target(m.as_ref(), &m.expr)?;
+                Shape::from(Type::RECORD)
+            }
+            ImutExprInt::Path(p) => self.path(p)?,
+            ImutExprInt::Local { idx, is_const, .. } => {
+                if *is_const {
+                    self.consts.get(*idx).map_or_else(Shape::any, Shape::of)
+                } else {
+                    self.locals.get(*idx).cloned().unwrap_or_else(Shape::any)
+                }
+            }
+            ImutExprInt::Literal(l) => Shape::of(&l.value),
+            ImutExprInt::Present { .. } => Shape::from(Type::BOOL),
+            ImutExprInt::Invoke1(i)
+            | ImutExprInt::Invoke2(i)
+            | ImutExprInt::Invoke3(i)
+            | ImutExprInt::Invoke(i) => self.invoke(i)?,
+            ImutExprInt::Try(t) => {
+                let shape = self.imut(&t.expr)?;
+                let arms = t
+                    .catches
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| (&c.pattern, &c.guard, i));
+                shape.join(&self.arms(Shape::error(), arms, |this, i| {
+                    this.imut_exprs(t.catches[i].exprs.iter().map(|e| &e.0))
+                })?)
+            }
+            ImutExprInt::InvokeAggr(_) => Shape::any(),
+            ImutExprInt::Recur(r) => {
+                self.imut_exprs(r.exprs.iter().map(|e| &e.0))?;
+                Shape::any()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::path::ModulePath;
+    use crate::registry;
+    use crate::Script;
+    use simd_json::json;
+
+    fn check(src: &str, schema: Option<simd_json::OwnedValue>) -> Result<Vec<String>> {
+        let reg = registry::registry();
+        let mut script =
+            match Script::parse(&ModulePath { mounts: vec![] }, "test", src.into(), &reg) {
+                Ok(s) => s,
+                Err(e) => panic!("{:?}", e.error()),
+            };
+        let schema = schema.map(Value::from);
+        let sigs = vec![FunctionSignatureDoc {
+            full_name: "string::len".to_string(),
+            args: vec!["s: string".to_string()],
+            result: "integer".to_string(),
+        }];
+        script.check_types(schema.as_ref(), &sigs)?;
+        Ok(script.warnings().iter().map(|w| w.msg.clone()).collect())
+    }
+
+    #[test]
+    fn types() {
+        assert_eq!(Type::parse("number"), Some(Type::INT | Type::FLOAT));
+        assert_eq!(format!("{}", Type::INT | Type::STRING), "integer or string");
+        assert_eq!(
+            binary(BinOpKind::Div, Type::INT, Type::INT),
+            Some(Type::FLOAT)
+        );
+        assert_eq!(binary(BinOpKind::Add, Type::BOOL, Type::INT), None);
+        assert_eq!(unary(UnaryOpKind::Not, Type::INT), None);
+        let s = Shape::of(&Value::from(json!({"a": 1, "b": "x"})));
+        let j = s.join(&Shape::of(&Value::from(json!({"a": 1.0}))));
+        assert_eq!(j.field("a").ty, Type::INT | Type::FLOAT);
+        assert_eq!(j.field("b").ty, Type::ANY);
+    }
+
+    #[test]
+    fn clean() -> Result<()> {
+        let w = check(
+            "let a = string::len(event.name); a + 1",
+            Some(json!({"name": "string"})),
+        )?;
+        assert!(w.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn definite() {
+        assert!(check("event.count + \"snot\"", Some(json!({"count": "integer"}))).is_err());
+        assert!(check("event.name.first", Some(json!({"name": "string"}))).is_err());
+        assert!(check("string::len(event.n)", Some(json!({"n": "integer"}))).is_err());
+        assert!(check("let a = [1]; -a", None).is_err());
+    }
+
+    #[test]
+    fn possible() -> Result<()> {
+        let w = check(
+            r#"
+let a = match event.flag of
+  case true => 1
+  default => "one"
+end;
+a + 1"#,
+            None,
+        )?;
+        assert_eq!(w.len(), 1);
+        assert!(w[0].starts_with("The binary operation `+`"));
+        Ok(())
+    }
+}
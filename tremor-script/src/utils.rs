@@ -15,6 +15,8 @@
 use crate::errors::Result;
 use simd_json::prelude::*;
 use simd_json::BorrowedValue as Value;
+use simd_json::StaticNode;
+use std::cmp::Ordering;
 use std::io::prelude::*;
 
 /// Fetches a hostname with `tremor-host.local` being the default
@@ -25,6 +27,14 @@ pub fn hostname() -> String {
         .unwrap_or_else(|_| "tremor-host.local".to_string())
 }
 
+/// Consistent hash of `key` into one of `slot_count` slots, this is the
+/// same hash `chash::jump` uses
+pub fn jump_hash(key: &str, slot_count: u32) -> u32 {
+    // This is 'tremor\0\0'  and '\0\0tremor' as integers
+    let jh = jumphash::JumpHasher::new_with_keys(8_390_880_576_440_238_080, 128_034_676_764_530);
+    jh.slot(&key, slot_count)
+}
+
 /// Serialize a Value in a sorted fashion to allow equality comparing the result
 pub fn sorsorted_serialize(j: &Value) -> Result<String> {
     // ballpark size of a 'sensible' message
@@ -79,6 +89,56 @@ fn sorted_serialize_<'v, W: Write>(j: &Value<'v>, w: &mut W) -> Result<()> {
     Ok(())
 }
 
+/// Rank of a value's type used to order values of different types:
+/// `null` < `bool` < number < `string` < `array` < `record`
+fn type_rank(v: &Value) -> u8 {
+    match v {
+        Value::Static(StaticNode::Null) => 0,
+        Value::Static(StaticNode::Bool(_)) => 1,
+        Value::Static(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// Compares two values imposing a total order over all values.
+///
+/// Values of the same type are compared naturally (numbers numerically,
+/// strings lexicographically, arrays element wise), records are compared
+/// by their sorted serialisation. Values of different types are ordered by
+/// type: `null` < `bool` < number < `string` < `array` < `record`.
+#[allow(clippy::cast_precision_loss)]
+pub fn value_cmp(lhs: &Value, rhs: &Value) -> Ordering {
+    match (lhs, rhs) {
+        (Value::Static(StaticNode::Bool(l)), Value::Static(StaticNode::Bool(r))) => l.cmp(r),
+        (Value::String(l), Value::String(r)) => l.cmp(r),
+        (Value::Array(l), Value::Array(r)) => l
+            .iter()
+            .zip(r.iter())
+            .map(|(l, r)| value_cmp(l, r))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or_else(|| l.len().cmp(&r.len())),
+        (Value::Object(_), Value::Object(_)) => {
+            let l = sorsorted_serialize(lhs).unwrap_or_default();
+            let r = sorsorted_serialize(rhs).unwrap_or_default();
+            l.cmp(&r)
+        }
+        (l, r) if type_rank(l) == 2 && type_rank(r) == 2 => {
+            if let (Some(l), Some(r)) = (l.as_u64(), r.as_u64()) {
+                l.cmp(&r)
+            } else if let (Some(l), Some(r)) = (l.as_i64(), r.as_i64()) {
+                l.cmp(&r)
+            } else if let (Some(l), Some(r)) = (l.cast_f64(), r.cast_f64()) {
+                l.partial_cmp(&r).unwrap_or(Ordering::Equal)
+            } else {
+                Ordering::Equal
+            }
+        }
+        (l, r) => type_rank(l).cmp(&type_rank(r)),
+    }
+}
+
 /// Loads an event file
 pub fn load_event_file(name: &str) -> crate::errors::Result<Vec<Value<'static>>> {
     use simd_json::to_borrowed_value;
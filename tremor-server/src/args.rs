@@ -38,6 +38,13 @@ pub fn parse<'a>() -> App<'a, 'a> {
                 .max_values(10000) // there is no 'as many as you want' but this comes close
                 .required(false),
         )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .short("w")
+                .help("Watch config and query files or directories and apply changes to them.")
+                .required(false),
+        )
         .arg(
             Arg::with_name("storage-directory")
                 .long("storage-directory")
@@ -77,6 +84,15 @@ pub fn parse<'a>() -> App<'a, 'a> {
                 .takes_value(true)
                 .default_value("tremor"),
         )
+        .arg(
+            Arg::with_name("plugin-directory")
+                .long("plugin-directory")
+                .short("p")
+                .help("Directory to load plugins from.")
+                .takes_value(true)
+                .min_values(1)
+                .required(false),
+        )
         .arg(
             Arg::with_name("recursion-limit")
                 .long("recursion-limit")
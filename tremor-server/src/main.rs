@@ -39,6 +39,7 @@ static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 extern crate log;
 
 mod args;
+mod watch;
 
 use crate::errors::{Error, Result};
 use crate::system::World;
@@ -53,16 +54,21 @@ use tremor_api as api;
 use tremor_pipeline::query::Query;
 use tremor_pipeline::FN_REGISTRY;
 use tremor_runtime::repository::{BindingArtefact, PipelineArtefact};
-use tremor_runtime::{self, config, errors, functions, metrics, system, url, version};
+use tremor_runtime::{self, config, errors, functions, metrics, plugin, system, url, version};
 
 #[cfg_attr(tarpaulin, skip)]
-async fn load_file(world: &World, file_name: &str) -> Result<usize> {
-    info!("Loading configuration from {}", file_name);
-    let mut count = 0;
+fn read_config_file(file_name: &str) -> Result<config::Config> {
     let file = File::open(file_name)
         .map_err(|e| Error::from(format!("Could not open file {} => {}", file_name, e)))?;
     let buffered_reader = BufReader::new(file);
-    let config: config::Config = serde_yaml::from_reader(buffered_reader)?;
+    Ok(serde_yaml::from_reader(buffered_reader)?)
+}
+
+#[cfg_attr(tarpaulin, skip)]
+async fn load_file(world: &World, file_name: &str) -> Result<usize> {
+    info!("Loading configuration from {}", file_name);
+    let mut count = 0;
+    let config = read_config_file(file_name)?;
     let config = tremor_runtime::incarnate(config)?;
 
     for o in config.offramps {
@@ -110,10 +116,9 @@ async fn load_file(world: &World, file_name: &str) -> Result<usize> {
 }
 
 #[cfg_attr(tarpaulin, skip)]
-async fn load_query_file(world: &World, file_name: &str) -> Result<usize> {
+fn read_query_file(file_name: &str) -> Result<(TremorURL, Query)> {
     use std::ffi::OsStr;
     use std::io::Read;
-    info!("Loading configuration from {}", file_name);
     let path = Path::new(file_name);
     let id = path
         .file_stem()
@@ -138,7 +143,13 @@ async fn load_query_file(world: &World, file_name: &str) -> Result<usize> {
         &aggr_reg,
     )?;
 
-    let id = TremorURL::parse(&format!("/pipeline/{}", id))?;
+    Ok((TremorURL::parse(&format!("/pipeline/{}", id))?, query))
+}
+
+#[cfg_attr(tarpaulin, skip)]
+async fn load_query_file(world: &World, file_name: &str) -> Result<usize> {
+    info!("Loading configuration from {}", file_name);
+    let (id, query) = read_query_file(file_name)?;
     info!("Loading {} from file.", id);
     world
         .repo
@@ -203,6 +214,15 @@ async fn run_dun() -> Result<()> {
         tremor_script::RECURSION_LIMIT = l;
     }
 
+    // Plugins are loaded before anything that could make use of them, only
+    // from directories explicitly asked for
+    for dir in matches.values_of("plugin-directory").into_iter().flatten() {
+        match plugin::load_dir(Path::new(dir)) {
+            Ok(n) => info!("Loaded {} plugin(s) from {}", n, dir),
+            Err(e) => error!("Failed to load plugins from {}: {}", dir, e),
+        }
+    }
+
     let storage_directory = matches
         .value_of("storage-directory")
         .map(std::string::ToString::to_string);
@@ -211,7 +231,7 @@ async fn run_dun() -> Result<()> {
 
     // We load queries first since those are only pipelines.
     let query_files: Vec<String> = match matches.values_of("query") {
-        Some(files) => files.map(String::from).collect(),
+        Some(files) => watch::expand(files, watch::Kind::Query)?,
         // read from default query file(s) now (returns empty if these paths don't exist too)
         None => glob::glob("/etc/tremor/config/*.trickle")?
             .filter_map(|p| p.ok().map(|p| p.display().to_string()))
@@ -226,7 +246,7 @@ async fn run_dun() -> Result<()> {
     }
 
     let config_files: Vec<String> = match matches.values_of("config") {
-        Some(files) => files.map(String::from).collect(),
+        Some(files) => watch::expand(files, watch::Kind::Config)?,
         // read from default config file(s) now (returns empty if these paths don't exist too)
         None => glob::glob("/etc/tremor/config/*.yaml")?
             .filter_map(|p| p.ok().map(|p| p.display().to_string()))
@@ -240,6 +260,16 @@ async fn run_dun() -> Result<()> {
         }
     }
 
+    if matches.is_present("watch") {
+        let configs: Vec<&str> = matches
+            .values_of("config")
+            .map_or_else(|| vec![watch::DEFAULT_DIR], Iterator::collect);
+        let queries: Vec<&str> = matches
+            .values_of("query")
+            .map_or_else(|| vec![watch::DEFAULT_DIR], Iterator::collect);
+        watch::Watch::new(world.clone(), &configs, &queries)?.start()?;
+    }
+
     let host = matches
         .value_of("host")
         .ok_or_else(|| Error::from("host argument missing"))?;
@@ -250,6 +280,8 @@ async fn run_dun() -> Result<()> {
 
     app.at("/version")
         .get(|r| async { fix_tide(api::version::get(r).await) });
+    app.at("/metrics")
+        .get(|r| async { fix_tide(api::metrics::get(r).await) });
     app.at("/binding")
         .get(|r| async { fix_tide(api::binding::list_artefact(r).await) })
         .post(|r| async { fix_tide(api::binding::publish_artefact(r).await) });
@@ -265,6 +297,7 @@ async fn run_dun() -> Result<()> {
         .post(|r| async { fix_tide(api::pipeline::publish_artefact(r).await) });
     app.at("/pipeline/{aid}")
         .get(|r| async { fix_tide(api::pipeline::get_artefact(r).await) })
+        .put(|r| async { fix_tide(api::pipeline::replace_artefact(r).await) })
         .delete(|r| async { fix_tide(api::pipeline::unpublish_artefact(r).await) });
     app.at("/onramp")
         .get(|r| async { fix_tide(api::onramp::list_artefact(r).await) })
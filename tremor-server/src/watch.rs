@@ -0,0 +1,220 @@
+// Copyright 2018-2020, Wayfair GmbH
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::{Error, Result};
+use crate::system::World;
+use crate::{read_config_file, read_query_file};
+use async_std::task;
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tremor_runtime::reload::{self, Artefacts};
+
+/// Directory that is watched when no config or query files are given
+pub(crate) const DEFAULT_DIR: &str = "/etc/tremor/config";
+
+/// The kind of file that is loaded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Kind {
+    /// YAML config
+    Config,
+    /// Trickle query
+    Query,
+}
+
+impl Kind {
+    fn of(path: &Path) -> Option<Self> {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("yaml") | Some("yml") => Some(Self::Config),
+            Some("trickle") => Some(Self::Query),
+            _ => None,
+        }
+    }
+}
+
+/// Replaces directories with the files of the given kind they contain
+pub(crate) fn expand<'a>(paths: impl Iterator<Item = &'a str>, kind: Kind) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if Path::new(path).is_dir() {
+            let mut entries: Vec<String> = fs::read_dir(path)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && Kind::of(p) == Some(kind))
+                .map(|p| p.display().to_string())
+                .collect();
+            entries.sort();
+            files.append(&mut entries);
+        } else {
+            files.push(path.to_string());
+        }
+    }
+    Ok(files)
+}
+
+fn read(path: &Path, kind: Kind) -> Result<Artefacts> {
+    let file_name = path.display().to_string();
+    match kind {
+        Kind::Config => Artefacts::from_config(read_config_file(&file_name)?),
+        Kind::Query => {
+            let (id, query) = read_query_file(&file_name)?;
+            Artefacts::from_query(id, query)
+        }
+    }
+}
+
+/// Watches config and query files, or directories containing them, and
+/// applies changes to the running system
+pub(crate) struct Watch {
+    world: World,
+    targets: Vec<(PathBuf, Kind)>,
+    files: HashMap<PathBuf, Artefacts>,
+}
+
+impl Watch {
+    /// Creates a watch, the files are expected to be loaded already
+    pub(crate) fn new(world: World, configs: &[&str], queries: &[&str]) -> Result<Self> {
+        let mut targets = Vec::new();
+        let mut files = HashMap::new();
+        let all = configs
+            .iter()
+            .map(|c| (c, Kind::Config))
+            .chain(queries.iter().map(|q| (q, Kind::Query)));
+        for (target, kind) in all {
+            let target = match fs::canonicalize(target) {
+                Ok(target) => target,
+                Err(e) => {
+                    warn!("Not watching {}: {}", target, e);
+                    continue;
+                }
+            };
+            for file in expand(target.to_str().into_iter(), kind)? {
+                let path = PathBuf::from(file);
+                let artefacts = read(&path, kind)?;
+                files.insert(path, artefacts);
+            }
+            targets.push((target, kind));
+        }
+        Ok(Self {
+            world,
+            targets,
+            files,
+        })
+    }
+
+    /// The kind of a file if it is one we watch
+    fn kind(&self, path: &Path) -> Option<Kind> {
+        self.targets.iter().find_map(|(target, kind)| {
+            if target == path
+                || (target.is_dir()
+                    && path.parent() == Some(target)
+                    && Kind::of(path) == Some(*kind))
+            {
+                Some(*kind)
+            } else {
+                None
+            }
+        })
+    }
+
+    async fn changed(&mut self, path: &Path) {
+        let kind = if let Some(kind) = self.kind(path) {
+            kind
+        } else {
+            return;
+        };
+        info!("Reloading {}", path.display());
+        // Invalid files are reported and leave the running system alone
+        let new = match read(path, kind) {
+            Ok(new) => new,
+            Err(e) => {
+                error!("Invalid {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let old = self.files.get(path).cloned().unwrap_or_default();
+        match reload::apply(&self.world, &old, &new).await {
+            Ok(()) => {
+                self.files.insert(path.to_path_buf(), new);
+            }
+            Err(e) => error!("Failed to apply {}: {}", path.display(), e),
+        }
+    }
+
+    async fn removed(&mut self, path: &Path) {
+        if let Some(old) = self.files.remove(path) {
+            info!("Removing {}", path.display());
+            if let Err(e) = reload::apply(&self.world, &old, &Artefacts::default()).await {
+                error!("Failed to remove {}: {}", path.display(), e);
+                self.files.insert(path.to_path_buf(), old);
+            }
+        }
+    }
+
+    /// Starts watching in the background
+    pub(crate) fn start(mut self) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::watcher(tx, Duration::from_secs(1))
+            .map_err(|e| Error::from(format!("Failed to start watcher: {}", e)))?;
+        // We watch the directory of single files as well, editors and
+        // volume mounts tend to replace files instead of writing to them
+        let mut dirs: Vec<&Path> = self
+            .targets
+            .iter()
+            .filter_map(|(target, _)| {
+                if target.is_dir() {
+                    Some(target.as_path())
+                } else {
+                    target.parent()
+                }
+            })
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            info!("Watching {}", dir.display());
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| Error::from(format!("Failed to watch {}: {}", dir.display(), e)))?;
+        }
+        thread::Builder::new()
+            .name("config-watch".into())
+            .spawn(move || {
+                for event in rx.iter() {
+                    match event {
+                        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => {
+                            task::block_on(self.changed(&path))
+                        }
+                        DebouncedEvent::Remove(path) => task::block_on(self.removed(&path)),
+                        DebouncedEvent::Rename(from, to) => {
+                            task::block_on(self.removed(&from));
+                            task::block_on(self.changed(&to));
+                        }
+                        DebouncedEvent::Error(e, path) => {
+                            error!("Failed to watch {:?}: {}", path, e)
+                        }
+                        _ => (),
+                    }
+                }
+                // the watcher stops once it is dropped, so it has to live
+                // as long as this thread
+                drop(watcher);
+            })?;
+        Ok(())
+    }
+}